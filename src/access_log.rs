@@ -0,0 +1,189 @@
+use std::{
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+    time::{Duration, Instant, SystemTime},
+};
+
+use axum::{
+    body::{Body, Bytes},
+    extract::{Request, State},
+    http::header,
+    middleware::Next,
+    response::Response,
+};
+use http_body::Frame;
+
+use crate::{auth::BasicAuth, client, request_id, service::Service};
+
+#[derive(Clone, Copy, Debug, clap::ValueEnum, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    /// The Common Log Format
+    Common,
+    /// The Combined Log Format, adding the referrer and user agent
+    Combined,
+    /// One JSON object per request, including its duration
+    Json,
+}
+
+#[derive(Clone, Copy)]
+pub struct Config {
+    pub format: Format,
+}
+
+/// What is known about a request before its response body is sent.
+struct Entry {
+    format: Format,
+    client: String,
+    user: Option<String>,
+    method: String,
+    target: String,
+    version: String,
+    referer: Option<String>,
+    user_agent: Option<String>,
+    request_id: Option<String>,
+    status: u16,
+    time: SystemTime,
+    start: Instant,
+}
+
+/// Middleware writing a line to stdout for every request once its response
+/// body has been sent, or the client went away, if there's a config.
+pub async fn middleware(
+    State(config): State<Option<Config>>,
+    State(client): State<Arc<client::Config>>,
+    State(service): State<Arc<Service>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let Some(config) = config else {
+        return next.run(request).await;
+    };
+    let (parts, body) = request.into_parts();
+    let header = |name| {
+        parts
+            .headers
+            .get(name)
+            .and_then(|value: &header::HeaderValue| value.to_str().ok())
+            .map(str::to_owned)
+    };
+    let entry = Entry {
+        format: config.format,
+        client: client::address(&parts, &client.proxies)
+            .map_or_else(|| "-".to_owned(), |ip| ip.to_string()),
+        user: header(header::AUTHORIZATION)
+            .and_then(|value| BasicAuth::parse(&value))
+            .map(|auth| auth.username),
+        method: parts.method.to_string(),
+        target: parts.uri.to_string(),
+        version: format!("{:?}", parts.version),
+        referer: header(header::REFERER),
+        user_agent: header(header::USER_AGENT),
+        request_id: None,
+        status: 0,
+        time: service.clock().now(),
+        start: Instant::now(),
+    };
+
+    let response = next.run(Request::from_parts(parts, body)).await;
+    let entry = Entry {
+        status: response.status().as_u16(),
+        request_id: response
+            .headers()
+            .get(request_id::HEADER)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_owned),
+        ..entry
+    };
+    response.map(|body| {
+        Body::new(LoggedBody {
+            inner: body,
+            bytes: 0,
+            entry: Some(entry),
+        })
+    })
+}
+
+impl Entry {
+    fn format(&self, bytes: u64, duration: Duration) -> String {
+        let quoted = |value: &Option<String>| {
+            serde_json::to_string(value.as_deref().unwrap_or("-")).unwrap()
+        };
+        let time = chrono::DateTime::<chrono::Utc>::from(self.time);
+        let common = format!(
+            "{} - {} [{}] \"{} {} {}\" {} {bytes}",
+            self.client,
+            self.user.as_deref().unwrap_or("-"),
+            time.format("%d/%b/%Y:%H:%M:%S +0000"),
+            self.method,
+            self.target,
+            self.version,
+            self.status,
+        );
+        match self.format {
+            Format::Common => common,
+            Format::Combined => format!(
+                "{common} {} {}",
+                quoted(&self.referer),
+                quoted(&self.user_agent)
+            ),
+            Format::Json => serde_json::json!({
+                "time": time.to_rfc3339(),
+                "client": self.client,
+                "user": self.user,
+                "method": self.method,
+                "target": self.target,
+                "version": self.version,
+                "status": self.status,
+                "bytes": bytes,
+                "duration_ms": duration.as_secs_f64() * 1000.0,
+                "referer": self.referer,
+                "user_agent": self.user_agent,
+                "request_id": self.request_id,
+            })
+            .to_string(),
+        }
+    }
+}
+
+/// Counts the bytes of a response body and logs the request when dropped.
+struct LoggedBody {
+    inner: Body,
+    bytes: u64,
+    entry: Option<Entry>,
+}
+
+impl http_body::Body for LoggedBody {
+    type Data = Bytes;
+    type Error = axum::Error;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Bytes>, axum::Error>>> {
+        let frame = Pin::new(&mut self.inner).poll_frame(cx);
+        if let Poll::Ready(Some(Ok(frame))) = &frame
+            && let Some(data) = frame.data_ref()
+        {
+            self.bytes += data.len() as u64;
+        }
+        frame
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> http_body::SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+impl Drop for LoggedBody {
+    fn drop(&mut self) {
+        if let Some(entry) = self.entry.take() {
+            println!("{}", entry.format(self.bytes, entry.start.elapsed()));
+        }
+    }
+}
@@ -0,0 +1,102 @@
+//! A minimal HTML dashboard for the users listed with --admin.
+
+use std::{fmt::Write, sync::Arc};
+
+use axum::{
+    Router,
+    extract::{Path, State},
+    http::{HeaderMap, StatusCode, header},
+    response::{Html, IntoResponse, Redirect, Response},
+    routing::{get, post},
+};
+use uuid::Uuid;
+
+use crate::{auth::Admin, html::escape, service::Service, stats};
+
+/// Pastes listed on the dashboard.
+const RECENT: usize = 50;
+
+/// Lets the page use its inline styles and post its own forms, nothing else.
+const CSP: &str = "default-src 'none'; style-src 'unsafe-inline'; form-action 'self'; \
+                   frame-ancestors 'none'";
+
+pub fn routes() -> Router<crate::AppState> {
+    Router::new()
+        .route("/admin", get(dashboard))
+        .route("/admin/paste/{id}/delete", post(delete_paste))
+}
+
+async fn dashboard(State(service): State<Arc<Service>>, admin: Admin) -> Response {
+    let recent = match service.recent_pastes(RECENT).await {
+        Ok(recent) => recent,
+        Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    };
+    let stats = stats::collect(&service);
+
+    let mut page = String::new();
+    write!(
+        page,
+        "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\">\
+         <meta name=\"robots\" content=\"noindex\"><title>Pastebin admin</title>\
+         <style>body{{font-family:sans-serif}}td,th{{padding:0 1em;text-align:left}}</style>\
+         </head><body>\n<h1>Pastebin admin</h1>\n<p>Signed in as {}.</p>\n\
+         <ul><li>{} pastes, {} bytes</li><li>{} users</li>\
+         <li>{} created in the last 24 hours</li><li>Up for {} s</li></ul>\n\
+         <h2>Recent pastes</h2>\n<table><tr><th>Paste</th><th>Owner</th><th>Size</th>\
+         <th>Written</th><th></th></tr>\n",
+        escape(&admin.username),
+        stats.pastes,
+        stats.bytes,
+        stats.users,
+        stats.created_last_24h,
+        stats.uptime_seconds,
+    )
+    .unwrap();
+    for paste in recent {
+        let written = chrono::DateTime::<chrono::Utc>::from(paste.written);
+        writeln!(
+            page,
+            "<tr><td><a href=\"paste/{id}\">{id}</a></td><td>{}</td><td>{}</td><td>{}</td>\
+             <td><form method=\"post\" action=\"admin/paste/{id}/delete\">\
+             <button>Delete</button></form></td></tr>",
+            escape(paste.metadata.owner.as_deref().unwrap_or("anonymous")),
+            paste.size,
+            written.format("%Y-%m-%d %H:%M:%S UTC"),
+            id = paste.id,
+        )
+        .unwrap();
+    }
+    page.push_str("</table>\n</body></html>\n");
+    ([(header::CONTENT_SECURITY_POLICY, CSP)], Html(page)).into_response()
+}
+
+/// Browsers send cached credentials along with forms posted from other
+/// sites, so only accept posts that come from the dashboard itself.
+fn is_same_origin(headers: &HeaderMap) -> bool {
+    let value = |name| headers.get(name).and_then(|value| value.to_str().ok());
+    match value("sec-fetch-site") {
+        Some(site) => site == "same-origin" || site == "none",
+        None => match (value(header::ORIGIN.as_str()), value(header::HOST.as_str())) {
+            (Some(origin), Some(host)) => {
+                origin.split_once("://").map(|(_, rest)| rest) == Some(host)
+            }
+            (Some(_), None) => false,
+            (None, _) => true,
+        },
+    }
+}
+
+async fn delete_paste(
+    State(service): State<Arc<Service>>,
+    _admin: Admin,
+    headers: HeaderMap,
+    Path(id): Path<Uuid>,
+) -> Response {
+    if !is_same_origin(&headers) {
+        return (StatusCode::FORBIDDEN, "Cross-site request").into_response();
+    }
+    match service.remove(&id).await {
+        Ok(()) => Redirect::to("../../../admin").into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
@@ -0,0 +1,408 @@
+use std::{
+    collections::{HashMap, HashSet},
+    io,
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use async_compression::tokio::{bufread::GzipDecoder, write::GzipEncoder};
+use async_zip::{Compression, ZipEntryBuilder, tokio::write::ZipFileWriter};
+use axum::body::Bytes;
+use futures::{Stream, StreamExt};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader, DuplexStream};
+use tokio_util::compat::FuturesAsyncWriteCompatExt;
+
+use crate::service::{Options, Service};
+
+/// Size of the in-memory pipe between the archive writer and the response.
+const PIPE_CAPACITY: usize = 64 * 1024;
+
+/// Name of the entry listing the pastes in an archive, ahead of them.
+pub const MANIFEST: &str = "manifest.json";
+
+/// Largest manifest an import reads, in bytes.
+const MAX_MANIFEST: u64 = 16 * 1024 * 1024;
+
+/// Longest entry name made from a paste's title, in bytes.
+const MAX_NAME: usize = 100;
+
+/// A paste in the [`MANIFEST`] of an archive, named `entry` in it.
+#[derive(Debug, serde::Deserialize, serde::Serialize)]
+pub struct Listed {
+    pub entry: String,
+    pub id: uuid::Uuid,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub language: Option<String>,
+    #[serde(default)]
+    pub public: bool,
+    #[serde(default)]
+    pub noindex: bool,
+    /// When the paste is deleted, in seconds since the Unix epoch.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<u64>,
+}
+
+/// Container format of a download of several pastes.
+#[derive(Clone, Copy, Debug, Default, serde::Deserialize)]
+pub enum Format {
+    #[serde(rename = "tar")]
+    Tar,
+    #[default]
+    #[serde(rename = "tar.gz")]
+    TarGz,
+    #[serde(rename = "zip")]
+    Zip,
+}
+
+impl Format {
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Self::Tar => "application/x-tar",
+            Self::TarGz => "application/gzip",
+            Self::Zip => "application/zip",
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Tar => "tar",
+            Self::TarGz => "tar.gz",
+            Self::Zip => "zip",
+        }
+    }
+}
+
+/// Streams an archive in `format` containing the pastes `ids`.
+///
+/// The archive is produced by a background task as the client reads it, so at
+/// most [`PIPE_CAPACITY`] bytes are buffered. If writing fails part way the
+/// stream ends with an error rather than a truncated but valid-looking body.
+pub fn stream(
+    service: Arc<Service>,
+    ids: Vec<String>,
+    format: Format,
+) -> impl Stream<Item = io::Result<Bytes>> {
+    piped(async move |mut writer| match format {
+        Format::Tar => {
+            write_tar(&service, &ids, &mut writer).await?;
+            writer.shutdown().await
+        }
+        Format::TarGz => {
+            let mut encoder = GzipEncoder::new(writer);
+            write_tar(&service, &ids, &mut encoder).await?;
+            encoder.shutdown().await
+        }
+        Format::Zip => write_zip(&service, &ids, writer).await,
+    })
+}
+
+/// Runs `write` in a background task and streams what it writes to the pipe,
+/// followed by its error if it fails.
+fn piped<F>(write: impl FnOnce(DuplexStream) -> F) -> impl Stream<Item = io::Result<Bytes>>
+where
+    F: Future<Output = io::Result<()>> + Send + 'static,
+{
+    let (writer, reader) = tokio::io::duplex(PIPE_CAPACITY);
+    let task = tokio::spawn(write(writer));
+
+    let outcome = futures::stream::once(async move {
+        match task.await {
+            Ok(Ok(())) => None,
+            Ok(Err(e)) => Some(Err(e)),
+            Err(e) => Some(Err(io::Error::other(e))),
+        }
+    })
+    .filter_map(futures::future::ready);
+
+    tokio_util::io::ReaderStream::new(reader).chain(outcome)
+}
+
+/// Lists the pastes `ids` with their metadata, naming each entry after the
+/// paste's title where it has one that's distinct.
+async fn manifest(service: &Service, ids: &[String]) -> io::Result<Vec<Listed>> {
+    let mut taken = HashSet::from([MANIFEST.to_owned()]);
+    let mut listed = Vec::new();
+    for id in ids {
+        let id = uuid::Uuid::parse_str(id).map_err(io::Error::other)?;
+        let metadata = service
+            .metadata(&id)
+            .await
+            .map_err(io::Error::other)?
+            .unwrap_or_default();
+        let entry = metadata
+            .title
+            .as_deref()
+            .map(entry_name)
+            .filter(|name| !name.is_empty() && !taken.contains(name))
+            .unwrap_or_else(|| id.to_string());
+        taken.insert(entry.clone());
+        listed.push(Listed {
+            entry,
+            id,
+            title: metadata.title,
+            language: metadata.language,
+            public: metadata.public,
+            noindex: metadata.noindex,
+            expires_at: metadata.expires_at,
+        });
+    }
+    Ok(listed)
+}
+
+/// A file name from a paste's title, keeping only characters that are safe
+/// on every platform and that can't escape the directory it's extracted to.
+fn entry_name(title: &str) -> String {
+    let mut name = String::new();
+    for c in title.trim().chars() {
+        let c = if c.is_alphanumeric() || matches!(c, '-' | '_' | '.' | ' ') {
+            c
+        } else {
+            '_'
+        };
+        if name.len() + c.len_utf8() > MAX_NAME {
+            break;
+        }
+        name.push(c);
+    }
+    name.trim_matches(['.', ' ']).to_owned()
+}
+
+async fn write_tar(
+    service: &Service,
+    ids: &[String],
+    writer: &mut (impl AsyncWrite + Unpin + Send),
+) -> io::Result<()> {
+    let listed = manifest(service, ids).await?;
+    let mut builder = tokio_tar::Builder::new_non_terminated(writer);
+    let contents = serde_json::to_vec_pretty(&listed)?;
+    let mut header = tokio_tar::Header::new_gnu();
+    header.set_size(contents.len() as u64);
+    header.set_mode(0o644);
+    header.set_mtime(unix_time(service.clock().now()));
+    builder
+        .append_data(&mut header, MANIFEST, &contents[..])
+        .await?;
+    for paste in &listed {
+        let reader = service
+            .read_file(&paste.id)
+            .await
+            .map_err(io::Error::other)?;
+        let metadata = reader.get_ref().metadata().await?;
+        let mut header = tokio_tar::Header::new_gnu();
+        header.set_metadata(&metadata);
+        builder
+            .append_data(&mut header, &paste.entry, reader)
+            .await?;
+    }
+    builder.finish().await
+}
+
+fn unix_time(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs())
+}
+
+async fn write_zip(
+    service: &Service,
+    ids: &[String],
+    writer: impl AsyncWrite + Unpin,
+) -> io::Result<()> {
+    let listed = manifest(service, ids).await?;
+    let mut zip = ZipFileWriter::with_tokio(writer);
+    let contents = serde_json::to_vec_pretty(&listed)?;
+    let now = service.clock().now();
+    let entry = ZipEntryBuilder::new(MANIFEST.into(), Compression::Deflate)
+        .last_modification_date(chrono::DateTime::<chrono::Utc>::from(now).into())
+        .unix_permissions(0o644);
+    zip.write_entry_whole(entry, &contents)
+        .await
+        .map_err(io::Error::other)?;
+    for paste in &listed {
+        let mut reader = service
+            .read_file(&paste.id)
+            .await
+            .map_err(io::Error::other)?;
+        let modified = reader.get_ref().metadata().await?.modified()?;
+        let entry = ZipEntryBuilder::new(paste.entry.clone().into(), Compression::Deflate)
+            .last_modification_date(chrono::DateTime::<chrono::Utc>::from(modified).into())
+            .unix_permissions(0o644);
+        let mut entry = zip
+            .write_entry_stream(entry)
+            .await
+            .map_err(io::Error::other)?
+            .compat_write();
+        tokio::io::copy(&mut reader, &mut entry).await?;
+        entry.into_inner().close().await.map_err(io::Error::other)?;
+    }
+    zip.close()
+        .await
+        .map_err(io::Error::other)?
+        .into_inner()
+        .shutdown()
+        .await
+}
+
+/// Creates a paste owned by `auth` for every regular file in a gzipped
+/// tarball, as produced by [`stream`] with [`Format::TarGz`]. Returns `(entry name, paste ID)` pairs.
+///
+/// Pastes listed in a [`MANIFEST`] ahead of them get the metadata and ID it
+/// gives them, and other entries named after a paste ID keep that ID. An ID
+/// that's already taken is replaced by a new one.
+pub async fn import_tar_gz(
+    service: &Service,
+    reader: impl AsyncRead + Unpin + Send,
+    auth: (String, String),
+) -> anyhow::Result<Vec<(String, String)>> {
+    let mut archive = tokio_tar::Archive::new(GzipDecoder::new(BufReader::new(reader)));
+    let mut entries = archive.entries()?;
+    let mut listed = HashMap::new();
+    let mut imported = Vec::new();
+    while let Some(entry) = entries.next().await {
+        let mut entry = entry?;
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+        let name = entry.path()?.to_string_lossy().into_owned();
+        if name == MANIFEST {
+            let mut contents = Vec::new();
+            (&mut entry)
+                .take(MAX_MANIFEST)
+                .read_to_end(&mut contents)
+                .await?;
+            let manifest: Vec<Listed> = serde_json::from_slice(&contents)?;
+            listed = manifest
+                .into_iter()
+                .map(|paste| (paste.entry.clone(), paste))
+                .collect();
+            continue;
+        }
+        let (preferred, options) = match listed.remove(&name) {
+            Some(paste) => (Some(paste.id), options(service, paste)),
+            None => {
+                let preferred = entry
+                    .path()?
+                    .file_name()
+                    .and_then(|name| name.to_str())
+                    .and_then(|name| uuid::Uuid::parse_str(name).ok());
+                (preferred, Options::default())
+            }
+        };
+        options.validate()?;
+
+        let auth = Some(auth.clone());
+        let id = match preferred {
+            Some(id) => {
+                match service
+                    .create_with_id(id, &mut entry, auth.clone(), options.clone())
+                    .await
+                {
+                    Err(e) if is_already_exists(&e) => {
+                        service
+                            .create_with_options(&mut entry, auth, options)
+                            .await?
+                    }
+                    result => result?,
+                }
+            }
+            None => {
+                service
+                    .create_with_options(&mut entry, auth, options)
+                    .await?
+            }
+        };
+        imported.push((name, id));
+    }
+    Ok(imported)
+}
+
+/// The options recreating a paste as listed in a manifest. One past its
+/// expiry gets a second left, for collection to delete as usual.
+fn options(service: &Service, paste: Listed) -> Options {
+    let now = unix_time(service.clock().now());
+    Options {
+        noindex: paste.noindex,
+        public: paste.public,
+        title: paste.title,
+        language: paste.language,
+        gist: false,
+        expires_in: paste
+            .expires_at
+            .map(|expires_at| expires_at.saturating_sub(now).max(1)),
+    }
+}
+
+fn is_already_exists(e: &anyhow::Error) -> bool {
+    e.downcast_ref::<io::Error>()
+        .is_some_and(|e| e.kind() == io::ErrorKind::AlreadyExists)
+}
+
+#[test]
+fn test_entry_name() {
+    assert_eq!(entry_name("notes.md"), "notes.md");
+    assert_eq!(entry_name("../../etc/passwd"), "_.._etc_passwd");
+    assert_eq!(entry_name(" .hidden. "), "hidden");
+    assert_eq!(entry_name("a/b\\c:d"), "a_b_c_d");
+    assert_eq!(entry_name("Grüße"), "Grüße");
+    assert_eq!(entry_name(&"é".repeat(MAX_NAME)).len(), MAX_NAME);
+}
+
+#[tokio::test]
+async fn test_import_restores_exported_metadata() {
+    use crate::{PastebinBuilder, testing::TempDir};
+
+    let auth = ("alice".to_owned(), "secret".to_owned());
+    let (from, to) = (TempDir::new().unwrap(), TempDir::new().unwrap());
+    let (source, _) = PastebinBuilder::new(from.path())
+        .user("alice", "secret")
+        .build()
+        .unwrap();
+    let options = Options {
+        title: Some("notes/today".to_owned()),
+        language: Some("rust".to_owned()),
+        public: true,
+        expires_in: Some(3600),
+        ..Options::default()
+    };
+    let titled = source
+        .create_with_options(&b"fn main() {}"[..], Some(auth.clone()), options)
+        .await
+        .unwrap();
+    let untitled = source.create(&b"hi"[..], Some(auth.clone())).await.unwrap();
+
+    let archive: Vec<u8> = stream(
+        source.clone(),
+        vec![titled.clone(), untitled.clone()],
+        Format::TarGz,
+    )
+    .map(|chunk| chunk.unwrap().to_vec())
+    .concat()
+    .await;
+    let (destination, _) = PastebinBuilder::new(to.path())
+        .user("alice", "secret")
+        .build()
+        .unwrap();
+    let imported = import_tar_gz(&destination, &archive[..], auth)
+        .await
+        .unwrap();
+    assert_eq!(
+        imported,
+        [
+            ("notes_today".to_owned(), titled.clone()),
+            (untitled.clone(), untitled),
+        ]
+    );
+
+    let id = titled.parse().unwrap();
+    let (original, restored) = (
+        source.metadata(&id).await.unwrap().unwrap(),
+        destination.metadata(&id).await.unwrap().unwrap(),
+    );
+    assert_eq!(restored.title.as_deref(), Some("notes/today"));
+    assert_eq!(restored.language.as_deref(), Some("rust"));
+    assert!(restored.public);
+    // The remaining time is rounded down to whole seconds on the way.
+    let (original, restored) = (original.expires_at.unwrap(), restored.expires_at.unwrap());
+    assert!(original.abs_diff(restored) <= 1);
+}
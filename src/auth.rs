@@ -0,0 +1,111 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::{FromRef, FromRequestParts, OptionalFromRequestParts},
+    http::{StatusCode, header, request::Parts},
+    response::{IntoResponse, Response},
+};
+use base64::Engine;
+
+use crate::service::Service;
+
+/// Credentials taken from an `Authorization: Basic` header.
+pub struct BasicAuth {
+    pub username: String,
+    pub password: String,
+}
+
+impl BasicAuth {
+    pub fn parse(value: &str) -> Option<Self> {
+        let encoded = value.strip_prefix("Basic ")?;
+        let decoded = base64::engine::general_purpose::STANDARD
+            .decode(encoded.trim())
+            .ok()?;
+        let decoded = String::from_utf8(decoded).ok()?;
+        let (username, password) = decoded.split_once(':')?;
+        Some(Self {
+            username: username.to_owned(),
+            password: password.to_owned(),
+        })
+    }
+}
+
+impl<S: Send + Sync> FromRequestParts<S> for BasicAuth {
+    type Rejection = Response;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        parts
+            .headers
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(Self::parse)
+            .ok_or_else(unauthorized)
+    }
+}
+
+/// A missing header means an anonymous request; a malformed one is rejected.
+impl<S: Send + Sync> OptionalFromRequestParts<S> for BasicAuth {
+    type Rejection = Response;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        _state: &S,
+    ) -> Result<Option<Self>, Self::Rejection> {
+        match parts.headers.get(header::AUTHORIZATION) {
+            None => Ok(None),
+            Some(value) => value
+                .to_str()
+                .ok()
+                .and_then(Self::parse)
+                .map(Some)
+                .ok_or_else(unauthorized),
+        }
+    }
+}
+
+/// An authenticated user listed with --admin.
+pub struct Admin {
+    pub username: String,
+}
+
+impl<S: Send + Sync> FromRequestParts<S> for Admin
+where
+    Arc<Service>: FromRef<S>,
+{
+    type Rejection = Response;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let auth = <BasicAuth as FromRequestParts<S>>::from_request_parts(parts, state).await?;
+        let service = Arc::<Service>::from_ref(state);
+        if !service.is_admin(&auth.username, &auth.password) {
+            return Err((StatusCode::FORBIDDEN, "Admins only").into_response());
+        }
+        Ok(Self {
+            username: auth.username,
+        })
+    }
+}
+
+impl From<BasicAuth> for (String, String) {
+    fn from(auth: BasicAuth) -> Self {
+        (auth.username, auth.password)
+    }
+}
+
+/// `401 Unauthorized`, asking for Basic credentials.
+pub fn unauthorized() -> Response {
+    (
+        StatusCode::UNAUTHORIZED,
+        [(header::WWW_AUTHENTICATE, "Basic realm=\"pastebin\"")],
+        "Not authorized",
+    )
+        .into_response()
+}
+
+#[test]
+fn test_parse_basic_auth() {
+    let auth = BasicAuth::parse("Basic dXNlcjpwYXNzOndvcmQ=").unwrap();
+    assert_eq!(auth.username, "user");
+    assert_eq!(auth.password, "pass:word");
+    assert!(BasicAuth::parse("Bearer dXNlcjpwYXNz").is_none());
+}
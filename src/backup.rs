@@ -0,0 +1,147 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, UNIX_EPOCH},
+};
+
+use tokio::time::MissedTickBehavior;
+
+use crate::service::Service;
+
+const SNAPSHOT_PREFIX: &str = "snapshot-";
+
+pub struct Schedule {
+    pub target: PathBuf,
+    pub interval: Duration,
+    pub keep: usize,
+}
+
+/// Periodically snapshots `service` into `schedule.target`, keeping only the
+/// newest `schedule.keep` snapshots.
+pub fn spawn(service: Arc<Service>, schedule: Schedule) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        let schedule = Arc::new(schedule);
+        let mut ticker = tokio::time::interval(schedule.interval);
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        // The first tick completes immediately; don't snapshot on startup.
+        ticker.tick().await;
+        loop {
+            ticker.tick().await;
+            let service = service.clone();
+            let schedule = schedule.clone();
+            let result = tokio::task::spawn_blocking(move || {
+                let path = write_snapshot(&service, &schedule.target)?;
+                prune(&schedule.target, schedule.keep)?;
+                anyhow::Ok(path)
+            })
+            .await;
+            match result {
+                Ok(Ok(path)) => tracing::info!("Wrote snapshot {}", path.display()),
+                Ok(Err(e)) => tracing::error!("Snapshot failed: {e}"),
+                Err(e) => tracing::error!("Snapshot task panicked: {e}"),
+            }
+        }
+    })
+}
+
+/// Writes a new snapshot directory under `target` and returns its path.
+///
+/// The snapshot is assembled in a `.partial` directory and renamed into place
+/// once complete, so an interrupted run never looks like a valid snapshot.
+pub fn write_snapshot(service: &Service, target: &Path) -> anyhow::Result<PathBuf> {
+    std::fs::create_dir_all(target)?;
+    let timestamp = service
+        .clock()
+        .now()
+        .duration_since(UNIX_EPOCH)?
+        .as_millis();
+    let name = format!("{SNAPSHOT_PREFIX}{timestamp:020}");
+    let partial = target.join(format!("{name}.partial"));
+    let path = target.join(name);
+
+    if partial.exists() {
+        std::fs::remove_dir_all(&partial)?;
+    }
+    service.snapshot(&partial)?;
+    std::fs::rename(&partial, &path)?;
+    Ok(path)
+}
+
+/// Removes all but the newest `keep` complete snapshots in `target`.
+pub fn prune(target: &Path, keep: usize) -> anyhow::Result<()> {
+    let mut snapshots = Vec::new();
+    for entry in std::fs::read_dir(target)? {
+        let entry = entry?;
+        let name = entry.file_name();
+        let Some(name) = name.to_str() else {
+            continue;
+        };
+        if name.starts_with(SNAPSHOT_PREFIX) && !name.ends_with(".partial") {
+            snapshots.push(entry.path());
+        }
+    }
+    // Timestamps are zero-padded, so lexical order is chronological order.
+    snapshots.sort();
+    let excess = snapshots.len().saturating_sub(keep);
+    for path in &snapshots[..excess] {
+        std::fs::remove_dir_all(path)?;
+    }
+    Ok(())
+}
+
+#[test]
+fn test_prune_keeps_newest() {
+    let dir = crate::testing::TempDir::new().unwrap();
+    let target = dir.path();
+    for ts in ["1", "2", "3"] {
+        std::fs::create_dir_all(target.join(format!("{SNAPSHOT_PREFIX}{ts:0>20}"))).unwrap();
+    }
+    std::fs::create_dir_all(target.join(format!("{SNAPSHOT_PREFIX}4.partial"))).unwrap();
+
+    prune(target, 2).unwrap();
+
+    let mut left: Vec<_> = std::fs::read_dir(target)
+        .unwrap()
+        .map(|e| e.unwrap().file_name().into_string().unwrap())
+        .collect();
+    left.sort();
+    assert_eq!(
+        left,
+        [
+            format!("{SNAPSHOT_PREFIX}{:0>20}", 2),
+            format!("{SNAPSHOT_PREFIX}{:0>20}", 3),
+            format!("{SNAPSHOT_PREFIX}4.partial"),
+        ]
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_snapshot_pairs_contents_with_their_checksum() {
+    use crate::{checksum::Integrity, testing::TempDir};
+
+    let (data, target) = (TempDir::new().unwrap(), TempDir::new().unwrap());
+    let (service, _) = crate::PastebinBuilder::new(data.path()).build().unwrap();
+    let id = service.create(&b"0"[..], None).await.unwrap();
+    let id: uuid::Uuid = id.parse().unwrap();
+    let writer = {
+        let service = service.clone();
+        tokio::spawn(async move {
+            for i in 0..200 {
+                let body = i.to_string().repeat(1 + i % 7 * 1000);
+                service.replace(&id, body.as_bytes(), None).await.unwrap();
+            }
+        })
+    };
+    let target_path = target.path().to_owned();
+    tokio::task::spawn_blocking(move || {
+        for i in 0..20 {
+            let snapshot = write_snapshot(&service, &target_path.join(i.to_string())).unwrap();
+            let integrity =
+                crate::checksum::verify_blocking(&snapshot.join("data"), &id.to_string()).unwrap();
+            assert!(matches!(integrity, Integrity::Valid));
+        }
+    })
+    .await
+    .unwrap();
+    writer.await.unwrap();
+}
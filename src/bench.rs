@@ -0,0 +1,200 @@
+//! The `bench` subcommand, which loads another instance with a mix of
+//! uploads and reads for a while and reports the latencies and throughput
+//! it saw, for capacity planning and catching performance regressions.
+
+use std::{
+    collections::BTreeMap,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use futures::StreamExt;
+use parking_lot::Mutex;
+use rand::Rng;
+use uuid::Uuid;
+
+use crate::remote::{self, Remote};
+
+pub struct Options {
+    pub concurrency: usize,
+    pub duration: Duration,
+    /// Sizes of the pastes written, one picked at random per write.
+    pub sizes: Vec<u64>,
+    /// Percentage of requests that are reads.
+    pub reads: u8,
+}
+
+/// Outcomes of one kind of request.
+#[derive(Default)]
+struct Samples {
+    latencies: Vec<Duration>,
+    bytes: u64,
+    errors: BTreeMap<String, usize>,
+}
+
+impl Samples {
+    fn record(&mut self, started: Instant, outcome: anyhow::Result<u64>) {
+        match outcome {
+            Ok(bytes) => {
+                self.latencies.push(started.elapsed());
+                self.bytes += bytes;
+            }
+            Err(e) => *self.errors.entry(e.to_string()).or_default() += 1,
+        }
+    }
+
+    fn merge(&mut self, other: Samples) {
+        self.latencies.extend(other.latencies);
+        self.bytes += other.bytes;
+        for (error, count) in other.errors {
+            *self.errors.entry(error).or_default() += count;
+        }
+    }
+
+    /// A line of the report, for requests made over `elapsed`.
+    fn row(&mut self, name: &str, elapsed: Duration) -> String {
+        self.latencies.sort_unstable();
+        let errors: usize = self.errors.values().sum();
+        let seconds = elapsed.as_secs_f64();
+        let [p50, p90, p99, max] =
+            [50.0, 90.0, 99.0, 100.0].map(|p| format!("{:.1?}", percentile(&self.latencies, p)));
+        format!(
+            "{name:<8}{:>10}{errors:>8}{:>10.1}{:>10.2}{p50:>10}{p90:>10}{p99:>10}{max:>10}",
+            self.latencies.len(),
+            self.latencies.len() as f64 / seconds,
+            self.bytes as f64 / seconds / 1e6,
+        )
+    }
+}
+
+/// The latency below which `p` percent of the `sorted` ones fall.
+fn percentile(sorted: &[Duration], p: f64) -> Duration {
+    if sorted.is_empty() {
+        return Duration::ZERO;
+    }
+    let rank = (p / 100.0 * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+pub async fn run(remote: Remote, options: Options) -> anyhow::Result<()> {
+    if options.sizes.is_empty() || options.concurrency == 0 {
+        anyhow::bail!("--size and --concurrency mustn't be empty or zero");
+    }
+    let (remote, options) = (Arc::new(remote), Arc::new(options));
+    // Reads need something to read, and failing here beats reporting only
+    // errors.
+    let mut ids = Vec::new();
+    for size in &options.sizes {
+        let id = write(&remote, *size)
+            .await
+            .map_err(|e| anyhow::anyhow!("Couldn't create a paste to read: {e}"))?;
+        ids.push(id);
+    }
+    let ids = Arc::new(Mutex::new(ids));
+
+    let started = Instant::now();
+    let deadline = started + options.duration;
+    let workers: Vec<_> = (0..options.concurrency)
+        .map(|_| tokio::spawn(work(remote.clone(), options.clone(), ids.clone(), deadline)))
+        .collect();
+    let (mut reads, mut writes) = (Samples::default(), Samples::default());
+    for worker in workers {
+        let (worker_reads, worker_writes) = worker.await?;
+        reads.merge(worker_reads);
+        writes.merge(worker_writes);
+    }
+    let elapsed = started.elapsed();
+
+    println!(
+        "{:.1?} with {} requests at once",
+        elapsed, options.concurrency
+    );
+    println!(
+        "{:<8}{:>10}{:>8}{:>10}{:>10}{:>10}{:>10}{:>10}{:>10}",
+        "", "requests", "errors", "req/s", "MB/s", "p50", "p90", "p99", "max"
+    );
+    println!("{}", reads.row("reads", elapsed));
+    println!("{}", writes.row("writes", elapsed));
+    for (error, count) in reads.errors.iter().chain(&writes.errors) {
+        println!("{count} x {error}");
+    }
+
+    let ids = std::mem::take(&mut *ids.lock());
+    if remote.is_authenticated() {
+        for id in &ids {
+            let response = remote
+                .request(reqwest::Method::DELETE, &format!("/paste/{id}"))
+                .send()
+                .await?;
+            remote::check(response).await?;
+        }
+    } else {
+        println!("Left {} anonymous pastes behind", ids.len());
+    }
+    Ok(())
+}
+
+/// Makes requests until `deadline`, returning the outcomes of its reads and
+/// writes.
+async fn work(
+    remote: Arc<Remote>,
+    options: Arc<Options>,
+    ids: Arc<Mutex<Vec<Uuid>>>,
+    deadline: Instant,
+) -> (Samples, Samples) {
+    let (mut reads, mut writes) = (Samples::default(), Samples::default());
+    while Instant::now() < deadline {
+        let started = Instant::now();
+        if rand::random_range(0..100) < options.reads {
+            let id = {
+                let ids = ids.lock();
+                ids[rand::random_range(0..ids.len())]
+            };
+            reads.record(started, read(&remote, id).await);
+        } else {
+            let size = options.sizes[rand::random_range(0..options.sizes.len())];
+            let written = write(&remote, size).await.map(|id| {
+                ids.lock().push(id);
+                size
+            });
+            writes.record(started, written);
+        }
+    }
+    (reads, writes)
+}
+
+/// Uploads a paste of `size` random bytes, so that no two share storage.
+async fn write(remote: &Remote, size: u64) -> anyhow::Result<Uuid> {
+    let mut contents = vec![0; size as usize];
+    rand::rng().fill(&mut contents[..]);
+    let response = remote
+        .request(reqwest::Method::POST, "/paste")
+        .body(contents)
+        .send()
+        .await?;
+    let url = remote::check(response).await?.text().await?;
+    remote::paste_id(url.trim())
+}
+
+/// Downloads paste `id`, returning its size.
+async fn read(remote: &Remote, id: Uuid) -> anyhow::Result<u64> {
+    let response = remote
+        .request(reqwest::Method::GET, &format!("/paste/{id}"))
+        .send()
+        .await?;
+    let mut body = remote::check(response).await?.bytes_stream();
+    let mut size = 0;
+    while let Some(chunk) = body.next().await {
+        size += chunk?.len() as u64;
+    }
+    Ok(size)
+}
+
+#[test]
+fn test_percentile() {
+    let latencies: Vec<_> = (1..=10).map(Duration::from_millis).collect();
+    assert_eq!(percentile(&latencies, 50.0), Duration::from_millis(5));
+    assert_eq!(percentile(&latencies, 99.0), Duration::from_millis(10));
+    assert_eq!(percentile(&latencies, 0.0), Duration::from_millis(1));
+    assert_eq!(percentile(&[], 50.0), Duration::ZERO);
+}
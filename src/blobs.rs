@@ -0,0 +1,162 @@
+//! Content-addressed storage shared by pastes with identical contents.
+//!
+//! Each distinct content is stored once as `blobs/<sha256>`, and every paste
+//! with that content is a hard link to it. The file system's link count
+//! doubles as the reference count: a blob whose only remaining link is its
+//! own name is unused and gets removed.
+
+use std::{
+    io,
+    path::{Path, PathBuf},
+};
+
+pub fn dir(data_dir: &Path) -> PathBuf {
+    data_dir.join("blobs")
+}
+
+pub fn blob_path(data_dir: &Path, digest: &[u8]) -> PathBuf {
+    dir(data_dir).join(hex::encode(digest))
+}
+
+/// Makes `dest` a link to the blob holding `digest`, consuming `tmp`, a
+/// freshly written file with those contents.
+///
+/// Unless `replace` is set this fails with [`io::ErrorKind::AlreadyExists`]
+/// if `dest` exists.
+pub async fn commit(
+    data_dir: &Path,
+    tmp: &Path,
+    digest: &[u8],
+    dest: &Path,
+    replace: bool,
+) -> io::Result<()> {
+    tokio::fs::create_dir_all(dir(data_dir)).await?;
+    let blob = blob_path(data_dir, digest);
+    let result = link(&blob, dest, replace).await;
+    let result = match result {
+        // No such content yet, or its blob was released in the meantime.
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            tokio::fs::rename(tmp, &blob).await?;
+            return link(&blob, dest, replace).await;
+        }
+        result => result,
+    };
+    tokio::fs::remove_file(tmp).await?;
+    result
+}
+
+/// Like [`commit`] without `replace`, for blocking code.
+pub fn commit_blocking(data_dir: &Path, tmp: &Path, digest: &[u8], dest: &Path) -> io::Result<()> {
+    std::fs::create_dir_all(dir(data_dir))?;
+    let blob = blob_path(data_dir, digest);
+    let result = match std::fs::hard_link(&blob, dest) {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            std::fs::rename(tmp, &blob)?;
+            return std::fs::hard_link(&blob, dest);
+        }
+        result => result,
+    };
+    std::fs::remove_file(tmp)?;
+    result
+}
+
+async fn link(blob: &Path, dest: &Path, replace: bool) -> io::Result<()> {
+    if !replace {
+        return tokio::fs::hard_link(blob, dest).await;
+    }
+    let mut tmp_name = dest.file_name().unwrap().to_owned();
+    tmp_name.push(format!("~{}", uuid::Uuid::new_v4().simple()));
+    let tmp = dest.with_file_name(tmp_name);
+    tokio::fs::hard_link(blob, &tmp).await?;
+    tokio::fs::rename(&tmp, dest).await
+}
+
+/// Removes the blob holding `digest`, and its gzipped copy, if no paste
+/// links to it anymore.
+pub fn release_blocking(data_dir: &Path, digest: &[u8]) -> io::Result<()> {
+    let blob = blob_path(data_dir, digest);
+    match std::fs::metadata(&blob) {
+        Ok(metadata) if link_count(&metadata) <= 1 => {
+            std::fs::remove_file(blob)?;
+            crate::variants::remove_blocking(data_dir, digest)
+        }
+        Ok(_) => Ok(()),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e),
+    }
+}
+
+pub async fn release(data_dir: &Path, digest: &[u8]) -> io::Result<()> {
+    let data_dir = data_dir.to_owned();
+    let digest = digest.to_owned();
+    tokio::task::spawn_blocking(move || release_blocking(&data_dir, &digest))
+        .await
+        .map_err(io::Error::other)?
+}
+
+/// Blobs no paste links to anymore, e.g. after a crash between unlinking a
+/// paste and releasing its blob, and gzipped copies of blobs that are gone.
+pub fn unreferenced_blocking(data_dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let read_dir = |dir: PathBuf| match std::fs::read_dir(dir) {
+        Ok(entries) => entries.collect::<io::Result<Vec<_>>>(),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(e),
+    };
+    let mut unreferenced = Vec::new();
+    for entry in read_dir(dir(data_dir))? {
+        if link_count(&entry.metadata()?) <= 1 {
+            unreferenced.push(entry.path());
+        }
+    }
+    for entry in read_dir(crate::variants::dir(data_dir))? {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        let blob = name.strip_suffix(".gz").unwrap_or(&name);
+        if !dir(data_dir).join(blob).exists() {
+            unreferenced.push(entry.path());
+        }
+    }
+    Ok(unreferenced)
+}
+
+#[cfg(unix)]
+fn link_count(metadata: &std::fs::Metadata) -> u64 {
+    std::os::unix::fs::MetadataExt::nlink(metadata)
+}
+
+/// Without a portable way to read link counts, blobs are never released.
+#[cfg(not(unix))]
+fn link_count(_metadata: &std::fs::Metadata) -> u64 {
+    u64::MAX
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn test_blobs_are_shared_until_released() {
+    let dir = crate::testing::TempDir::new().unwrap();
+    let data_dir = dir.path();
+    let digest = crate::checksum::digest(b"same");
+    let [first, second] = ["first", "second"].map(|name| data_dir.join(name));
+    for (i, dest) in [&first, &second].into_iter().enumerate() {
+        let tmp = data_dir.join(format!("tmp{i}"));
+        tokio::fs::write(&tmp, "same").await.unwrap();
+        commit(data_dir, &tmp, &digest, dest, false).await.unwrap();
+        assert!(!tmp.exists());
+    }
+    let blob = blob_path(data_dir, &digest);
+    assert_eq!(link_count(&std::fs::metadata(&blob).unwrap()), 3);
+    assert_eq!(std::fs::read_to_string(&second).unwrap(), "same");
+
+    std::fs::remove_file(&first).unwrap();
+    release(data_dir, &digest).await.unwrap();
+    assert!(blob.exists());
+    assert_eq!(std::fs::read_to_string(&second).unwrap(), "same");
+    assert!(unreferenced_blocking(data_dir).unwrap().is_empty());
+
+    std::fs::remove_file(&second).unwrap();
+    assert_eq!(
+        unreferenced_blocking(data_dir).unwrap(),
+        std::slice::from_ref(&blob)
+    );
+    release(data_dir, &digest).await.unwrap();
+    assert!(!blob.exists());
+}
@@ -0,0 +1,63 @@
+//! Response bodies of paste contents, handed to hyper with as little copying
+//! as it allows. hyper writes bodies from buffers it's given rather than
+//! from files, and TLS encrypts them in user space anyway, so there's no
+//! sendfile: instead cached pastes are passed on as the buffers they're
+//! cached in, and files are read in large chunks, into pooled buffers, that
+//! hyper writes with vectored writes as they are.
+
+use std::sync::Arc;
+
+use axum::body::Body;
+use futures::StreamExt;
+use tokio::io::AsyncRead;
+
+use crate::{buffers::Pool, cache::Reader};
+
+/// Bytes of a paste read before the response status is sent.
+const PREFETCH: usize = 64 * 1024;
+
+/// A body streaming `reader` in chunks read into buffers from `pool`.
+pub fn stream(pool: &Arc<Pool>, reader: impl AsyncRead + Unpin + Send + 'static) -> Body {
+    Body::from_stream(crate::buffers::stream(pool, reader))
+}
+
+/// A body with the contents of a paste. The first [`PREFETCH`] bytes of a
+/// file are read up front, so that a read error within them (such as a
+/// failed integrity check of a small paste) can still be reported as an
+/// error response instead of an aborted body.
+pub async fn prefetched(pool: &Arc<Pool>, reader: Reader) -> std::io::Result<Body> {
+    let reader = match reader {
+        Reader::Memory(cursor) => return Ok(Body::from(cursor.into_inner())),
+        reader => reader,
+    };
+    let mut stream = crate::buffers::stream(pool, reader);
+    let mut prefix = Vec::new();
+    let mut prefetched = 0;
+    while prefetched < PREFETCH {
+        match stream.next().await {
+            Some(chunk) => {
+                let chunk = chunk?;
+                prefetched += chunk.len();
+                prefix.push(Ok(chunk));
+            }
+            None => break,
+        }
+    }
+    Ok(Body::from_stream(
+        futures::stream::iter(prefix).chain(stream),
+    ))
+}
+
+#[tokio::test]
+async fn test_cached_contents_are_not_copied() {
+    let contents = bytes::Bytes::from_static(b"hello");
+    let pool = Arc::new(Pool::new(1024, 1));
+    let body = prefetched(
+        &pool,
+        Reader::Memory(std::io::Cursor::new(contents.clone())),
+    )
+    .await
+    .unwrap();
+    let frame = body.into_data_stream().next().await.unwrap().unwrap();
+    assert_eq!(frame.as_ptr(), contents.as_ptr());
+}
@@ -0,0 +1,235 @@
+//! Listings of the public pastes, newest or most viewed first, as HTML pages
+//! for browsers and as JSON.
+
+use std::{collections::HashMap, fmt::Write, sync::Arc};
+
+use axum::{
+    Json,
+    extract::{Query, State},
+    http::{HeaderMap, StatusCode, header},
+    response::{Html, IntoResponse, Response},
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    html::escape,
+    public,
+    service::{Recent, Service},
+};
+
+/// Pastes per page.
+pub const PAGE_SIZE: usize = 50;
+
+/// Pastes listed as trending.
+const TRENDING: usize = 50;
+
+/// Lets the page use its inline styles, nothing else.
+const CSP: &str = "default-src 'none'; style-src 'unsafe-inline'";
+
+#[derive(Deserialize)]
+pub struct PageQuery {
+    /// Page of the listing, starting at 1.
+    #[serde(default = "first_page")]
+    page: usize,
+}
+
+fn first_page() -> usize {
+    1
+}
+
+impl Default for PageQuery {
+    fn default() -> Self {
+        Self { page: first_page() }
+    }
+}
+
+#[derive(Serialize)]
+struct Listing {
+    page: usize,
+    pages: usize,
+    pastes: Vec<Entry>,
+}
+
+#[derive(Serialize)]
+struct Entry {
+    id: uuid::Uuid,
+    title: Option<String>,
+    language: Option<String>,
+    size: u64,
+    /// When the paste was last written, in RFC 3339.
+    written: String,
+    /// Recent views, counting older ones less, for trending pastes.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    views: Option<f64>,
+}
+
+impl From<&Recent> for Entry {
+    fn from(paste: &Recent) -> Self {
+        Self {
+            id: paste.id,
+            title: paste.metadata.title.clone(),
+            language: paste.metadata.language.clone(),
+            size: paste.size,
+            written: chrono::DateTime::<chrono::Utc>::from(paste.written)
+                .format("%Y-%m-%dT%H:%M:%SZ")
+                .to_string(),
+            views: None,
+        }
+    }
+}
+
+async fn listing(
+    service: &Service,
+    cache: &public::Cache,
+    page: usize,
+) -> Result<Listing, Response> {
+    let pastes = cache
+        .pastes(service)
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response())?;
+    let pages = pastes.len().div_ceil(PAGE_SIZE).max(1);
+    if page == 0 || page > pages {
+        return Err((StatusCode::NOT_FOUND, "No such page").into_response());
+    }
+    Ok(Listing {
+        page,
+        pages,
+        pastes: pastes
+            .iter()
+            .skip((page - 1) * PAGE_SIZE)
+            .take(PAGE_SIZE)
+            .map(Entry::from)
+            .collect(),
+    })
+}
+
+/// `GET /pastes/public`
+pub async fn json(
+    State(service): State<Arc<Service>>,
+    State(cache): State<Arc<public::Cache>>,
+    Query(query): Query<PageQuery>,
+) -> Response {
+    match listing(&service, &cache, query.page).await {
+        Ok(listing) => Json(listing).into_response(),
+        Err(response) => response,
+    }
+}
+
+/// `GET /browse`
+pub async fn html(
+    State(service): State<Arc<Service>>,
+    State(cache): State<Arc<public::Cache>>,
+    Query(query): Query<PageQuery>,
+) -> Response {
+    let listing = match listing(&service, &cache, query.page).await {
+        Ok(listing) => listing,
+        Err(response) => return response,
+    };
+    let mut footer = String::new();
+    if listing.page > 1 {
+        write!(
+            footer,
+            "<a href=\"browse?page={}\">Newer</a> ",
+            listing.page - 1
+        )
+        .unwrap();
+    }
+    write!(footer, "Page {} of {}", listing.page, listing.pages).unwrap();
+    if listing.page < listing.pages {
+        write!(
+            footer,
+            " <a href=\"browse?page={}\">Older</a>",
+            listing.page + 1
+        )
+        .unwrap();
+    }
+    render("Public pastes", &listing.pastes, &footer)
+}
+
+/// The public pastes with the most recent views.
+async fn trending(service: &Service, cache: &public::Cache) -> Result<Vec<Entry>, Response> {
+    let pastes = cache
+        .pastes(service)
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response())?;
+    let pastes: HashMap<_, _> = pastes.iter().map(|paste| (paste.id, paste)).collect();
+    Ok(service
+        .trending()
+        .into_iter()
+        .filter_map(|(id, views)| {
+            let mut entry = Entry::from(*pastes.get(&id)?);
+            entry.views = Some((views * 100.0).round() / 100.0);
+            Some(entry)
+        })
+        .take(TRENDING)
+        .collect())
+}
+
+/// `GET /pastes/trending`
+pub async fn trending_json(
+    State(service): State<Arc<Service>>,
+    State(cache): State<Arc<public::Cache>>,
+) -> Response {
+    match trending(&service, &cache).await {
+        Ok(pastes) => Json(pastes).into_response(),
+        Err(response) => response,
+    }
+}
+
+/// `GET /trending`
+pub async fn trending_html(
+    State(service): State<Arc<Service>>,
+    State(cache): State<Arc<public::Cache>>,
+) -> Response {
+    match trending(&service, &cache).await {
+        Ok(pastes) => render(
+            "Trending pastes",
+            &pastes,
+            "Views count half as much after two days.",
+        ),
+        Err(response) => response,
+    }
+}
+
+/// A page listing `pastes`, followed by the HTML in `footer`.
+fn render(heading: &str, pastes: &[Entry], footer: &str) -> Response {
+    let mut page = String::new();
+    write!(
+        page,
+        "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"><title>{heading}</title>\
+         <style>body{{font-family:sans-serif}}td,th{{padding:0 1em;text-align:left}}</style>\
+         </head><body>\n<h1>{heading}</h1>\n<p><a href=\"browse\">Newest</a> \
+         <a href=\"trending\">Trending</a></p>\n"
+    )
+    .unwrap();
+    if pastes.is_empty() {
+        page.push_str("<p>Nothing to show yet.</p>\n");
+    } else {
+        page.push_str("<table><tr><th>Title</th><th>Language</th><th>Written</th></tr>\n");
+        for paste in pastes {
+            let title = match &paste.title {
+                Some(title) => escape(title),
+                None => format!("Paste {}", paste.id),
+            };
+            writeln!(
+                page,
+                "<tr><td><a href=\"paste/{}/view\">{title}</a></td><td>{}</td><td>{}</td></tr>",
+                paste.id,
+                escape(paste.language.as_deref().unwrap_or("")),
+                paste.written,
+            )
+            .unwrap();
+        }
+        page.push_str("</table>\n");
+    }
+    write!(page, "<p>{footer}</p>\n</body></html>\n").unwrap();
+    ([(header::CONTENT_SECURITY_POLICY, CSP)], Html(page)).into_response()
+}
+
+/// Whether a request prefers an HTML page, as browsers do.
+pub fn wants_html(headers: &HeaderMap) -> bool {
+    headers
+        .get(header::ACCEPT)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|accept| accept.contains("text/html"))
+}
@@ -0,0 +1,111 @@
+//! IO buffers reused across uploads and downloads, so that transfers under
+//! load don't each allocate and free their own. A download's chunks are
+//! split off its buffer and handed to hyper; once hyper has written and
+//! dropped them, the buffer takes their memory back rather than allocating
+//! more.
+
+use std::{
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use bytes::{Bytes, BytesMut};
+use futures::Stream;
+use parking_lot::Mutex;
+use tokio::io::AsyncRead;
+
+pub struct Pool {
+    size: usize,
+    max_idle: usize,
+    idle: Mutex<Vec<BytesMut>>,
+}
+
+impl Pool {
+    /// Buffers of `size` bytes, keeping up to `max_idle` unused ones around.
+    pub fn new(size: usize, max_idle: usize) -> Self {
+        Self {
+            size: size.max(1),
+            max_idle,
+            idle: Mutex::default(),
+        }
+    }
+
+    /// An empty buffer with room for at least the pool's buffer size.
+    pub fn take(&self) -> BytesMut {
+        let mut buffer = self.idle.lock().pop().unwrap_or_default();
+        buffer.reserve(self.size);
+        buffer
+    }
+
+    /// Returns `buffer` to the pool for reuse.
+    pub fn put(&self, mut buffer: BytesMut) {
+        buffer.clear();
+        let mut idle = self.idle.lock();
+        if idle.len() < self.max_idle {
+            idle.push(buffer);
+        }
+    }
+}
+
+/// The contents of `reader` in chunks read into a buffer from `pool`.
+pub fn stream<R: AsyncRead + Unpin>(pool: &Arc<Pool>, reader: R) -> ReaderStream<R> {
+    ReaderStream {
+        reader: Some(reader),
+        buffer: pool.take(),
+        pool: pool.clone(),
+    }
+}
+
+/// Like [`tokio_util::io::ReaderStream`], with a pooled buffer.
+pub struct ReaderStream<R> {
+    /// `None` once the end or an error is reached.
+    reader: Option<R>,
+    buffer: BytesMut,
+    pool: Arc<Pool>,
+}
+
+impl<R: AsyncRead + Unpin> Stream for ReaderStream<R> {
+    type Item = std::io::Result<Bytes>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        let Some(reader) = &mut this.reader else {
+            return Poll::Ready(None);
+        };
+        if this.buffer.capacity() == 0 {
+            this.buffer.reserve(this.pool.size);
+        }
+        match tokio_util::io::poll_read_buf(Pin::new(reader), cx, &mut this.buffer) {
+            Poll::Pending => Poll::Pending,
+            Poll::Ready(Ok(0)) => {
+                this.reader = None;
+                Poll::Ready(None)
+            }
+            Poll::Ready(Ok(_)) => Poll::Ready(Some(Ok(this.buffer.split().freeze()))),
+            Poll::Ready(Err(e)) => {
+                this.reader = None;
+                Poll::Ready(Some(Err(e)))
+            }
+        }
+    }
+}
+
+impl<R> Drop for ReaderStream<R> {
+    fn drop(&mut self) {
+        self.pool.put(std::mem::take(&mut self.buffer));
+    }
+}
+
+#[test]
+fn test_reuses_buffers() {
+    let pool = Pool::new(1024, 1);
+    let buffer = pool.take();
+    let address = buffer.as_ptr();
+    assert!(buffer.capacity() >= 1024);
+    pool.put(buffer);
+    pool.put(BytesMut::new());
+    let reused = pool.take();
+    assert_eq!(reused.as_ptr(), address);
+    assert_ne!(pool.take().as_ptr(), address);
+}
@@ -0,0 +1,158 @@
+//! Assembling an instance in code rather than from the command line, for
+//! embedding it in another application or testing it end to end.
+
+use std::{path::PathBuf, sync::Arc};
+
+use axum::Router;
+
+use crate::{AppState, cli::Args, clock::Clock, ids::IdGenerator, service::Service};
+
+/// Configures an instance as its command line options would, starting from
+/// their defaults rather than from the environment.
+pub struct PastebinBuilder {
+    args: Args,
+    state: Option<PathBuf>,
+    users: Vec<(String, String)>,
+    clock: Option<Arc<dyn Clock>>,
+    ids: Option<Arc<dyn IdGenerator>>,
+}
+
+impl PastebinBuilder {
+    /// An instance storing pastes in `data_dir`, the only storage there is.
+    pub fn new(data_dir: impl Into<PathBuf>) -> Self {
+        let mut args = Args::defaults();
+        args.data_dir = data_dir.into();
+        Self {
+            args,
+            state: None,
+            users: Vec::new(),
+            clock: None,
+            ids: None,
+        }
+    }
+
+    /// Where accounts and ownership are loaded from, `db.json` in the data
+    /// directory by default. Nothing saves them there but
+    /// [`Service::dump_state`].
+    pub fn state_file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.state = Some(path.into());
+        self
+    }
+
+    /// Registers an account, or checks that the one by that name in the state
+    /// file has this password.
+    pub fn user(mut self, username: &str, password: &str) -> Self {
+        self.users.push((username.to_owned(), password.to_owned()));
+        self
+    }
+
+    pub fn admin(mut self, username: &str) -> Self {
+        self.args.admin.push(username.to_owned());
+        self
+    }
+
+    /// Largest pastes anonymous users and account holders may upload.
+    pub fn size_limits(mut self, anonymous: Option<u64>, user: Option<u64>) -> Self {
+        self.args.max_anonymous_size = anonymous;
+        self.args.max_user_size = user;
+        self
+    }
+
+    /// Pastes each client address may create per minute, in bursts of up to
+    /// `burst`.
+    pub fn create_rate(mut self, per_minute: u32, burst: u32) -> Self {
+        self.args.create_rate = Some(per_minute);
+        self.args.create_burst = Some(burst);
+        self
+    }
+
+    /// Requests for pastes each client address may make per minute, in
+    /// bursts of up to `burst`.
+    pub fn read_rate(mut self, per_minute: u32, burst: u32) -> Self {
+        self.args.read_rate = Some(per_minute);
+        self.args.read_burst = Some(burst);
+        self
+    }
+
+    pub fn storage_budget(mut self, bytes: u64) -> Self {
+        self.args.storage_budget = Some(bytes);
+        self
+    }
+
+    /// See [`Service::with_clock`].
+    pub fn clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = Some(clock);
+        self
+    }
+
+    /// See [`Service::with_ids`].
+    pub fn ids(mut self, ids: Arc<dyn IdGenerator>) -> Self {
+        self.ids = Some(ids);
+        self
+    }
+
+    /// Sets any other option, as named on the command line.
+    pub fn configure(mut self, configure: impl FnOnce(&mut Args)) -> Self {
+        configure(&mut self.args);
+        self
+    }
+
+    /// The service and the HTTP app serving it. Nothing runs in the
+    /// background: until [`Service::warm_index`] is done, lookups go to the
+    /// filesystem, and expired pastes stay until something collects them.
+    pub fn build(mut self) -> anyhow::Result<(Arc<Service>, Router)> {
+        self.args.state = match self.state {
+            Some(path) => path,
+            None => self.args.data_dir.join("db.json"),
+        };
+        let mut service = crate::open_service(&self.args)?;
+        if let Some(clock) = self.clock {
+            service = service.with_clock(clock);
+        }
+        if let Some(ids) = self.ids {
+            service = service.with_ids(ids);
+        }
+        let service = Arc::new(service);
+        for (username, password) in &self.users {
+            if !service.authenticate(username, password) {
+                service.register_user(username, password)?;
+            }
+        }
+        let state = AppState::new(&self.args, service.clone())?;
+        let router = crate::router(&self.args, state)?;
+        Ok((service, router))
+    }
+}
+
+#[tokio::test]
+async fn test_builds_a_working_instance() {
+    use axum::{body::Body, extract::Request, http::StatusCode};
+    use tower::ServiceExt;
+
+    let dir = crate::testing::TempDir::new().unwrap();
+    let (service, router) = PastebinBuilder::new(dir.path())
+        .user("alice", "secret")
+        .size_limits(Some(4), None)
+        .build()
+        .unwrap();
+    assert!(service.authenticate("alice", "secret"));
+
+    let upload = |body: &'static str| Request::post("/paste").body(Body::from(body)).unwrap();
+    let response = router.clone().oneshot(upload("too long")).await.unwrap();
+    assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    let response = router.clone().oneshot(upload("hi")).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    let id = String::from_utf8(body.to_vec()).unwrap();
+    let id = id.trim().rsplit('/').next().unwrap();
+    let request = Request::get(format!("/paste/{id}"))
+        .body(Body::empty())
+        .unwrap();
+    let response = router.oneshot(request).await.unwrap();
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    assert_eq!(&body[..], b"hi");
+}
@@ -0,0 +1,176 @@
+//! An in-memory cache of small pastes, so that popular snippets are served
+//! without touching the filesystem. It's bounded in entries and in bytes,
+//! evicting the least recently read paste first, and writes to a paste drop
+//! it from the cache.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    io::Cursor,
+    pin::Pin,
+    sync::atomic::{AtomicU64, Ordering},
+    task::{Context, Poll},
+};
+
+use bytes::Bytes;
+use parking_lot::Mutex;
+use tokio::io::{AsyncRead, ReadBuf};
+use uuid::Uuid;
+
+use crate::checksum::ChecksumReader;
+
+/// Largest paste cached, however large the cache.
+const MAX_PASTE: usize = 1024 * 1024;
+
+pub struct Cache {
+    max_entries: usize,
+    max_bytes: usize,
+    inner: Mutex<Inner>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+#[derive(Default)]
+struct Inner {
+    /// Contents and when they were last read, as a tick of `clock`.
+    entries: HashMap<Uuid, (Bytes, u64)>,
+    by_use: BTreeMap<u64, Uuid>,
+    clock: u64,
+    bytes: usize,
+    /// Bumped whenever a paste is dropped, so that contents read before
+    /// that aren't cached after it.
+    generation: u64,
+}
+
+impl Inner {
+    fn remove(&mut self, id: &Uuid) {
+        if let Some((contents, used)) = self.entries.remove(id) {
+            self.by_use.remove(&used);
+            self.bytes -= contents.len();
+        }
+    }
+}
+
+impl Cache {
+    pub fn new(max_entries: usize, max_bytes: usize) -> Self {
+        Self {
+            max_entries,
+            max_bytes,
+            inner: Mutex::default(),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    /// Size up to which pastes are cached: a small share of the cache, so
+    /// that one paste can't push out all others.
+    pub fn max_paste(&self) -> u64 {
+        (self.max_bytes / 16).min(MAX_PASTE) as u64
+    }
+
+    /// The contents of paste `id`, if cached.
+    pub fn get(&self, id: &Uuid) -> Option<Bytes> {
+        let mut inner = self.inner.lock();
+        inner.clock += 1;
+        let clock = inner.clock;
+        let Some((contents, used)) = inner.entries.get_mut(id) else {
+            self.misses.fetch_add(1, Ordering::Relaxed);
+            return None;
+        };
+        let (contents, previous) = (contents.clone(), std::mem::replace(used, clock));
+        inner.by_use.remove(&previous);
+        inner.by_use.insert(clock, *id);
+        self.hits.fetch_add(1, Ordering::Relaxed);
+        Some(contents)
+    }
+
+    /// To pass to [`Cache::insert`] along with contents read after calling
+    /// this.
+    pub fn generation(&self) -> u64 {
+        self.inner.lock().generation
+    }
+
+    /// Caches `contents` as those of paste `id`, unless a paste was dropped
+    /// since `generation`, in which case they may be outdated.
+    pub fn insert(&self, id: Uuid, contents: Bytes, generation: u64) {
+        if contents.len() as u64 > self.max_paste() {
+            return;
+        }
+        let mut inner = self.inner.lock();
+        if inner.generation != generation {
+            return;
+        }
+        inner.remove(&id);
+        inner.clock += 1;
+        let clock = inner.clock;
+        inner.bytes += contents.len();
+        inner.entries.insert(id, (contents, clock));
+        inner.by_use.insert(clock, id);
+        while inner.entries.len() > self.max_entries || inner.bytes > self.max_bytes {
+            let Some((_, oldest)) = inner.by_use.pop_first() else {
+                break;
+            };
+            inner.remove(&oldest);
+        }
+    }
+
+    /// Drops paste `id`, whose contents changed or are gone.
+    pub fn remove(&self, id: &Uuid) {
+        let mut inner = self.inner.lock();
+        inner.generation += 1;
+        inner.remove(id);
+    }
+
+    /// Hits, misses, cached pastes and their total size.
+    pub fn stats(&self) -> (u64, u64, usize, usize) {
+        let inner = self.inner.lock();
+        (
+            self.hits.load(Ordering::Relaxed),
+            self.misses.load(Ordering::Relaxed),
+            inner.entries.len(),
+            inner.bytes,
+        )
+    }
+}
+
+/// The contents of a paste, read into memory, from the cache or not, or
+/// from its file, through io_uring or not.
+pub enum Reader {
+    Memory(Cursor<Bytes>),
+    File(ChecksumReader<tokio::fs::File>),
+    #[cfg(feature = "io-uring")]
+    Uring(ChecksumReader<crate::uring::File>),
+}
+
+impl AsyncRead for Reader {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        match self.get_mut() {
+            Self::Memory(cursor) => Pin::new(cursor).poll_read(cx, buf),
+            Self::File(file) => Pin::new(file).poll_read(cx, buf),
+            #[cfg(feature = "io-uring")]
+            Self::Uring(file) => Pin::new(file).poll_read(cx, buf),
+        }
+    }
+}
+
+#[test]
+fn test_evicts_least_recently_read() {
+    let cache = Cache::new(2, 1024);
+    let [a, b, c] = [Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4()];
+    for id in [a, b] {
+        cache.insert(id, Bytes::from_static(b"hi"), cache.generation());
+    }
+    assert!(cache.get(&a).is_some());
+    cache.insert(c, Bytes::from_static(b"hi"), cache.generation());
+    assert!(cache.get(&b).is_none());
+    assert!(cache.get(&a).is_some());
+
+    let generation = cache.generation();
+    cache.remove(&a);
+    cache.insert(a, Bytes::from_static(b"stale"), generation);
+    assert!(cache.get(&a).is_none());
+    assert_eq!(cache.stats().2, 1);
+}
@@ -0,0 +1,212 @@
+use std::{
+    io,
+    path::{Path, PathBuf},
+    pin::Pin,
+    task::{Context, Poll, ready},
+};
+
+use sha2::{Digest, Sha256};
+use tokio::io::{AsyncRead, ReadBuf};
+
+/// Path of the file holding the checksum of paste `id`.
+///
+/// The file uses the `sha256sum` format, so a data directory can also be
+/// checked with `sha256sum -c *.sha256`.
+pub fn sidecar_path(data_dir: &Path, id: &str) -> PathBuf {
+    data_dir.join(format!("{id}.sha256"))
+}
+
+pub fn format_sidecar(id: &str, digest: &[u8]) -> String {
+    format!("{}  {id}\n", hex::encode(digest))
+}
+
+pub fn parse_sidecar(contents: &str) -> Option<Vec<u8>> {
+    let hex_digest = contents.split_whitespace().next()?;
+    hex::decode(hex_digest).ok()
+}
+
+/// Reads the stored checksum of paste `id`, if it has one.
+pub async fn load(data_dir: &Path, id: &str) -> io::Result<Option<Vec<u8>>> {
+    match tokio::fs::read_to_string(sidecar_path(data_dir, id)).await {
+        Ok(contents) => Ok(parse_sidecar(&contents)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+pub async fn store(data_dir: &Path, id: &str, digest: &[u8]) -> io::Result<()> {
+    write_atomically(&sidecar_path(data_dir, id), format_sidecar(id, digest)).await
+}
+
+/// Writes `contents` to `path` through a temporary file, so that a crash
+/// midway leaves the previous contents rather than part of the new ones.
+pub async fn write_atomically(path: &Path, contents: impl AsRef<[u8]>) -> io::Result<()> {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(format!("~{}", uuid::Uuid::new_v4().simple()));
+    let tmp = PathBuf::from(tmp);
+    tokio::fs::write(&tmp, contents).await?;
+    if let Err(e) = tokio::fs::rename(&tmp, path).await {
+        tokio::fs::remove_file(&tmp).await.ok();
+        return Err(e);
+    }
+    Ok(())
+}
+
+/// The SHA-256 digest a client sent in a `Content-Digest` header (RFC 9530),
+/// such as `sha-256=:LCa0a2j/xo/5m0U8HTBBNBNCLXBkg7+g+YpeiGJm564=:`. Fails if
+/// the header is malformed or has no SHA-256 digest, the only algorithm
+/// supported.
+pub fn parse_content_digest(header: &str) -> anyhow::Result<Vec<u8>> {
+    use base64::Engine;
+
+    for member in header.split(',') {
+        let Some((algorithm, value)) = member.trim().split_once('=') else {
+            anyhow::bail!("Malformed Content-Digest");
+        };
+        if algorithm != "sha-256" {
+            continue;
+        }
+        let encoded = value
+            .strip_prefix(':')
+            .and_then(|value| value.strip_suffix(':'))
+            .ok_or(anyhow::anyhow!("Malformed Content-Digest"))?;
+        let digest = base64::engine::general_purpose::STANDARD.decode(encoded)?;
+        anyhow::ensure!(
+            digest.len() == 32,
+            "Content-Digest sha-256 must be 32 bytes"
+        );
+        return Ok(digest);
+    }
+    anyhow::bail!("Content-Digest has no sha-256 digest, the only algorithm supported")
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Integrity {
+    Valid,
+    Mismatch,
+    /// The paste predates checksums, or its checksum file was lost.
+    Unknown,
+}
+
+/// Recomputes the checksum of paste `id` and compares it to the stored one.
+///
+/// This is blocking; it reads the whole paste.
+pub fn verify_blocking(data_dir: &Path, id: &str) -> io::Result<Integrity> {
+    let expected = match std::fs::read_to_string(sidecar_path(data_dir, id)) {
+        Ok(contents) => parse_sidecar(&contents),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+        Err(e) => return Err(e),
+    };
+    let Some(expected) = expected else {
+        return Ok(Integrity::Unknown);
+    };
+    Ok(if digest_blocking(&data_dir.join(id))? == expected {
+        Integrity::Valid
+    } else {
+        Integrity::Mismatch
+    })
+}
+
+pub fn digest(contents: &[u8]) -> Vec<u8> {
+    Sha256::digest(contents).to_vec()
+}
+
+/// The error of reading a paste whose contents don't match its checksum.
+pub fn mismatch() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "Paste failed integrity check")
+}
+
+pub fn digest_blocking(path: &Path) -> io::Result<Vec<u8>> {
+    let mut hasher = Sha256::new();
+    io::copy(&mut std::fs::File::open(path)?, &mut hasher)?;
+    Ok(hasher.finalize().to_vec())
+}
+
+/// Hashes everything read through it.
+///
+/// When constructed with an expected digest, reaching the end of the stream
+/// with a different digest fails the read with [`io::ErrorKind::InvalidData`]
+/// instead of reporting a clean end of file.
+pub struct ChecksumReader<R> {
+    inner: R,
+    hasher: Sha256,
+    expected: Option<Vec<u8>>,
+}
+
+impl<R> ChecksumReader<R> {
+    pub fn new(inner: R) -> Self {
+        Self {
+            inner,
+            hasher: Sha256::new(),
+            expected: None,
+        }
+    }
+
+    pub fn verifying(inner: R, expected: Option<Vec<u8>>) -> Self {
+        Self {
+            expected,
+            ..Self::new(inner)
+        }
+    }
+
+    pub fn get_ref(&self) -> &R {
+        &self.inner
+    }
+
+    /// The digest of the bytes read so far.
+    pub fn digest(&self) -> Vec<u8> {
+        self.hasher.clone().finalize().to_vec()
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for ChecksumReader<R> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let before = buf.filled().len();
+        let had_room = buf.remaining() > 0;
+        ready!(Pin::new(&mut this.inner).poll_read(cx, buf))?;
+
+        let read = &buf.filled()[before..];
+        this.hasher.update(read);
+        if read.is_empty()
+            && had_room
+            && let Some(expected) = this.expected.take()
+            && this.digest() != expected
+        {
+            return Poll::Ready(Err(mismatch()));
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[tokio::test]
+async fn test_checksum_reader_detects_mismatch() {
+    use tokio::io::AsyncReadExt;
+
+    let mut reader = ChecksumReader::new(&b"hello"[..]);
+    reader.read_to_end(&mut Vec::new()).await.unwrap();
+    let digest = reader.digest();
+
+    let mut valid = ChecksumReader::verifying(&b"hello"[..], Some(digest.clone()));
+    assert!(valid.read_to_end(&mut Vec::new()).await.is_ok());
+
+    let mut corrupt = ChecksumReader::verifying(&b"jello"[..], Some(digest));
+    let err = corrupt.read_to_end(&mut Vec::new()).await.unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+}
+
+#[test]
+fn test_parse_content_digest() {
+    let digest = parse_content_digest(
+        "sha-512=:AAAA:, sha-256=:LPJNul+wow4m6DsqxbninhsWHlwfp0JecwQzYpOLmCQ=:",
+    )
+    .unwrap();
+    assert_eq!(hex::encode(digest)[..8], *"2cf24dba");
+    assert!(parse_content_digest("sha-512=:AAAA:").is_err());
+    assert!(parse_content_digest("sha-256=LPJNul+wow4m6DsqxbninhsWHlwfp0JecwQzYpOLmCQ=").is_err());
+    assert!(parse_content_digest("sha-256=:AAAA:").is_err());
+}
@@ -0,0 +1,511 @@
+//! Upload sessions, a simpler take on resumable uploads than tus: a client
+//! creates a session with `POST /upload-sessions`, PUTs the parts of the
+//! paste to `/upload-sessions/{id}/chunks/{index}` in any order, retrying
+//! those that failed, and then POSTs to `/upload-sessions/{id}/finalize`,
+//! which creates the paste out of chunks 0 to n at once.
+//!
+//! Each session is a directory of the data directory's `upload-sessions`
+//! subdirectory. Sessions left alone for a day are removed by the garbage
+//! collection.
+
+use std::{
+    collections::BTreeMap,
+    path::{Path as FsPath, PathBuf},
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use axum::{
+    Json, Router,
+    extract::{Path, Query, Request, State},
+    http::{StatusCode, header},
+    response::{IntoResponse, Response},
+    routing::{get, post, put},
+};
+use futures::{StreamExt, TryStreamExt};
+use serde::{Deserialize, Serialize};
+use tokio::io::AsyncWriteExt;
+use uuid::Uuid;
+
+use crate::{
+    auth::{self, BasicAuth},
+    client,
+    service::{self, Service},
+};
+
+/// Largest chunk accepted.
+const MAX_CHUNK: u64 = 64 * 1024 * 1024;
+
+/// Largest paste a session may assemble, whatever the size limits.
+const MAX_SIZE: u64 = 1024 * 1024 * 1024;
+
+/// Highest chunk index accepted.
+const MAX_INDEX: u32 = 9_999;
+
+/// How long a session may go without a chunk arriving before it's removed.
+const MAX_IDLE: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Suffix of a session's directory while it's being finalized.
+const FINALIZING: &str = "~finalizing";
+
+/// What a session's paste is created with, stored as `session.json`.
+#[derive(Deserialize, Serialize)]
+struct Session {
+    /// The user the paste is created for, who alone may use the session.
+    owner: Option<String>,
+    options: service::Options,
+}
+
+pub fn routes() -> Router<crate::AppState> {
+    Router::new()
+        .route("/upload-sessions", post(create))
+        .route("/upload-sessions/{id}", get(status).delete(abort))
+        .route("/upload-sessions/{id}/chunks/{index}", put(put_chunk))
+        .route("/upload-sessions/{id}/finalize", post(finalize))
+}
+
+fn dir(data_dir: &FsPath) -> PathBuf {
+    data_dir.join("upload-sessions")
+}
+
+async fn create(
+    State(service): State<Arc<Service>>,
+    State(client): State<Arc<client::Config>>,
+    auth: Option<BasicAuth>,
+    Query(options): Query<service::Options>,
+    request: Request,
+) -> Response {
+    if let Err(e) = options.validate() {
+        return (StatusCode::BAD_REQUEST, e.to_string()).into_response();
+    }
+    if let Some(auth) = &auth
+        && service.list(&auth.username, &auth.password).is_err()
+    {
+        return auth::unauthorized();
+    }
+    let id = service.ids().generate();
+    let session = Session {
+        owner: auth.map(|auth| auth.username),
+        options,
+    };
+    let path = dir(service.data_dir()).join(id.to_string());
+    let created = async {
+        tokio::fs::create_dir_all(&path).await?;
+        let json = serde_json::to_vec(&session).expect("Sessions serialize");
+        tokio::fs::write(path.join("session.json"), json).await
+    };
+    if let Err(e) = created.await {
+        return (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response();
+    }
+    let (parts, _) = request.into_parts();
+    let url = match client::base_url(&parts, &client) {
+        Some(base_url) => format!("{base_url}/upload-sessions/{id}"),
+        None => format!("{}/upload-sessions/{id}", client.path_prefix),
+    };
+    (
+        StatusCode::CREATED,
+        [(header::LOCATION, url.clone())],
+        format!("{url}\n"),
+    )
+        .into_response()
+}
+
+/// The session directory of `id`, if it exists and `auth` may use it.
+/// Other users' sessions are reported missing, like pastes are.
+async fn open(
+    service: &Service,
+    id: &Uuid,
+    auth: Option<&BasicAuth>,
+) -> Result<(PathBuf, Session), Response> {
+    let path = dir(service.data_dir()).join(id.to_string());
+    let session = match tokio::fs::read(path.join("session.json")).await {
+        Ok(json) => serde_json::from_slice::<Session>(&json)
+            .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response())?,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            return Err((StatusCode::NOT_FOUND, "No such session").into_response());
+        }
+        Err(e) => {
+            return Err((StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response());
+        }
+    };
+    let Some(owner) = &session.owner else {
+        return Ok((path, session));
+    };
+    match auth {
+        None => Err(auth::unauthorized()),
+        Some(auth)
+            if auth.username == *owner && service.list(&auth.username, &auth.password).is_ok() =>
+        {
+            Ok((path, session))
+        }
+        Some(_) => Err((StatusCode::NOT_FOUND, "No such session").into_response()),
+    }
+}
+
+/// The chunks in session directory `path`, by index, with their sizes.
+async fn chunks(path: &FsPath) -> std::io::Result<BTreeMap<u32, u64>> {
+    let mut chunks = BTreeMap::new();
+    let mut entries = tokio::fs::read_dir(path).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        let Some(index) = entry
+            .file_name()
+            .to_str()
+            .and_then(|name| name.parse().ok())
+        else {
+            continue;
+        };
+        chunks.insert(index, entry.metadata().await?.len());
+    }
+    Ok(chunks)
+}
+
+#[derive(Serialize)]
+struct Status {
+    chunks: Vec<Chunk>,
+    /// Total size of the chunks.
+    size: u64,
+}
+
+#[derive(Serialize)]
+struct Chunk {
+    index: u32,
+    size: u64,
+}
+
+/// `GET /upload-sessions/{id}`, which chunks arrived.
+async fn status(
+    State(service): State<Arc<Service>>,
+    Path(id): Path<Uuid>,
+    auth: Option<BasicAuth>,
+) -> Response {
+    let path = match open(&service, &id, auth.as_ref()).await {
+        Ok((path, _)) => path,
+        Err(response) => return response,
+    };
+    match chunks(&path).await {
+        Ok(chunks) => Json(Status {
+            size: chunks.values().sum(),
+            chunks: chunks
+                .into_iter()
+                .map(|(index, size)| Chunk { index, size })
+                .collect(),
+        })
+        .into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
+/// `PUT /upload-sessions/{id}/chunks/{index}`, replacing any earlier chunk
+/// with that index.
+async fn put_chunk(
+    State(service): State<Arc<Service>>,
+    Path((id, index)): Path<(Uuid, u32)>,
+    auth: Option<BasicAuth>,
+    request: Request,
+) -> Response {
+    if index > MAX_INDEX {
+        return (
+            StatusCode::BAD_REQUEST,
+            format!("Chunk indices go up to {MAX_INDEX}"),
+        )
+            .into_response();
+    }
+    let (path, session) = match open(&service, &id, auth.as_ref()).await {
+        Ok(opened) => opened,
+        Err(response) => return response,
+    };
+    let others: u64 = match chunks(&path).await {
+        Ok(chunks) => chunks
+            .into_iter()
+            .filter(|(other, _)| *other != index)
+            .map(|(_, size)| size)
+            .sum(),
+        Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    };
+    let max_size = service
+        .size_limit(session.owner.is_some())
+        .map_or(MAX_SIZE, |limit| limit.min(MAX_SIZE));
+    let limit = MAX_CHUNK.min(max_size.saturating_sub(others));
+
+    // Written aside first, so that a chunk cut short doesn't replace one
+    // that arrived whole.
+    let tmp = path.join(format!("{index}~{}", Uuid::new_v4().simple()));
+    let written = async {
+        let mut file = tokio::fs::File::create_new(&tmp).await?;
+        let mut size = 0;
+        let mut stream = request.into_body().into_data_stream();
+        while let Some(chunk) = stream.next().await {
+            let chunk = chunk.map_err(|e| {
+                std::io::Error::new(std::io::ErrorKind::ConnectionAborted, e.to_string())
+            })?;
+            size += chunk.len() as u64;
+            if size > limit {
+                return Ok(false);
+            }
+            file.write_all(&chunk).await?;
+        }
+        file.sync_data().await?;
+        tokio::fs::rename(&tmp, path.join(index.to_string())).await?;
+        touch(&path, service.clock().now())?;
+        Ok::<_, std::io::Error>(true)
+    };
+    match written.await {
+        Ok(true) => StatusCode::NO_CONTENT.into_response(),
+        Ok(false) => {
+            tokio::fs::remove_file(&tmp).await.ok();
+            let message = if limit < MAX_CHUNK {
+                format!("Pastes are limited to {max_size} bytes")
+            } else {
+                format!("Chunks are limited to {MAX_CHUNK} bytes")
+            };
+            (StatusCode::PAYLOAD_TOO_LARGE, message).into_response()
+        }
+        // The session went away while the chunk was arriving.
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            (StatusCode::NOT_FOUND, "No such session").into_response()
+        }
+        Err(e) => {
+            tokio::fs::remove_file(&tmp).await.ok();
+            (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response()
+        }
+    }
+}
+
+/// `POST /upload-sessions/{id}/finalize`, creating the paste out of chunks
+/// 0 to n and ending the session.
+async fn finalize(
+    State(service): State<Arc<Service>>,
+    State(client): State<Arc<client::Config>>,
+    Path(id): Path<Uuid>,
+    auth: Option<BasicAuth>,
+    request: Request,
+) -> Response {
+    let (path, session) = match open(&service, &id, auth.as_ref()).await {
+        Ok(opened) => opened,
+        Err(response) => return response,
+    };
+    // Moved aside so that chunks arriving now, and finalizing twice, fail.
+    let finalizing = path.with_file_name(format!("{id}{FINALIZING}"));
+    match tokio::fs::rename(&path, &finalizing).await {
+        Ok(()) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            return (StatusCode::NOT_FOUND, "No such session").into_response();
+        }
+        Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+    let created = assemble(&service, &finalizing, session, auth).await;
+    if created.is_err() {
+        // Left to be fixed up by uploading the missing chunks.
+        if let Err(e) = tokio::fs::rename(&finalizing, &path).await {
+            tracing::error!("Restoring upload session {id}: {e}");
+        }
+    } else if let Err(e) = tokio::fs::remove_dir_all(&finalizing).await {
+        tracing::warn!("Removing finalized upload session {id}: {e}");
+    }
+    let id = match created {
+        Ok(id) => id,
+        Err(response) => return response,
+    };
+    let (parts, _) = request.into_parts();
+    match client::base_url(&parts, &client) {
+        Some(base_url) => {
+            let url = format!("{base_url}/paste/{id}");
+            ([(header::LOCATION, url.clone())], format!("{url}\n")).into_response()
+        }
+        None => format!("{id}\n").into_response(),
+    }
+}
+
+/// Creates the paste out of the chunks in session directory `path`, which
+/// must run from 0 without gaps.
+async fn assemble(
+    service: &Service,
+    path: &FsPath,
+    session: Session,
+    auth: Option<BasicAuth>,
+) -> Result<String, Response> {
+    let chunks = chunks(path)
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response())?;
+    if let Some(missing) = (0..).zip(chunks.keys()).find(|(i, index)| i != *index) {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            format!("Chunk {} is missing", missing.0),
+        )
+            .into_response());
+    }
+    let paths: Vec<_> = chunks
+        .keys()
+        .map(|index| path.join(index.to_string()))
+        .collect();
+    let stream = futures::stream::iter(paths)
+        .then(tokio::fs::File::open)
+        .map_ok(tokio_util::io::ReaderStream::new)
+        .try_flatten();
+    let reader = tokio_util::io::StreamReader::new(Box::pin(stream));
+    service
+        .create_with_options(reader, auth.map(Into::into), session.options)
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response())
+}
+
+/// `DELETE /upload-sessions/{id}`, dropping a session the client gave up on.
+async fn abort(
+    State(service): State<Arc<Service>>,
+    Path(id): Path<Uuid>,
+    auth: Option<BasicAuth>,
+) -> Response {
+    let path = match open(&service, &id, auth.as_ref()).await {
+        Ok((path, _)) => path,
+        Err(response) => return response,
+    };
+    match tokio::fs::remove_dir_all(&path).await {
+        Ok(()) => StatusCode::NO_CONTENT.into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
+/// Marks the session in directory `path` as having had a chunk arrive at
+/// `now`.
+fn touch(path: &FsPath, now: SystemTime) -> std::io::Result<()> {
+    let file = std::fs::File::options()
+        .append(true)
+        .open(path.join("session.json"))?;
+    file.set_modified(now)
+}
+
+/// Removes the sessions under `data_dir` no chunk arrived for in longer than
+/// `MAX_IDLE` before `now`, returning how many there were.
+pub async fn sweep(data_dir: &FsPath, now: SystemTime) -> std::io::Result<usize> {
+    let mut entries = match tokio::fs::read_dir(dir(data_dir)).await {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(0),
+        Err(e) => return Err(e),
+    };
+    let mut swept = 0;
+    while let Some(entry) = entries.next_entry().await? {
+        let name = entry.file_name();
+        let is_session = name.to_str().is_some_and(|name| {
+            Uuid::parse_str(name.strip_suffix(FINALIZING).unwrap_or(name)).is_ok()
+        });
+        if !is_session {
+            continue;
+        }
+        // Adding a chunk updates the modification time of `session.json`.
+        let modified = match tokio::fs::metadata(entry.path().join("session.json")).await {
+            Ok(metadata) => metadata.modified()?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                entry.metadata().await?.modified()?
+            }
+            Err(e) => return Err(e),
+        };
+        if now
+            .duration_since(modified)
+            .is_ok_and(|idle| idle > MAX_IDLE)
+        {
+            tokio::fs::remove_dir_all(entry.path()).await?;
+            swept += 1;
+        }
+    }
+    Ok(swept)
+}
+
+#[tokio::test]
+async fn test_session_assembles_chunks_in_order() {
+    let server = crate::testing::TestServer::start_with(|builder| builder.user("alice", "secret"))
+        .await
+        .unwrap();
+    let created = server
+        .client()
+        .post(server.url("/upload-sessions?title=parts"))
+        .basic_auth("alice", Some("secret"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(created.status(), StatusCode::CREATED);
+    let session = created.text().await.unwrap().trim().to_owned();
+    let put = |index: u32, body: &'static str| {
+        server
+            .client()
+            .put(format!("{session}/chunks/{index}"))
+            .basic_auth("alice", Some("secret"))
+            .body(body)
+            .send()
+    };
+    let finalize = || {
+        server
+            .client()
+            .post(format!("{session}/finalize"))
+            .basic_auth("alice", Some("secret"))
+            .send()
+    };
+
+    // Only its owner may use the session.
+    let response = server.client().get(&session).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+    assert_eq!(put(2, "!").await.unwrap().status(), StatusCode::NO_CONTENT);
+    assert_eq!(
+        put(0, "hel").await.unwrap().status(),
+        StatusCode::NO_CONTENT
+    );
+    let response = finalize().await.unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    assert_eq!(response.text().await.unwrap(), "Chunk 1 is missing");
+
+    // A retried chunk replaces the earlier one.
+    assert_eq!(put(1, "xx").await.unwrap().status(), StatusCode::NO_CONTENT);
+    assert_eq!(put(1, "lo").await.unwrap().status(), StatusCode::NO_CONTENT);
+    let status = server
+        .client()
+        .get(&session)
+        .basic_auth("alice", Some("secret"))
+        .send()
+        .await
+        .unwrap();
+    let status: serde_json::Value = status.json().await.unwrap();
+    assert_eq!(status["size"], 6);
+
+    let response = finalize().await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let url = response.text().await.unwrap().trim().to_owned();
+    let contents = server.client().get(&url).send().await.unwrap();
+    assert_eq!(contents.text().await.unwrap(), "hello!");
+    let id = Uuid::parse_str(url.rsplit('/').next().unwrap()).unwrap();
+    let metadata = server.service.metadata(&id).await.unwrap().unwrap();
+    assert_eq!(metadata.title.as_deref(), Some("parts"));
+    assert_eq!(finalize().await.unwrap().status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_chunks_are_held_to_the_size_limit() {
+    let server =
+        crate::testing::TestServer::start_with(|builder| builder.size_limits(Some(5), None))
+            .await
+            .unwrap();
+    let created = server
+        .client()
+        .post(server.url("/upload-sessions"))
+        .send()
+        .await
+        .unwrap();
+    let session = created.text().await.unwrap().trim().to_owned();
+    let put = |index: u32, body: &'static str| {
+        server
+            .client()
+            .put(format!("{session}/chunks/{index}"))
+            .body(body)
+            .send()
+    };
+    assert_eq!(
+        put(0, "hel").await.unwrap().status(),
+        StatusCode::NO_CONTENT
+    );
+    let response = put(1, "lo!").await.unwrap();
+    assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    assert_eq!(
+        response.text().await.unwrap(),
+        "Pastes are limited to 5 bytes"
+    );
+    assert_eq!(put(1, "lo").await.unwrap().status(), StatusCode::NO_CONTENT);
+}
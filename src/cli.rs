@@ -1,21 +1,900 @@
-use std::path::PathBuf;
+use std::{
+    collections::HashMap,
+    net::{IpAddr, SocketAddr, ToSocketAddrs},
+    path::PathBuf,
+    str::FromStr,
+    time::Duration,
+};
 
-use clap::Parser;
+use axum::http::{HeaderName, HeaderValue, Method, header};
+use clap::{CommandFactory, FromArgMatches, Parser, Subcommand};
+use tower_http::cors::{AllowHeaders, AllowOrigin, CorsLayer};
 
 #[derive(Parser)]
 pub struct Args {
-    #[arg(long, default_value_t = 3000)]
+    /// TOML file with default values for these options
+    #[arg(long, env = "PASTEBIN_CONFIG")]
+    pub config: Option<PathBuf>,
+
+    /// Port to listen on, unless systemd passes in listening sockets
+    #[arg(long, default_value_t = 3000, env = "PASTEBIN_PORT")]
     pub port: u16,
 
-    #[arg(long, default_value = ".")]
+    #[arg(long, default_value = ".", env = "PASTEBIN_DATA_DIR")]
     pub data_dir: PathBuf,
 
-    #[arg(default_value = "db.json")]
+    /// Address to listen on, e.g. `127.0.0.1`, `[::]:8080` or `localhost:3001`.
+    /// Can be given several times. Addresses without a port use --port.
+    /// Defaults to 0.0.0.0
+    #[arg(long, value_delimiter = ',', env = "PASTEBIN_BIND")]
+    pub bind: Vec<String>,
+
+    /// Listen on a Unix domain socket at this path instead of a TCP port
+    #[arg(long, env = "PASTEBIN_UNIX_SOCKET")]
+    pub unix_socket: Option<PathBuf>,
+
+    /// Address to accept pastes on over plain TCP, e.g. `0.0.0.0:9999`, for
+    /// `echo hi | nc host 9999`. The reply is the paste's URL if --base-url
+    /// is set, its ID otherwise
+    #[arg(long, env = "PASTEBIN_TCP_UPLOAD")]
+    pub tcp_upload: Option<String>,
+
+    /// Address to accept email on over SMTP, e.g. `127.0.0.1:2525`, behind
+    /// a mail server verifying senders. Messages to --smtp-domain from an
+    /// --email-user become pastes, one per attachment or of the text
+    #[arg(long, env = "PASTEBIN_SMTP")]
+    pub smtp: Option<String>,
+
+    /// Domain whose addresses --smtp accepts mail for, e.g.
+    /// `paste.example.com`
+    #[arg(long, env = "PASTEBIN_SMTP_DOMAIN")]
+    pub smtp_domain: Option<String>,
+
+    /// Sender allowed to paste by email and the user owning their pastes,
+    /// as `alice@example.com=alice`. Can be given several times
+    #[arg(long, value_delimiter = ',', env = "PASTEBIN_EMAIL_USER")]
+    pub email_user: Vec<String>,
+
+    /// Mail server to send the URLs of emailed pastes back through, as
+    /// `host:port`. Without it they're only in the SMTP response
+    #[arg(long, env = "PASTEBIN_SMTP_RELAY")]
+    pub smtp_relay: Option<String>,
+
+    /// Hours before one of their pastes expires to email its owner, if they
+    /// set an address, with a link to extend it. Needs --smtp-relay and
+    /// --base-url
+    #[arg(long, env = "PASTEBIN_EXPIRY_REMINDER_HOURS")]
+    pub expiry_reminder_hours: Option<u64>,
+
+    /// Address to serve public pastes on over the Gemini protocol, e.g.
+    /// `0.0.0.0:1965`. Needs --tls-cert and --tls-key
+    #[arg(long, env = "PASTEBIN_GEMINI")]
+    pub gemini: Option<String>,
+
+    #[arg(default_value = "db.json", env = "PASTEBIN_STATE")]
     pub state: PathBuf,
 
-    #[arg(long, short)]
+    #[arg(long, short, env = "PASTEBIN_USERNAME")]
     pub username: Option<String>,
 
-    #[arg(long, short)]
+    #[arg(long, short, env = "PASTEBIN_PASSWORD", hide_env_values = true)]
     pub password: Option<String>,
+
+    /// User allowed to use the admin endpoints. Can be given several times
+    #[arg(long, value_delimiter = ',', env = "PASTEBIN_ADMIN")]
+    pub admin: Vec<String>,
+
+    /// Only let admins see `/stats`
+    #[arg(long, env = "PASTEBIN_PRIVATE_STATS")]
+    pub private_stats: bool,
+
+    /// Directory to write periodic snapshots of the state and pastes into
+    #[arg(long, env = "PASTEBIN_SNAPSHOT_DIR")]
+    pub snapshot_dir: Option<PathBuf>,
+
+    /// Seconds between snapshots
+    #[arg(long, default_value_t = 3600, env = "PASTEBIN_SNAPSHOT_INTERVAL")]
+    pub snapshot_interval: u64,
+
+    /// Number of snapshots to retain
+    #[arg(long, default_value_t = 24, env = "PASTEBIN_SNAPSHOT_KEEP")]
+    pub snapshot_keep: usize,
+
+    /// Seconds between background checksum verifications of all pastes
+    #[arg(long, env = "PASTEBIN_SCRUB_INTERVAL")]
+    pub scrub_interval: Option<u64>,
+
+    /// Move pastes that fail verification into the data directory's
+    /// `quarantine` subdirectory
+    #[arg(long, env = "PASTEBIN_SCRUB_QUARANTINE")]
+    pub scrub_quarantine: bool,
+
+    /// Directory or instance URL to mirror paste writes and deletes to
+    #[arg(long, env = "PASTEBIN_REPLICATE_TO")]
+    pub replicate_to: Option<String>,
+
+    /// Shared secret for pushing pastes to another instance, and for
+    /// accepting pushes from one
+    #[arg(long, env = "PASTEBIN_REPLICATION_TOKEN", hide_env_values = true)]
+    pub replication_token: Option<String>,
+
+    /// URL to post a JSON event to whenever a paste is created, updated,
+    /// deleted or expires. Can be given several times
+    #[arg(long, value_delimiter = ',', env = "PASTEBIN_WEBHOOK")]
+    pub webhook: Vec<String>,
+
+    /// GitHub token with the gist scope, for mirroring pastes created with
+    /// `?gist=true` to secret gists
+    #[arg(long, env = "PASTEBIN_GITHUB_TOKEN", hide_env_values = true)]
+    pub github_token: Option<String>,
+
+    /// GitHub API to create gists with, e.g. that of GitHub Enterprise
+    #[arg(
+        long,
+        default_value = "https://api.github.com",
+        env = "PASTEBIN_GITHUB_API_URL"
+    )]
+    pub github_api_url: String,
+
+    /// Instance to push this one's public pastes to, which must list this
+    /// instance's key with --federation-trust. Can be given several times
+    #[arg(long, value_delimiter = ',', env = "PASTEBIN_FEDERATE_TO")]
+    pub federate_to: Vec<String>,
+
+    /// Key of an instance whose pushed pastes are mirrored here, as shown at
+    /// its `/federation/key`. Can be given several times
+    #[arg(long, value_delimiter = ',', env = "PASTEBIN_FEDERATION_TRUST")]
+    pub federation_trust: Vec<String>,
+
+    /// Ed25519 key to sign pastes pushed with --federate-to, generated if
+    /// missing
+    #[arg(
+        long,
+        default_value = "federation_ed25519_key",
+        env = "PASTEBIN_FEDERATION_KEY"
+    )]
+    pub federation_key: PathBuf,
+
+    /// Key for signing webhook payloads with HMAC-SHA256, sent as
+    /// `X-Pastebin-Signature: sha256=<hex>`
+    #[arg(long, env = "PASTEBIN_WEBHOOK_SECRET", hide_env_values = true)]
+    pub webhook_secret: Option<String>,
+
+    /// Slack incoming webhook URL to announce new pastes matching
+    /// --notify-filter to. Can be given several times
+    #[arg(long, value_delimiter = ',', env = "PASTEBIN_NOTIFY_SLACK")]
+    pub notify_slack: Vec<String>,
+
+    /// Discord webhook URL to announce new pastes matching --notify-filter
+    /// to. Can be given several times
+    #[arg(long, value_delimiter = ',', env = "PASTEBIN_NOTIFY_DISCORD")]
+    pub notify_discord: Vec<String>,
+
+    /// Matrix room ID, e.g. `!abc:example.org`, to announce new pastes
+    /// matching --notify-filter in as the user of --matrix-token. Can be
+    /// given several times
+    #[arg(long, value_delimiter = ',', env = "PASTEBIN_NOTIFY_MATRIX")]
+    pub notify_matrix: Vec<String>,
+
+    /// Homeserver of the --notify-matrix rooms
+    #[arg(
+        long,
+        default_value = "https://matrix.org",
+        env = "PASTEBIN_MATRIX_HOMESERVER"
+    )]
+    pub matrix_homeserver: String,
+
+    /// Access token of the Matrix user posting to --notify-matrix rooms
+    #[arg(long, env = "PASTEBIN_MATRIX_TOKEN", hide_env_values = true)]
+    pub matrix_token: Option<String>,
+
+    /// Which new pastes are announced, as terms that must all hold:
+    /// `public:true|false|any`, `language:rust,go`, `user:alice,bob` and
+    /// `title:text`. Defaults to all public pastes
+    #[arg(long, env = "PASTEBIN_NOTIFY_FILTER")]
+    pub notify_filter: Option<String>,
+
+    /// Shell command to run whenever a paste is created, updated, deleted or
+    /// expires. It gets `PASTEBIN_EVENT`, `PASTEBIN_PASTE_ID` and
+    /// `PASTEBIN_PASTE_PATH` in its environment and the event as JSON on
+    /// stdin. Can be given several times
+    #[arg(long, env = "PASTEBIN_EXEC_HOOK")]
+    pub exec_hook: Vec<String>,
+
+    /// Maximum total size of all pastes in bytes. When exceeded, the least
+    /// recently read anonymous pastes are deleted
+    #[arg(long, env = "PASTEBIN_STORAGE_BUDGET")]
+    pub storage_budget: Option<u64>,
+
+    /// Earlier contents to keep of each paste when it's replaced, listed at
+    /// `/paste/{id}/versions`. They don't count towards --storage-budget
+    #[arg(long, default_value_t = 0, env = "PASTEBIN_KEEP_VERSIONS")]
+    pub keep_versions: usize,
+
+    /// Largest paste in bytes that may be uploaded without logging in
+    #[arg(long, env = "PASTEBIN_MAX_ANONYMOUS_SIZE")]
+    pub max_anonymous_size: Option<u64>,
+
+    /// Largest paste in bytes that a logged-in user may upload
+    #[arg(long, env = "PASTEBIN_MAX_USER_SIZE")]
+    pub max_user_size: Option<u64>,
+
+    /// Bytes read or written at once when transferring a paste
+    #[arg(long, default_value_t = 256 * 1024, env = "PASTEBIN_BUFFER_SIZE")]
+    pub buffer_size: usize,
+
+    /// Unused transfer buffers kept for reuse by later transfers
+    #[arg(long, default_value_t = 64, env = "PASTEBIN_IDLE_BUFFERS")]
+    pub idle_buffers: usize,
+
+    /// Whether uploads are synced to disk before the response, in batches,
+    /// or when the OS gets around to it
+    #[arg(long, value_enum, default_value_t, env = "PASTEBIN_DURABILITY")]
+    pub durability: crate::durability::Policy,
+
+    /// Milliseconds between syncs with `--durability batch`
+    #[arg(long, default_value_t = 1000, env = "PASTEBIN_SYNC_INTERVAL")]
+    pub sync_interval: u64,
+
+    /// Read and write pastes too large to cache through io_uring rather
+    /// than the blocking pool. Needs a build with the io-uring feature
+    #[arg(long, env = "PASTEBIN_IO_URING")]
+    pub io_uring: bool,
+
+    /// Bytes of memory to cache small, frequently read pastes in, or 0 to
+    /// read every paste from disk
+    #[arg(long, default_value_t = 64 * 1024 * 1024, env = "PASTEBIN_CACHE_SIZE")]
+    pub cache_size: usize,
+
+    /// Pastes kept in the cache at most
+    #[arg(long, default_value_t = 10_000, env = "PASTEBIN_CACHE_ENTRIES")]
+    pub cache_entries: usize,
+
+    /// Delete anonymous pastes this many days after they were created
+    #[arg(long, env = "PASTEBIN_ANONYMOUS_RETENTION_DAYS")]
+    pub anonymous_retention_days: Option<u64>,
+
+    /// Seconds between garbage collection runs
+    #[arg(long, default_value_t = 3600, env = "PASTEBIN_GC_INTERVAL")]
+    pub gc_interval: u64,
+
+    /// Seconds between saves of the state file, when accounts or ownership
+    /// changed
+    #[arg(long, default_value_t = 10, env = "PASTEBIN_STATE_SAVE_INTERVAL")]
+    pub state_save_interval: u64,
+
+    /// Seconds to wait for open requests and streams to finish on shutdown
+    /// before they're cut off
+    #[arg(long, default_value_t = 30, env = "PASTEBIN_SHUTDOWN_TIMEOUT")]
+    pub shutdown_timeout: u64,
+
+    /// Log filter, e.g. `info` or `pastebin=debug,tower_http=info`
+    #[arg(long, default_value = "info", env = "PASTEBIN_LOG_LEVEL")]
+    pub log_level: String,
+
+    #[arg(long, value_enum, default_value_t, env = "PASTEBIN_LOG_FORMAT")]
+    pub log_format: crate::logging::Format,
+
+    /// Write a line for every request to stdout in this format
+    #[arg(long, value_enum, env = "PASTEBIN_ACCESS_LOG")]
+    pub access_log: Option<crate::access_log::Format>,
+
+    /// Public URL of the instance, including any --path-prefix, for links
+    /// to pastes. Defaults to the URL requests were sent to
+    #[arg(long, env = "PASTEBIN_BASE_URL")]
+    pub base_url: Option<String>,
+
+    /// Path to serve all routes under, e.g. `/paste` for an instance
+    /// reverse-proxied at `https://example.com/paste/`
+    #[arg(long, env = "PASTEBIN_PATH_PREFIX")]
+    pub path_prefix: Option<String>,
+
+    /// File to serve as `/robots.txt`. By default crawlers are kept out of
+    /// the admin and bulk endpoints only, and pointed to the sitemap if
+    /// --base-url is set
+    #[arg(long, env = "PASTEBIN_ROBOTS_TXT")]
+    pub robots_txt: Option<PathBuf>,
+
+    /// Address or network, e.g. `10.0.0.0/8`, of a reverse proxy whose
+    /// `X-Forwarded-For`, `-Proto` and `-Host` or `Forwarded` headers are
+    /// believed, for the access log, rate limits and generated URLs. Can be
+    /// given several times
+    #[arg(long, value_delimiter = ',', env = "PASTEBIN_TRUSTED_PROXY")]
+    pub trusted_proxy: Vec<String>,
+
+    /// Believe the proxy headers of every peer, for instances only reachable
+    /// through a reverse proxy
+    #[arg(long, env = "PASTEBIN_TRUST_PROXY")]
+    pub trust_proxy: bool,
+
+    /// Pastes a client IP may create per minute
+    #[arg(long, env = "PASTEBIN_CREATE_RATE")]
+    pub create_rate: Option<u32>,
+
+    /// Pastes a client IP may create at once before --create-rate applies.
+    /// Defaults to --create-rate
+    #[arg(long, env = "PASTEBIN_CREATE_BURST")]
+    pub create_burst: Option<u32>,
+
+    /// Pastes and archives a client IP may read per minute
+    #[arg(long, env = "PASTEBIN_READ_RATE")]
+    pub read_rate: Option<u32>,
+
+    /// Reads a client IP may make at once before --read-rate applies.
+    /// Defaults to --read-rate
+    #[arg(long, env = "PASTEBIN_READ_BURST")]
+    pub read_burst: Option<u32>,
+
+    /// Requests an authenticated user may make per minute, unless set for
+    /// the user with the `rate-limit` command
+    #[arg(long, env = "PASTEBIN_USER_RATE")]
+    pub user_rate: Option<u32>,
+
+    /// Requests a user may make at once before --user-rate applies.
+    /// Defaults to --user-rate
+    #[arg(long, env = "PASTEBIN_USER_BURST")]
+    pub user_burst: Option<u32>,
+
+    /// Pastes an authenticated user may create per minute. When set, their
+    /// pastes count against this instead of --create-rate
+    #[arg(long, env = "PASTEBIN_USER_CREATE_RATE")]
+    pub user_create_rate: Option<u32>,
+
+    /// Pastes a user may create at once before --user-create-rate applies.
+    /// Defaults to --user-create-rate
+    #[arg(long, env = "PASTEBIN_USER_CREATE_BURST")]
+    pub user_create_burst: Option<u32>,
+
+    /// Seconds a client has to read a paste or archive, after which the
+    /// response is cut off
+    #[arg(long, env = "PASTEBIN_READ_TIMEOUT")]
+    pub read_timeout: Option<u64>,
+
+    /// Seconds a client has to upload a paste or archive and receive the
+    /// response
+    #[arg(long, env = "PASTEBIN_UPLOAD_TIMEOUT")]
+    pub upload_timeout: Option<u64>,
+
+    /// Bytes per second an upload must average over 10 seconds to not be
+    /// aborted
+    #[arg(long, env = "PASTEBIN_MIN_UPLOAD_RATE")]
+    pub min_upload_rate: Option<u64>,
+
+    /// Requests handled at the same time, beyond which new ones are
+    /// answered with 503 right away
+    #[arg(long, env = "PASTEBIN_MAX_CONCURRENT_REQUESTS")]
+    pub max_concurrent_requests: Option<usize>,
+
+    /// Origin allowed to call the API from a browser, or `*` for any. Can
+    /// be given several times
+    #[arg(long, value_delimiter = ',', env = "PASTEBIN_CORS_ORIGIN")]
+    pub cors_origin: Vec<String>,
+
+    /// Methods allowed in cross-origin requests
+    #[arg(
+        long,
+        value_delimiter = ',',
+        default_value = "GET,POST,PUT",
+        env = "PASTEBIN_CORS_METHODS"
+    )]
+    pub cors_methods: Vec<String>,
+
+    /// Request headers allowed in cross-origin requests, or `*` for any
+    #[arg(
+        long,
+        value_delimiter = ',',
+        default_value = "authorization,content-type",
+        env = "PASTEBIN_CORS_HEADERS"
+    )]
+    pub cors_headers: Vec<String>,
+
+    /// PEM certificate chain to serve HTTPS with. Reloaded when it changes
+    #[arg(long, env = "PASTEBIN_TLS_CERT")]
+    pub tls_cert: Option<PathBuf>,
+
+    /// PEM private key for --tls-cert
+    #[arg(long, env = "PASTEBIN_TLS_KEY")]
+    pub tls_key: Option<PathBuf>,
+
+    /// Also serve HTTP/3 over QUIC, on the UDP ports of the TCP listeners,
+    /// and advertise it with `Alt-Svc`. Needs --tls-cert and --tls-key
+    #[arg(long, env = "PASTEBIN_HTTP3")]
+    pub http3: bool,
+
+    /// URL of the instance the `client` subcommands talk to, e.g.
+    /// `https://paste.example.com`
+    #[arg(long, env = "PASTEBIN_REMOTE")]
+    pub remote: Option<String>,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Import an archive produced by `GET /pastes/export` as pastes owned by
+    /// --username
+    Import { archive: PathBuf },
+
+    /// Create a paste owned by --username for every file in a directory
+    ImportDir {
+        dir: PathBuf,
+
+        /// Layout of the directory, e.g. an export from another pastebin
+        #[arg(long, value_enum, default_value_t)]
+        format: crate::import::Format,
+    },
+
+    /// Set the request limit of a user in the state file, overriding
+    /// --user-rate. Run while the server is stopped
+    RateLimit {
+        user: String,
+
+        /// Requests per minute. Without it, the user falls back to
+        /// --user-rate
+        #[arg(long)]
+        per_minute: Option<u32>,
+
+        /// Requests at once. Defaults to --per-minute
+        #[arg(long, requires = "per_minute")]
+        burst: Option<u32>,
+    },
+
+    /// Check the state file against the data directory
+    Doctor {
+        /// Drop references to missing, duplicate and invalid pastes
+        #[arg(long)]
+        repair: bool,
+    },
+
+    /// Load the --remote instance with uploads and reads, as --username if
+    /// given, and report latency percentiles and throughput
+    Bench {
+        /// Requests in flight at once
+        #[arg(long, default_value_t = 16)]
+        concurrency: usize,
+
+        /// Seconds to run for
+        #[arg(long, default_value_t = 10)]
+        duration: u64,
+
+        /// Bytes per uploaded paste. Repeat to upload a mix of sizes
+        #[arg(long = "size", default_values_t = [1024])]
+        sizes: Vec<u64>,
+
+        /// Percentage of requests that are reads
+        #[arg(long, default_value_t = 90, value_parser = clap::value_parser!(u8).range(0..=100))]
+        reads: u8,
+    },
+
+    /// Use the --remote instance, as --username if given
+    Client {
+        #[command(subcommand)]
+        command: ClientCommand,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum ClientCommand {
+    /// Upload a file, or stdin, and print the paste's URL
+    Post {
+        file: Option<PathBuf>,
+
+        /// Defaults to the file's name
+        #[arg(long)]
+        title: Option<String>,
+
+        /// Language of the contents, e.g. `rust`
+        #[arg(long)]
+        language: Option<String>,
+
+        /// List the paste publicly
+        #[arg(long)]
+        public: bool,
+
+        /// Ask search engines not to index the paste
+        #[arg(long)]
+        noindex: bool,
+
+        /// Upload without --username, so that no one owns the paste
+        #[arg(long)]
+        anonymous: bool,
+    },
+
+    /// Print a paste's contents
+    Get {
+        /// ID or URL of the paste
+        paste: String,
+    },
+
+    /// List the pastes of --username, newest first
+    List {
+        #[arg(long, value_enum, default_value_t)]
+        format: crate::remote::Format,
+    },
+
+    /// Delete one of the pastes of --username
+    Delete {
+        /// ID or URL of the paste
+        paste: String,
+    },
+}
+
+impl Args {
+    /// Parses the command line and `PASTEBIN_*` environment variables,
+    /// filling in options set by neither from the `--config` file if one is
+    /// given.
+    pub fn load() -> anyhow::Result<Self> {
+        Self::from_matches(Self::command().get_matches())
+    }
+
+    /// Every option at its default, ignoring the environment, for instances
+    /// configured in code.
+    pub fn defaults() -> Self {
+        let matches = Self::command()
+            .mut_args(|arg| arg.env(None))
+            .get_matches_from(["pastebin"]);
+        Self::from_arg_matches(&matches).expect("the defaults are valid")
+    }
+
+    /// Like [`Args::load`], but returns command line errors instead of
+    /// exiting, for rereading the `--config` file while running.
+    pub fn reload() -> anyhow::Result<Self> {
+        Self::from_matches(Self::command().try_get_matches()?)
+    }
+
+    fn from_matches(matches: clap::ArgMatches) -> anyhow::Result<Self> {
+        let mut args = Self::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+        if let Some(path) = &args.config {
+            crate::config::Config::load(path)?.apply(&mut args, &matches);
+        }
+        if args.tls_cert.is_some() != args.tls_key.is_some() {
+            anyhow::bail!("--tls-cert and --tls-key must be given together");
+        }
+        if args.gemini.is_some() && args.tls_cert.is_none() {
+            anyhow::bail!("--gemini needs --tls-cert and --tls-key");
+        }
+        if args.http3 && args.tls_cert.is_none() {
+            anyhow::bail!("--http3 needs --tls-cert and --tls-key");
+        }
+        if args.io_uring && !cfg!(feature = "io-uring") {
+            anyhow::bail!("--io-uring needs a build with the io-uring feature");
+        }
+        Ok(args)
+    }
+
+    pub fn snapshot_schedule(&self) -> Option<crate::backup::Schedule> {
+        let target = self.snapshot_dir.clone()?;
+        Some(crate::backup::Schedule {
+            target,
+            interval: Duration::from_secs(self.snapshot_interval.max(1)),
+            keep: self.snapshot_keep,
+        })
+    }
+
+    pub fn gc_policy(&self) -> crate::gc::Policy {
+        crate::gc::Policy {
+            interval: Duration::from_secs(self.gc_interval.max(1)),
+            anonymous_max_age: self
+                .anonymous_retention_days
+                .map(|days| Duration::from_secs(days * 24 * 60 * 60)),
+        }
+    }
+
+    /// The TCP addresses to listen on. Without --bind this is 0.0.0.0 and
+    /// --port, unless only a Unix socket is requested.
+    pub fn bind_addresses(&self) -> anyhow::Result<Vec<SocketAddr>> {
+        if self.bind.is_empty() {
+            return Ok(match self.unix_socket {
+                Some(_) => Vec::new(),
+                None => vec![SocketAddr::from(([0, 0, 0, 0], self.port))],
+            });
+        }
+        let mut addresses = Vec::new();
+        for bind in &self.bind {
+            let resolved = if let Ok(ip) = bind.parse::<IpAddr>() {
+                Ok(vec![SocketAddr::new(ip, self.port)])
+            } else if bind.contains(':') {
+                bind.to_socket_addrs().map(Iterator::collect)
+            } else {
+                (bind.as_str(), self.port)
+                    .to_socket_addrs()
+                    .map(Iterator::collect)
+            };
+            let resolved: Vec<_> =
+                resolved.map_err(|e| anyhow::anyhow!("Invalid --bind address {bind:?}: {e}"))?;
+            addresses.extend(resolved);
+        }
+        Ok(addresses)
+    }
+
+    /// The --tcp-upload address, if given.
+    pub fn tcp_upload_address(&self) -> anyhow::Result<Option<SocketAddr>> {
+        resolve("--tcp-upload", self.tcp_upload.as_deref())
+    }
+
+    /// The --gemini address, if given.
+    pub fn gemini_address(&self) -> anyhow::Result<Option<SocketAddr>> {
+        resolve("--gemini", self.gemini.as_deref())
+    }
+
+    /// The --smtp address and the email gateway's settings, if given.
+    pub fn email(&self) -> anyhow::Result<Option<(SocketAddr, crate::email::Config)>> {
+        let Some(address) = resolve("--smtp", self.smtp.as_deref())? else {
+            return Ok(None);
+        };
+        let Some(domain) = &self.smtp_domain else {
+            anyhow::bail!("--smtp needs --smtp-domain");
+        };
+        let mut users = HashMap::new();
+        for mapping in &self.email_user {
+            let Some((sender, username)) = mapping.split_once('=') else {
+                anyhow::bail!("Invalid --email-user {mapping:?}, expected address=username");
+            };
+            users.insert(sender.trim().to_lowercase(), username.trim().to_owned());
+        }
+        let config = crate::email::Config {
+            domain: domain.clone(),
+            users,
+            relay: self.smtp_relay.clone(),
+        };
+        Ok(Some((address, config)))
+    }
+
+    /// The expiry reminders, if --expiry-reminder-hours is given.
+    pub fn expiry_reminders(&self) -> anyhow::Result<Option<crate::reminders::Config>> {
+        let Some(hours) = self.expiry_reminder_hours else {
+            return Ok(None);
+        };
+        let Some(relay) = &self.smtp_relay else {
+            anyhow::bail!("--expiry-reminder-hours needs --smtp-relay");
+        };
+        let Some(base_url) = &self.base_url else {
+            anyhow::bail!("--expiry-reminder-hours needs --base-url, for the links to pastes");
+        };
+        let domain = match &self.smtp_domain {
+            Some(domain) => domain.clone(),
+            None => match reqwest::Url::parse(base_url)?.host_str() {
+                Some(host) => host.to_owned(),
+                None => anyhow::bail!("--base-url {base_url:?} has no host to send email from"),
+            },
+        };
+        Ok(Some(crate::reminders::Config {
+            before: Duration::from_secs(hours.saturating_mul(60 * 60)),
+            relay: relay.clone(),
+            domain,
+            base_url: base_url.trim_end_matches('/').to_owned(),
+        }))
+    }
+
+    /// The paste cache, unless disabled with a --cache-size of 0.
+    pub fn cache(&self) -> Option<crate::cache::Cache> {
+        (self.cache_size > 0 && self.cache_entries > 0)
+            .then(|| crate::cache::Cache::new(self.cache_entries, self.cache_size))
+    }
+
+    pub fn gist(&self) -> Option<crate::gist::Config> {
+        Some(crate::gist::Config {
+            token: self.github_token.clone()?,
+            api_url: self.github_api_url.trim_end_matches('/').to_owned(),
+        })
+    }
+
+    /// The chat notifiers, if any are configured.
+    pub fn notify(&self) -> anyhow::Result<Option<crate::notify::Config>> {
+        use crate::notify::Target;
+
+        let mut targets: Vec<Target> = (self.notify_slack.iter().cloned().map(Target::Slack))
+            .chain(self.notify_discord.iter().cloned().map(Target::Discord))
+            .collect();
+        if !self.notify_matrix.is_empty() {
+            let Some(token) = &self.matrix_token else {
+                anyhow::bail!("--notify-matrix requires --matrix-token");
+            };
+            targets.extend(self.notify_matrix.iter().map(|room| Target::Matrix {
+                homeserver: self.matrix_homeserver.trim_end_matches('/').to_owned(),
+                token: token.clone(),
+                room: room.clone(),
+            }));
+        }
+        if targets.is_empty() {
+            return Ok(None);
+        }
+        let Some(base_url) = &self.base_url else {
+            anyhow::bail!("Notifications require --base-url, for the links to pastes");
+        };
+        Ok(Some(crate::notify::Config {
+            base_url: base_url.trim_end_matches('/').to_owned(),
+            targets,
+            filter: match &self.notify_filter {
+                Some(filter) => crate::notify::Filter::parse(filter)?,
+                None => crate::notify::Filter::default(),
+            },
+        }))
+    }
+
+    pub fn access_log(&self) -> Option<crate::access_log::Config> {
+        Some(crate::access_log::Config {
+            format: self.access_log?,
+        })
+    }
+
+    pub fn create_rate(&self) -> Option<crate::rate_limit::Rate> {
+        let per_minute = self.create_rate?;
+        Some(crate::rate_limit::Rate {
+            per_minute,
+            burst: self.create_burst.unwrap_or(per_minute),
+        })
+    }
+
+    pub fn read_rate(&self) -> Option<crate::rate_limit::Rate> {
+        let per_minute = self.read_rate?;
+        Some(crate::rate_limit::Rate {
+            per_minute,
+            burst: self.read_burst.unwrap_or(per_minute),
+        })
+    }
+
+    pub fn user_rate(&self) -> Option<crate::rate_limit::Rate> {
+        let per_minute = self.user_rate?;
+        Some(crate::rate_limit::Rate {
+            per_minute,
+            burst: self.user_burst.unwrap_or(per_minute),
+        })
+    }
+
+    pub fn user_create_rate(&self) -> Option<crate::rate_limit::Rate> {
+        let per_minute = self.user_create_rate?;
+        Some(crate::rate_limit::Rate {
+            per_minute,
+            burst: self.user_create_burst.unwrap_or(per_minute),
+        })
+    }
+
+    pub fn sync_interval(&self) -> Duration {
+        Duration::from_millis(self.sync_interval.max(1))
+    }
+
+    pub fn state_save_interval(&self) -> Duration {
+        Duration::from_secs(self.state_save_interval.max(1))
+    }
+
+    pub fn shutdown_timeout(&self) -> Duration {
+        Duration::from_secs(self.shutdown_timeout)
+    }
+
+    pub fn buffers(&self) -> crate::buffers::Pool {
+        crate::buffers::Pool::new(self.buffer_size, self.idle_buffers)
+    }
+
+    pub fn size_limits(&self) -> crate::service::SizeLimits {
+        crate::service::SizeLimits {
+            anonymous: self.max_anonymous_size,
+            user: self.max_user_size,
+        }
+    }
+
+    pub fn timeouts(&self) -> Option<crate::timeout::Config> {
+        if self.read_timeout.is_none()
+            && self.upload_timeout.is_none()
+            && self.min_upload_rate.is_none()
+        {
+            return None;
+        }
+        Some(crate::timeout::Config {
+            read: self.read_timeout.map(Duration::from_secs),
+            upload: self.upload_timeout.map(Duration::from_secs),
+            min_upload_rate: self.min_upload_rate,
+        })
+    }
+
+    /// The CORS policy, if any origins are allowed.
+    pub fn cors(&self) -> anyhow::Result<Option<CorsLayer>> {
+        if self.cors_origin.is_empty() {
+            return Ok(None);
+        }
+        let invalid = |option: &str, value: &str| anyhow::anyhow!("Invalid {option} {value:?}");
+        let origins = if self.cors_origin.iter().any(|origin| origin == "*") {
+            AllowOrigin::any()
+        } else {
+            let origins = self.cors_origin.iter().map(|origin| {
+                HeaderValue::from_str(origin).map_err(|_| invalid("--cors-origin", origin))
+            });
+            AllowOrigin::list(origins.collect::<Result<Vec<_>, _>>()?)
+        };
+        let methods = self.cors_methods.iter().map(|method| {
+            Method::from_str(&method.to_uppercase()).map_err(|_| invalid("--cors-methods", method))
+        });
+        let headers = if self.cors_headers.iter().any(|header| header == "*") {
+            AllowHeaders::any()
+        } else {
+            let headers = self.cors_headers.iter().map(|header| {
+                HeaderName::from_str(header).map_err(|_| invalid("--cors-headers", header))
+            });
+            AllowHeaders::list(headers.collect::<Result<Vec<_>, _>>()?)
+        };
+        Ok(Some(
+            CorsLayer::new()
+                .allow_origin(origins)
+                .allow_methods(methods.collect::<Result<Vec<_>, _>>()?)
+                .allow_headers(headers)
+                .expose_headers([
+                    crate::request_id::HEADER,
+                    header::RETRY_AFTER,
+                    HeaderName::from_static("x-ratelimit-limit"),
+                    HeaderName::from_static("x-ratelimit-remaining"),
+                    HeaderName::from_static("x-ratelimit-reset"),
+                ]),
+        ))
+    }
+
+    pub fn client(&self) -> anyhow::Result<crate::client::Config> {
+        Ok(crate::client::Config {
+            proxies: crate::client::Proxies::new(self.trust_proxy, &self.trusted_proxy)?,
+            https: self.tls_cert.is_some(),
+            path_prefix: self.path_prefix(),
+            base_url: self
+                .base_url
+                .as_ref()
+                .map(|url| url.trim_end_matches('/').to_owned()),
+        })
+    }
+
+    /// --path-prefix with a leading and without a trailing slash, or empty.
+    pub fn path_prefix(&self) -> String {
+        let prefix = self.path_prefix.as_deref().unwrap_or_default();
+        match prefix.trim_matches('/') {
+            "" => String::new(),
+            prefix => format!("/{prefix}"),
+        }
+    }
+
+    /// The contents of `/robots.txt`.
+    pub fn robots_txt(&self) -> anyhow::Result<String> {
+        match &self.robots_txt {
+            Some(path) => std::fs::read_to_string(path)
+                .map_err(|e| anyhow::anyhow!("Couldn't read {}: {e}", path.display())),
+            None => {
+                let prefix = self.path_prefix();
+                let mut robots_txt = format!(
+                    "User-agent: *\nDisallow: {prefix}/admin\nDisallow: {prefix}/pastes/\n"
+                );
+                // Crawlers need an absolute URL, which is only known up front
+                // if configured.
+                if let Some(base_url) = &self.base_url {
+                    let base_url = base_url.trim_end_matches('/');
+                    robots_txt.push_str(&format!("Sitemap: {base_url}/sitemap.xml\n"));
+                }
+                Ok(robots_txt)
+            }
+        }
+    }
+
+    pub fn tls_files(&self) -> Option<crate::tls::Files> {
+        Some(crate::tls::Files {
+            cert: self.tls_cert.clone()?,
+            key: self.tls_key.clone()?,
+        })
+    }
+
+    pub fn scrub_schedule(&self) -> Option<crate::scrub::Schedule> {
+        Some(crate::scrub::Schedule {
+            interval: Duration::from_secs(self.scrub_interval?.max(1)),
+            quarantine: self.scrub_quarantine,
+        })
+    }
+}
+
+/// Resolves the address given for `option`, if any, to the first of its
+/// socket addresses.
+fn resolve(option: &str, address: Option<&str>) -> anyhow::Result<Option<SocketAddr>> {
+    let Some(address) = address else {
+        return Ok(None);
+    };
+    address
+        .to_socket_addrs()
+        .map_err(|e| anyhow::anyhow!("Invalid {option} address {address:?}: {e}"))?
+        .next()
+        .map(Some)
+        .ok_or_else(|| anyhow::anyhow!("{option} address {address:?} didn't resolve"))
 }
@@ -0,0 +1,230 @@
+use std::net::{IpAddr, SocketAddr};
+
+use axum::{
+    extract::ConnectInfo,
+    http::{HeaderMap, header, request::Parts},
+};
+
+/// What is needed to tell who sent a request, and under which URL they
+/// reached the instance.
+#[derive(Debug, Default)]
+pub struct Config {
+    pub proxies: Proxies,
+    /// Whether the server itself is serving HTTPS.
+    pub https: bool,
+    /// Path the routes are mounted under, e.g. `/paste`, or empty.
+    pub path_prefix: String,
+    /// The public URL of the instance, if configured, without a trailing
+    /// slash.
+    pub base_url: Option<String>,
+}
+
+/// Peers whose `X-Forwarded-*` and `Forwarded` headers are believed.
+#[derive(Debug, Default)]
+pub struct Proxies {
+    any: bool,
+    networks: Vec<Network>,
+}
+
+#[derive(Debug, PartialEq)]
+struct Network {
+    address: IpAddr,
+    prefix: u32,
+}
+
+impl Network {
+    fn parse(value: &str) -> anyhow::Result<Self> {
+        let invalid = || anyhow::anyhow!("Invalid proxy address {value:?}");
+        let (address, prefix) = match value.split_once('/') {
+            Some((address, prefix)) => (address, Some(prefix)),
+            None => (value, None),
+        };
+        let address: IpAddr = address.parse().map_err(|_| invalid())?;
+        let bits = if address.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(prefix) => prefix
+                .parse()
+                .ok()
+                .filter(|&p| p <= bits)
+                .ok_or_else(invalid)?,
+            None => bits,
+        };
+        Ok(Self { address, prefix })
+    }
+
+    fn contains(&self, ip: IpAddr) -> bool {
+        match (self.address, ip.to_canonical()) {
+            (IpAddr::V4(network), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix).unwrap_or(0);
+                u32::from(network) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(network), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix).unwrap_or(0);
+                u128::from(network) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+impl Proxies {
+    /// Trusts every peer if `any` is set, otherwise the given addresses and
+    /// networks such as `10.0.0.0/8`.
+    pub fn new(any: bool, trusted: &[String]) -> anyhow::Result<Self> {
+        let networks = trusted
+            .iter()
+            .map(|network| Network::parse(network))
+            .collect::<anyhow::Result<_>>()?;
+        Ok(Self { any, networks })
+    }
+
+    /// Whether headers from `peer` are believed. Connections over a Unix
+    /// socket come from a local proxy, if one is configured at all.
+    fn trusts(&self, peer: Option<IpAddr>) -> bool {
+        match peer {
+            _ if self.any => true,
+            Some(ip) => self.networks.iter().any(|network| network.contains(ip)),
+            None => !self.networks.is_empty(),
+        }
+    }
+}
+
+fn peer(request: &Parts) -> Option<IpAddr> {
+    request
+        .extensions
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(addr)| addr.ip())
+}
+
+/// The values of `key` in the elements of the `Forwarded` headers, in
+/// order.
+fn forwarded(headers: &HeaderMap, key: &str) -> Vec<String> {
+    headers
+        .get_all(header::FORWARDED)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .filter_map(|element| {
+            element.split(';').find_map(|pair| {
+                let (name, value) = pair.split_once('=')?;
+                name.trim()
+                    .eq_ignore_ascii_case(key)
+                    .then(|| value.trim().trim_matches('"').to_owned())
+            })
+        })
+        .collect()
+}
+
+/// Parses a node of `X-Forwarded-For` or a `for=` of `Forwarded`, which
+/// may carry a port, e.g. `[2001:db8::1]:4711`.
+fn parse_node(node: &str) -> Option<IpAddr> {
+    let node = node.trim();
+    if let Ok(ip) = node.parse() {
+        return Some(ip);
+    }
+    if let Some(rest) = node.strip_prefix('[') {
+        return rest.split_once(']')?.0.parse().ok();
+    }
+    node.rsplit_once(':')?.0.parse().ok()
+}
+
+fn header<'a>(request: &'a Parts, name: &str) -> Option<&'a str> {
+    request.headers.get(name)?.to_str().ok()
+}
+
+/// The address of the client that sent a request. Behind trusted proxies it
+/// is the last address in `X-Forwarded-For` (or `Forwarded`) that isn't a
+/// trusted proxy itself, otherwise the peer of the connection. Unknown for
+/// Unix socket connections without proxy headers.
+pub fn address(request: &Parts, proxies: &Proxies) -> Option<IpAddr> {
+    let peer = peer(request);
+    if !proxies.trusts(peer) {
+        return peer;
+    }
+    let mut chain: Vec<IpAddr> = match header(request, "x-forwarded-for") {
+        Some(value) => value.split(',').filter_map(parse_node).collect(),
+        None => forwarded(&request.headers, "for")
+            .iter()
+            .filter_map(|node| parse_node(node))
+            .collect(),
+    };
+    let first = chain.first().copied();
+    while let Some(ip) = chain.pop() {
+        if !proxies.trusts(Some(ip)) {
+            return Some(ip);
+        }
+    }
+    first.or(peer)
+}
+
+/// The URL the client reaches the instance under, e.g.
+/// `https://example.com/paste`. Unless configured, it is made up of the
+/// scheme and host a trusted proxy received the request under.
+pub fn base_url(request: &Parts, config: &Config) -> Option<String> {
+    if let Some(base_url) = &config.base_url {
+        return Some(base_url.clone());
+    }
+    let (mut scheme, mut host) = (None, None);
+    if config.proxies.trusts(peer(request)) {
+        scheme = header(request, "x-forwarded-proto")
+            .map(str::to_owned)
+            .or_else(|| forwarded(&request.headers, "proto").pop());
+        host = header(request, "x-forwarded-host")
+            .map(str::to_owned)
+            .or_else(|| forwarded(&request.headers, "host").pop());
+    }
+    let host = host
+        .or_else(|| header(request, header::HOST.as_str()).map(str::to_owned))
+        .or_else(|| request.uri.authority().map(ToString::to_string))?;
+    let scheme = scheme.unwrap_or_else(|| if config.https { "https" } else { "http" }.to_owned());
+    Some(format!("{scheme}://{host}{}", config.path_prefix))
+}
+
+#[test]
+fn test_address_skips_trusted_proxies() {
+    let request = |peer: [u8; 4], headers: &[(&str, &str)]| {
+        let mut builder = axum::http::Request::builder();
+        for (name, value) in headers {
+            builder = builder.header(*name, *value);
+        }
+        let (mut parts, ()) = builder.body(()).unwrap().into_parts();
+        parts
+            .extensions
+            .insert(ConnectInfo(SocketAddr::from((peer, 1234))));
+        parts
+    };
+    let proxies = Proxies::new(false, &["10.0.0.0/8".to_owned()]).unwrap();
+    let forwarded_for = [("x-forwarded-for", "203.0.113.7, 198.51.100.1, 10.1.2.3")];
+
+    let from_proxy = request([10, 0, 0, 1], &forwarded_for);
+    assert_eq!(
+        address(&from_proxy, &proxies),
+        Some([198, 51, 100, 1].into())
+    );
+    let direct = request([192, 0, 2, 1], &forwarded_for);
+    assert_eq!(address(&direct, &proxies), Some([192, 0, 2, 1].into()));
+    let rfc7239 = request(
+        [10, 0, 0, 1],
+        &[("forwarded", "for=\"[2001:db8::1]:4711\";proto=https")],
+    );
+    assert_eq!(address(&rfc7239, &proxies), "2001:db8::1".parse().ok());
+
+    let config = Config {
+        proxies,
+        https: false,
+        path_prefix: "/pastebin".to_owned(),
+        base_url: None,
+    };
+    let with_host = request(
+        [10, 0, 0, 1],
+        &[
+            ("host", "internal:3000"),
+            ("x-forwarded-proto", "https"),
+            ("x-forwarded-host", "paste.example"),
+        ],
+    );
+    assert_eq!(
+        base_url(&with_host, &config).as_deref(),
+        Some("https://paste.example/pastebin")
+    );
+}
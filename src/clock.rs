@@ -0,0 +1,125 @@
+//! Where the service gets the time from, so that tests can move it along
+//! instead of waiting for pastes to expire.
+
+use std::{
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use parking_lot::Mutex;
+
+pub trait Clock: Send + Sync {
+    fn now(&self) -> SystemTime;
+}
+
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+}
+
+/// A clock that only moves when told to. Clones share the time, so a test
+/// can keep one to advance after handing another to the service.
+#[derive(Clone)]
+pub struct ManualClock(Arc<Mutex<SystemTime>>);
+
+impl ManualClock {
+    pub fn new(now: SystemTime) -> Self {
+        Self(Arc::new(Mutex::new(now)))
+    }
+
+    pub fn advance(&self, by: Duration) {
+        *self.0.lock() += by;
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> SystemTime {
+        *self.0.lock()
+    }
+}
+
+#[tokio::test]
+async fn test_pastes_expire_by_the_service_clock() {
+    use crate::{ids::SequentialIds, service::Service, testing::TempDir};
+
+    let dir = TempDir::new().unwrap();
+    let clock = ManualClock::new(SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000));
+    let service = Service::new(dir.path().to_owned(), Default::default())
+        .unwrap()
+        .with_clock(Arc::new(clock.clone()))
+        .with_ids(Arc::new(SequentialIds::default()));
+    let options = crate::service::Options {
+        expires_in: Some(60),
+        ..Default::default()
+    };
+    let id = service
+        .create_with_options(&b"hello"[..], None, options)
+        .await
+        .unwrap();
+    assert_eq!(id, uuid::Uuid::from_u128(1).to_string());
+
+    clock.advance(Duration::from_secs(59));
+    assert_eq!(service.purge_expired(clock.now()).await.unwrap(), 0);
+    clock.advance(Duration::from_secs(1));
+    assert_eq!(service.purge_expired(clock.now()).await.unwrap(), 1);
+}
+
+#[tokio::test]
+async fn test_events_and_metrics_use_the_service_clock() {
+    use crate::{service::Service, testing::TempDir};
+
+    let dir = TempDir::new().unwrap();
+    let clock = ManualClock::new(SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000));
+    let service = Service::new(dir.path().to_owned(), Default::default())
+        .unwrap()
+        .with_clock(Arc::new(clock.clone()));
+    let mut events = service.events().subscribe("test");
+    service.create(&b"hello"[..], None).await.unwrap();
+    assert_eq!(events.recv().await.unwrap().at, clock.now());
+    assert_eq!(service.metrics().created_recently(clock.now()), 1);
+    clock.advance(Duration::from_secs(24 * 60 * 60));
+    assert_eq!(service.metrics().created_recently(clock.now()), 0);
+}
+
+#[tokio::test]
+async fn test_upload_sessions_are_named_and_swept_by_the_service() {
+    use crate::{chunked, ids::SequentialIds, testing::TestServer};
+
+    let clock = ManualClock::new(SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000));
+    let server = TestServer::start_with(|builder| {
+        builder
+            .clock(Arc::new(clock.clone()))
+            .ids(Arc::new(SequentialIds::default()))
+    })
+    .await
+    .unwrap();
+    let response = server
+        .client()
+        .post(server.url("/upload-sessions"))
+        .send()
+        .await
+        .unwrap();
+    let session = response.text().await.unwrap();
+    assert!(
+        session
+            .trim()
+            .ends_with(&uuid::Uuid::from_u128(1).to_string())
+    );
+    let response = server
+        .client()
+        .put(format!("{}/chunks/0", session.trim()))
+        .body("hello")
+        .send()
+        .await
+        .unwrap();
+    assert!(response.status().is_success());
+
+    let data_dir = server.data_dir();
+    clock.advance(Duration::from_secs(23 * 60 * 60));
+    assert_eq!(chunked::sweep(data_dir, clock.now()).await.unwrap(), 0);
+    clock.advance(Duration::from_secs(2 * 60 * 60));
+    assert_eq!(chunked::sweep(data_dir, clock.now()).await.unwrap(), 1);
+}
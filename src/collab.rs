@@ -0,0 +1,455 @@
+//! `/paste/{id}/collab`, a WebSocket over which several clients edit a paste
+//! at once.
+//!
+//! The server orders the edits: each client sends [`ot`] operations along
+//! with the revision they're based on, which are transformed against the
+//! edits it hadn't seen yet before being applied and passed on to the other
+//! clients. This is the protocol of ot.js, whose client can be used as is.
+//!
+//! Messages from the server are JSON objects whose `type` is
+//! - `init`, with the `revision` and `text` to start from, sent first,
+//! - `ack`, with the `revision` a client's operation was applied as,
+//! - `op`, with another client's `ops` and the `revision` they made,
+//! - `error`, with a `message`, after which the connection is closed, as it
+//!   is on shutdown.
+//!
+//! Clients send `{"revision": ..., "ops": [...]}`. Edits are written to the
+//! paste with [`Service::replace`], as the user who started the session, every
+//! few seconds and once the last client leaves; replacing the paste otherwise
+//! meanwhile is overwritten.
+
+use std::{
+    collections::HashMap,
+    sync::{
+        Arc, Weak,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::Duration,
+};
+
+use axum::{
+    extract::{Path, Request, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use tokio::{io::AsyncReadExt, sync::broadcast};
+use tokio_util::sync::CancellationToken;
+use uuid::Uuid;
+
+use crate::{
+    auth::{self, BasicAuth},
+    ot::{self, Operation},
+    service::Service,
+    ws::{self, WebSocket},
+};
+
+/// Largest paste that can be edited, in bytes.
+const MAX_SIZE: usize = 1024 * 1024;
+
+/// Operations kept to transform late edits against. A client further behind
+/// than that has to reconnect.
+const HISTORY: usize = 1000;
+
+/// How often edits are written to the paste.
+const SAVE_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Operations buffered for a client that doesn't keep up.
+const BACKLOG: usize = 256;
+
+/// The sessions of the pastes being edited.
+#[derive(Default)]
+pub struct Sessions {
+    sessions: Mutex<HashMap<Uuid, Arc<Session>>>,
+    clients: AtomicU64,
+}
+
+struct Session {
+    document: Mutex<Document>,
+    updates: broadcast::Sender<Arc<Update>>,
+    /// Clients connected to the session.
+    clients: Mutex<usize>,
+    /// The credentials edits are saved with: the owner's, for owned pastes.
+    auth: Option<(String, String)>,
+}
+
+struct Document {
+    text: String,
+    /// The revision of the oldest operation in `history`.
+    first_revision: u64,
+    history: Vec<Operation>,
+    /// Whether there are edits not written to the paste yet.
+    dirty: bool,
+}
+
+impl Document {
+    fn revision(&self) -> u64 {
+        self.first_revision + self.history.len() as u64
+    }
+}
+
+#[derive(Debug)]
+struct Update {
+    revision: u64,
+    ops: Operation,
+    client: u64,
+}
+
+#[derive(Deserialize)]
+struct Edit {
+    revision: u64,
+    ops: Operation,
+}
+
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum Message<'a> {
+    Init { revision: u64, text: &'a str },
+    Ack { revision: u64 },
+    Op { revision: u64, ops: &'a Operation },
+    Error { message: String },
+}
+
+impl Message<'_> {
+    fn send(&self, socket: &WebSocket) {
+        socket.send(&serde_json::to_string(self).expect("Messages serialize"));
+    }
+}
+
+impl Session {
+    /// Transforms `edit` from `client` against the operations since its
+    /// revision and applies it.
+    fn apply(&self, client: u64, edit: Edit) -> anyhow::Result<()> {
+        let mut document = self.document.lock();
+        if edit.revision > document.revision() {
+            anyhow::bail!("Unknown revision {}", edit.revision);
+        }
+        let Some(start) = edit.revision.checked_sub(document.first_revision) else {
+            anyhow::bail!("Revision {} is too old, reconnect", edit.revision);
+        };
+        let mut ops = edit.ops;
+        for concurrent in &document.history[start as usize..] {
+            ops = ot::transform(&ops, concurrent)?.0;
+        }
+        let text = ops.apply(&document.text)?;
+        if text.len() > MAX_SIZE {
+            anyhow::bail!("Pastes can't grow beyond {MAX_SIZE} bytes here");
+        }
+        document.text = text;
+        document.history.push(ops.clone());
+        if document.history.len() > HISTORY {
+            document.history.remove(0);
+            document.first_revision += 1;
+        }
+        document.dirty = true;
+        // Sending under the lock keeps updates in order.
+        let update = Update {
+            revision: document.revision(),
+            ops,
+            client,
+        };
+        self.updates.send(Arc::new(update)).ok();
+        Ok(())
+    }
+
+    /// Writes the edits to the paste, if there are any.
+    async fn save(&self, service: &Service, id: &Uuid) {
+        let text = {
+            let mut document = self.document.lock();
+            if !std::mem::take(&mut document.dirty) {
+                return;
+            }
+            document.text.clone()
+        };
+        if let Err(e) = service
+            .replace(id, text.as_bytes(), self.auth.clone())
+            .await
+        {
+            tracing::error!("Saving edits to paste {id}: {e}");
+        }
+    }
+}
+
+impl Sessions {
+    /// The session editing paste `id`, started with `auth` if there's none.
+    /// Callers check that `auth` may edit the paste.
+    async fn join(
+        &self,
+        service: &Arc<Service>,
+        id: Uuid,
+        auth: Option<(String, String)>,
+    ) -> Result<Arc<Session>, Response> {
+        if let Some(session) = self.sessions.lock().get(&id) {
+            *session.clients.lock() += 1;
+            return Ok(session.clone());
+        }
+        let text = load(service, &id).await?;
+        let mut sessions = self.sessions.lock();
+        let session = sessions.entry(id).or_insert_with(|| {
+            let session = Arc::new(Session {
+                document: Mutex::new(Document {
+                    text,
+                    first_revision: 0,
+                    history: Vec::new(),
+                    dirty: false,
+                }),
+                updates: broadcast::channel(BACKLOG).0,
+                clients: Mutex::new(0),
+                auth,
+            });
+            tokio::spawn(save_periodically(
+                Arc::downgrade(&session),
+                service.clone(),
+                id,
+            ));
+            session
+        });
+        *session.clients.lock() += 1;
+        Ok(session.clone())
+    }
+
+    /// Drops a client from the session of paste `id`, ending the session
+    /// with the last one.
+    async fn leave(&self, service: &Service, id: Uuid, session: Arc<Session>) {
+        let last = {
+            let mut sessions = self.sessions.lock();
+            let mut clients = session.clients.lock();
+            *clients -= 1;
+            if *clients == 0 {
+                sessions.remove(&id);
+            }
+            *clients == 0
+        };
+        if last {
+            session.save(service, &id).await;
+        }
+    }
+}
+
+async fn load(service: &Service, id: &Uuid) -> Result<String, Response> {
+    let internal =
+        |e: anyhow::Error| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response();
+    let reader = service.read(id).await.map_err(internal)?;
+    let mut contents = Vec::new();
+    reader
+        .take(MAX_SIZE as u64 + 1)
+        .read_to_end(&mut contents)
+        .await
+        .map_err(|e| internal(e.into()))?;
+    if contents.len() > MAX_SIZE {
+        return Err((
+            StatusCode::PAYLOAD_TOO_LARGE,
+            format!("Only pastes of up to {MAX_SIZE} bytes can be edited"),
+        )
+            .into_response());
+    }
+    String::from_utf8(contents).map_err(|_| {
+        (
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "Only UTF-8 pastes can be edited",
+        )
+            .into_response()
+    })
+}
+
+async fn save_periodically(session: Weak<Session>, service: Arc<Service>, id: Uuid) {
+    let mut interval = tokio::time::interval(SAVE_INTERVAL);
+    loop {
+        interval.tick().await;
+        let Some(session) = session.upgrade() else {
+            break;
+        };
+        session.save(&service, &id).await;
+    }
+}
+
+/// `GET /paste/{id}/collab`
+///
+/// Owned pastes can only be edited with their owner's credentials.
+pub async fn get(
+    State(service): State<Arc<Service>>,
+    State(sessions): State<Arc<Sessions>>,
+    State(shutdown): State<CancellationToken>,
+    Path(id): Path<Uuid>,
+    auth: Option<BasicAuth>,
+    mut request: Request,
+) -> Response {
+    if !service.exists(&id) {
+        return (StatusCode::NOT_FOUND, "Paste not found").into_response();
+    }
+    match &auth {
+        Some(auth) => {
+            if !service.authenticate(&auth.username, &auth.password) {
+                return auth::unauthorized();
+            }
+            if !service.owns(&auth.username, &id) {
+                return (StatusCode::NOT_FOUND, "Paste not found").into_response();
+            }
+        }
+        None if service.owner(&id).is_some() => return auth::unauthorized(),
+        None => {}
+    }
+    let session = match sessions.join(&service, id, auth.map(Into::into)).await {
+        Ok(session) => session,
+        Err(response) => return response,
+    };
+    let client = sessions.clients.fetch_add(1, Ordering::Relaxed);
+    let joined = session.clone();
+    let response = ws::upgrade(&mut request, {
+        let (service, sessions) = (service.clone(), sessions.clone());
+        move |socket| async move {
+            // Edited in a task of its own so that the client leaves even if
+            // editing panics.
+            let editing = tokio::spawn({
+                let session = session.clone();
+                async move { edit(socket, &session, client, &shutdown).await }
+            });
+            if let Err(e) = editing.await {
+                tracing::error!("Editing paste {id}: {e}");
+            }
+            sessions.leave(&service, id, session).await;
+        }
+    });
+    if !response.status().is_informational() {
+        sessions.leave(&service, id, joined).await;
+    }
+    response
+}
+
+/// Relays the edits of `client` and of the others until it disconnects or
+/// the server shuts down.
+async fn edit(mut socket: WebSocket, session: &Session, client: u64, shutdown: &CancellationToken) {
+    let mut updates = {
+        let document = session.document.lock();
+        Message::Init {
+            revision: document.revision(),
+            text: &document.text,
+        }
+        .send(&socket);
+        // Subscribed under the lock so that no update is missed or repeated.
+        session.updates.subscribe()
+    };
+    loop {
+        tokio::select! {
+            message = socket.recv() => {
+                let Some(message) = message else { break };
+                let result = serde_json::from_str(&message)
+                    .map_err(anyhow::Error::from)
+                    .and_then(|edit| session.apply(client, edit));
+                if let Err(e) = result {
+                    Message::Error { message: e.to_string() }.send(&socket);
+                    socket.close();
+                    break;
+                }
+            }
+            update = updates.recv() => match update {
+                Ok(update) if update.client == client => {
+                    Message::Ack { revision: update.revision }.send(&socket);
+                }
+                Ok(update) => Message::Op {
+                    revision: update.revision,
+                    ops: &update.ops,
+                }
+                .send(&socket),
+                Err(e) => {
+                    Message::Error { message: format!("Fell behind, reconnect: {e}") }
+                        .send(&socket);
+                    socket.close();
+                    break;
+                }
+            },
+            () = shutdown.cancelled() => {
+                Message::Error { message: "The server is shutting down".to_owned() }
+                    .send(&socket);
+                socket.close();
+                break;
+            }
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_concurrent_edits_are_transformed_and_saved() {
+    let dir = crate::testing::TempDir::new().unwrap();
+    let (service, _) = crate::PastebinBuilder::new(dir.path()).build().unwrap();
+    let id = service.create(&b"Hello, world"[..], None).await.unwrap();
+    let id = Uuid::parse_str(&id).unwrap();
+    let sessions = Sessions::default();
+    let session = sessions.join(&service, id, None).await.unwrap();
+    let other = sessions.join(&service, id, None).await.unwrap();
+    assert!(Arc::ptr_eq(&session, &other));
+    let mut updates = session.updates.subscribe();
+
+    let edit = |revision, ops: &str| Edit {
+        revision,
+        ops: serde_json::from_str(ops).unwrap(),
+    };
+    session.apply(1, edit(0, r#"[5, " there", 7]"#)).unwrap();
+    // Made without seeing the first edit, so it's moved past it.
+    session.apply(2, edit(0, r#"[12, "!"]"#)).unwrap();
+    assert_eq!(session.document.lock().text, "Hello there, world!");
+    let update = updates.recv().await.unwrap();
+    assert_eq!((update.client, update.revision), (1, 1));
+    let update = updates.recv().await.unwrap();
+    assert_eq!((update.client, update.revision), (2, 2));
+    assert!(session.apply(1, edit(3, "[19]")).is_err());
+
+    sessions.leave(&service, id, other).await;
+    assert_eq!(sessions.sessions.lock().len(), 1);
+    sessions.leave(&service, id, session).await;
+    assert!(sessions.sessions.lock().is_empty());
+    let mut saved = String::new();
+    let mut reader = service.read(&id).await.unwrap();
+    reader.read_to_string(&mut saved).await.unwrap();
+    assert_eq!(saved, "Hello there, world!");
+}
+
+#[tokio::test]
+async fn test_owned_pastes_are_edited_as_their_owner() {
+    let server = crate::testing::TestServer::start_with(|builder| builder.user("alice", "secret"))
+        .await
+        .unwrap();
+    let url = server
+        .client()
+        .post(server.url("/paste"))
+        .basic_auth("alice", Some("secret"))
+        .body("mine")
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    let collab = || server.client().get(format!("{}/collab", url.trim()));
+    let response = collab().send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    let response = collab()
+        .basic_auth("alice", Some("wrong"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+    // Edits are saved with the credentials the session was joined with.
+    let dir = crate::testing::TempDir::new().unwrap();
+    let (service, _) = crate::PastebinBuilder::new(dir.path())
+        .user("alice", "secret")
+        .build()
+        .unwrap();
+    let auth = Some(("alice".to_owned(), "secret".to_owned()));
+    let id = service.create(&b"mine"[..], auth.clone()).await.unwrap();
+    let id = Uuid::parse_str(&id).unwrap();
+    let sessions = Sessions::default();
+    let session = sessions.join(&service, id, auth).await.unwrap();
+    let edit = Edit {
+        revision: 0,
+        ops: serde_json::from_str(r#"[4, "!"]"#).unwrap(),
+    };
+    session.apply(1, edit).unwrap();
+    sessions.leave(&service, id, session).await;
+    let mut saved = String::new();
+    let mut reader = service.read(&id).await.unwrap();
+    reader.read_to_string(&mut saved).await.unwrap();
+    assert_eq!(saved, "mine!");
+}
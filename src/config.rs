@@ -0,0 +1,151 @@
+use std::path::{Path, PathBuf};
+
+use clap::parser::ValueSource;
+use serde::Deserialize;
+
+use crate::cli::Args;
+
+/// Settings read from a `--config` file. Keys are named after the long
+/// command line options, with underscores instead of dashes.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    port: Option<u16>,
+    data_dir: Option<PathBuf>,
+    bind: Option<Vec<String>>,
+    unix_socket: Option<PathBuf>,
+    tcp_upload: Option<String>,
+    gemini: Option<String>,
+    smtp: Option<String>,
+    smtp_domain: Option<String>,
+    email_user: Option<Vec<String>>,
+    smtp_relay: Option<String>,
+    expiry_reminder_hours: Option<u64>,
+    state: Option<PathBuf>,
+    username: Option<String>,
+    password: Option<String>,
+    admin: Option<Vec<String>>,
+    private_stats: Option<bool>,
+    snapshot_dir: Option<PathBuf>,
+    snapshot_interval: Option<u64>,
+    snapshot_keep: Option<usize>,
+    scrub_interval: Option<u64>,
+    scrub_quarantine: Option<bool>,
+    replicate_to: Option<String>,
+    replication_token: Option<String>,
+    webhook: Option<Vec<String>>,
+    github_token: Option<String>,
+    github_api_url: Option<String>,
+    federate_to: Option<Vec<String>>,
+    federation_trust: Option<Vec<String>>,
+    federation_key: Option<PathBuf>,
+    notify_slack: Option<Vec<String>>,
+    notify_discord: Option<Vec<String>>,
+    notify_matrix: Option<Vec<String>>,
+    matrix_homeserver: Option<String>,
+    matrix_token: Option<String>,
+    notify_filter: Option<String>,
+    exec_hook: Option<Vec<String>>,
+    webhook_secret: Option<String>,
+    storage_budget: Option<u64>,
+    keep_versions: Option<usize>,
+    max_anonymous_size: Option<u64>,
+    max_user_size: Option<u64>,
+    buffer_size: Option<usize>,
+    idle_buffers: Option<usize>,
+    durability: Option<crate::durability::Policy>,
+    sync_interval: Option<u64>,
+    io_uring: Option<bool>,
+    cache_size: Option<usize>,
+    cache_entries: Option<usize>,
+    anonymous_retention_days: Option<u64>,
+    gc_interval: Option<u64>,
+    state_save_interval: Option<u64>,
+    shutdown_timeout: Option<u64>,
+    log_level: Option<String>,
+    log_format: Option<crate::logging::Format>,
+    access_log: Option<crate::access_log::Format>,
+    base_url: Option<String>,
+    path_prefix: Option<String>,
+    robots_txt: Option<PathBuf>,
+    trusted_proxy: Option<Vec<String>>,
+    trust_proxy: Option<bool>,
+    create_rate: Option<u32>,
+    create_burst: Option<u32>,
+    read_rate: Option<u32>,
+    read_burst: Option<u32>,
+    user_rate: Option<u32>,
+    user_burst: Option<u32>,
+    user_create_rate: Option<u32>,
+    user_create_burst: Option<u32>,
+    read_timeout: Option<u64>,
+    upload_timeout: Option<u64>,
+    min_upload_rate: Option<u64>,
+    max_concurrent_requests: Option<usize>,
+    cors_origin: Option<Vec<String>>,
+    cors_methods: Option<Vec<String>>,
+    cors_headers: Option<Vec<String>>,
+    tls_cert: Option<PathBuf>,
+    tls_key: Option<PathBuf>,
+    http3: Option<bool>,
+    remote: Option<String>,
+}
+
+impl Config {
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let contents = std::fs::read_to_string(path)
+            .map_err(|e| anyhow::anyhow!("Couldn't read {}: {e}", path.display()))?;
+        toml::from_str(&contents).map_err(|e| anyhow::anyhow!("Invalid {}: {e}", path.display()))
+    }
+
+    /// Fills in the settings of `args` that weren't given on the command
+    /// line or in the environment, as recorded in `matches`.
+    pub fn apply(self, args: &mut Args, matches: &clap::ArgMatches) {
+        let unset = |id: &str| {
+            !matches!(
+                matches.value_source(id),
+                Some(ValueSource::CommandLine | ValueSource::EnvVariable)
+            )
+        };
+        macro_rules! apply {
+            ($($field:ident),* ; $($optional:ident),*) => {
+                $(if let Some(value) = self.$field && unset(stringify!($field)) {
+                    args.$field = value;
+                })*
+                $(if let Some(value) = self.$optional && unset(stringify!($optional)) {
+                    args.$optional = Some(value);
+                })*
+            };
+        }
+        apply!(
+            port, bind, data_dir, state, admin, private_stats, snapshot_interval, snapshot_keep,
+            scrub_quarantine, gc_interval, log_level, log_format, trusted_proxy, trust_proxy,
+            cors_origin, cors_methods, cors_headers, webhook, exec_hook, federate_to,
+            federation_trust, federation_key, github_api_url, notify_slack, notify_discord,
+            notify_matrix, matrix_homeserver, email_user, buffer_size, idle_buffers, durability,
+            sync_interval, io_uring, cache_size, cache_entries, state_save_interval,
+            shutdown_timeout, http3, keep_versions;
+            unix_socket, tcp_upload, gemini, github_token, username, password, snapshot_dir,
+            scrub_interval, replicate_to, replication_token, webhook_secret, storage_budget,
+            max_anonymous_size, max_user_size, anonymous_retention_days, access_log, base_url,
+            path_prefix, robots_txt, create_rate, create_burst, read_rate, read_burst, user_rate,
+            user_burst, user_create_rate, user_create_burst, read_timeout, upload_timeout,
+            min_upload_rate, max_concurrent_requests, tls_cert, tls_key, remote, matrix_token,
+            notify_filter, smtp, smtp_domain, smtp_relay, expiry_reminder_hours
+        );
+    }
+}
+
+#[test]
+fn test_command_line_overrides_config() {
+    use clap::{CommandFactory, FromArgMatches};
+
+    let matches = Args::command().get_matches_from(["pastebin", "--port", "4000"]);
+    let mut args = Args::from_arg_matches(&matches).unwrap();
+    let config: Config = toml::from_str("port = 5000\nstorage_budget = 1024\n").unwrap();
+    config.apply(&mut args, &matches);
+
+    assert_eq!(args.port, 4000);
+    assert_eq!(args.storage_budget, Some(1024));
+    assert_eq!(args.gc_interval, 3600);
+}
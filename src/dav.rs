@@ -0,0 +1,484 @@
+//! A WebDAV view of a user's pastes at `/dav/`, so that they can be mounted
+//! as a network drive and edited with desktop tools.
+//!
+//! The pastes are the files of a single collection, named after their
+//! titles, or their IDs for pastes without a title of their own. Writing a
+//! new file creates a paste titled after it, and moving one renames it.
+//! Editors that save by writing a temporary file and moving it over the
+//! original replace the original's contents instead. Locks are granted
+//! without anything being locked, as clients insist on them before writing.
+
+use std::{collections::HashMap, sync::Arc};
+
+use axum::{
+    extract::{Path, Request, State},
+    http::{HeaderMap, StatusCode, header},
+    response::{IntoResponse, Response},
+};
+use uuid::Uuid;
+
+use crate::{
+    auth::{self, BasicAuth},
+    client, html,
+    service::{self, Recent, Service},
+};
+
+const ALLOW: &str = "OPTIONS, GET, HEAD, PUT, DELETE, PROPFIND, PROPPATCH, MOVE, LOCK, UNLOCK";
+
+/// A paste as a file of the collection.
+struct File {
+    name: String,
+    paste: Recent,
+}
+
+pub async fn collection(
+    State(service): State<Arc<Service>>,
+    State(client): State<Arc<client::Config>>,
+    auth: BasicAuth,
+    request: Request,
+) -> Response {
+    handle(&service, &client, auth, None, request).await
+}
+
+pub async fn file(
+    State(service): State<Arc<Service>>,
+    State(client): State<Arc<client::Config>>,
+    auth: BasicAuth,
+    Path(name): Path<String>,
+    request: Request,
+) -> Response {
+    handle(&service, &client, auth, Some(name), request).await
+}
+
+async fn handle(
+    service: &Service,
+    client: &client::Config,
+    auth: BasicAuth,
+    name: Option<String>,
+    request: Request,
+) -> Response {
+    let ids = match service.list(&auth.username, &auth.password) {
+        Ok(ids) => ids,
+        Err(_) => return auth::unauthorized(),
+    };
+    let files = match files(service, &ids).await {
+        Ok(files) => files,
+        Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    };
+    let href = |name: &str| format!("{}/dav/{}", client.path_prefix, html::encode_query(name));
+    let method = request.method().as_str();
+    let Some(name) = name else {
+        return match method {
+            "OPTIONS" => options(),
+            "PROPFIND" => {
+                let mut responses =
+                    vec![collection_response(&format!("{}/dav/", client.path_prefix))];
+                if depth(request.headers()) != "0" {
+                    responses.extend(
+                        files
+                            .iter()
+                            .map(|file| file_response(&href(&file.name), file)),
+                    );
+                }
+                multistatus(responses)
+            }
+            "GET" | "HEAD" => {
+                let names: String = files
+                    .iter()
+                    .map(|file| format!("{}\n", file.name))
+                    .collect();
+                names.into_response()
+            }
+            "PROPPATCH" => proppatch(&format!("{}/dav/", client.path_prefix)),
+            "LOCK" => lock(),
+            "UNLOCK" => StatusCode::NO_CONTENT.into_response(),
+            _ => (StatusCode::METHOD_NOT_ALLOWED, [(header::ALLOW, ALLOW)]).into_response(),
+        };
+    };
+    let file = files.iter().find(|file| file.name == name);
+    match (method, file) {
+        ("OPTIONS", _) => options(),
+        ("PROPFIND", Some(file)) => multistatus(vec![file_response(&href(&name), file)]),
+        ("GET" | "HEAD", Some(file)) => get(service, file).await,
+        ("PUT", _) => put(service, auth, &name, file, request).await,
+        ("DELETE", Some(file)) => {
+            match service
+                .delete(file.paste.id, &auth.username, &auth.password)
+                .await
+            {
+                Ok(()) => StatusCode::NO_CONTENT.into_response(),
+                Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+            }
+        }
+        ("MOVE", Some(file)) => {
+            let Some(destination) = destination(client, request.headers()) else {
+                return (StatusCode::BAD_REQUEST, "Invalid Destination").into_response();
+            };
+            let target = files.iter().find(|file| file.name == destination);
+            let overwrite = request
+                .headers()
+                .get("overwrite")
+                .is_none_or(|value| value != "F");
+            move_file(service, &auth, file, &destination, target, overwrite).await
+        }
+        ("PROPPATCH", Some(_)) => proppatch(&href(&name)),
+        ("LOCK", _) => lock(),
+        ("UNLOCK", _) => StatusCode::NO_CONTENT.into_response(),
+        ("MKCOL", _) => (StatusCode::FORBIDDEN, "Pastes can't be put in folders").into_response(),
+        ("PROPFIND" | "GET" | "HEAD" | "DELETE" | "MOVE" | "PROPPATCH", None) => {
+            StatusCode::NOT_FOUND.into_response()
+        }
+        _ => (StatusCode::METHOD_NOT_ALLOWED, [(header::ALLOW, ALLOW)]).into_response(),
+    }
+}
+
+/// The user's pastes, named after their titles where those are usable as
+/// file names and unique.
+async fn files(service: &Service, ids: &[String]) -> anyhow::Result<Vec<File>> {
+    let mut pastes = Vec::new();
+    for id in ids {
+        if let Some(paste) = service.paste(&Uuid::parse_str(id)?).await? {
+            pastes.push(paste);
+        }
+    }
+    let mut titles = HashMap::new();
+    for paste in &pastes {
+        if let Some(title) = &paste.metadata.title {
+            *titles.entry(title.clone()).or_insert(0) += 1;
+        }
+    }
+    Ok(pastes
+        .into_iter()
+        .map(|paste| {
+            let name = match &paste.metadata.title {
+                Some(title) if titles[title] == 1 && is_file_name(title) => title.clone(),
+                _ => paste.id.to_string(),
+            };
+            File { name, paste }
+        })
+        .collect())
+}
+
+/// Whether `title` can name a file without being taken for another one's ID.
+fn is_file_name(title: &str) -> bool {
+    !title.is_empty()
+        && title != "."
+        && title != ".."
+        && !title.contains('/')
+        && Uuid::parse_str(title).is_err()
+}
+
+/// Files Finder litters shares with, which aren't worth pastes.
+fn is_junk(name: &str) -> bool {
+    name == ".DS_Store" || name.starts_with("._")
+}
+
+fn depth(headers: &HeaderMap) -> &str {
+    headers
+        .get("depth")
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or("infinity")
+}
+
+/// The file name the `Destination` header of a MOVE points to.
+fn destination(client: &client::Config, headers: &HeaderMap) -> Option<String> {
+    let destination = headers.get("destination")?.to_str().ok()?;
+    // Either an absolute URL or an absolute path.
+    let path = match destination.split_once("://") {
+        Some((_, rest)) => &rest[rest.find('/')?..],
+        None => destination,
+    };
+    let name = path
+        .strip_prefix(&client.path_prefix)?
+        .strip_prefix("/dav/")?;
+    let name = html::decode_percent(name)?;
+    is_file_name(&name).then_some(name)
+}
+
+fn options() -> Response {
+    (
+        [
+            (header::ALLOW, ALLOW),
+            (header::HeaderName::from_static("dav"), "1, 2"),
+            // Makes Windows try WebDAV rather than FrontPage extensions.
+            (header::HeaderName::from_static("ms-author-via"), "DAV"),
+        ],
+        (),
+    )
+        .into_response()
+}
+
+async fn get(service: &Service, file: &File) -> Response {
+    let reader = match service.read(&file.paste.id).await {
+        Ok(reader) => reader,
+        Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    };
+    (
+        crate::USER_CONTENT_HEADERS,
+        [
+            (header::CONTENT_TYPE, "text/plain; charset=utf-8".to_owned()),
+            (header::CONTENT_LENGTH, file.paste.size.to_string()),
+            (header::LAST_MODIFIED, http_date(&file.paste)),
+            (header::ETAG, etag(&file.paste)),
+        ],
+        crate::body::stream(service.buffers(), reader),
+    )
+        .into_response()
+}
+
+async fn put(
+    service: &Service,
+    auth: BasicAuth,
+    name: &str,
+    file: Option<&File>,
+    request: Request,
+) -> Response {
+    if is_junk(name) {
+        return (StatusCode::FORBIDDEN, "Not a paste").into_response();
+    }
+    let (parts, body) = request.into_parts();
+    let reader = match crate::upload_reader(&parts.headers, body) {
+        Ok(reader) => reader,
+        Err(e) => return e.into_response(),
+    };
+    if let Some(file) = file {
+        return match service
+            .replace(&file.paste.id, reader, Some(auth.into()))
+            .await
+        {
+            Ok(()) => StatusCode::NO_CONTENT.into_response(),
+            Err(e) => crate::upload_error(e),
+        };
+    }
+    let options = service::Options {
+        title: Some(name.to_owned()),
+        ..Default::default()
+    };
+    if let Err(e) = options.validate() {
+        return (StatusCode::BAD_REQUEST, e.to_string()).into_response();
+    }
+    match service
+        .create_with_options(reader, Some(auth.into()), options)
+        .await
+    {
+        Ok(_) => StatusCode::CREATED.into_response(),
+        Err(e) => crate::upload_error(e),
+    }
+}
+
+/// Renames `file` to `destination`, or moves its contents into `target` if
+/// that already exists.
+async fn move_file(
+    service: &Service,
+    auth: &BasicAuth,
+    file: &File,
+    destination: &str,
+    target: Option<&File>,
+    overwrite: bool,
+) -> Response {
+    let Some(target) = target else {
+        let title = Some(destination.to_owned());
+        if let Err(e) = (service::Options {
+            title: title.clone(),
+            ..Default::default()
+        })
+        .validate()
+        {
+            return (StatusCode::BAD_REQUEST, e.to_string()).into_response();
+        }
+        return match service
+            .retitle(&file.paste.id, title, &auth.username, &auth.password)
+            .await
+        {
+            Ok(()) => StatusCode::CREATED.into_response(),
+            Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+        };
+    };
+    if target.paste.id == file.paste.id {
+        return StatusCode::NO_CONTENT.into_response();
+    }
+    if !overwrite {
+        return StatusCode::PRECONDITION_FAILED.into_response();
+    }
+    let credentials = (auth.username.clone(), auth.password.clone());
+    let result = async {
+        let reader = service.read(&file.paste.id).await?;
+        service
+            .replace(&target.paste.id, reader, Some(credentials))
+            .await?;
+        service
+            .delete(file.paste.id, &auth.username, &auth.password)
+            .await
+    };
+    match result.await {
+        Ok(()) => StatusCode::NO_CONTENT.into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
+/// Grants a lock that locks nothing, which is all clients need to go on.
+fn lock() -> Response {
+    let token = format!("opaquelocktoken:{}", Uuid::new_v4());
+    let body = format!(
+        "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n\
+         <D:prop xmlns:D=\"DAV:\"><D:lockdiscovery><D:activelock>\
+         <D:locktype><D:write/></D:locktype><D:lockscope><D:exclusive/></D:lockscope>\
+         <D:depth>0</D:depth><D:timeout>Second-3600</D:timeout>\
+         <D:locktoken><D:href>{token}</D:href></D:locktoken>\
+         </D:activelock></D:lockdiscovery></D:prop>\n"
+    );
+    (
+        [
+            (
+                header::CONTENT_TYPE,
+                "application/xml; charset=utf-8".to_owned(),
+            ),
+            (
+                header::HeaderName::from_static("lock-token"),
+                format!("<{token}>"),
+            ),
+        ],
+        body,
+    )
+        .into_response()
+}
+
+/// Accepts property changes, e.g. the times Windows sets after writing a
+/// file, without keeping any.
+fn proppatch(href: &str) -> Response {
+    multistatus(vec![format!(
+        "<D:response><D:href>{}</D:href><D:propstat><D:prop/>\
+         <D:status>HTTP/1.1 200 OK</D:status></D:propstat></D:response>",
+        html::escape(href)
+    )])
+}
+
+fn multistatus(responses: Vec<String>) -> Response {
+    let body = format!(
+        "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n\
+         <D:multistatus xmlns:D=\"DAV:\">\n{}</D:multistatus>\n",
+        responses.concat()
+    );
+    (
+        StatusCode::MULTI_STATUS,
+        [(header::CONTENT_TYPE, "application/xml; charset=utf-8")],
+        body,
+    )
+        .into_response()
+}
+
+const SUPPORTED_LOCK: &str = "<D:supportedlock><D:lockentry>\
+    <D:lockscope><D:exclusive/></D:lockscope><D:locktype><D:write/></D:locktype>\
+    </D:lockentry></D:supportedlock>";
+
+fn collection_response(href: &str) -> String {
+    format!(
+        "<D:response><D:href>{}</D:href><D:propstat><D:prop>\
+         <D:displayname>pastes</D:displayname>\
+         <D:resourcetype><D:collection/></D:resourcetype>{SUPPORTED_LOCK}\
+         </D:prop><D:status>HTTP/1.1 200 OK</D:status></D:propstat></D:response>\n",
+        html::escape(href)
+    )
+}
+
+fn file_response(href: &str, file: &File) -> String {
+    format!(
+        "<D:response><D:href>{}</D:href><D:propstat><D:prop>\
+         <D:displayname>{}</D:displayname><D:resourcetype/>\
+         <D:getcontenttype>text/plain; charset=utf-8</D:getcontenttype>\
+         <D:getcontentlength>{}</D:getcontentlength>\
+         <D:getlastmodified>{}</D:getlastmodified>\
+         <D:getetag>{}</D:getetag>{SUPPORTED_LOCK}\
+         </D:prop><D:status>HTTP/1.1 200 OK</D:status></D:propstat></D:response>\n",
+        html::escape(href),
+        html::escape(&file.name),
+        file.paste.size,
+        http_date(&file.paste),
+        html::escape(&etag(&file.paste)),
+    )
+}
+
+fn http_date(paste: &Recent) -> String {
+    chrono::DateTime::<chrono::Utc>::from(paste.written)
+        .format("%a, %d %b %Y %H:%M:%S GMT")
+        .to_string()
+}
+
+/// Changes whenever the contents do, as they're always rewritten whole.
+fn etag(paste: &Recent) -> String {
+    let written = paste
+        .written
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default();
+    format!("\"{:x}-{:x}\"", paste.size, written.as_nanos())
+}
+
+#[tokio::test]
+async fn test_files_are_pastes_named_by_title() {
+    use axum::http::Method;
+
+    let server = crate::testing::TestServer::start_with(|builder| builder.user("alice", "secret"))
+        .await
+        .unwrap();
+    let request = |method: &str, name: &str| {
+        server
+            .client()
+            .request(
+                Method::from_bytes(method.as_bytes()).unwrap(),
+                server.url(&format!("/dav/{name}")),
+            )
+            .basic_auth("alice", Some("secret"))
+    };
+    let status =
+        |request: reqwest::RequestBuilder| async move { request.send().await.unwrap().status() };
+
+    let response = server.client().get(server.url("/dav/")).send().await;
+    assert_eq!(response.unwrap().status(), StatusCode::UNAUTHORIZED);
+    assert_eq!(
+        status(request("PUT", "notes.txt").body("first")).await,
+        StatusCode::CREATED
+    );
+    assert_eq!(
+        status(request("PUT", ".DS_Store").body("junk")).await,
+        StatusCode::FORBIDDEN
+    );
+
+    // Saving through a temporary file replaces the original's contents.
+    assert_eq!(
+        status(request("PUT", "notes.txt.tmp").body("second")).await,
+        StatusCode::CREATED
+    );
+    let replace = request("MOVE", "notes.txt.tmp").header("destination", "/dav/notes.txt");
+    assert_eq!(status(replace).await, StatusCode::NO_CONTENT);
+    let listing = request("GET", "")
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    assert_eq!(listing, "notes.txt\n");
+    let contents = request("GET", "notes.txt").send().await.unwrap();
+    assert_eq!(contents.text().await.unwrap(), "second");
+
+    let rename = request("MOVE", "notes.txt").header("destination", "/dav/todo%20list");
+    assert_eq!(status(rename).await, StatusCode::CREATED);
+    let propfind = request("PROPFIND", "")
+        .header("depth", "1")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(propfind.status(), StatusCode::MULTI_STATUS);
+    let body = propfind.text().await.unwrap();
+    assert!(body.contains("/dav/todo%20list"), "{body}");
+    assert!(!body.contains("notes.txt"), "{body}");
+
+    assert_eq!(
+        status(request("DELETE", "todo list")).await,
+        StatusCode::NO_CONTENT
+    );
+    assert_eq!(
+        status(request("GET", "todo list")).await,
+        StatusCode::NOT_FOUND
+    );
+}
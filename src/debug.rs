@@ -0,0 +1,234 @@
+//! Endpoints under `/debug` for admins to diagnose a running instance, a
+//! stalled one in particular, without restarting it:
+//!
+//! - `/debug/runtime?seconds=N` samples how busy each runtime worker is for
+//!   N seconds. A worker that's busy the whole time without parking is stuck
+//!   in something that doesn't yield.
+//! - `/debug/requests` lists the requests being handled, oldest first,
+//!   which shows what a stall holds up.
+//! - `/debug/alloc` counts allocations, in builds with the `alloc-stats`
+//!   feature, which swaps in a counting allocator.
+//!
+//! There's no CPU profiler among the dependencies, so nothing samples
+//! stacks; the runtime sampling stands in for one.
+
+use std::{
+    collections::HashMap,
+    fmt::Write,
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+use axum::{
+    Router,
+    extract::{Query, Request, State},
+    http::{Method, Uri, header},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    routing::get,
+};
+use parking_lot::Mutex;
+use serde::Deserialize;
+
+use crate::{auth::Admin, request_id::RequestId};
+
+/// Longest runtime sample, so a request can't tie up a connection for long.
+const MAX_SAMPLE: u64 = 30;
+
+pub fn routes() -> Router<crate::AppState> {
+    Router::new()
+        .route("/debug/runtime", get(runtime))
+        .route("/debug/requests", get(requests))
+        .route("/debug/alloc", get(alloc))
+}
+
+fn text(body: String) -> Response {
+    ([(header::CONTENT_TYPE, "text/plain; charset=utf-8")], body).into_response()
+}
+
+#[derive(Deserialize)]
+struct SampleQuery {
+    seconds: Option<u64>,
+}
+
+async fn runtime(_: Admin, Query(query): Query<SampleQuery>) -> Response {
+    let window = Duration::from_secs(query.seconds.unwrap_or(1).clamp(1, MAX_SAMPLE));
+    let metrics = tokio::runtime::Handle::current().metrics();
+    let workers = metrics.num_workers();
+    let sample = |worker| {
+        (
+            metrics.worker_total_busy_duration(worker),
+            metrics.worker_park_count(worker),
+        )
+    };
+    let before: Vec<_> = (0..workers).map(sample).collect();
+    tokio::time::sleep(window).await;
+    let after: Vec<_> = (0..workers).map(sample).collect();
+
+    let mut body = format!(
+        "Workers: {workers}\nAlive tasks: {}\nGlobal queue depth: {}\n\n\
+         Over {window:?}:\nworker   busy  parks\n",
+        metrics.num_alive_tasks(),
+        metrics.global_queue_depth(),
+    );
+    for (worker, ((busy_before, parks_before), (busy_after, parks_after))) in
+        before.into_iter().zip(after).enumerate()
+    {
+        let busy = (busy_after - busy_before).as_secs_f64() / window.as_secs_f64();
+        writeln!(
+            body,
+            "{worker:>6} {:>5.1}% {:>6}",
+            busy * 100.0,
+            parks_after - parks_before
+        )
+        .unwrap();
+    }
+    text(body)
+}
+
+/// The requests being handled, recorded by [`track`].
+#[derive(Default)]
+pub struct InFlight {
+    next: AtomicU64,
+    requests: Mutex<HashMap<u64, Handling>>,
+}
+
+struct Handling {
+    method: Method,
+    uri: Uri,
+    id: Option<RequestId>,
+    started: Instant,
+}
+
+/// Removes a request from [`InFlight`] once handled, or dropped.
+struct Handled<'a>(&'a InFlight, u64);
+
+impl Drop for Handled<'_> {
+    fn drop(&mut self) {
+        self.0.requests.lock().remove(&self.1);
+    }
+}
+
+/// Records each request in [`InFlight`] until its response is ready. The body
+/// may still be streaming after that.
+pub async fn track(
+    State(in_flight): State<Arc<InFlight>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let key = in_flight.next.fetch_add(1, Ordering::Relaxed);
+    let handling = Handling {
+        method: request.method().clone(),
+        uri: request.uri().clone(),
+        id: request.extensions().get::<RequestId>().cloned(),
+        started: Instant::now(),
+    };
+    in_flight.requests.lock().insert(key, handling);
+    let _handled = Handled(&in_flight, key);
+    next.run(request).await
+}
+
+async fn requests(_: Admin, State(in_flight): State<Arc<InFlight>>) -> Response {
+    let now = Instant::now();
+    let mut requests: Vec<_> = in_flight
+        .requests
+        .lock()
+        .values()
+        .map(|handling| {
+            let id = handling.id.as_ref().map_or("-", RequestId::as_str);
+            let age = now.duration_since(handling.started);
+            (age, format!("{} {} {id}", handling.method, handling.uri))
+        })
+        .collect();
+    requests.sort_unstable_by_key(|(age, _)| std::cmp::Reverse(*age));
+    let mut body = String::new();
+    for (age, request) in requests {
+        writeln!(body, "{age:>10.1?} {request}").unwrap();
+    }
+    text(body)
+}
+
+#[cfg(feature = "alloc-stats")]
+async fn alloc(_: Admin) -> Response {
+    let stats = counting::stats();
+    text(format!(
+        "Allocations: {}\nLive bytes: {}\nPeak bytes: {}\n",
+        stats.allocations, stats.live, stats.peak
+    ))
+}
+
+#[cfg(not(feature = "alloc-stats"))]
+async fn alloc(_: Admin) -> Response {
+    (
+        axum::http::StatusCode::NOT_FOUND,
+        "Built without the alloc-stats feature",
+    )
+        .into_response()
+}
+
+/// An allocator counting what goes through it, on top of the system's.
+#[cfg(feature = "alloc-stats")]
+mod counting {
+    use std::{
+        alloc::{GlobalAlloc, Layout, System},
+        sync::atomic::{AtomicU64, AtomicUsize, Ordering},
+    };
+
+    static ALLOCATIONS: AtomicU64 = AtomicU64::new(0);
+    static LIVE: AtomicUsize = AtomicUsize::new(0);
+    static PEAK: AtomicUsize = AtomicUsize::new(0);
+
+    pub struct Stats {
+        pub allocations: u64,
+        pub live: usize,
+        pub peak: usize,
+    }
+
+    pub fn stats() -> Stats {
+        Stats {
+            allocations: ALLOCATIONS.load(Ordering::Relaxed),
+            live: LIVE.load(Ordering::Relaxed),
+            peak: PEAK.load(Ordering::Relaxed),
+        }
+    }
+
+    fn allocated(size: usize) {
+        let live = LIVE.fetch_add(size, Ordering::Relaxed) + size;
+        PEAK.fetch_max(live, Ordering::Relaxed);
+    }
+
+    struct Counting;
+
+    // SAFETY: every call is passed on to `System` as it is.
+    unsafe impl GlobalAlloc for Counting {
+        unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+            let ptr = unsafe { System.alloc(layout) };
+            if !ptr.is_null() {
+                ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+                allocated(layout.size());
+            }
+            ptr
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+            unsafe { System.dealloc(ptr, layout) };
+            LIVE.fetch_sub(layout.size(), Ordering::Relaxed);
+        }
+
+        unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+            let new = unsafe { System.realloc(ptr, layout, new_size) };
+            if !new.is_null() {
+                ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+                LIVE.fetch_sub(layout.size(), Ordering::Relaxed);
+                allocated(new_size);
+            }
+            new
+        }
+    }
+
+    #[global_allocator]
+    static ALLOCATOR: Counting = Counting;
+}
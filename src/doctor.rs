@@ -0,0 +1,324 @@
+use std::{collections::HashMap, fmt, path::Path};
+
+use crate::{
+    blobs,
+    checksum::{self, Integrity},
+    meta,
+    state::State,
+};
+
+/// An inconsistency between the state file and the data directory.
+#[derive(Debug, PartialEq)]
+pub enum Problem {
+    /// A user lists a paste ID that isn't a UUID.
+    InvalidId { user: String, id: String },
+    /// A paste is listed more than once, by the same or another user.
+    Duplicate {
+        user: String,
+        id: String,
+        first_owner: String,
+    },
+    /// A user lists a paste whose file doesn't exist.
+    Missing { user: String, id: String },
+    /// A paste file exists but can't be read.
+    Unreadable { id: String, error: String },
+    /// A paste's contents don't match its stored checksum.
+    ChecksumMismatch { id: String },
+    /// A paste has no stored checksum.
+    MissingChecksum { id: String },
+    /// A checksum file exists for a paste that doesn't.
+    OrphanedChecksum { id: String },
+    /// A metadata file exists for a paste that doesn't.
+    OrphanedMetadata { id: String },
+    /// A paste's metadata names an owner who doesn't list it.
+    Unlisted { user: String, id: String },
+    /// A paste's metadata names an owner who isn't in the state file.
+    UnknownOwner { user: String, id: String },
+    /// A deduplicated paste content no paste links to anymore.
+    UnreferencedBlob { path: String },
+}
+
+impl Problem {
+    /// Whether [`check`] fixes the problem when repairing.
+    pub fn is_repairable(&self) -> bool {
+        !matches!(
+            self,
+            Self::Unreadable { .. } | Self::ChecksumMismatch { .. } | Self::UnknownOwner { .. }
+        )
+    }
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidId { user, id } => write!(f, "{user}: invalid paste ID {id:?}"),
+            Self::Duplicate {
+                user,
+                id,
+                first_owner,
+            } => write!(f, "{user}: paste {id} is already owned by {first_owner}"),
+            Self::Missing { user, id } => write!(f, "{user}: paste {id} has no data file"),
+            Self::Unreadable { id, error } => write!(f, "paste {id} is unreadable: {error}"),
+            Self::ChecksumMismatch { id } => write!(f, "paste {id} doesn't match its checksum"),
+            Self::MissingChecksum { id } => write!(f, "paste {id} has no checksum"),
+            Self::OrphanedChecksum { id } => write!(f, "checksum for missing paste {id}"),
+            Self::OrphanedMetadata { id } => write!(f, "metadata for missing paste {id}"),
+            Self::Unlisted { user, id } => write!(f, "{user}: owns paste {id} but doesn't list it"),
+            Self::UnknownOwner { user, id } => {
+                write!(f, "paste {id} is owned by unknown user {user}")
+            }
+            Self::UnreferencedBlob { path } => write!(f, "unreferenced blob {path}"),
+        }
+    }
+}
+
+/// Cross-checks `state` against the pastes in `data_dir`.
+///
+/// With `repair`, references to invalid, duplicate and missing pastes are
+/// dropped from `state`, pastes are listed again for the owners named in
+/// their metadata, checksums are recorded for pastes without one, checksums
+/// and metadata are deleted for pastes that no longer exist, and
+/// unreferenced blobs are removed. The caller is responsible for saving
+/// `state`.
+pub fn check(state: &mut State, data_dir: &Path, repair: bool) -> Vec<Problem> {
+    let mut problems = Vec::new();
+    let mut owners: HashMap<String, String> = HashMap::new();
+
+    let mut users: Vec<_> = state.users_mut().collect();
+    users.sort_by(|a, b| a.username.cmp(&b.username));
+    for user in users {
+        let mut kept = Vec::new();
+        for id in std::mem::take(&mut user.paste_ids) {
+            let problem = if uuid::Uuid::parse_str(&id).is_err() {
+                Some(Problem::InvalidId {
+                    user: user.username.clone(),
+                    id: id.clone(),
+                })
+            } else if let Some(first_owner) = owners.get(&id) {
+                Some(Problem::Duplicate {
+                    user: user.username.clone(),
+                    id: id.clone(),
+                    first_owner: first_owner.clone(),
+                })
+            } else if !data_dir.join(&id).is_file() {
+                Some(Problem::Missing {
+                    user: user.username.clone(),
+                    id: id.clone(),
+                })
+            } else {
+                None
+            };
+
+            match problem {
+                None => {
+                    owners.insert(id.clone(), user.username.clone());
+                    kept.push(id);
+                }
+                Some(problem) => {
+                    if !repair {
+                        kept.push(id);
+                    }
+                    problems.push(problem);
+                }
+            }
+        }
+        user.paste_ids = kept;
+    }
+
+    problems.extend(check_owners(state, data_dir, &owners, repair));
+    problems.extend(check_files(data_dir, repair));
+    problems.extend(check_blobs(data_dir, repair));
+    problems
+}
+
+/// Compares the owners recorded in the pastes' metadata with `owners`, the
+/// owner of each paste according to the state file.
+fn check_owners(
+    state: &mut State,
+    data_dir: &Path,
+    owners: &HashMap<String, String>,
+    repair: bool,
+) -> Vec<Problem> {
+    let entries = match std::fs::read_dir(data_dir) {
+        Ok(entries) => entries,
+        // Reported by check_files.
+        Err(_) => return Vec::new(),
+    };
+
+    let mut problems = Vec::new();
+    for entry in entries.flatten() {
+        let id = entry.file_name().to_string_lossy().into_owned();
+        if uuid::Uuid::parse_str(&id).is_err() || owners.contains_key(&id) {
+            continue;
+        }
+        let Some(user) = (match meta::load_blocking(data_dir, &id) {
+            Ok(metadata) => metadata.and_then(|metadata| metadata.owner),
+            Err(e) => {
+                problems.push(Problem::Unreadable {
+                    id: meta::path(data_dir, &id).display().to_string(),
+                    error: e.to_string(),
+                });
+                continue;
+            }
+        }) else {
+            continue;
+        };
+        match state.user_mut(&user) {
+            Some(owner) => {
+                if repair {
+                    owner.paste_ids.push(id.clone());
+                }
+                problems.push(Problem::Unlisted { user, id });
+            }
+            None => problems.push(Problem::UnknownOwner { user, id }),
+        }
+    }
+    problems.sort_by_key(|p| p.to_string());
+    problems
+}
+
+fn check_blobs(data_dir: &Path, repair: bool) -> Vec<Problem> {
+    let unreferenced = match blobs::unreferenced_blocking(data_dir) {
+        Ok(unreferenced) => unreferenced,
+        Err(e) => {
+            return vec![Problem::Unreadable {
+                id: blobs::dir(data_dir).display().to_string(),
+                error: e.to_string(),
+            }];
+        }
+    };
+    unreferenced
+        .into_iter()
+        .map(|path| {
+            if repair && let Err(e) = std::fs::remove_file(&path) {
+                tracing::warn!("Couldn't remove {}: {e}", path.display());
+            }
+            Problem::UnreferencedBlob {
+                path: path.display().to_string(),
+            }
+        })
+        .collect()
+}
+
+fn check_files(data_dir: &Path, repair: bool) -> Vec<Problem> {
+    let entries = match std::fs::read_dir(data_dir) {
+        Ok(entries) => entries,
+        Err(e) => {
+            return vec![Problem::Unreadable {
+                id: data_dir.display().to_string(),
+                error: e.to_string(),
+            }];
+        }
+    };
+
+    let mut problems = Vec::new();
+    for entry in entries.flatten() {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if let Some(id) = name.strip_suffix(".sha256")
+            && uuid::Uuid::parse_str(id).is_ok()
+            && !data_dir.join(id).exists()
+        {
+            if repair && let Err(e) = std::fs::remove_file(entry.path()) {
+                tracing::warn!("Couldn't remove {}: {e}", entry.path().display());
+            }
+            problems.push(Problem::OrphanedChecksum { id: id.to_owned() });
+            continue;
+        }
+        if let Some(id) = name.strip_suffix(".meta.json")
+            && uuid::Uuid::parse_str(id).is_ok()
+            && !data_dir.join(id).exists()
+        {
+            if repair && let Err(e) = std::fs::remove_file(entry.path()) {
+                tracing::warn!("Couldn't remove {}: {e}", entry.path().display());
+            }
+            problems.push(Problem::OrphanedMetadata { id: id.to_owned() });
+            continue;
+        }
+        if uuid::Uuid::parse_str(&name).is_err() {
+            continue;
+        }
+        match checksum::verify_blocking(data_dir, &name) {
+            Ok(Integrity::Valid) => {}
+            Ok(Integrity::Mismatch) => problems.push(Problem::ChecksumMismatch { id: name }),
+            Ok(Integrity::Unknown) => {
+                if repair && let Err(e) = record_checksum(data_dir, &name) {
+                    problems.push(Problem::Unreadable {
+                        id: name,
+                        error: e.to_string(),
+                    });
+                    continue;
+                }
+                problems.push(Problem::MissingChecksum { id: name });
+            }
+            Err(e) => problems.push(Problem::Unreadable {
+                id: name,
+                error: e.to_string(),
+            }),
+        }
+    }
+    problems.sort_by_key(|p| p.to_string());
+    problems
+}
+
+fn record_checksum(data_dir: &Path, id: &str) -> std::io::Result<()> {
+    let digest = checksum::digest_blocking(&data_dir.join(id))?;
+    std::fs::write(
+        checksum::sidecar_path(data_dir, id),
+        checksum::format_sidecar(id, &digest),
+    )
+}
+
+#[test]
+fn test_check_repairs_references() {
+    let dir = crate::testing::TempDir::new().unwrap();
+    let data_dir = dir.path();
+    let present = uuid::Uuid::new_v4().to_string();
+    let missing = uuid::Uuid::new_v4().to_string();
+    let unlisted = uuid::Uuid::new_v4().to_string();
+    std::fs::write(data_dir.join(&present), "hi").unwrap();
+    std::fs::write(data_dir.join(&unlisted), "hi").unwrap();
+    std::fs::write(meta::path(data_dir, &unlisted), r#"{"owner":"alice"}"#).unwrap();
+    record_checksum(data_dir, &unlisted).unwrap();
+
+    let mut state = State::default();
+    for user in ["alice", "bob"] {
+        state.create(user, "pw");
+    }
+    state.user_mut("alice").unwrap().paste_ids = vec![present.clone(), missing.clone()];
+    state.user_mut("bob").unwrap().paste_ids = vec![present.clone(), "nope".to_owned()];
+
+    let problems = check(&mut state, data_dir, true);
+    let integrity = checksum::verify_blocking(data_dir, &present).unwrap();
+
+    assert_eq!(
+        problems,
+        [
+            Problem::Missing {
+                user: "alice".to_owned(),
+                id: missing,
+            },
+            Problem::Duplicate {
+                user: "bob".to_owned(),
+                id: present.clone(),
+                first_owner: "alice".to_owned(),
+            },
+            Problem::InvalidId {
+                user: "bob".to_owned(),
+                id: "nope".to_owned(),
+            },
+            Problem::Unlisted {
+                user: "alice".to_owned(),
+                id: unlisted.clone(),
+            },
+            Problem::MissingChecksum {
+                id: present.clone(),
+            },
+        ]
+    );
+    assert_eq!(integrity, Integrity::Valid);
+    assert_eq!(
+        state.user_mut("alice").unwrap().paste_ids,
+        [present, unlisted]
+    );
+    assert!(state.user_mut("bob").unwrap().paste_ids.is_empty());
+}
@@ -0,0 +1,124 @@
+//! How hard writes try to survive a crash or power loss. Syncing every
+//! paste to disk before answering is safe but slow; batching syncs bounds
+//! what a crash can lose to the last interval; leaving it to the OS is
+//! fastest, and loses whatever it hadn't written yet.
+
+use std::{
+    collections::HashSet,
+    io,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use parking_lot::Mutex;
+
+use crate::service::Service;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, clap::ValueEnum, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Policy {
+    /// Sync a paste's files and directories before answering the request
+    Strict,
+    /// Sync written files every --sync-interval in the background
+    Batch,
+    /// Leave syncing to the OS
+    #[default]
+    Fast,
+}
+
+/// Syncs written files as its [`Policy`] has it.
+#[derive(Default)]
+pub struct Syncer {
+    policy: Policy,
+    /// Files and directories written since the last batch.
+    pending: Mutex<HashSet<PathBuf>>,
+}
+
+impl Syncer {
+    pub fn new(policy: Policy) -> Self {
+        Self {
+            policy,
+            pending: Mutex::default(),
+        }
+    }
+
+    /// Syncs `file`, about to be renamed into place, if syncs are strict, so
+    /// that it's never in place with its contents missing.
+    pub async fn before_rename(&self, file: &tokio::fs::File) -> io::Result<()> {
+        match self.policy {
+            Policy::Strict => file.sync_all().await,
+            Policy::Batch | Policy::Fast => Ok(()),
+        }
+    }
+
+    /// Syncs `paths`, files written and the directories files were created
+    /// or renamed in, right away or with the next batch.
+    pub async fn written(&self, paths: Vec<PathBuf>) -> io::Result<()> {
+        match self.policy {
+            Policy::Strict => tokio::task::spawn_blocking(move || sync_blocking(paths))
+                .await
+                .map_err(io::Error::other)?,
+            Policy::Batch => {
+                self.pending.lock().extend(paths);
+                Ok(())
+            }
+            Policy::Fast => Ok(()),
+        }
+    }
+
+    /// Syncs everything written since the last batch.
+    pub async fn flush(&self) -> io::Result<()> {
+        let paths: Vec<_> = std::mem::take(&mut *self.pending.lock())
+            .into_iter()
+            .collect();
+        if paths.is_empty() {
+            return Ok(());
+        }
+        tokio::task::spawn_blocking(move || sync_blocking(paths))
+            .await
+            .map_err(io::Error::other)?
+    }
+}
+
+/// Syncs each of `paths`, skipping any removed since they were written.
+fn sync_blocking(paths: Vec<PathBuf>) -> io::Result<()> {
+    for path in paths {
+        match sync_path(&path) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+fn sync_path(path: &Path) -> io::Result<()> {
+    std::fs::File::open(path)?.sync_all()
+}
+
+/// Syncs the files written by `service` every `interval`, for
+/// [`Policy::Batch`].
+pub fn spawn(service: Arc<Service>, interval: Duration) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(interval).await;
+            if let Err(e) = service.syncer().flush().await {
+                tracing::error!("Syncing written pastes failed: {e}");
+            }
+        }
+    })
+}
+
+#[tokio::test]
+async fn test_batches_until_flushed() {
+    let dir = crate::testing::TempDir::new().unwrap();
+    let dir = dir.path();
+    let syncer = Syncer::new(Policy::Batch);
+    syncer
+        .written(vec![dir.to_owned(), dir.join("removed")])
+        .await
+        .unwrap();
+    assert_eq!(syncer.pending.lock().len(), 2);
+    syncer.flush().await.unwrap();
+    assert!(syncer.pending.lock().is_empty());
+}
@@ -0,0 +1,645 @@
+//! An email gateway: messages sent to any address at --smtp-domain become
+//! pastes owned by the user --email-user maps the sender to, one per
+//! attachment, or one of the text if there are none. The URLs are given in
+//! the SMTP reply and, with --smtp-relay, mailed back to the sender.
+//!
+//! Senders are taken at their word, so the listener belongs behind a mail
+//! server that verifies them, e.g. with SPF and DKIM, and forwards to it.
+
+use std::{collections::HashMap, net::SocketAddr, sync::Arc, time::Duration};
+
+use base64::Engine;
+use tokio::{
+    io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader},
+    net::{TcpListener, TcpStream},
+};
+
+use crate::{
+    client,
+    html::decode_percent,
+    rate_limit::Limits,
+    service::{Options, Service},
+};
+
+/// Largest message accepted, as advertised with the SIZE extension.
+const MAX_MESSAGE: usize = 25 * 1024 * 1024;
+
+/// Longest line read, well beyond the 1000 bytes SMTP allows.
+const MAX_LINE: u64 = 64 * 1024;
+
+/// Pastes made of one message at most, e.g. of its attachments.
+const MAX_PASTES: usize = 20;
+
+/// Time a client has to send each command, and the relay to answer each.
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// Longest title a paste may have, in bytes, so long file names fit.
+const MAX_TITLE: usize = 200;
+
+pub struct Config {
+    /// Domain whose addresses accept mail, also used to greet.
+    pub domain: String,
+    /// Sender addresses, in lowercase, and the users owning their pastes.
+    pub users: HashMap<String, String>,
+    /// Mail server the replies are sent through, as `host:port`.
+    pub relay: Option<String>,
+}
+
+/// Accepts mail on `listener` until the process exits. Messages count
+/// against the create rate limit of the sending server's address.
+pub async fn serve(
+    listener: TcpListener,
+    config: Arc<Config>,
+    service: Arc<Service>,
+    limits: Arc<Limits>,
+    client: Arc<client::Config>,
+) {
+    loop {
+        let (stream, peer) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                tracing::warn!("Accepting an SMTP connection: {e}");
+                continue;
+            }
+        };
+        let (config, service, limits, client) = (
+            config.clone(),
+            service.clone(),
+            limits.clone(),
+            client.clone(),
+        );
+        tokio::spawn(async move {
+            if let Err(e) = session(stream, peer, &config, &service, &limits, &client).await {
+                tracing::debug!("SMTP session with {peer}: {e}");
+            }
+        });
+    }
+}
+
+/// The envelope of the message being received.
+#[derive(Default)]
+struct Envelope {
+    /// The sender's address and the user they're mapped to.
+    sender: Option<(String, String)>,
+    /// The first recipient, which replies are sent from.
+    recipient: Option<String>,
+}
+
+async fn session(
+    stream: TcpStream,
+    peer: SocketAddr,
+    config: &Config,
+    service: &Service,
+    limits: &Limits,
+    client: &client::Config,
+) -> anyhow::Result<()> {
+    let (read, mut write) = stream.into_split();
+    let mut read = BufReader::new(read);
+    let domain = &config.domain;
+    respond(&mut write, &format!("220 {domain} pastebin ESMTP")).await?;
+    let mut envelope = Envelope::default();
+    loop {
+        let Some(line) = tokio::time::timeout(COMMAND_TIMEOUT, read_line(&mut read)).await?? else {
+            return Ok(());
+        };
+        let line = String::from_utf8_lossy(&line);
+        let line = line.trim_end();
+        let (verb, argument) = line.split_once(' ').unwrap_or((line, ""));
+        let response = match verb.to_ascii_uppercase().as_str() {
+            "HELO" => format!("250 {domain}"),
+            "EHLO" => {
+                format!("250-{domain}\r\n250-SIZE {MAX_MESSAGE}\r\n250-8BITMIME\r\n250 PIPELINING")
+            }
+            "MAIL" => match address(argument, "FROM:") {
+                None => "501 5.5.4 Syntax: MAIL FROM:<address>".to_owned(),
+                Some(sender) => match config.users.get(&sender.to_lowercase()) {
+                    None => format!("550 5.7.1 <{sender}> may not paste here"),
+                    Some(_) if limits.create.check(peer.ip()).is_err() => {
+                        "450 4.7.1 Too many pastes, try again later".to_owned()
+                    }
+                    Some(username) => {
+                        envelope = Envelope {
+                            sender: Some((sender.to_lowercase(), username.clone())),
+                            recipient: None,
+                        };
+                        "250 2.1.0 OK".to_owned()
+                    }
+                },
+            },
+            "RCPT" if envelope.sender.is_none() => "503 5.5.1 MAIL first".to_owned(),
+            "RCPT" => match address(argument, "TO:") {
+                None => "501 5.5.4 Syntax: RCPT TO:<address>".to_owned(),
+                Some(recipient)
+                    if recipient
+                        .rsplit_once('@')
+                        .is_some_and(|(_, at)| at.eq_ignore_ascii_case(domain)) =>
+                {
+                    envelope
+                        .recipient
+                        .get_or_insert_with(|| recipient.to_owned());
+                    "250 2.1.5 OK".to_owned()
+                }
+                Some(_) => "550 5.7.1 Relaying denied".to_owned(),
+            },
+            "DATA" if envelope.recipient.is_none() => "503 5.5.1 RCPT first".to_owned(),
+            "DATA" => {
+                respond(&mut write, "354 End data with <CR><LF>.<CR><LF>").await?;
+                let data = read_data(&mut read).await?;
+                let envelope = std::mem::take(&mut envelope);
+                match data {
+                    Some(data) => deliver(&data, envelope, config, service, client).await,
+                    None => format!("552 5.3.4 Messages are limited to {MAX_MESSAGE} bytes"),
+                }
+            }
+            "RSET" => {
+                envelope = Envelope::default();
+                "250 2.0.0 OK".to_owned()
+            }
+            "NOOP" => "250 2.0.0 OK".to_owned(),
+            "VRFY" => "252 2.5.0 Cannot VRFY user".to_owned(),
+            "QUIT" => {
+                respond(&mut write, &format!("221 2.0.0 {domain} closing")).await?;
+                return Ok(());
+            }
+            _ => "502 5.5.2 Command not recognized".to_owned(),
+        };
+        respond(&mut write, &response).await?;
+    }
+}
+
+async fn respond(write: &mut (impl AsyncWrite + Unpin), response: &str) -> std::io::Result<()> {
+    write.write_all(format!("{response}\r\n").as_bytes()).await
+}
+
+/// The next line, with its line break, or `None` at the end of input.
+async fn read_line(read: &mut (impl AsyncBufRead + Unpin)) -> std::io::Result<Option<Vec<u8>>> {
+    let mut line = Vec::new();
+    read.take(MAX_LINE).read_until(b'\n', &mut line).await?;
+    if line.is_empty() {
+        return Ok(None);
+    }
+    if !line.ends_with(b"\n") {
+        let kind = if line.len() as u64 == MAX_LINE {
+            std::io::ErrorKind::InvalidData
+        } else {
+            std::io::ErrorKind::UnexpectedEof
+        };
+        return Err(kind.into());
+    }
+    Ok(Some(line))
+}
+
+/// The address in the argument of MAIL or RCPT, e.g. `FROM:<a@b> SIZE=10`.
+fn address<'a>(argument: &'a str, prefix: &str) -> Option<&'a str> {
+    let (start, rest) = argument.split_at_checked(prefix.len())?;
+    if !start.eq_ignore_ascii_case(prefix) {
+        return None;
+    }
+    let rest = rest.trim_start().strip_prefix('<')?;
+    let (address, _parameters) = rest.split_once('>')?;
+    Some(address)
+}
+
+/// The message after DATA, up to the line with a single dot, or `None` if
+/// it's larger than [`MAX_MESSAGE`].
+async fn read_data(read: &mut (impl AsyncBufRead + Unpin)) -> anyhow::Result<Option<Vec<u8>>> {
+    let mut data = Vec::new();
+    let mut too_large = false;
+    loop {
+        let line = tokio::time::timeout(COMMAND_TIMEOUT, read_line(read))
+            .await??
+            .ok_or_else(|| anyhow::anyhow!("Connection closed during DATA"))?;
+        if line == b".\r\n" || line == b".\n" {
+            break;
+        }
+        // Lines starting with a dot have another one put in front.
+        let line = line.strip_prefix(b".").unwrap_or(&line);
+        if data.len() + line.len() > MAX_MESSAGE {
+            too_large = true;
+        } else if !too_large {
+            data.extend_from_slice(line);
+        }
+    }
+    Ok((!too_large).then_some(data))
+}
+
+/// Makes pastes of `data` and returns the SMTP response listing them.
+async fn deliver(
+    data: &[u8],
+    envelope: Envelope,
+    config: &Config,
+    service: &Service,
+    client: &client::Config,
+) -> String {
+    let (Some((sender, username)), Some(recipient)) = (envelope.sender, envelope.recipient) else {
+        return "503 5.5.1 MAIL and RCPT first".to_owned();
+    };
+    let message = Part::parse(data);
+    let mut pastes = Vec::new();
+    let mut text = None;
+    message.collect(&mut pastes, &mut text, 0);
+    let subject = message.header("subject").map(decode_words);
+    if pastes.is_empty()
+        && let Some(text) = text.filter(|text| !text.trim().is_empty())
+    {
+        pastes.push((subject.clone(), text.into_bytes()));
+    }
+    if pastes.is_empty() {
+        return "554 5.6.0 Nothing to paste".to_owned();
+    }
+    pastes.truncate(MAX_PASTES);
+
+    let mut urls = Vec::new();
+    for (title, contents) in pastes {
+        let options = Options {
+            title: title.map(|title| truncate(&title, MAX_TITLE)),
+            ..Options::default()
+        };
+        match service
+            .create_for(&username, contents.as_slice(), options)
+            .await
+        {
+            Ok(id) => urls.push(match &client.base_url {
+                Some(base_url) => format!("{base_url}/paste/{id}"),
+                None => id,
+            }),
+            Err(e) => {
+                tracing::error!("Creating a paste mailed by {sender}: {e}");
+                return "451 4.3.0 Couldn't create the paste".to_owned();
+            }
+        }
+    }
+    tracing::info!("Created {} pastes mailed by {sender}", urls.len());
+    if let Some(relay) = &config.relay {
+        let reply = reply(
+            service,
+            &recipient,
+            &sender,
+            subject.as_deref(),
+            message.header("message-id"),
+            &urls,
+        );
+        let (relay, domain) = (relay.clone(), config.domain.clone());
+        tokio::spawn(async move {
+            if let Err(e) = send(&relay, &domain, &sender, &reply).await {
+                tracing::error!("Mailing {sender} their paste URLs through {relay}: {e}");
+            }
+        });
+    }
+    let mut response = String::new();
+    for url in &urls {
+        response.push_str(&format!("250-{url}\r\n"));
+    }
+    response + "250 2.0.0 Pasted"
+}
+
+/// `text` cut to at most `max` bytes, on a character boundary.
+fn truncate(text: &str, max: usize) -> String {
+    let mut end = text.len().min(max);
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    text[..end].to_owned()
+}
+
+/// A message or a part of a multipart one.
+struct Part<'a> {
+    /// Header names in lowercase, and their unfolded values.
+    headers: Vec<(String, String)>,
+    body: &'a [u8],
+}
+
+impl<'a> Part<'a> {
+    fn parse(data: &'a [u8]) -> Self {
+        let (head, body) = match find(data, b"\r\n\r\n")
+            .map(|at| (at, 4))
+            .or_else(|| find(data, b"\n\n").map(|at| (at, 2)))
+        {
+            Some((at, separator)) => (&data[..at], &data[at + separator..]),
+            None => (data, &data[data.len()..]),
+        };
+        let mut headers: Vec<(String, String)> = Vec::new();
+        for line in String::from_utf8_lossy(head).lines() {
+            if line.starts_with([' ', '\t']) {
+                if let Some((_, value)) = headers.last_mut() {
+                    value.push(' ');
+                    value.push_str(line.trim());
+                }
+            } else if let Some((name, value)) = line.split_once(':') {
+                headers.push((name.trim().to_lowercase(), value.trim().to_owned()));
+            }
+        }
+        Self { headers, body }
+    }
+
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(header, _)| header == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// The body without its transfer encoding.
+    fn decoded(&self) -> Vec<u8> {
+        let encoding = self.header("content-transfer-encoding").unwrap_or_default();
+        if encoding.eq_ignore_ascii_case("base64") {
+            let text: Vec<u8> = self
+                .body
+                .iter()
+                .copied()
+                .filter(|byte| !byte.is_ascii_whitespace())
+                .collect();
+            base64::engine::general_purpose::STANDARD
+                .decode(&text)
+                .unwrap_or(text)
+        } else if encoding.eq_ignore_ascii_case("quoted-printable") {
+            decode_quoted_printable(self.body, false)
+        } else {
+            self.body.to_vec()
+        }
+    }
+
+    /// Adds the attachments among the part and those it's made of to
+    /// `pastes` as titles and contents, and the first plain text to `text`.
+    fn collect(
+        &self,
+        pastes: &mut Vec<(Option<String>, Vec<u8>)>,
+        text: &mut Option<String>,
+        depth: u32,
+    ) {
+        let (content_type, type_parameters) =
+            parameters(self.header("content-type").unwrap_or("text/plain"));
+        if let Some(boundary) = content_type
+            .strip_prefix("multipart/")
+            .and(type_parameters.get("boundary"))
+        {
+            // Deeper nesting than this is no legitimate message.
+            if depth < 10 {
+                for part in split_multipart(self.body, boundary) {
+                    Part::parse(part).collect(pastes, text, depth + 1);
+                }
+            }
+            return;
+        }
+        let (disposition, disposition_parameters) =
+            parameters(self.header("content-disposition").unwrap_or_default());
+        let filename = disposition_parameters
+            .get("filename")
+            .or_else(|| type_parameters.get("name"))
+            .map(|name| decode_words(name));
+        let charset = type_parameters.get("charset").map(String::as_str);
+        if disposition == "attachment" || filename.is_some() {
+            let mut contents = self.decoded();
+            if content_type.starts_with("text/") {
+                contents = to_utf8(&contents, charset).into_bytes();
+            }
+            pastes.push((filename, contents));
+        } else if content_type == "text/plain" && text.is_none() {
+            *text = Some(to_utf8(&self.decoded(), charset));
+        }
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+/// The value of a header like Content-Type in lowercase, and its
+/// parameters with their names in lowercase.
+fn parameters(value: &str) -> (String, HashMap<String, String>) {
+    let mut parts = value.split(';');
+    let value = parts.next().unwrap_or_default().trim().to_lowercase();
+    let mut parameters = HashMap::new();
+    for parameter in parts {
+        let Some((name, value)) = parameter.split_once('=') else {
+            continue;
+        };
+        let name = name.trim().to_lowercase();
+        let value = value.trim().trim_matches('"');
+        // RFC 2231 values like `utf-8''na%C3%AFve.txt`.
+        match name.strip_suffix('*') {
+            Some(name) => {
+                let encoded = value.splitn(3, '\'').nth(2).unwrap_or(value);
+                let decoded = decode_percent(encoded).unwrap_or_else(|| encoded.to_owned());
+                parameters.insert(name.to_owned(), decoded);
+            }
+            None => {
+                parameters.insert(name, value.to_owned());
+            }
+        }
+    }
+    (value, parameters)
+}
+
+/// The parts of a multipart body between the `boundary` delimiters.
+fn split_multipart<'a>(body: &'a [u8], boundary: &str) -> Vec<&'a [u8]> {
+    let delimiter = format!("--{boundary}");
+    let mut parts = Vec::new();
+    let mut start = None;
+    let mut at = 0;
+    while at < body.len() {
+        let end = find(&body[at..], b"\n").map_or(body.len(), |found| at + found + 1);
+        let line = body[at..end].trim_ascii_end();
+        if let Some(rest) = line.strip_prefix(delimiter.as_bytes())
+            && (rest.is_empty() || rest == b"--")
+        {
+            if let Some(start) = start {
+                // The line break before the delimiter belongs to it.
+                let part = &body[start..at];
+                let part = part.strip_suffix(b"\n").unwrap_or(part);
+                parts.push(part.strip_suffix(b"\r").unwrap_or(part));
+            }
+            if rest == b"--" {
+                break;
+            }
+            start = Some(end);
+        }
+        at = end;
+    }
+    parts
+}
+
+/// Decodes quoted-printable `text`, in which `_` stands for a space in
+/// encoded words.
+fn decode_quoted_printable(text: &[u8], underscores: bool) -> Vec<u8> {
+    let mut decoded = Vec::with_capacity(text.len());
+    let mut at = 0;
+    while at < text.len() {
+        match text[at] {
+            b'=' if text[at + 1..].starts_with(b"\r\n") => at += 3,
+            b'=' if text[at + 1..].starts_with(b"\n") => at += 2,
+            b'=' if let Some(hex) = text.get(at + 1..at + 3)
+                && let Some(byte) = std::str::from_utf8(hex)
+                    .ok()
+                    .and_then(|hex| u8::from_str_radix(hex, 16).ok()) =>
+            {
+                decoded.push(byte);
+                at += 3;
+            }
+            b'_' if underscores => {
+                decoded.push(b' ');
+                at += 1;
+            }
+            byte => {
+                decoded.push(byte);
+                at += 1;
+            }
+        }
+    }
+    decoded
+}
+
+/// `bytes` in `charset` as a string. Only UTF-8 and Latin-1 are told
+/// apart, which covers nearly all mail.
+fn to_utf8(bytes: &[u8], charset: Option<&str>) -> String {
+    match charset.map(str::to_lowercase).as_deref() {
+        Some("iso-8859-1" | "latin1" | "windows-1252") => {
+            bytes.iter().map(|&byte| char::from(byte)).collect()
+        }
+        _ => String::from_utf8_lossy(bytes).into_owned(),
+    }
+}
+
+/// Decodes the RFC 2047 encoded words in a header, like
+/// `=?utf-8?B?w6TDtg==?=`, and drops line breaks.
+fn decode_words(value: &str) -> String {
+    let mut decoded = String::new();
+    let mut previous_encoded = false;
+    for (i, word) in value.split(' ').enumerate() {
+        let encoded = word
+            .strip_prefix("=?")
+            .and_then(|word| word.strip_suffix("?="))
+            .and_then(|word| {
+                let mut parts = word.splitn(3, '?');
+                let (charset, encoding, text) = (parts.next()?, parts.next()?, parts.next()?);
+                let bytes = match encoding {
+                    "B" | "b" => base64::engine::general_purpose::STANDARD
+                        .decode(text)
+                        .ok()?,
+                    "Q" | "q" => decode_quoted_printable(text.as_bytes(), true),
+                    _ => return None,
+                };
+                Some(to_utf8(&bytes, Some(charset)))
+            });
+        // Spaces between encoded words are dropped.
+        if i > 0 && !(previous_encoded && encoded.is_some()) {
+            decoded.push(' ');
+        }
+        previous_encoded = encoded.is_some();
+        decoded.push_str(encoded.as_deref().unwrap_or(word));
+    }
+    decoded.replace(char::is_control, " ")
+}
+
+/// The reply to a message from `to` listing the pastes made of it.
+fn reply(
+    service: &Service,
+    from: &str,
+    to: &str,
+    subject: Option<&str>,
+    message_id: Option<&str>,
+    urls: &[String],
+) -> String {
+    let subject = format!("Re: {}", subject.unwrap_or("Your paste"));
+    let subject = if subject.is_ascii() {
+        subject
+    } else {
+        let encoded = base64::engine::general_purpose::STANDARD.encode(subject);
+        format!("=?utf-8?B?{encoded}?=")
+    };
+    let domain = from.rsplit_once('@').map_or(from, |(_, domain)| domain);
+    let mut reply = format!(
+        "From: <{from}>\r\nTo: <{to}>\r\nSubject: {subject}\r\nDate: {}\r\n\
+         Message-ID: <{}@{domain}>\r\n",
+        chrono::DateTime::<chrono::Utc>::from(service.clock().now()).to_rfc2822(),
+        service.ids().generate(),
+    );
+    if let Some(message_id) = message_id.filter(|id| !id.contains(char::is_control)) {
+        reply.push_str(&format!(
+            "In-Reply-To: {message_id}\r\nReferences: {message_id}\r\n"
+        ));
+    }
+    reply.push_str(
+        "Auto-Submitted: auto-replied\r\nMIME-Version: 1.0\r\n\
+         Content-Type: text/plain; charset=utf-8\r\nContent-Transfer-Encoding: 8bit\r\n\r\n",
+    );
+    reply.push_str(if urls.len() == 1 {
+        "Your paste is at:\r\n\r\n"
+    } else {
+        "Your pastes are at:\r\n\r\n"
+    });
+    for url in urls {
+        reply.push_str(&format!("    {url}\r\n"));
+    }
+    reply
+}
+
+/// Sends `message` to `to` through `relay`, with the null sender that
+/// automatic replies use so that they're never answered in turn.
+pub async fn send(relay: &str, domain: &str, to: &str, message: &str) -> anyhow::Result<()> {
+    let stream = tokio::time::timeout(COMMAND_TIMEOUT, TcpStream::connect(relay)).await??;
+    let (read, mut write) = stream.into_split();
+    let mut read = BufReader::new(read);
+    expect(&mut read, 220).await?;
+    for (command, code) in [
+        (format!("EHLO {domain}"), 250),
+        ("MAIL FROM:<>".to_owned(), 250),
+        (format!("RCPT TO:<{to}>"), 250),
+        ("DATA".to_owned(), 354),
+    ] {
+        respond(&mut write, &command).await?;
+        expect(&mut read, code).await?;
+    }
+    let mut data = String::new();
+    for line in message.split_inclusive("\r\n") {
+        if line.starts_with('.') {
+            data.push('.');
+        }
+        data.push_str(line);
+    }
+    data.push_str(".\r\n");
+    write.write_all(data.as_bytes()).await?;
+    expect(&mut read, 250).await?;
+    respond(&mut write, "QUIT").await?;
+    Ok(())
+}
+
+/// Reads a possibly multiline response, failing unless it has `code`.
+async fn expect(read: &mut (impl AsyncBufRead + Unpin), code: u16) -> anyhow::Result<()> {
+    loop {
+        let line = tokio::time::timeout(COMMAND_TIMEOUT, read_line(read))
+            .await??
+            .ok_or_else(|| anyhow::anyhow!("Connection closed"))?;
+        let line = String::from_utf8_lossy(&line);
+        let line = line.trim_end();
+        if line.as_bytes().get(3) == Some(&b'-') {
+            continue;
+        }
+        if line.get(..3) != Some(&code.to_string()) {
+            anyhow::bail!("Expected {code}, got {line:?}");
+        }
+        return Ok(());
+    }
+}
+
+#[test]
+fn test_parses_attachments() {
+    let message =
+        b"From: a@example.com\r\nSubject: =?utf-8?Q?caf=C3=A9?= \r\n =?utf-8?B?bG9n?=\r\n\
+        Content-Type: multipart/mixed; boundary=\"b\"\r\n\r\n\
+        preamble\r\n--b\r\nContent-Type: text/plain; charset=utf-8\r\n\r\nSee attached\r\n\
+        --b\r\nContent-Type: text/plain; name=\"x.log\"\r\n\
+        Content-Transfer-Encoding: base64\r\n\r\naGVs\r\nbG8=\r\n--b--\r\nepilogue\r\n";
+    let message = Part::parse(message);
+    assert_eq!(
+        message.header("subject").map(decode_words).as_deref(),
+        Some("cafélog")
+    );
+    let mut pastes = Vec::new();
+    let mut text = None;
+    message.collect(&mut pastes, &mut text, 0);
+    assert_eq!(pastes, [(Some("x.log".to_owned()), b"hello".to_vec())]);
+    assert_eq!(text.as_deref(), Some("See attached"));
+}
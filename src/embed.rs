@@ -0,0 +1,115 @@
+//! Embedding pastes in other sites. `/embed.js` is a snippet for blogs to
+//! include where a paste should appear, which frames `/paste/{id}/embed`: the
+//! paste highlighted, resized to fit and reloaded whenever the paste changes.
+
+use std::{fmt::Write, sync::Arc};
+
+use axum::{
+    Router,
+    extract::{Path, State},
+    http::{StatusCode, header},
+    response::{Html, IntoResponse, Response},
+    routing::get,
+};
+use tokio::io::AsyncReadExt;
+use uuid::Uuid;
+
+use crate::{highlight, html::escape, service::Service};
+
+/// Bytes of a paste shown in the embed, less than the view shows since
+/// embeds sit in other pages.
+const EMBED_LIMIT: u64 = 256 * 1024;
+
+/// Lets the embed use its inline styles and its own script, which listens to
+/// the paste's events. It doesn't restrict `frame-ancestors`, being meant
+/// to be framed.
+const CSP: &str = "default-src 'none'; style-src 'unsafe-inline'; script-src 'self'; \
+                   connect-src 'self'";
+
+const STYLE: &str = "body{margin:0;font:13px monospace;background:#fafafa;color:#222}\
+                     header{display:flex;gap:1em;padding:.4em .8em;border-bottom:1px solid #ddd;\
+                     font-family:sans-serif}header .language{color:#777;flex:1}\
+                     pre{margin:0;padding:.8em;white-space:pre-wrap;word-break:break-word}\
+                     .c{color:#6a737d}.s{color:#032f62}.n{color:#005cc5}.k{color:#d73a49}";
+
+const SNIPPET: &str = include_str!("embed/embed.js");
+const LIVE: &str = include_str!("embed/live.js");
+
+pub fn routes() -> Router<crate::AppState> {
+    Router::new()
+        .route("/paste/{id}/embed", get(page))
+        .route("/embed.js", get(|| script(SNIPPET)))
+        .route("/embed/live.js", get(|| script(LIVE)))
+}
+
+async fn page(State(service): State<Arc<Service>>, Path(id): Path<Uuid>) -> Response {
+    if !service.exists(&id) {
+        return (StatusCode::NOT_FOUND, "Paste not found").into_response();
+    }
+    let reader = match service.read(&id).await {
+        Ok(reader) => reader,
+        Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    };
+    let metadata = match service.metadata(&id).await {
+        Ok(metadata) => metadata.unwrap_or_default(),
+        Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    };
+    let mut contents = Vec::new();
+    if let Err(e) = reader
+        .take(EMBED_LIMIT + 1)
+        .read_to_end(&mut contents)
+        .await
+    {
+        return (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response();
+    }
+    let truncated = contents.len() as u64 > EMBED_LIMIT;
+    contents.truncate(EMBED_LIMIT as usize);
+    let contents = String::from_utf8_lossy(&contents);
+
+    // Links are relative to /paste/{id}/embed, and open outside the frame.
+    let title = escape(metadata.title.as_deref().unwrap_or(&format!("Paste {id}")));
+    let language = escape(metadata.language.as_deref().unwrap_or_default());
+    let mut page = String::new();
+    write!(
+        page,
+        "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"><title>{title}</title>\n\
+         <base target=\"_blank\"><style>{STYLE}</style></head><body>\n\
+         <header><a href=\"view\">{title}</a><span class=\"language\">{language}</span>\
+         <a href=\"../{id}\">raw</a></header>\n<pre><code>{}</code></pre>\n",
+        highlight::html(&contents, metadata.language.as_deref()),
+    )
+    .unwrap();
+    if truncated {
+        writeln!(
+            page,
+            "<header>Cut off, see <a href=\"view\">the paste</a>.</header>"
+        )
+        .unwrap();
+    }
+    write!(
+        page,
+        "<script src=\"../../embed/live.js\" data-events=\"events\"></script>\n</body></html>\n"
+    )
+    .unwrap();
+    (
+        [
+            (header::CONTENT_SECURITY_POLICY, CSP),
+            (header::X_CONTENT_TYPE_OPTIONS, "nosniff"),
+            // Embeds reload on changes, which has to fetch the new version.
+            (header::CACHE_CONTROL, "no-cache"),
+        ],
+        Html(page),
+    )
+        .into_response()
+}
+
+async fn script(contents: &'static str) -> Response {
+    (
+        [
+            (header::CONTENT_TYPE, "text/javascript; charset=utf-8"),
+            (header::CACHE_CONTROL, "public, max-age=3600"),
+        ],
+        contents,
+    )
+        .into_response()
+}
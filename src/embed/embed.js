@@ -0,0 +1,32 @@
+// Inlines a paste into another site, as the iframe of its embed page sized
+// to fit. Include it where the paste should appear:
+//
+//   <script src="https://host/embed.js" data-paste="<ID or URL>" async></script>
+//
+// `data-max-height` caps the height in pixels, 600 unless given.
+"use strict";
+(() => {
+  const script = document.currentScript;
+  const base = new URL(".", script.src);
+  const id = (script.dataset.paste || "")
+    .split(/[/?#]/)
+    .reverse()
+    .find((part) => /^[0-9a-f]{8}-[0-9a-f]{4}-[0-9a-f]{4}-[0-9a-f]{4}-[0-9a-f]{12}$/i.test(part));
+  if (!id) {
+    console.error("embed.js: data-paste isn't a paste ID or URL");
+    return;
+  }
+  const maxHeight = Number(script.dataset.maxHeight) || 600;
+  const frame = document.createElement("iframe");
+  frame.src = new URL("paste/" + id + "/embed", base).href;
+  frame.title = "Paste";
+  frame.loading = "lazy";
+  frame.style.cssText = "border:1px solid #ddd;border-radius:4px;height:150px;width:100%";
+  script.after(frame);
+  addEventListener("message", (event) => {
+    if (event.source === frame.contentWindow && event.origin === base.origin
+      && typeof event.data?.height === "number") {
+      frame.style.height = Math.min(event.data.height, maxHeight) + 2 + "px";
+    }
+  });
+})();
@@ -0,0 +1,18 @@
+// Runs inside /paste/{id}/embed: tells the embedding page how tall the paste
+// is, and reloads when the paste changes so that embeds stay in sync.
+"use strict";
+(() => {
+  const script = document.currentScript;
+  const report = () => parent.postMessage({ height: document.documentElement.scrollHeight }, "*");
+  addEventListener("load", report);
+  addEventListener("resize", report);
+  const events = new EventSource(script.dataset.events);
+  events.addEventListener("paste.updated", () => location.reload());
+  const gone = () => {
+    events.close();
+    document.querySelector("pre").textContent = "This paste is gone.";
+    report();
+  };
+  events.addEventListener("paste.deleted", gone);
+  events.addEventListener("paste.expired", gone);
+})();
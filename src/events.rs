@@ -0,0 +1,169 @@
+//! Events that features such as webhooks react to, so that [`Service`]
+//! methods don't need to know about each of them.
+//!
+//! [`Service`]: crate::service::Service
+
+use std::{
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::SystemTime,
+};
+
+use tokio::sync::broadcast;
+use uuid::Uuid;
+
+/// Events buffered for subscribers. One that falls further behind misses
+/// the oldest of them.
+const BACKLOG: usize = 1024;
+
+#[derive(Clone, Debug)]
+pub enum Event {
+    PasteCreated(Uuid),
+    PasteUpdated(Uuid),
+    /// Deleted by its owner, a moderator or to stay within the storage
+    /// budget.
+    PasteDeleted(Uuid),
+    /// Deleted by the retention policy.
+    PasteExpired(Uuid),
+    UserRegistered(String),
+}
+
+impl Event {
+    /// The name of a paste event, e.g. `paste.created`, and the paste.
+    pub fn paste(&self) -> Option<(&'static str, Uuid)> {
+        match *self {
+            Self::PasteCreated(id) => Some(("paste.created", id)),
+            Self::PasteUpdated(id) => Some(("paste.updated", id)),
+            Self::PasteDeleted(id) => Some(("paste.deleted", id)),
+            Self::PasteExpired(id) => Some(("paste.expired", id)),
+            Self::UserRegistered(_) => None,
+        }
+    }
+}
+
+/// An [`Event`] along with when it happened.
+#[derive(Clone, Debug)]
+pub struct Emitted {
+    pub at: SystemTime,
+    pub event: Event,
+}
+
+impl Emitted {
+    /// When the event happened, in RFC 3339.
+    pub fn timestamp(&self) -> String {
+        chrono::DateTime::<chrono::Utc>::from(self.at)
+            .format("%Y-%m-%dT%H:%M:%SZ")
+            .to_string()
+    }
+}
+
+/// Delivers every event to every subscriber, in order, out of a single
+/// buffer of [`BACKLOG`] events. Subscribers that fall further behind skip
+/// the events they missed, so that a stalled client can't hold on to
+/// memory. Those are logged and counted, see [`Bus::dropped`].
+pub struct Bus {
+    sender: broadcast::Sender<Emitted>,
+    dropped: Arc<AtomicU64>,
+}
+
+impl Default for Bus {
+    fn default() -> Self {
+        Self {
+            sender: broadcast::channel(BACKLOG).0,
+            dropped: Arc::default(),
+        }
+    }
+}
+
+impl Bus {
+    /// Receives all events emitted from now on, for `subscriber`, as it's
+    /// named in the log if it falls behind.
+    pub fn subscribe(&self, subscriber: &'static str) -> Subscription {
+        Subscription {
+            receiver: self.sender.subscribe(),
+            subscriber,
+            dropped: self.dropped.clone(),
+        }
+    }
+
+    /// Events skipped by subscribers that fell behind since startup, over
+    /// all subscribers.
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    /// Announces `event`, which happened `at`, by the service's clock.
+    pub fn emit(&self, event: Event, at: SystemTime) {
+        let emitted = Emitted { at, event };
+        // Fails only if there are no subscribers.
+        self.sender.send(emitted).ok();
+    }
+}
+
+/// The events emitted on a [`Bus`] since subscribing.
+pub struct Subscription {
+    receiver: broadcast::Receiver<Emitted>,
+    subscriber: &'static str,
+    dropped: Arc<AtomicU64>,
+}
+
+impl Subscription {
+    /// The next event, or `None` once the bus is gone. Cancel safe.
+    pub async fn recv(&mut self) -> Option<Emitted> {
+        loop {
+            match self.receiver.recv().await {
+                Ok(emitted) => return Some(emitted),
+                Err(broadcast::error::RecvError::Lagged(missed)) => {
+                    self.dropped.fetch_add(missed, Ordering::Relaxed);
+                    tracing::warn!(
+                        "The {} fell behind and missed {missed} event(s)",
+                        self.subscriber
+                    );
+                }
+                Err(broadcast::error::RecvError::Closed) => return None,
+            }
+        }
+    }
+}
+
+/// Logs every event, as an audit trail of changes to the instance.
+pub fn spawn_log(mut events: Subscription) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        while let Some(Emitted { event, .. }) = events.recv().await {
+            match event {
+                Event::PasteCreated(id) => tracing::info!("Paste {id} created"),
+                Event::PasteUpdated(id) => tracing::info!("Paste {id} updated"),
+                Event::PasteDeleted(id) => tracing::info!("Paste {id} deleted"),
+                Event::PasteExpired(id) => tracing::info!("Paste {id} expired"),
+                Event::UserRegistered(username) => tracing::info!("User {username} registered"),
+            }
+        }
+    })
+}
+
+#[tokio::test]
+async fn test_slow_subscribers_skip_missed_events() {
+    let bus = Bus::default();
+    let mut slow = bus.subscribe("test");
+    let users: Vec<_> = (0..BACKLOG + 5).map(|i| i.to_string()).collect();
+    for username in &users {
+        bus.emit(Event::UserRegistered(username.clone()), SystemTime::now());
+    }
+    let Some(Emitted {
+        event: Event::UserRegistered(first),
+        ..
+    }) = slow.recv().await
+    else {
+        panic!("Expected an event");
+    };
+    assert_eq!(first, "5");
+    assert_eq!(bus.dropped(), 5);
+    drop(bus);
+    let mut received = 1;
+    while slow.recv().await.is_some() {
+        received += 1;
+    }
+    assert_eq!(received, BACKLOG);
+}
@@ -0,0 +1,355 @@
+//! Mirroring of public pastes between instances, so that communities can
+//! carry each other's content.
+//!
+//! An instance pushes its own public pastes to the --federate-to peers as
+//! they're created, updated and deleted. Each message is signed with the
+//! instance's --federation-key. Peers mirror what's signed by a key that
+//! they list with --federation-trust. Mirrors keep the paste's ID and
+//! record which key they came from, so that a peer can only ever change
+//! or delete its own pastes. Mirrored pastes aren't pushed on, which
+//! keeps peers of peers from looping.
+
+use std::{
+    collections::HashSet,
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use axum::{
+    Router,
+    body::Body,
+    extract::State,
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    routing::{get, post},
+};
+use base64::Engine;
+use ring::signature::{ED25519, Ed25519KeyPair, KeyPair, UnparsedPublicKey};
+use serde::{Deserialize, Serialize};
+use tokio::io::AsyncReadExt;
+use uuid::Uuid;
+
+use crate::{
+    events::{Event, Subscription},
+    service::{self, Service},
+};
+
+const MAX_ATTEMPTS: u32 = 5;
+
+/// Header carrying the Ed25519 signature of the message, as
+/// `ed25519=<base64>`.
+const SIGNATURE_HEADER: &str = "x-pastebin-signature";
+
+/// Header carrying the key the message is signed with, in base64.
+const KEY_HEADER: &str = "x-pastebin-key";
+
+/// How old a message may be, so that it can't be replayed to bring back a
+/// deleted paste.
+const MAX_AGE: Duration = Duration::from_secs(5 * 60);
+
+/// Largest message accepted, which holds a paste in base64.
+const MAX_MESSAGE: usize = 64 * 1024 * 1024;
+
+/// What a peer is told about one of the instance's public pastes.
+#[derive(Debug, Deserialize, Serialize)]
+struct Message<P> {
+    id: Uuid,
+    /// When the message was sent, in RFC 3339.
+    sent: String,
+    /// The paste as it is now, or `None` if it was deleted.
+    paste: Option<P>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct Paste {
+    title: Option<String>,
+    language: Option<String>,
+    /// The contents, in base64.
+    content: String,
+}
+
+/// How keys are given to and shown by the instance.
+pub fn encode_key(key: &Ed25519KeyPair) -> String {
+    base64::engine::general_purpose::STANDARD.encode(key.public_key())
+}
+
+/// Pushes the instance's own public pastes among `events` to `peers` in the
+/// background, retrying failed deliveries a few times. Events missed while
+/// falling behind, e.g. during a long outage of a peer, aren't pushed, so
+/// the peers' mirrors can miss changes until the paste changes again; the
+/// misses are counted in `pastebin_events_dropped_total`.
+pub fn spawn(
+    mut events: Subscription,
+    service: Arc<Service>,
+    key: Arc<Ed25519KeyPair>,
+    peers: Vec<String>,
+) -> tokio::task::JoinHandle<()> {
+    let client = reqwest::Client::new();
+    tokio::spawn(async move {
+        // Deletions are only pushed for pastes that were public, as the
+        // metadata saying so is gone by then.
+        let mut public = match own_public_pastes(&service).await {
+            Ok(public) => public,
+            Err(e) => {
+                tracing::error!("Listing public pastes to federate: {e}");
+                HashSet::new()
+            }
+        };
+        while let Some(emitted) = events.recv().await {
+            let (id, paste) = match emitted.event {
+                Event::PasteCreated(id) | Event::PasteUpdated(id) => {
+                    match paste(&service, &id).await {
+                        Ok(Some(paste)) => {
+                            public.insert(id);
+                            (id, Some(paste))
+                        }
+                        Ok(None) => continue,
+                        Err(e) => {
+                            tracing::error!("Reading paste {id} to federate: {e}");
+                            continue;
+                        }
+                    }
+                }
+                Event::PasteDeleted(id) | Event::PasteExpired(id) if public.remove(&id) => {
+                    (id, None)
+                }
+                _ => continue,
+            };
+            for peer in &peers {
+                let url = format!("{}/federation/inbox", peer.trim_end_matches('/'));
+                let mut attempt = 1;
+                loop {
+                    // Signed anew for every attempt, to stay recent enough.
+                    let message = Message {
+                        id,
+                        sent: chrono::DateTime::<chrono::Utc>::from(service.clock().now())
+                            .format("%Y-%m-%dT%H:%M:%SZ")
+                            .to_string(),
+                        paste: paste.as_ref(),
+                    };
+                    let body = serde_json::to_vec(&message).expect("Messages serialize");
+                    let signature =
+                        base64::engine::general_purpose::STANDARD.encode(key.sign(&body));
+                    let request = client
+                        .post(&url)
+                        .header(reqwest::header::CONTENT_TYPE, "application/json")
+                        .header(KEY_HEADER, encode_key(&key))
+                        .header(SIGNATURE_HEADER, format!("ed25519={signature}"))
+                        .body(body);
+                    let delivered = async { request.send().await?.error_for_status() }.await;
+                    match delivered {
+                        Ok(_) => break,
+                        Err(e) if attempt < MAX_ATTEMPTS => {
+                            tracing::warn!("Pushing paste {id} to {peer} failed, retrying: {e}");
+                            tokio::time::sleep(Duration::from_secs(1 << attempt)).await;
+                            attempt += 1;
+                        }
+                        Err(e) => {
+                            tracing::error!("Giving up on pushing paste {id} to {peer}: {e}");
+                            break;
+                        }
+                    }
+                }
+            }
+        }
+    })
+}
+
+async fn own_public_pastes(service: &Service) -> anyhow::Result<HashSet<Uuid>> {
+    Ok(service
+        .recent_pastes(usize::MAX)
+        .await?
+        .into_iter()
+        .filter(|paste| paste.metadata.public && paste.metadata.origin.is_none())
+        .map(|paste| paste.id)
+        .collect())
+}
+
+/// Paste `id` as pushed to peers, if it's a public one of the instance's own.
+async fn paste(service: &Service, id: &Uuid) -> anyhow::Result<Option<Paste>> {
+    let Some(paste) = service.paste(id).await? else {
+        return Ok(None);
+    };
+    if !paste.metadata.public || paste.metadata.origin.is_some() {
+        return Ok(None);
+    }
+    let mut content = Vec::new();
+    service.read(id).await?.read_to_end(&mut content).await?;
+    Ok(Some(Paste {
+        title: paste.metadata.title,
+        language: paste.metadata.language,
+        content: base64::engine::general_purpose::STANDARD.encode(content),
+    }))
+}
+
+/// Keys of peers whose pastes are mirrored, in base64.
+#[derive(Clone, Default)]
+pub struct Trusted(Arc<HashSet<String>>);
+
+impl Trusted {
+    /// The --federation-trust keys, checked to be Ed25519 public keys.
+    pub fn new(keys: &[String]) -> anyhow::Result<Self> {
+        for key in keys {
+            let decoded = base64::engine::general_purpose::STANDARD.decode(key);
+            if !decoded.is_ok_and(|key| key.len() == 32) {
+                anyhow::bail!("Invalid --federation-trust key {key:?}");
+            }
+        }
+        Ok(Self(Arc::new(keys.iter().cloned().collect())))
+    }
+}
+
+/// `GET /federation/key` when the instance pushes to peers, for them to
+/// trust, and `POST /federation/inbox` when it trusts any.
+pub fn routes(key: Option<&Ed25519KeyPair>, trusted: &Trusted) -> Router<crate::AppState> {
+    let mut router = Router::new();
+    if let Some(key) = key {
+        let key = encode_key(key);
+        router = router.route(
+            "/federation/key",
+            get(move || std::future::ready(key.clone())),
+        );
+    }
+    if !trusted.0.is_empty() {
+        router = router.route("/federation/inbox", post(inbox));
+    }
+    router
+}
+
+async fn inbox(
+    State(service): State<Arc<Service>>,
+    State(trusted): State<Trusted>,
+    headers: HeaderMap,
+    body: Body,
+) -> Response {
+    let header = |name| headers.get(name).and_then(|value| value.to_str().ok());
+    let Some(key) = header(KEY_HEADER).filter(|key| trusted.0.contains(*key)) else {
+        return (StatusCode::FORBIDDEN, "Not a trusted peer").into_response();
+    };
+    let body = match axum::body::to_bytes(body, MAX_MESSAGE).await {
+        Ok(body) => body,
+        Err(e) => return (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    };
+    let verified = header(SIGNATURE_HEADER)
+        .and_then(|signature| signature.strip_prefix("ed25519="))
+        .and_then(|signature| {
+            base64::engine::general_purpose::STANDARD
+                .decode(signature)
+                .ok()
+        })
+        .zip(base64::engine::general_purpose::STANDARD.decode(key).ok())
+        .is_some_and(|(signature, key)| {
+            UnparsedPublicKey::new(&ED25519, key)
+                .verify(&body, &signature)
+                .is_ok()
+        });
+    if !verified {
+        return (StatusCode::UNAUTHORIZED, "Invalid signature").into_response();
+    }
+    let message: Message<Paste> = match serde_json::from_slice(&body) {
+        Ok(message) => message,
+        Err(e) => return (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    };
+    let fresh = chrono::DateTime::parse_from_rfc3339(&message.sent).is_ok_and(|sent| {
+        let sent = SystemTime::from(sent);
+        let now = service.clock().now();
+        now.duration_since(sent)
+            .or_else(|_| sent.duration_since(now))
+            .is_ok_and(|age| age <= MAX_AGE)
+    });
+    if !fresh {
+        return (StatusCode::BAD_REQUEST, "Stale message").into_response();
+    }
+    let Some(paste) = message.paste else {
+        return match service.unmirror(&message.id, key).await {
+            Ok(()) => StatusCode::OK.into_response(),
+            Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+        };
+    };
+    let content = match base64::engine::general_purpose::STANDARD.decode(&paste.content) {
+        Ok(content) => content,
+        Err(e) => return (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    };
+    let options = service::Options {
+        public: true,
+        title: paste.title,
+        language: paste.language,
+        ..Default::default()
+    };
+    if let Err(e) = options.validate() {
+        return (StatusCode::BAD_REQUEST, e.to_string()).into_response();
+    }
+    match service
+        .mirror(message.id, content.as_slice(), options, key.to_owned())
+        .await
+    {
+        Ok(()) => StatusCode::OK.into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
+#[tokio::test]
+async fn test_inbox_checks_signature_and_freshness() {
+    use ring::rand::SystemRandom;
+
+    let pkcs8 = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new()).unwrap();
+    let peer = Ed25519KeyPair::from_pkcs8(pkcs8.as_ref()).unwrap();
+    let trusted = encode_key(&peer);
+    let server = crate::testing::TestServer::start_with(|builder| {
+        builder.configure(|args| args.federation_trust = vec![trusted.clone()])
+    })
+    .await
+    .unwrap();
+
+    let id = Uuid::from_u128(1);
+    let message = |sent: chrono::DateTime<chrono::Utc>| {
+        serde_json::to_vec(&Message {
+            id,
+            sent: sent.to_rfc3339(),
+            paste: Some(Paste {
+                title: Some("Mirrored".to_owned()),
+                language: None,
+                content: base64::engine::general_purpose::STANDARD.encode("hello"),
+            }),
+        })
+        .unwrap()
+    };
+    let send = async |key: &str, signature: &[u8], body: Vec<u8>| {
+        let signature = base64::engine::general_purpose::STANDARD.encode(signature);
+        server
+            .client()
+            .post(server.url("/federation/inbox"))
+            .header(KEY_HEADER, key)
+            .header(SIGNATURE_HEADER, format!("ed25519={signature}"))
+            .body(body)
+            .send()
+            .await
+            .unwrap()
+            .status()
+    };
+
+    let now = chrono::DateTime::<chrono::Utc>::from(server.service.clock().now());
+    let body = message(now);
+    let pkcs8 = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new()).unwrap();
+    let stranger = Ed25519KeyPair::from_pkcs8(pkcs8.as_ref()).unwrap();
+    let status = send(
+        &encode_key(&stranger),
+        stranger.sign(&body).as_ref(),
+        body.clone(),
+    )
+    .await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    let status = send(&trusted, stranger.sign(&body).as_ref(), body.clone()).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    let stale = message(now - MAX_AGE * 2);
+    let status = send(&trusted, peer.sign(&stale).as_ref(), stale).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert!(!server.service.exists(&id));
+
+    let status = send(&trusted, peer.sign(&body).as_ref(), body).await;
+    assert_eq!(status, StatusCode::OK);
+    let mirrored = server.service.metadata(&id).await.unwrap().unwrap();
+    assert_eq!(mirrored.title.as_deref(), Some("Mirrored"));
+    assert_eq!(mirrored.origin, Some(trusted));
+}
@@ -0,0 +1,107 @@
+//! Atom feeds of the most recently written public pastes, of the whole
+//! instance and of each user.
+
+use std::{fmt::Write, sync::Arc, time::SystemTime};
+
+use axum::{
+    extract::{Path, Request, State},
+    http::{StatusCode, header},
+    response::{IntoResponse, Response},
+};
+
+use crate::{client, html::escape, public, service::Service};
+
+/// Entries in a feed.
+const ENTRIES: usize = 50;
+
+fn timestamp(time: SystemTime) -> String {
+    chrono::DateTime::<chrono::Utc>::from(time)
+        .format("%Y-%m-%dT%H:%M:%SZ")
+        .to_string()
+}
+
+async fn feed(
+    service: &Service,
+    cache: &public::Cache,
+    client: &client::Config,
+    request: Request,
+    owner: Option<&str>,
+) -> Response {
+    let (parts, _) = request.into_parts();
+    let Some(base_url) = client::base_url(&parts, client) else {
+        return (StatusCode::BAD_REQUEST, "Missing Host header").into_response();
+    };
+    let pastes = match cache.pastes(service).await {
+        Ok(pastes) => pastes,
+        Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    };
+    let entries: Vec<_> = pastes
+        .iter()
+        .filter(|paste| owner.is_none() || paste.metadata.owner.as_deref() == owner)
+        .take(ENTRIES)
+        .collect();
+
+    let self_url = escape(&format!("{base_url}{}", parts.uri.path()));
+    let base_url = escape(&base_url);
+    let title = match owner {
+        Some(owner) => format!("Pastes by {}", escape(owner)),
+        None => "Recent pastes".to_owned(),
+    };
+    let updated = entries
+        .first()
+        .map_or_else(|| service.clock().now(), |paste| paste.written);
+    let mut xml = String::new();
+    write!(
+        xml,
+        "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n\
+         <feed xmlns=\"http://www.w3.org/2005/Atom\">\n\
+         <id>{self_url}</id>\n<title>{title}</title>\n<updated>{}</updated>\n\
+         <link rel=\"self\" href=\"{self_url}\"/>\n<link href=\"{base_url}/\"/>\n",
+        timestamp(updated),
+    )
+    .unwrap();
+    for paste in entries {
+        let id = paste.id;
+        let title = match &paste.metadata.title {
+            Some(title) => escape(title),
+            None => format!("Paste {id}"),
+        };
+        let author = escape(paste.metadata.owner.as_deref().unwrap_or("anonymous"));
+        write!(
+            xml,
+            "<entry>\n<id>urn:uuid:{id}</id>\n<title>{title}</title>\n\
+             <updated>{}</updated>\n<author><name>{author}</name></author>\n\
+             <link href=\"{base_url}/paste/{id}/view\"/>\n",
+            timestamp(paste.written),
+        )
+        .unwrap();
+        if let Some(language) = &paste.metadata.language {
+            writeln!(xml, "<category term=\"{}\"/>", escape(language)).unwrap();
+        }
+        xml.push_str("</entry>\n");
+    }
+    xml.push_str("</feed>\n");
+    ([(header::CONTENT_TYPE, "application/atom+xml")], xml).into_response()
+}
+
+pub async fn instance(
+    State(service): State<Arc<Service>>,
+    State(cache): State<Arc<public::Cache>>,
+    State(client): State<Arc<client::Config>>,
+    request: Request,
+) -> Response {
+    feed(&service, &cache, &client, request, None).await
+}
+
+pub async fn user(
+    State(service): State<Arc<Service>>,
+    State(cache): State<Arc<public::Cache>>,
+    State(client): State<Arc<client::Config>>,
+    Path(username): Path<String>,
+    request: Request,
+) -> Response {
+    if !service.user_exists(&username) {
+        return (StatusCode::NOT_FOUND, "No such user").into_response();
+    }
+    feed(&service, &cache, &client, request, Some(&username)).await
+}
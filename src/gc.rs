@@ -0,0 +1,37 @@
+use std::{sync::Arc, time::Duration};
+
+use crate::{chunked, service::Service};
+
+pub struct Policy {
+    pub interval: Duration,
+    /// Anonymous pastes created longer ago than this are deleted.
+    pub anonymous_max_age: Option<Duration>,
+}
+
+/// Periodically deletes pastes that fall outside the retention `policy` or
+/// expired, and abandoned upload sessions.
+pub fn spawn(service: Arc<Service>, policy: Policy) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        loop {
+            if let Some(max_age) = policy.anonymous_max_age {
+                let cutoff = service.clock().now() - max_age;
+                match service.purge_anonymous(cutoff).await {
+                    Ok(0) => {}
+                    Ok(purged) => tracing::info!("Purged {purged} expired anonymous paste(s)"),
+                    Err(e) => tracing::error!("Garbage collection failed: {e}"),
+                }
+            }
+            match service.purge_expired(service.clock().now()).await {
+                Ok(0) => {}
+                Ok(purged) => tracing::info!("Purged {purged} expired paste(s)"),
+                Err(e) => tracing::error!("Purging expired pastes failed: {e}"),
+            }
+            match chunked::sweep(service.data_dir(), service.clock().now()).await {
+                Ok(0) => {}
+                Ok(swept) => tracing::info!("Removed {swept} abandoned upload session(s)"),
+                Err(e) => tracing::error!("Removing abandoned upload sessions failed: {e}"),
+            }
+            tokio::time::sleep(policy.interval).await;
+        }
+    })
+}
@@ -0,0 +1,191 @@
+//! A read-only mirror of the public pastes over the Gemini protocol:
+//! `gemini://host/` is a text/gemini index of them, newest first, and
+//! `gemini://host/paste/{id}` serves one as plain text. Gemini requires TLS,
+//! so this needs the --tls-cert certificate.
+
+use std::{fmt::Write as _, net::SocketAddr, sync::Arc, time::Duration};
+
+use axum_server::tls_rustls::RustlsConfig;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader},
+    net::TcpListener,
+};
+use tokio_rustls::TlsAcceptor;
+
+use crate::{
+    browse::PAGE_SIZE,
+    html, public,
+    rate_limit::Limits,
+    service::{Recent, Service},
+};
+
+/// Time a client has to send its request and read the response.
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Longest URL a request may have, as the specification sets it.
+const MAX_URL: usize = 1024;
+
+/// Serves requests on `listener` until the process exits. They count against
+/// the read rate limit of their address.
+pub async fn serve(
+    listener: TcpListener,
+    tls: RustlsConfig,
+    service: Arc<Service>,
+    cache: Arc<public::Cache>,
+    limits: Arc<Limits>,
+) {
+    loop {
+        let (stream, peer) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                tracing::warn!("Accepting a Gemini connection: {e}");
+                continue;
+            }
+        };
+        // Taken anew for every connection to pick up reloaded certificates.
+        // HTTP's ALPN protocols would make clients offering none fail.
+        let mut config = (*tls.get_inner()).clone();
+        config.alpn_protocols.clear();
+        let acceptor = TlsAcceptor::from(Arc::new(config));
+        let (service, cache, limits) = (service.clone(), cache.clone(), limits.clone());
+        tokio::spawn(async move {
+            let served = tokio::time::timeout(TIMEOUT, async {
+                let mut stream = acceptor.accept(stream).await?;
+                respond(&mut stream, peer, &service, &cache, &limits).await?;
+                stream.shutdown().await
+            });
+            match served.await {
+                Ok(Ok(())) => {}
+                Ok(Err(e)) => tracing::debug!("Gemini request from {peer}: {e}"),
+                Err(_) => tracing::debug!("Gemini request from {peer} timed out"),
+            }
+        });
+    }
+}
+
+async fn respond(
+    stream: &mut (impl AsyncRead + AsyncWrite + Unpin),
+    peer: SocketAddr,
+    service: &Service,
+    cache: &public::Cache,
+    limits: &Limits,
+) -> std::io::Result<()> {
+    let mut line = Vec::new();
+    BufReader::new(&mut *stream)
+        .take(MAX_URL as u64 + 2)
+        .read_until(b'\n', &mut line)
+        .await?;
+    let Some(url) = std::str::from_utf8(&line)
+        .ok()
+        .and_then(|line| line.strip_suffix("\r\n"))
+    else {
+        return header(stream, 59, "Bad request").await;
+    };
+    let Some(path) = path(url) else {
+        if url.contains("://") && !url.starts_with("gemini://") {
+            return header(stream, 53, "Only gemini:// URLs are served").await;
+        }
+        return header(stream, 59, "Bad request").await;
+    };
+    if let Err(throttled) = limits.read.check(peer.ip()) {
+        let seconds = throttled.retry_after.as_secs().max(1);
+        return header(stream, 44, &seconds.to_string()).await;
+    }
+    let page = match path.as_str() {
+        "/" => Some(1),
+        path => path
+            .strip_prefix("/page/")
+            .and_then(|page| page.parse().ok()),
+    };
+    if let Some(page) = page {
+        return match cache.pastes(service).await {
+            Ok(pastes) => match index(&pastes, page) {
+                Some(index) => {
+                    header(stream, 20, "text/gemini; charset=utf-8").await?;
+                    stream.write_all(index.as_bytes()).await
+                }
+                None => header(stream, 51, "No such page").await,
+            },
+            Err(e) => {
+                tracing::error!("Listing public pastes for Gemini: {e}");
+                header(stream, 40, "Couldn't list the pastes").await
+            }
+        };
+    }
+    let Some(id) = path
+        .strip_prefix("/paste/")
+        .and_then(|id| uuid::Uuid::parse_str(id).ok())
+    else {
+        return header(stream, 51, "Not found").await;
+    };
+    // Unlisted pastes aren't mirrored, even by ID.
+    let reader = match service.paste(&id).await {
+        Ok(Some(paste)) if paste.metadata.public => service.read(&id).await,
+        Ok(_) => return header(stream, 51, "Not found").await,
+        Err(e) => Err(e),
+    };
+    match reader {
+        Ok(mut reader) => {
+            header(stream, 20, "text/plain; charset=utf-8").await?;
+            tokio::io::copy(&mut reader, stream).await.map(drop)
+        }
+        Err(e) => {
+            tracing::error!("Reading paste {id} for Gemini: {e}");
+            header(stream, 40, "Couldn't read the paste").await
+        }
+    }
+}
+
+/// The decoded path of a `gemini://` request URL, `/` if it has none.
+fn path(url: &str) -> Option<String> {
+    let rest = url.strip_prefix("gemini://")?;
+    let path = match rest.find('/') {
+        Some(start) => &rest[start..],
+        None => "/",
+    };
+    let path = path.split(['?', '#']).next().unwrap_or_default();
+    html::decode_percent(path)
+}
+
+async fn header(
+    stream: &mut (impl AsyncWrite + Unpin),
+    status: u8,
+    meta: &str,
+) -> std::io::Result<()> {
+    stream
+        .write_all(format!("{status} {meta}\r\n").as_bytes())
+        .await
+}
+
+/// Page `page` of the index of `pastes`, if there's such a page.
+fn index(pastes: &[Recent], page: usize) -> Option<String> {
+    let pages = pastes.len().div_ceil(PAGE_SIZE).max(1);
+    if page == 0 || page > pages {
+        return None;
+    }
+    let mut index = String::from("# Public pastes\n\n");
+    if pastes.is_empty() {
+        index.push_str("There are no public pastes yet.\n");
+    }
+    for paste in pastes.iter().skip((page - 1) * PAGE_SIZE).take(PAGE_SIZE) {
+        let written = chrono::DateTime::<chrono::Utc>::from(paste.written).format("%Y-%m-%d");
+        let title = paste.metadata.title.as_deref().unwrap_or("Untitled");
+        // A line break would end the link line early.
+        let title = title.replace(char::is_control, " ");
+        write!(index, "=> /paste/{} {written} {title}", paste.id).unwrap();
+        if let Some(language) = &paste.metadata.language {
+            write!(index, " ({language})").unwrap();
+        }
+        index.push('\n');
+    }
+    if pages > 1 {
+        index.push('\n');
+        if page > 1 {
+            writeln!(index, "=> /page/{} Newer pastes", page - 1).unwrap();
+        }
+        if page < pages {
+            writeln!(index, "=> /page/{} Older pastes", page + 1).unwrap();
+        }
+    }
+    Some(index)
+}
@@ -0,0 +1,135 @@
+//! Mirroring of pastes to GitHub gists. Pastes created with `?gist=true`
+//! get a secret gist with the same contents, which is updated whenever the
+//! paste is replaced. The gist's URL is recorded in the paste's metadata.
+
+use std::{sync::Arc, time::Duration};
+
+use serde::Deserialize;
+use tokio::io::AsyncReadExt;
+use uuid::Uuid;
+
+use crate::{
+    events::{Event, Subscription},
+    service::Service,
+};
+
+const MAX_ATTEMPTS: u32 = 5;
+
+pub struct Config {
+    pub token: String,
+    /// E.g. `https://api.github.com`.
+    pub api_url: String,
+}
+
+/// The parts of a gist the API returns that are needed here.
+#[derive(Deserialize)]
+struct Gist {
+    html_url: String,
+    #[serde(default)]
+    files: serde_json::Map<String, serde_json::Value>,
+}
+
+/// Creates or updates the gists of the pastes among `events` in the
+/// background, retrying failed requests a few times. If GitHub is slow
+/// enough for events to pile up past the bus's backlog, the oldest are
+/// skipped, as counted in `pastebin_events_dropped_total`, and their gists
+/// are only brought up to date by the paste's next update.
+pub fn spawn(
+    mut events: Subscription,
+    service: Arc<Service>,
+    config: Config,
+) -> tokio::task::JoinHandle<()> {
+    let client = reqwest::Client::new();
+    tokio::spawn(async move {
+        while let Some(emitted) = events.recv().await {
+            let (Event::PasteCreated(id) | Event::PasteUpdated(id)) = emitted.event else {
+                continue;
+            };
+            let mut attempt = 1;
+            loop {
+                match sync(&client, &config, &service, &id).await {
+                    Ok(()) => break,
+                    Err(e) if attempt < MAX_ATTEMPTS => {
+                        tracing::warn!("Mirroring paste {id} to a gist failed, retrying: {e}");
+                        tokio::time::sleep(Duration::from_secs(1 << attempt)).await;
+                        attempt += 1;
+                    }
+                    Err(e) => {
+                        tracing::error!("Giving up on mirroring paste {id} to a gist: {e}");
+                        break;
+                    }
+                }
+            }
+        }
+    })
+}
+
+/// Brings the gist of paste `id` up to date, if it's to have one.
+async fn sync(
+    client: &reqwest::Client,
+    config: &Config,
+    service: &Service,
+    id: &Uuid,
+) -> anyhow::Result<()> {
+    let Some(metadata) = service.metadata(id).await? else {
+        return Ok(());
+    };
+    if !metadata.gist {
+        return Ok(());
+    }
+    let mut content = Vec::new();
+    service.read(id).await?.read_to_end(&mut content).await?;
+    // Gists only hold text, and their file names can't have slashes.
+    let content = String::from_utf8_lossy(&content);
+    let filename = match &metadata.title {
+        Some(title) => title.replace('/', "-"),
+        None => format!("{id}.txt"),
+    };
+    let description = metadata.title.as_deref().unwrap_or("Paste");
+    let request = |method, url: String| {
+        client
+            .request(method, url)
+            .bearer_auth(&config.token)
+            .header(reqwest::header::ACCEPT, "application/vnd.github+json")
+            .header(reqwest::header::USER_AGENT, "pastebin")
+            .header("x-github-api-version", "2022-11-28")
+    };
+
+    let Some(url) = metadata.gist_url else {
+        let body = serde_json::json!({
+            "description": description,
+            "public": false,
+            "files": {filename: {"content": content}},
+        });
+        let gist: Gist = request(reqwest::Method::POST, format!("{}/gists", config.api_url))
+            .json(&body)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        tracing::info!("Mirrored paste {id} to {}", gist.html_url);
+        return service.record_gist(id, gist.html_url).await;
+    };
+    let gist_id = url.rsplit('/').next().unwrap_or_default();
+    let gist_url = format!("{}/gists/{gist_id}", config.api_url);
+    // Files named after an earlier title go, so that the gist keeps one.
+    let gist: Gist = request(reqwest::Method::GET, gist_url.clone())
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+    let mut files = serde_json::Map::new();
+    for name in gist.files.keys().filter(|name| **name != filename) {
+        files.insert(name.clone(), serde_json::Value::Null);
+    }
+    files.insert(filename, serde_json::json!({"content": content}));
+    let body = serde_json::json!({"description": description, "files": files});
+    request(reqwest::Method::PATCH, gist_url)
+        .json(&body)
+        .send()
+        .await?
+        .error_for_status()?;
+    Ok(())
+}
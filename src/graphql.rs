@@ -0,0 +1,1187 @@
+//! `/graphql`, a read-only GraphQL API over users, pastes and their
+//! metadata, letting frontends ask for exactly the fields they show.
+//!
+//! There's no GraphQL library to be had, so this implements the part of the
+//! language queries need: fields with arguments and aliases, variables,
+//! fragments, and the `@skip` and `@include` directives. There are no
+//! mutations, subscriptions or introspection beyond `__typename`, and a field
+//! that fails is null even if its type isn't. A query may cost at most
+//! [`MAX_COST`]: a point per field resolved, including each of those of every
+//! paste in a list, and one per KiB of content. The schema:
+//!
+//! ```graphql
+//! type Query {
+//!   paste(id: ID!): Paste
+//!   "Public pastes, newest first."
+//!   pastes(page: Int = 1): [Paste!]!
+//!   "Public pastes whose title or language contains `query`."
+//!   search(query: String!, limit: Int = 50): [Paste!]!
+//!   trending(limit: Int = 50): [Paste!]!
+//!   user(username: String!): User
+//!   "The user whose credentials came with the request."
+//!   me: User
+//! }
+//!
+//! type Paste {
+//!   id: ID!
+//!   title: String
+//!   language: String
+//!   size: Int!
+//!   written: String!
+//!   public: Boolean!
+//!   noindex: Boolean!
+//!   "Only known for public pastes and to the owner."
+//!   owner: User
+//!   views: Float!
+//!   "Up to 1 MiB, decoded as UTF-8."
+//!   content: String
+//! }
+//!
+//! type User {
+//!   username: String!
+//!   "Public pastes, or all of them for the user themselves."
+//!   pastes(page: Int = 1): [Paste!]!
+//! }
+//! ```
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, OnceLock},
+};
+
+use axum::{
+    Json,
+    extract::{Query, State},
+    http::{StatusCode, header},
+    response::{IntoResponse, Response},
+};
+use futures::future::BoxFuture;
+use parking_lot::Mutex;
+use serde::Deserialize;
+use serde_json::{Map, Value as JsonValue};
+use tokio::io::AsyncReadExt;
+use uuid::Uuid;
+
+use crate::{
+    auth::BasicAuth,
+    public,
+    service::{Recent, Service},
+};
+
+/// Pastes per page of a listing.
+const PAGE_SIZE: usize = 50;
+
+/// Most pastes `search` and `trending` return.
+const MAX_LIMIT: usize = 100;
+
+/// Deepest selection answered, which keeps e.g. alternating owners and
+/// their pastes from making a query arbitrarily expensive.
+const MAX_DEPTH: usize = 8;
+
+/// Bytes of a paste returned as its content.
+const CONTENT_LIMIT: u64 = 1024 * 1024;
+
+/// Most a query may cost, so that aliases and lists can't make it as
+/// expensive as they like within [`MAX_DEPTH`].
+const MAX_COST: u64 = 10_000;
+
+/// Deepest nesting of braces and brackets parsed.
+const MAX_NESTING: usize = 64;
+
+#[derive(Deserialize)]
+pub struct Request {
+    query: String,
+    #[serde(default)]
+    variables: Option<Map<String, JsonValue>>,
+    #[serde(default, rename = "operationName")]
+    operation_name: Option<String>,
+}
+
+/// `GET /graphql?query=...&variables=...`, where the variables are JSON.
+#[derive(Deserialize)]
+pub struct GetRequest {
+    query: String,
+    variables: Option<String>,
+    #[serde(rename = "operationName")]
+    operation_name: Option<String>,
+}
+
+pub async fn get(
+    service: State<Arc<Service>>,
+    cache: State<Arc<public::Cache>>,
+    auth: Option<BasicAuth>,
+    Query(request): Query<GetRequest>,
+) -> Response {
+    let variables = match request.variables.as_deref().map(serde_json::from_str) {
+        None => None,
+        Some(Ok(variables)) => Some(variables),
+        Some(Err(e)) => {
+            return (StatusCode::BAD_REQUEST, format!("Invalid variables: {e}")).into_response();
+        }
+    };
+    let request = Request {
+        query: request.query,
+        variables,
+        operation_name: request.operation_name,
+    };
+    post(service, cache, auth, Json(request)).await
+}
+
+pub async fn post(
+    State(service): State<Arc<Service>>,
+    State(cache): State<Arc<public::Cache>>,
+    auth: Option<BasicAuth>,
+    Json(request): Json<Request>,
+) -> Response {
+    if let Some(auth) = &auth
+        && !service.authenticate(&auth.username, &auth.password)
+    {
+        return (
+            StatusCode::UNAUTHORIZED,
+            [(header::WWW_AUTHENTICATE, "Basic")],
+            "Not authorized",
+        )
+            .into_response();
+    }
+    let document = match Parser::parse(&request.query) {
+        Ok(document) => document,
+        Err(message) => {
+            return Json(serde_json::json!({"errors": [{"message": message}]})).into_response();
+        }
+    };
+    let operation = match document.operation(request.operation_name.as_deref()) {
+        Ok(operation) => operation,
+        Err(message) => {
+            return Json(serde_json::json!({"errors": [{"message": message}]})).into_response();
+        }
+    };
+    let variables = request.variables.unwrap_or_default();
+    let context = Context {
+        service: &service,
+        cache: &cache,
+        viewer: auth,
+        variables: operation
+            .variables
+            .iter()
+            .map(|(name, default)| {
+                let value = variables.get(name).cloned();
+                let value = value.or_else(|| default.as_ref().map(Value::to_json));
+                (name.clone(), value.unwrap_or(JsonValue::Null))
+            })
+            .collect(),
+        fragments: &document.fragments,
+        errors: Mutex::default(),
+        spent: Mutex::default(),
+        trending: OnceLock::new(),
+        views: OnceLock::new(),
+    };
+    let data = resolve(&context, &Object::Query, &operation.selections, Vec::new()).await;
+    let mut response = serde_json::json!({ "data": data });
+    let errors = context.errors.into_inner();
+    if !errors.is_empty() {
+        response["errors"] = errors.into();
+    }
+    Json(response).into_response()
+}
+
+struct Context<'a> {
+    service: &'a Service,
+    cache: &'a public::Cache,
+    /// Credentials the request came with, already verified.
+    viewer: Option<BasicAuth>,
+    variables: HashMap<String, JsonValue>,
+    fragments: &'a HashMap<String, Fragment>,
+    errors: Mutex<Vec<JsonValue>>,
+    /// What answering the query has cost so far.
+    spent: Mutex<u64>,
+    /// The trending pastes and the views of each, only worked out once.
+    trending: OnceLock<Vec<(Uuid, f64)>>,
+    views: OnceLock<HashMap<Uuid, f64>>,
+}
+
+enum Object {
+    Query,
+    Paste(Recent),
+    User(String),
+}
+
+impl Object {
+    fn type_name(&self) -> &'static str {
+        match self {
+            Object::Query => "Query",
+            Object::Paste(_) => "Paste",
+            Object::User(_) => "User",
+        }
+    }
+}
+
+/// What a field resolves to, before any selection on it is applied.
+enum Resolved {
+    Leaf(JsonValue),
+    Object(Option<Object>),
+    List(Vec<Object>),
+}
+
+impl From<Option<String>> for Resolved {
+    fn from(value: Option<String>) -> Self {
+        Resolved::Leaf(value.into())
+    }
+}
+
+/// Resolves `selections` on `object`, recording errors in `context`.
+fn resolve<'a>(
+    context: &'a Context<'a>,
+    object: &'a Object,
+    selections: &'a [Selection],
+    path: Vec<JsonValue>,
+) -> BoxFuture<'a, JsonValue> {
+    Box::pin(async move {
+        let mut fields = Vec::new();
+        if let Err(message) = context.collect(object.type_name(), selections, &mut fields) {
+            context
+                .errors
+                .lock()
+                .push(serde_json::json!({"message": message, "path": path}));
+            return JsonValue::Null;
+        }
+        let mut data = Map::new();
+        for field in fields {
+            let key = field.alias.as_ref().unwrap_or(&field.name);
+            let mut path = path.clone();
+            path.push(key.as_str().into());
+            let value = match field_value(context, object, field, &path).await {
+                Ok(value) => value,
+                Err(message) => {
+                    context
+                        .errors
+                        .lock()
+                        .push(serde_json::json!({"message": message, "path": path}));
+                    JsonValue::Null
+                }
+            };
+            data.insert(key.clone(), value);
+        }
+        data.into()
+    })
+}
+
+async fn field_value(
+    context: &Context<'_>,
+    object: &Object,
+    field: &Field,
+    path: &[JsonValue],
+) -> Result<JsonValue, String> {
+    if path.iter().filter(|segment| segment.is_string()).count() > MAX_DEPTH {
+        return Err(format!("Selections can only be {MAX_DEPTH} deep"));
+    }
+    let resolved = if field.name == "__typename" {
+        Resolved::Leaf(object.type_name().into())
+    } else {
+        match object {
+            Object::Query => query_field(context, field).await?,
+            Object::Paste(paste) => paste_field(context, paste, field).await?,
+            Object::User(username) => user_field(context, username, field).await?,
+        }
+    };
+    match resolved {
+        Resolved::Leaf(value) if field.selections.is_empty() => Ok(value),
+        Resolved::Leaf(_) => Err(format!("{} has no fields to select", field.name)),
+        _ if field.selections.is_empty() => Err(format!("{} needs a selection", field.name)),
+        Resolved::Object(None) => Ok(JsonValue::Null),
+        Resolved::Object(Some(object)) => {
+            Ok(resolve(context, &object, &field.selections, path.to_vec()).await)
+        }
+        Resolved::List(objects) => {
+            let mut values = Vec::with_capacity(objects.len());
+            for (i, object) in objects.iter().enumerate() {
+                let mut path = path.to_vec();
+                path.push(i.into());
+                values.push(resolve(context, object, &field.selections, path).await);
+            }
+            Ok(values.into())
+        }
+    }
+}
+
+async fn query_field(context: &Context<'_>, field: &Field) -> Result<Resolved, String> {
+    let service = context.service;
+    Ok(match field.name.as_str() {
+        "paste" => {
+            let id = context.string_argument(field, "id")?;
+            let id: Uuid = id.parse().map_err(|e| format!("Invalid paste ID: {e}"))?;
+            let paste = service.paste(&id).await.map_err(|e| e.to_string())?;
+            Resolved::Object(paste.map(Object::Paste))
+        }
+        "pastes" => {
+            let page = context.page_argument(field)?;
+            let pastes = context.public_pastes().await?;
+            Resolved::List(page_of(pastes.iter().cloned(), page))
+        }
+        "search" => {
+            let query = context.string_argument(field, "query")?.to_lowercase();
+            let limit = context.limit_argument(field)?;
+            let matches = |text: &Option<String>| {
+                text.as_ref()
+                    .is_some_and(|text| text.to_lowercase().contains(&query))
+            };
+            let pastes = context.public_pastes().await?;
+            Resolved::List(
+                pastes
+                    .iter()
+                    .filter(|paste| {
+                        matches(&paste.metadata.title) || matches(&paste.metadata.language)
+                    })
+                    .take(limit)
+                    .cloned()
+                    .map(Object::Paste)
+                    .collect(),
+            )
+        }
+        "trending" => {
+            let limit = context.limit_argument(field)?;
+            let pastes = context.public_pastes().await?;
+            let pastes: HashMap<_, _> = pastes.iter().map(|paste| (paste.id, paste)).collect();
+            Resolved::List(
+                context
+                    .trending()
+                    .iter()
+                    .filter_map(|(id, _)| {
+                        pastes.get(id).map(|paste| Object::Paste((*paste).clone()))
+                    })
+                    .take(limit)
+                    .collect(),
+            )
+        }
+        "user" => {
+            let username = context.string_argument(field, "username")?;
+            Resolved::Object(
+                service
+                    .user_exists(&username)
+                    .then_some(Object::User(username)),
+            )
+        }
+        "me" => Resolved::Object(
+            context
+                .viewer
+                .as_ref()
+                .map(|viewer| Object::User(viewer.username.clone())),
+        ),
+        name => return Err(format!("Query has no field {name}")),
+    })
+}
+
+async fn paste_field(
+    context: &Context<'_>,
+    paste: &Recent,
+    field: &Field,
+) -> Result<Resolved, String> {
+    let metadata = &paste.metadata;
+    Ok(match field.name.as_str() {
+        "id" => Resolved::Leaf(paste.id.to_string().into()),
+        "title" => metadata.title.clone().into(),
+        "language" => metadata.language.clone().into(),
+        "size" => Resolved::Leaf(paste.size.into()),
+        "written" => Resolved::Leaf(
+            chrono::DateTime::<chrono::Utc>::from(paste.written)
+                .format("%Y-%m-%dT%H:%M:%SZ")
+                .to_string()
+                .into(),
+        ),
+        "public" => Resolved::Leaf(metadata.public.into()),
+        "noindex" => Resolved::Leaf(metadata.noindex.into()),
+        "owner" => {
+            let viewer = context.viewer.as_ref().map(|viewer| &viewer.username);
+            let visible =
+                metadata.public || (viewer.is_some() && metadata.owner.as_ref() == viewer);
+            Resolved::Object(metadata.owner.clone().filter(|_| visible).map(Object::User))
+        }
+        "views" => {
+            let views = context.views().get(&paste.id).copied();
+            Resolved::Leaf(views.unwrap_or(0.0).into())
+        }
+        "content" => {
+            context.charge(paste.size.min(CONTENT_LIMIT) / 1024)?;
+            let reader = context
+                .service
+                .read(&paste.id)
+                .await
+                .map_err(|e| e.to_string())?;
+            let mut contents = Vec::new();
+            reader
+                .take(CONTENT_LIMIT + 1)
+                .read_to_end(&mut contents)
+                .await
+                .map_err(|e| e.to_string())?;
+            if contents.len() as u64 > CONTENT_LIMIT {
+                return Err(format!(
+                    "Paste is larger than {CONTENT_LIMIT} bytes, get /paste/{} instead",
+                    paste.id
+                ));
+            }
+            Some(String::from_utf8_lossy(&contents).into_owned()).into()
+        }
+        name => return Err(format!("Paste has no field {name}")),
+    })
+}
+
+async fn user_field(
+    context: &Context<'_>,
+    username: &str,
+    field: &Field,
+) -> Result<Resolved, String> {
+    Ok(match field.name.as_str() {
+        "username" => Resolved::Leaf(username.into()),
+        "pastes" => {
+            let page = context.page_argument(field)?;
+            match &context.viewer {
+                Some(viewer) if viewer.username == username => {
+                    let ids = context
+                        .service
+                        .list(&viewer.username, &viewer.password)
+                        .map_err(|e| e.to_string())?;
+                    let mut pastes = Vec::new();
+                    for id in ids.iter().filter_map(|id| id.parse().ok()) {
+                        if let Some(paste) = context
+                            .service
+                            .paste(&id)
+                            .await
+                            .map_err(|e| e.to_string())?
+                        {
+                            pastes.push(paste);
+                        }
+                    }
+                    pastes.sort_by_key(|paste| std::cmp::Reverse(paste.written));
+                    Resolved::List(page_of(pastes.into_iter(), page))
+                }
+                _ => {
+                    let pastes = context.public_pastes().await?;
+                    let owned = pastes
+                        .iter()
+                        .filter(|paste| paste.metadata.owner.as_deref() == Some(username))
+                        .cloned();
+                    Resolved::List(page_of(owned, page))
+                }
+            }
+        }
+        name => return Err(format!("User has no field {name}")),
+    })
+}
+
+fn page_of(pastes: impl Iterator<Item = Recent>, page: usize) -> Vec<Object> {
+    pastes
+        .skip((page - 1) * PAGE_SIZE)
+        .take(PAGE_SIZE)
+        .map(Object::Paste)
+        .collect()
+}
+
+impl Context<'_> {
+    /// Counts `cost` against [`MAX_COST`], failing once it's spent.
+    fn charge(&self, cost: u64) -> Result<(), String> {
+        let mut spent = self.spent.lock();
+        *spent += cost;
+        if *spent > MAX_COST {
+            return Err(format!("Query costs more than {MAX_COST}"));
+        }
+        Ok(())
+    }
+
+    fn trending(&self) -> &[(Uuid, f64)] {
+        self.trending.get_or_init(|| self.service.trending())
+    }
+
+    fn views(&self) -> &HashMap<Uuid, f64> {
+        self.views
+            .get_or_init(|| self.trending().iter().copied().collect())
+    }
+
+    async fn public_pastes(&self) -> Result<Arc<Vec<Recent>>, String> {
+        self.cache
+            .pastes(self.service)
+            .await
+            .map_err(|e| e.to_string())
+    }
+
+    fn argument(&self, field: &Field, name: &str) -> JsonValue {
+        field
+            .arguments
+            .iter()
+            .find(|(argument, _)| argument == name)
+            .map(|(_, value)| self.value(value))
+            .unwrap_or(JsonValue::Null)
+    }
+
+    fn string_argument(&self, field: &Field, name: &str) -> Result<String, String> {
+        match self.argument(field, name) {
+            JsonValue::String(value) => Ok(value),
+            JsonValue::Null => Err(format!("{} requires {name}", field.name)),
+            value => Err(format!("{name} must be a string, not {value}")),
+        }
+    }
+
+    fn int_argument(&self, field: &Field, name: &str, default: u64) -> Result<u64, String> {
+        match self.argument(field, name) {
+            JsonValue::Null => Ok(default),
+            value => value
+                .as_u64()
+                .ok_or_else(|| format!("{name} must be a non-negative integer, not {value}")),
+        }
+    }
+
+    fn page_argument(&self, field: &Field) -> Result<usize, String> {
+        match self.int_argument(field, "page", 1)? {
+            0 => Err("Pages start at 1".to_owned()),
+            page => Ok(page as usize),
+        }
+    }
+
+    fn limit_argument(&self, field: &Field) -> Result<usize, String> {
+        Ok((self.int_argument(field, "limit", 50)? as usize).min(MAX_LIMIT))
+    }
+
+    fn value(&self, value: &Value) -> JsonValue {
+        match value {
+            Value::Variable(name) => self.variables.get(name).cloned().unwrap_or(JsonValue::Null),
+            Value::List(values) => values.iter().map(|value| self.value(value)).collect(),
+            Value::Object(fields) => fields
+                .iter()
+                .map(|(name, value)| (name.clone(), self.value(value)))
+                .collect::<Map<_, _>>()
+                .into(),
+            value => value.to_json(),
+        }
+    }
+
+    /// Whether the directives on a selection leave it in.
+    fn included(&self, directives: &[Directive]) -> bool {
+        directives.iter().all(|directive| {
+            let condition = directive
+                .arguments
+                .iter()
+                .find(|(name, _)| name == "if")
+                .map(|(_, value)| self.value(value));
+            match directive.name.as_str() {
+                "skip" => condition != Some(JsonValue::Bool(true)),
+                "include" => condition == Some(JsonValue::Bool(true)),
+                _ => true,
+            }
+        })
+    }
+
+    /// The fields selected on an object of type `type_name`, with fragments
+    /// spread, charging a point for each field and spread.
+    fn collect<'a>(
+        &'a self,
+        type_name: &str,
+        selections: &'a [Selection],
+        fields: &mut Vec<&'a Field>,
+    ) -> Result<(), String> {
+        for selection in selections {
+            match selection {
+                Selection::Field(field) => {
+                    if self.included(&field.directives) {
+                        self.charge(1)?;
+                        fields.push(field);
+                    }
+                }
+                Selection::Spread { name, directives } => {
+                    if let Some(fragment) = self.fragments.get(name)
+                        && fragment.on == type_name
+                        && self.included(directives)
+                    {
+                        self.charge(1)?;
+                        self.collect(type_name, &fragment.selections, fields)?;
+                    }
+                }
+                Selection::Inline {
+                    on,
+                    directives,
+                    selections,
+                } => {
+                    if on.as_deref().is_none_or(|on| on == type_name) && self.included(directives) {
+                        self.collect(type_name, selections, fields)?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+struct Document {
+    operations: Vec<Operation>,
+    fragments: HashMap<String, Fragment>,
+}
+
+impl Document {
+    fn operation(&self, name: Option<&str>) -> Result<&Operation, String> {
+        let operation = match name {
+            Some(name) => self
+                .operations
+                .iter()
+                .find(|operation| operation.name.as_deref() == Some(name))
+                .ok_or_else(|| format!("No operation named {name}"))?,
+            None => match self.operations.as_slice() {
+                [operation] => operation,
+                _ => return Err("operationName is required with several operations".to_owned()),
+            },
+        };
+        if operation.kind != "query" {
+            return Err(format!(
+                "Only queries are supported, not {}s",
+                operation.kind
+            ));
+        }
+        Ok(operation)
+    }
+
+    /// How many spreads deep fragment `name` goes, failing if it spreads
+    /// itself, which would recurse until the stack overflows, or goes deeper
+    /// than [`MAX_DEPTH`]. `path` holds the fragments being spread.
+    fn spread_depth<'a>(
+        &'a self,
+        name: &'a str,
+        path: &mut Vec<&'a str>,
+        depths: &mut HashMap<&'a str, usize>,
+    ) -> Result<usize, String> {
+        if path.contains(&name) {
+            return Err(format!("Fragment {name} spreads itself"));
+        }
+        if let Some(depth) = depths.get(name) {
+            return Ok(*depth);
+        }
+        let Some(fragment) = self.fragments.get(name) else {
+            return Ok(0);
+        };
+        path.push(name);
+        let mut spreads = Vec::new();
+        spreads_in(&fragment.selections, &mut spreads);
+        let mut depth = 0;
+        for spread in spreads {
+            depth = depth.max(1 + self.spread_depth(spread, path, depths)?);
+            if depth > MAX_DEPTH {
+                return Err(format!("Fragments can only spread {MAX_DEPTH} deep"));
+            }
+        }
+        path.pop();
+        depths.insert(name, depth);
+        Ok(depth)
+    }
+}
+
+/// The names of the fragments spread in `selections`, at any depth.
+fn spreads_in<'a>(selections: &'a [Selection], spreads: &mut Vec<&'a str>) {
+    for selection in selections {
+        match selection {
+            Selection::Field(field) => spreads_in(&field.selections, spreads),
+            Selection::Spread { name, .. } => spreads.push(name),
+            Selection::Inline { selections, .. } => spreads_in(selections, spreads),
+        }
+    }
+}
+
+struct Operation {
+    kind: String,
+    name: Option<String>,
+    /// Variables, with their defaults.
+    variables: Vec<(String, Option<Value>)>,
+    selections: Vec<Selection>,
+}
+
+struct Fragment {
+    on: String,
+    selections: Vec<Selection>,
+}
+
+enum Selection {
+    Field(Field),
+    Spread {
+        name: String,
+        directives: Vec<Directive>,
+    },
+    Inline {
+        on: Option<String>,
+        directives: Vec<Directive>,
+        selections: Vec<Selection>,
+    },
+}
+
+struct Field {
+    alias: Option<String>,
+    name: String,
+    arguments: Vec<(String, Value)>,
+    directives: Vec<Directive>,
+    selections: Vec<Selection>,
+}
+
+struct Directive {
+    name: String,
+    arguments: Vec<(String, Value)>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Value {
+    Variable(String),
+    Int(i64),
+    Float(f64),
+    String(String),
+    Boolean(bool),
+    Null,
+    Enum(String),
+    List(Vec<Value>),
+    Object(Vec<(String, Value)>),
+}
+
+impl Value {
+    /// The value as JSON, with variables as null.
+    fn to_json(&self) -> JsonValue {
+        match self {
+            Value::Variable(_) | Value::Null => JsonValue::Null,
+            Value::Int(n) => (*n).into(),
+            Value::Float(n) => (*n).into(),
+            Value::String(s) | Value::Enum(s) => s.as_str().into(),
+            Value::Boolean(b) => (*b).into(),
+            Value::List(values) => values.iter().map(Value::to_json).collect(),
+            Value::Object(fields) => fields
+                .iter()
+                .map(|(name, value)| (name.clone(), value.to_json()))
+                .collect::<Map<_, _>>()
+                .into(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Punctuator(char),
+    Spread,
+    Name(String),
+    Int(i64),
+    Float(f64),
+    String(String),
+}
+
+fn tokenize(source: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = source.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            // Commas are insignificant, like whitespace.
+            ' ' | '\t' | '\n' | '\r' | ',' | '\u{feff}' => {}
+            '#' => while chars.next_if(|&c| c != '\n' && c != '\r').is_some() {},
+            '!' | '$' | '&' | '(' | ')' | ':' | '=' | '@' | '[' | ']' | '{' | '|' | '}' => {
+                tokens.push(Token::Punctuator(c));
+            }
+            '.' => {
+                if chars.next() != Some('.') || chars.next() != Some('.') {
+                    return Err("Expected ...".to_owned());
+                }
+                tokens.push(Token::Spread);
+            }
+            '"' => {
+                if chars.peek() == Some(&'"') {
+                    chars.next();
+                    if chars.peek() == Some(&'"') {
+                        return Err("Block strings aren't supported".to_owned());
+                    }
+                    tokens.push(Token::String(String::new()));
+                    continue;
+                }
+                let mut string = String::new();
+                loop {
+                    match chars.next() {
+                        None | Some('\n' | '\r') => return Err("Unterminated string".to_owned()),
+                        Some('"') => break,
+                        Some('\\') => string.push(match chars.next() {
+                            Some('n') => '\n',
+                            Some('r') => '\r',
+                            Some('t') => '\t',
+                            Some('b') => '\u{8}',
+                            Some('f') => '\u{c}',
+                            Some('u') => {
+                                let hex: String = chars.by_ref().take(4).collect();
+                                u32::from_str_radix(&hex, 16)
+                                    .ok()
+                                    .and_then(char::from_u32)
+                                    .ok_or_else(|| format!("Invalid escape \\u{hex}"))?
+                            }
+                            Some(c @ ('"' | '\\' | '/')) => c,
+                            c => return Err(format!("Invalid escape \\{}", c.unwrap_or(' '))),
+                        }),
+                        Some(c) => string.push(c),
+                    }
+                }
+                tokens.push(Token::String(string));
+            }
+            '-' | '0'..='9' => {
+                let mut number = String::from(c);
+                while let Some(c) =
+                    chars.next_if(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '+' | '-'))
+                {
+                    number.push(c);
+                }
+                if let Ok(n) = number.parse() {
+                    tokens.push(Token::Int(n));
+                } else if let Ok(n) = number.parse() {
+                    tokens.push(Token::Float(n));
+                } else {
+                    return Err(format!("Invalid number {number}"));
+                }
+            }
+            c if c == '_' || c.is_ascii_alphabetic() => {
+                let mut name = String::from(c);
+                while let Some(c) = chars.next_if(|c| *c == '_' || c.is_ascii_alphanumeric()) {
+                    name.push(c);
+                }
+                tokens.push(Token::Name(name));
+            }
+            c => return Err(format!("Unexpected character {c:?}")),
+        }
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: std::iter::Peekable<std::vec::IntoIter<Token>>,
+    /// Braces and brackets open.
+    nesting: usize,
+}
+
+impl Parser {
+    fn parse(source: &str) -> Result<Document, String> {
+        let mut parser = Parser {
+            tokens: tokenize(source)?.into_iter().peekable(),
+            nesting: 0,
+        };
+        let mut document = Document {
+            operations: Vec::new(),
+            fragments: HashMap::new(),
+        };
+        while let Some(token) = parser.tokens.peek() {
+            match token {
+                Token::Punctuator('{') => document.operations.push(Operation {
+                    kind: "query".to_owned(),
+                    name: None,
+                    variables: Vec::new(),
+                    selections: parser.selections()?,
+                }),
+                Token::Name(name) if name == "fragment" => {
+                    parser.tokens.next();
+                    let name = parser.name()?;
+                    parser.keyword("on")?;
+                    let on = parser.name()?;
+                    let selections = parser.selections()?;
+                    document.fragments.insert(name, Fragment { on, selections });
+                }
+                Token::Name(_) => {
+                    let kind = parser.name()?;
+                    let name = match parser.tokens.peek() {
+                        Some(Token::Name(_)) => Some(parser.name()?),
+                        _ => None,
+                    };
+                    let variables = parser.variables()?;
+                    parser.directives()?;
+                    let selections = parser.selections()?;
+                    document.operations.push(Operation {
+                        kind,
+                        name,
+                        variables,
+                        selections,
+                    });
+                }
+                token => return Err(format!("Unexpected {token:?}")),
+            }
+        }
+        if document.operations.is_empty() {
+            return Err("No operation".to_owned());
+        }
+        let mut depths = HashMap::new();
+        for name in document.fragments.keys() {
+            document.spread_depth(name, &mut Vec::new(), &mut depths)?;
+        }
+        Ok(document)
+    }
+
+    fn next(&mut self) -> Result<Token, String> {
+        self.tokens
+            .next()
+            .ok_or_else(|| "Unexpected end of query".to_owned())
+    }
+
+    fn punctuator(&mut self, punctuator: char) -> Result<(), String> {
+        match self.next()? {
+            Token::Punctuator(c) if c == punctuator => Ok(()),
+            token => Err(format!("Expected {punctuator}, not {token:?}")),
+        }
+    }
+
+    /// Opens a brace or bracket, failing past [`MAX_NESTING`]. The caller
+    /// closes it again by decrementing `nesting`.
+    fn nest(&mut self) -> Result<(), String> {
+        self.nesting += 1;
+        if self.nesting > MAX_NESTING {
+            return Err(format!("Queries can only nest {MAX_NESTING} deep"));
+        }
+        Ok(())
+    }
+
+    fn skip_punctuator(&mut self, punctuator: char) -> bool {
+        self.tokens
+            .next_if_eq(&Token::Punctuator(punctuator))
+            .is_some()
+    }
+
+    fn name(&mut self) -> Result<String, String> {
+        match self.next()? {
+            Token::Name(name) => Ok(name),
+            token => Err(format!("Expected a name, not {token:?}")),
+        }
+    }
+
+    fn keyword(&mut self, keyword: &str) -> Result<(), String> {
+        match self.name()? {
+            name if name == keyword => Ok(()),
+            name => Err(format!("Expected {keyword}, not {name}")),
+        }
+    }
+
+    fn variables(&mut self) -> Result<Vec<(String, Option<Value>)>, String> {
+        let mut variables = Vec::new();
+        if !self.skip_punctuator('(') {
+            return Ok(variables);
+        }
+        while !self.skip_punctuator(')') {
+            self.punctuator('$')?;
+            let name = self.name()?;
+            self.punctuator(':')?;
+            // Types aren't checked, arguments are when they're used.
+            self.skip_type()?;
+            let default = if self.skip_punctuator('=') {
+                Some(self.value(true)?)
+            } else {
+                None
+            };
+            self.directives()?;
+            variables.push((name, default));
+        }
+        Ok(variables)
+    }
+
+    fn skip_type(&mut self) -> Result<(), String> {
+        if self.skip_punctuator('[') {
+            self.nest()?;
+            self.skip_type()?;
+            self.punctuator(']')?;
+            self.nesting -= 1;
+        } else {
+            self.name()?;
+        }
+        self.skip_punctuator('!');
+        Ok(())
+    }
+
+    fn selections(&mut self) -> Result<Vec<Selection>, String> {
+        self.punctuator('{')?;
+        self.nest()?;
+        let mut selections = Vec::new();
+        while !self.skip_punctuator('}') {
+            if self.tokens.next_if_eq(&Token::Spread).is_some() {
+                let selection = match self.tokens.peek() {
+                    Some(Token::Name(name)) if name != "on" => Selection::Spread {
+                        name: self.name()?,
+                        directives: self.directives()?,
+                    },
+                    _ => {
+                        let on = match self.tokens.peek() {
+                            Some(Token::Name(_)) => {
+                                self.keyword("on")?;
+                                Some(self.name()?)
+                            }
+                            _ => None,
+                        };
+                        Selection::Inline {
+                            on,
+                            directives: self.directives()?,
+                            selections: self.selections()?,
+                        }
+                    }
+                };
+                selections.push(selection);
+                continue;
+            }
+            let mut name = self.name()?;
+            let mut alias = None;
+            if self.skip_punctuator(':') {
+                alias = Some(name);
+                name = self.name()?;
+            }
+            let arguments = self.arguments()?;
+            let directives = self.directives()?;
+            let selections_of_field = match self.tokens.peek() {
+                Some(Token::Punctuator('{')) => self.selections()?,
+                _ => Vec::new(),
+            };
+            selections.push(Selection::Field(Field {
+                alias,
+                name,
+                arguments,
+                directives,
+                selections: selections_of_field,
+            }));
+        }
+        self.nesting -= 1;
+        Ok(selections)
+    }
+
+    fn arguments(&mut self) -> Result<Vec<(String, Value)>, String> {
+        let mut arguments = Vec::new();
+        if !self.skip_punctuator('(') {
+            return Ok(arguments);
+        }
+        while !self.skip_punctuator(')') {
+            let name = self.name()?;
+            self.punctuator(':')?;
+            arguments.push((name, self.value(false)?));
+        }
+        Ok(arguments)
+    }
+
+    fn directives(&mut self) -> Result<Vec<Directive>, String> {
+        let mut directives = Vec::new();
+        while self.skip_punctuator('@') {
+            directives.push(Directive {
+                name: self.name()?,
+                arguments: self.arguments()?,
+            });
+        }
+        Ok(directives)
+    }
+
+    /// A value, which can't refer to variables if it's `constant`.
+    fn value(&mut self, constant: bool) -> Result<Value, String> {
+        Ok(match self.next()? {
+            Token::Punctuator('$') if !constant => Value::Variable(self.name()?),
+            Token::Int(n) => Value::Int(n),
+            Token::Float(n) => Value::Float(n),
+            Token::String(s) => Value::String(s),
+            Token::Name(name) => match name.as_str() {
+                "true" => Value::Boolean(true),
+                "false" => Value::Boolean(false),
+                "null" => Value::Null,
+                _ => Value::Enum(name),
+            },
+            Token::Punctuator('[') => {
+                self.nest()?;
+                let mut values = Vec::new();
+                while !self.skip_punctuator(']') {
+                    values.push(self.value(constant)?);
+                }
+                self.nesting -= 1;
+                Value::List(values)
+            }
+            Token::Punctuator('{') => {
+                self.nest()?;
+                let mut fields = Vec::new();
+                while !self.skip_punctuator('}') {
+                    let name = self.name()?;
+                    self.punctuator(':')?;
+                    fields.push((name, self.value(constant)?));
+                }
+                self.nesting -= 1;
+                Value::Object(fields)
+            }
+            token => return Err(format!("Expected a value, not {token:?}")),
+        })
+    }
+}
+
+#[test]
+fn test_parse_query() {
+    let document = Parser::parse(
+        r#"
+        query Listing($page: Int = 2, $content: Boolean!) {
+            recent: pastes(page: $page) { id ...Details content @include(if: $content) }
+        }
+        fragment Details on Paste { title, owner { username } }
+        "#,
+    )
+    .unwrap();
+    let operation = document.operation(None).unwrap();
+    assert_eq!(operation.name.as_deref(), Some("Listing"));
+    assert_eq!(
+        operation.variables[0],
+        ("page".to_owned(), Some(Value::Int(2)))
+    );
+    let Selection::Field(field) = &operation.selections[0] else {
+        panic!("Expected a field");
+    };
+    assert_eq!(field.alias.as_deref(), Some("recent"));
+    assert_eq!(
+        field.arguments,
+        [("page".to_owned(), Value::Variable("page".to_owned()))]
+    );
+    assert_eq!(field.selections.len(), 3);
+    assert_eq!(document.fragments["Details"].selections.len(), 2);
+}
+
+#[test]
+fn test_rejects_fragment_cycles_and_deep_nesting() {
+    let cycle = "{ pastes { ...A } } fragment A on Paste { owner { ...B } } \
+                 fragment B on User { pastes { ...A } }";
+    let error = Parser::parse(cycle).err().unwrap();
+    assert!(error.ends_with("spreads itself"), "{error}");
+    let chain: String = (0..=MAX_DEPTH)
+        .map(|i| format!("fragment F{i} on Paste {{ ...F{} }}", i + 1))
+        .collect();
+    let chain = Parser::parse(&format!("{{ pastes {{ ...F0 }} }} {chain}"));
+    assert!(chain.is_err());
+    let nested = format!("{{ paste(id: {}) {{ id }} }}", "[".repeat(100_000));
+    assert!(Parser::parse(&nested).is_err());
+}
+
+#[tokio::test]
+async fn test_queries_have_a_budget() {
+    use crate::testing::TempDir;
+
+    let dir = TempDir::new().unwrap();
+    let service = Arc::new(Service::new(dir.path().to_owned(), Default::default()).unwrap());
+    let options = crate::service::Options {
+        public: true,
+        ..Default::default()
+    };
+    for _ in 0..PAGE_SIZE {
+        service
+            .create_with_options(&b"hello"[..], None, options.clone())
+            .await
+            .unwrap();
+    }
+    let query = |query: String| async {
+        let request = Request {
+            query,
+            variables: None,
+            operation_name: None,
+        };
+        let response = post(
+            State(service.clone()),
+            State(Arc::default()),
+            None,
+            Json(request),
+        )
+        .await;
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        serde_json::from_slice::<JsonValue>(&body).unwrap()
+    };
+
+    let response = query("{ pastes { id views content } }".to_owned()).await;
+    assert!(response.get("errors").is_none(), "{response}");
+    assert_eq!(response["data"]["pastes"][0]["content"], "hello");
+    // Aliases multiply the cost of a listing.
+    let aliases: String = (0..200)
+        .map(|i| format!("p{i}: pastes {{ id }} "))
+        .collect();
+    let response = query(format!("{{ {aliases} }}")).await;
+    let message = &response["errors"][0]["message"];
+    assert_eq!(*message, format!("Query costs more than {MAX_COST}"));
+}
@@ -0,0 +1,419 @@
+//! The gRPC service `pastebin.v1.Pastes` from `proto/pastebin.proto`, for
+//! systems that prefer gRPC to REST. It shares the [`Service`] and the
+//! credentials of the REST API.
+//!
+//! gRPC is spoken by hand over the HTTP/2 the server offers anyway: messages
+//! are protobuf, each prefixed with its length, and the status of a call is
+//! sent in trailers. Compressed messages aren't supported.
+
+use std::{convert::Infallible, sync::Arc};
+
+use axum::{
+    Router,
+    body::Body,
+    extract::State,
+    http::{HeaderMap, HeaderValue, header},
+    response::{IntoResponse, Response},
+    routing::post,
+};
+use bytes::{Buf, Bytes, BytesMut};
+use futures::{Stream, StreamExt};
+use http_body::Frame;
+use uuid::Uuid;
+
+use crate::{
+    auth::BasicAuth,
+    html::encode_query,
+    service::{self, Service},
+};
+
+/// Largest message accepted from a client.
+const MAX_MESSAGE: usize = 4 * 1024 * 1024;
+
+/// Bytes of content sent per `ReadResponse`.
+const CHUNK: usize = 64 * 1024;
+
+pub fn routes() -> Router<crate::AppState> {
+    Router::new()
+        .route("/pastebin.v1.Pastes/Create", post(create))
+        .route("/pastebin.v1.Pastes/Read", post(read))
+        .route("/pastebin.v1.Pastes/Replace", post(replace))
+        .route("/pastebin.v1.Pastes/Delete", post(delete))
+        .route("/pastebin.v1.Pastes/List", post(list))
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Code {
+    Ok = 0,
+    InvalidArgument = 3,
+    NotFound = 5,
+    ResourceExhausted = 8,
+    Unimplemented = 12,
+    Internal = 13,
+    Unauthenticated = 16,
+}
+
+#[derive(Debug)]
+struct Status {
+    code: Code,
+    message: String,
+}
+
+impl Status {
+    fn new(code: Code, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+        }
+    }
+}
+
+impl From<anyhow::Error> for Status {
+    fn from(e: anyhow::Error) -> Self {
+        let code = match e.downcast_ref::<std::io::Error>() {
+            Some(e) if e.kind() == std::io::ErrorKind::NotFound => Code::NotFound,
+            Some(e) if e.kind() == std::io::ErrorKind::FileTooLarge => Code::ResourceExhausted,
+            _ => Code::Internal,
+        };
+        Self::new(code, e.to_string())
+    }
+}
+
+/// A response sending `messages`, and the status of the call once they're
+/// done or one of them failed.
+fn respond(messages: impl Stream<Item = Result<Vec<u8>, Status>> + Send + 'static) -> Response {
+    let frames = futures::stream::unfold(Some(Box::pin(messages)), |messages| async move {
+        let mut messages = messages?;
+        let status = match messages.next().await {
+            Some(Ok(message)) => {
+                let mut framed = Vec::with_capacity(5 + message.len());
+                framed.push(0);
+                framed.extend_from_slice(&(message.len() as u32).to_be_bytes());
+                framed.extend_from_slice(&message);
+                return Some((Frame::data(Bytes::from(framed)), Some(messages)));
+            }
+            Some(Err(status)) => status,
+            None => Status::new(Code::Ok, ""),
+        };
+        if !matches!(status.code, Code::Ok) {
+            tracing::debug!("gRPC call failed: {status:?}");
+        }
+        let mut trailers = HeaderMap::new();
+        trailers.insert("grpc-status", HeaderValue::from(status.code as u16));
+        if !status.message.is_empty() {
+            let message = HeaderValue::try_from(encode_query(&status.message))
+                .expect("Percent-encoded text is a valid header");
+            trailers.insert("grpc-message", message);
+        }
+        Some((Frame::trailers(trailers), None))
+    });
+    (
+        [(header::CONTENT_TYPE, "application/grpc")],
+        Body::new(http_body_util::StreamBody::new(
+            frames.map(Ok::<_, Infallible>),
+        )),
+    )
+        .into_response()
+}
+
+fn unary(result: Result<Vec<u8>, Status>) -> Response {
+    respond(futures::stream::iter([result]))
+}
+
+/// The messages a client sent in `body`.
+fn messages(body: Body) -> impl Stream<Item = Result<Bytes, Status>> + Send {
+    let state = (body.into_data_stream(), BytesMut::new());
+    futures::stream::unfold(Some(state), |state| async move {
+        let (mut data, mut buffer) = state?;
+        loop {
+            if buffer.len() >= 5 {
+                let len = u32::from_be_bytes(buffer[1..5].try_into().unwrap()) as usize;
+                if buffer[0] != 0 {
+                    let status = Status::new(Code::Unimplemented, "Compression isn't supported");
+                    return Some((Err(status), None));
+                }
+                if len > MAX_MESSAGE {
+                    let status = Status::new(
+                        Code::ResourceExhausted,
+                        format!("Messages are limited to {MAX_MESSAGE} bytes"),
+                    );
+                    return Some((Err(status), None));
+                }
+                if buffer.len() >= 5 + len {
+                    buffer.advance(5);
+                    let message = buffer.split_to(len).freeze();
+                    return Some((Ok(message), Some((data, buffer))));
+                }
+            }
+            match data.next().await {
+                Some(Ok(chunk)) => buffer.extend_from_slice(&chunk),
+                Some(Err(e)) => {
+                    return Some((Err(Status::new(Code::Internal, e.to_string())), None));
+                }
+                None if buffer.is_empty() => return None,
+                None => {
+                    let status = Status::new(Code::InvalidArgument, "Truncated message");
+                    return Some((Err(status), None));
+                }
+            }
+        }
+    })
+}
+
+/// The single message of a unary call.
+async fn message(body: Body) -> Result<Bytes, Status> {
+    let mut messages = Box::pin(messages(body));
+    let message = messages.next().await.unwrap_or(Ok(Bytes::new()))?;
+    match messages.next().await {
+        None => Ok(message),
+        Some(Err(status)) => Err(status),
+        Some(Ok(_)) => Err(Status::new(Code::InvalidArgument, "Expected one message")),
+    }
+}
+
+/// The content of `messages`, which is in `field` of each, as a reader.
+fn content(
+    messages: impl Stream<Item = Result<Bytes, Status>> + Send + Unpin,
+    field: u32,
+) -> impl tokio::io::AsyncRead + Send + Unpin {
+    tokio_util::io::StreamReader::new(messages.map(move |message| {
+        message
+            .and_then(|message| Ok(Message::decode(&message)?.bytes(field).unwrap_or_default()))
+            .map_err(|status| std::io::Error::other(status.message))
+    }))
+}
+
+fn unauthenticated() -> Status {
+    Status::new(Code::Unauthenticated, "Credentials required")
+}
+
+fn parse_id(message: &Message) -> Result<Uuid, Status> {
+    message
+        .string(1)?
+        .unwrap_or_default()
+        .parse()
+        .map_err(|e| Status::new(Code::InvalidArgument, format!("Invalid paste ID: {e}")))
+}
+
+async fn create(
+    State(service): State<Arc<Service>>,
+    auth: Option<BasicAuth>,
+    body: Body,
+) -> Response {
+    let mut messages = Box::pin(messages(body));
+    let first = match messages.next().await.transpose() {
+        Ok(first) => first.unwrap_or_default(),
+        Err(status) => return unary(Err(status)),
+    };
+    let result = async {
+        let request = Message::decode(&first)?;
+        let options = service::Options {
+            title: request.string(2)?,
+            language: request.string(3)?,
+            public: request.varint(4).is_some_and(|value| value != 0),
+            noindex: request.varint(5).is_some_and(|value| value != 0),
+            ..Default::default()
+        };
+        options
+            .validate()
+            .map_err(|e| Status::new(Code::InvalidArgument, e.to_string()))?;
+        let messages = futures::stream::iter([Ok(first.clone())]).chain(messages);
+        let id = service
+            .create_with_options(content(messages, 1), auth.map(Into::into), options)
+            .await?;
+        let mut response = Vec::new();
+        encode_bytes(&mut response, 1, id.as_bytes());
+        Ok(response)
+    }
+    .await;
+    unary(result)
+}
+
+async fn read(State(service): State<Arc<Service>>, body: Body) -> Response {
+    let reader = async {
+        let id = parse_id(&Message::decode(&message(body).await?)?)?;
+        Ok(service.read(&id).await?)
+    }
+    .await;
+    match reader {
+        Ok(reader) => respond(
+            tokio_util::io::ReaderStream::with_capacity(reader, CHUNK).map(|chunk| {
+                let chunk = chunk.map_err(|e| Status::from(anyhow::Error::from(e)))?;
+                let mut response = Vec::with_capacity(chunk.len() + 8);
+                encode_bytes(&mut response, 1, &chunk);
+                Ok(response)
+            }),
+        ),
+        Err(status) => unary(Err(status)),
+    }
+}
+
+async fn replace(
+    State(service): State<Arc<Service>>,
+    auth: Option<BasicAuth>,
+    body: Body,
+) -> Response {
+    let mut messages = Box::pin(messages(body));
+    let result = async {
+        let first = messages.next().await.transpose()?.unwrap_or_default();
+        let id = parse_id(&Message::decode(&first)?)?;
+        let messages = futures::stream::iter([Ok(first.clone())]).chain(messages);
+        service
+            .replace(&id, content(messages, 2), auth.map(Into::into))
+            .await?;
+        Ok(Vec::new())
+    }
+    .await;
+    unary(result)
+}
+
+async fn delete(
+    State(service): State<Arc<Service>>,
+    auth: Option<BasicAuth>,
+    body: Body,
+) -> Response {
+    let result = async {
+        let id = parse_id(&Message::decode(&message(body).await?)?)?;
+        let auth = auth.ok_or_else(unauthenticated)?;
+        service.delete(id, &auth.username, &auth.password).await?;
+        Ok(Vec::new())
+    }
+    .await;
+    unary(result)
+}
+
+async fn list(
+    State(service): State<Arc<Service>>,
+    auth: Option<BasicAuth>,
+    body: Body,
+) -> Response {
+    let result = async {
+        message(body).await?;
+        let auth = auth.ok_or_else(unauthenticated)?;
+        let ids = service
+            .list(&auth.username, &auth.password)
+            .map_err(|e| Status::new(Code::Unauthenticated, e.to_string()))?;
+        let mut response = Vec::new();
+        for id in ids {
+            encode_bytes(&mut response, 1, id.as_bytes());
+        }
+        Ok(response)
+    }
+    .await;
+    unary(result)
+}
+
+/// The fields of a protobuf message, of which only varints and
+/// length-delimited ones are of interest. The last occurrence of a field
+/// wins, as in protobuf.
+struct Message<'a> {
+    fields: Vec<(u32, Field<'a>)>,
+}
+
+#[derive(Clone, Copy)]
+enum Field<'a> {
+    Varint(u64),
+    Bytes(&'a [u8]),
+}
+
+impl<'a> Message<'a> {
+    fn decode(mut message: &'a [u8]) -> Result<Self, Status> {
+        let invalid = || Status::new(Code::InvalidArgument, "Invalid protobuf message");
+        let mut fields = Vec::new();
+        while !message.is_empty() {
+            let key = read_varint(&mut message).ok_or_else(invalid)?;
+            let field = (key >> 3) as u32;
+            let skip = match key & 7 {
+                0 => {
+                    let value = read_varint(&mut message).ok_or_else(invalid)?;
+                    fields.push((field, Field::Varint(value)));
+                    0
+                }
+                1 => 8,
+                2 => {
+                    let len = read_varint(&mut message).ok_or_else(invalid)? as usize;
+                    let bytes = message.get(..len).ok_or_else(invalid)?;
+                    fields.push((field, Field::Bytes(bytes)));
+                    len
+                }
+                5 => 4,
+                _ => return Err(invalid()),
+            };
+            message = message.get(skip..).ok_or_else(invalid)?;
+        }
+        Ok(Self { fields })
+    }
+
+    fn get(&self, field: u32) -> Option<Field<'a>> {
+        self.fields
+            .iter()
+            .rev()
+            .find(|(number, _)| *number == field)
+            .map(|(_, value)| *value)
+    }
+
+    fn varint(&self, field: u32) -> Option<u64> {
+        match self.get(field)? {
+            Field::Varint(value) => Some(value),
+            Field::Bytes(_) => None,
+        }
+    }
+
+    fn bytes(&self, field: u32) -> Option<Bytes> {
+        match self.get(field)? {
+            Field::Bytes(bytes) => Some(Bytes::copy_from_slice(bytes)),
+            Field::Varint(_) => None,
+        }
+    }
+
+    /// A string field, where empty means unset as in proto3.
+    fn string(&self, field: u32) -> Result<Option<String>, Status> {
+        let Some(bytes) = self.bytes(field).filter(|bytes| !bytes.is_empty()) else {
+            return Ok(None);
+        };
+        String::from_utf8(bytes.to_vec())
+            .map(Some)
+            .map_err(|_| Status::new(Code::InvalidArgument, format!("Field {field} isn't UTF-8")))
+    }
+}
+
+fn read_varint(buffer: &mut &[u8]) -> Option<u64> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let (&byte, rest) = buffer.split_first()?;
+        *buffer = rest;
+        value |= u64::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return Some(value);
+        }
+    }
+    None
+}
+
+fn write_varint(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn encode_bytes(out: &mut Vec<u8>, field: u32, bytes: &[u8]) {
+    write_varint(out, u64::from(field) << 3 | 2);
+    write_varint(out, bytes.len() as u64);
+    out.extend_from_slice(bytes);
+}
+
+#[test]
+fn test_messages_round_trip() {
+    let mut encoded = Vec::new();
+    encode_bytes(&mut encoded, 1, b"id");
+    encode_bytes(&mut encoded, 2, &[7; 300]);
+    // A varint field, 4: 150.
+    encoded.extend_from_slice(&[0x20, 0x96, 0x01]);
+    let message = Message::decode(&encoded).unwrap();
+    assert_eq!(message.string(1).unwrap().as_deref(), Some("id"));
+    assert_eq!(message.bytes(2).unwrap().len(), 300);
+    assert_eq!(message.varint(4), Some(150));
+    assert_eq!(message.string(3).unwrap(), None);
+}
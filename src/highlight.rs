@@ -0,0 +1,379 @@
+//! A small syntax highlighter for the languages pastes are usually in. It
+//! only tells comments, strings, numbers and keywords apart, as spans with
+//! the classes `c`, `s`, `n` and `k`, which is enough to make code readable
+//! without a grammar for every language.
+
+use std::fmt::Write as _;
+
+use crate::html::escape;
+
+struct Syntax {
+    line_comment: &'static [&'static str],
+    block_comment: Option<(&'static str, &'static str)>,
+    /// Characters that start a string ended by the same character.
+    quotes: &'static str,
+    keywords: &'static [&'static str],
+    /// Whether keywords match in any case, as in SQL.
+    ignore_case: bool,
+}
+
+const RUST: &[&str] = &[
+    "as", "async", "await", "break", "const", "continue", "crate", "dyn", "else", "enum", "extern",
+    "false", "fn", "for", "if", "impl", "in", "let", "loop", "match", "mod", "move", "mut", "pub",
+    "ref", "return", "self", "Self", "static", "struct", "super", "trait", "true", "type",
+    "unsafe", "use", "where", "while",
+];
+
+const C_FAMILY: &[&str] = &[
+    "abstract",
+    "auto",
+    "bool",
+    "break",
+    "case",
+    "catch",
+    "char",
+    "class",
+    "const",
+    "continue",
+    "default",
+    "delete",
+    "do",
+    "double",
+    "else",
+    "enum",
+    "extends",
+    "extern",
+    "false",
+    "final",
+    "float",
+    "for",
+    "fun",
+    "func",
+    "goto",
+    "if",
+    "implements",
+    "import",
+    "int",
+    "interface",
+    "let",
+    "long",
+    "namespace",
+    "new",
+    "null",
+    "override",
+    "package",
+    "private",
+    "protected",
+    "public",
+    "return",
+    "short",
+    "signed",
+    "sizeof",
+    "static",
+    "struct",
+    "switch",
+    "template",
+    "this",
+    "throw",
+    "true",
+    "try",
+    "typedef",
+    "union",
+    "unsigned",
+    "using",
+    "val",
+    "var",
+    "virtual",
+    "void",
+    "volatile",
+    "when",
+    "while",
+];
+
+const GO: &[&str] = &[
+    "break",
+    "case",
+    "chan",
+    "const",
+    "continue",
+    "default",
+    "defer",
+    "else",
+    "fallthrough",
+    "false",
+    "for",
+    "func",
+    "go",
+    "goto",
+    "if",
+    "import",
+    "interface",
+    "map",
+    "nil",
+    "package",
+    "range",
+    "return",
+    "select",
+    "struct",
+    "switch",
+    "true",
+    "type",
+    "var",
+];
+
+const JAVASCRIPT: &[&str] = &[
+    "async",
+    "await",
+    "break",
+    "case",
+    "catch",
+    "class",
+    "const",
+    "continue",
+    "default",
+    "delete",
+    "do",
+    "else",
+    "export",
+    "extends",
+    "false",
+    "finally",
+    "for",
+    "from",
+    "function",
+    "if",
+    "import",
+    "in",
+    "instanceof",
+    "interface",
+    "let",
+    "new",
+    "null",
+    "of",
+    "return",
+    "static",
+    "super",
+    "switch",
+    "this",
+    "throw",
+    "true",
+    "try",
+    "type",
+    "typeof",
+    "undefined",
+    "var",
+    "void",
+    "while",
+    "yield",
+];
+
+const PYTHON: &[&str] = &[
+    "and", "as", "assert", "async", "await", "break", "class", "continue", "def", "del", "elif",
+    "else", "except", "False", "finally", "for", "from", "global", "if", "import", "in", "is",
+    "lambda", "None", "nonlocal", "not", "or", "pass", "raise", "return", "True", "try", "while",
+    "with", "yield",
+];
+
+const RUBY: &[&str] = &[
+    "begin", "break", "case", "class", "def", "do", "else", "elsif", "end", "ensure", "false",
+    "for", "if", "in", "module", "next", "nil", "not", "or", "and", "require", "rescue", "return",
+    "self", "then", "true", "unless", "until", "when", "while", "yield",
+];
+
+const SHELL: &[&str] = &[
+    "case", "do", "done", "elif", "else", "esac", "export", "fi", "for", "function", "if", "in",
+    "local", "readonly", "return", "select", "then", "until", "while",
+];
+
+const SQL: &[&str] = &[
+    "and",
+    "as",
+    "asc",
+    "by",
+    "create",
+    "delete",
+    "desc",
+    "distinct",
+    "drop",
+    "from",
+    "group",
+    "having",
+    "insert",
+    "into",
+    "join",
+    "left",
+    "limit",
+    "not",
+    "null",
+    "on",
+    "or",
+    "order",
+    "primary",
+    "key",
+    "references",
+    "right",
+    "select",
+    "set",
+    "table",
+    "update",
+    "values",
+    "where",
+];
+
+const LUA: &[&str] = &[
+    "and", "break", "do", "else", "elseif", "end", "false", "for", "function", "if", "in", "local",
+    "nil", "not", "or", "repeat", "return", "then", "true", "until", "while",
+];
+
+const LITERALS: &[&str] = &["true", "false", "null"];
+
+fn syntax(language: &str) -> Option<Syntax> {
+    let c_like = |quotes, keywords| Syntax {
+        line_comment: &["//"],
+        block_comment: Some(("/*", "*/")),
+        quotes,
+        keywords,
+        ignore_case: false,
+    };
+    let hash_comments = |keywords| Syntax {
+        line_comment: &["#"],
+        block_comment: None,
+        quotes: "\"'",
+        keywords,
+        ignore_case: false,
+    };
+    Some(match language.to_ascii_lowercase().as_str() {
+        "rust" | "rs" => c_like("\"", RUST),
+        "c" | "cpp" | "c++" | "csharp" | "c#" | "java" | "kotlin" | "swift" | "php" => {
+            c_like("\"'", C_FAMILY)
+        }
+        "go" => c_like("\"'`", GO),
+        "javascript" | "js" | "typescript" | "ts" => c_like("\"'`", JAVASCRIPT),
+        "json" => c_like("\"", LITERALS),
+        "python" | "py" => hash_comments(PYTHON),
+        "ruby" | "rb" => hash_comments(RUBY),
+        "bash" | "sh" | "shell" | "zsh" => hash_comments(SHELL),
+        "toml" | "yaml" | "yml" => hash_comments(LITERALS),
+        "sql" => Syntax {
+            line_comment: &["--"],
+            block_comment: Some(("/*", "*/")),
+            quotes: "'\"",
+            keywords: SQL,
+            ignore_case: true,
+        },
+        "lua" => Syntax {
+            line_comment: &["--"],
+            block_comment: None,
+            quotes: "\"'",
+            keywords: LUA,
+            ignore_case: false,
+        },
+        _ => return None,
+    })
+}
+
+/// `code` as HTML, highlighted if `language` is one this knows.
+pub fn html(code: &str, language: Option<&str>) -> String {
+    let Some(syntax) = language.and_then(syntax) else {
+        return escape(code);
+    };
+    let mut html = String::with_capacity(code.len() * 2);
+    let mut plain = String::new();
+    let mut rest = code;
+    while let Some(c) = rest.chars().next() {
+        let token = if let Some(len) = comment(&syntax, rest) {
+            Some(("c", len))
+        } else if syntax.quotes.contains(c) {
+            Some(("s", string(rest, c)))
+        } else if c.is_ascii_digit() {
+            Some(("n", word(rest)))
+        } else if c.is_alphabetic() || c == '_' {
+            let len = word(rest);
+            let is_keyword = syntax.keywords.iter().any(|keyword| {
+                if syntax.ignore_case {
+                    keyword.eq_ignore_ascii_case(&rest[..len])
+                } else {
+                    *keyword == &rest[..len]
+                }
+            });
+            if !is_keyword {
+                plain.push_str(&rest[..len]);
+                rest = &rest[len..];
+                continue;
+            }
+            Some(("k", len))
+        } else {
+            None
+        };
+        match token {
+            Some((class, len)) => {
+                html.push_str(&escape(&plain));
+                plain.clear();
+                write!(
+                    html,
+                    "<span class=\"{class}\">{}</span>",
+                    escape(&rest[..len])
+                )
+                .unwrap();
+                rest = &rest[len..];
+            }
+            None => {
+                plain.push(c);
+                rest = &rest[c.len_utf8()..];
+            }
+        }
+    }
+    html.push_str(&escape(&plain));
+    html
+}
+
+/// Length of the comment `code` starts with, if it does.
+fn comment(syntax: &Syntax, code: &str) -> Option<usize> {
+    if let Some((start, end)) = syntax.block_comment
+        && code.starts_with(start)
+    {
+        return Some(match code[start.len()..].find(end) {
+            Some(at) => start.len() + at + end.len(),
+            None => code.len(),
+        });
+    }
+    syntax
+        .line_comment
+        .iter()
+        .any(|start| code.starts_with(start))
+        .then(|| code.find('\n').unwrap_or(code.len()))
+}
+
+/// Length of the string `code` starts with, up to its closing `quote`.
+/// Strings other than backquoted ones end at the end of the line, so that a
+/// stray quote doesn't swallow the rest of the paste.
+fn string(code: &str, quote: char) -> usize {
+    let mut chars = code.char_indices().skip(1);
+    while let Some((at, c)) = chars.next() {
+        match c {
+            '\\' => {
+                chars.next();
+            }
+            '\n' if quote != '`' => return at,
+            c if c == quote => return at + c.len_utf8(),
+            _ => {}
+        }
+    }
+    code.len()
+}
+
+/// Length of the identifier or number `code` starts with.
+fn word(code: &str) -> usize {
+    code.find(|c: char| !(c.is_alphanumeric() || c == '_'))
+        .unwrap_or(code.len())
+}
+
+#[test]
+fn test_highlights_rust() {
+    assert_eq!(
+        html("let x = \"a<b\"; // 42\nformat1(7)", Some("rust")),
+        "<span class=\"k\">let</span> x = <span class=\"s\">&quot;a&lt;b&quot;</span>; \
+         <span class=\"c\">// 42</span>\nformat1(<span class=\"n\">7</span>)"
+    );
+    assert_eq!(html("<b>", Some("brainfuck")), "&lt;b&gt;");
+}
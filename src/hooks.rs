@@ -0,0 +1,101 @@
+//! Operator-supplied commands run on paste events, e.g. to scan or archive
+//! new pastes.
+
+use std::{path::PathBuf, process::Stdio, time::Duration};
+
+use serde::Serialize;
+use tokio::{io::AsyncWriteExt, process::Command};
+use uuid::Uuid;
+
+use crate::{events::Subscription, meta};
+
+/// Time a hook may run before it is killed.
+const TIMEOUT: Duration = Duration::from_secs(60);
+
+/// What a hook gets on stdin.
+#[derive(Serialize)]
+struct Input {
+    event: &'static str,
+    id: Uuid,
+    timestamp: String,
+    /// Gone for deleted pastes.
+    metadata: Option<meta::Metadata>,
+}
+
+/// Runs each of `commands` through the shell for every paste event, one at
+/// a time. A hook gets the event in `PASTEBIN_EVENT`, the paste's ID in
+/// `PASTEBIN_PASTE_ID` and the path of its contents in `PASTEBIN_PASTE_PATH`,
+/// and the event with the paste's metadata as JSON on stdin.
+///
+/// Slow hooks hold up the events after them; those more than the bus's
+/// backlog behind never run the hooks, and are counted in
+/// `pastebin_events_dropped_total`.
+pub fn spawn(
+    mut events: Subscription,
+    data_dir: PathBuf,
+    commands: Vec<String>,
+) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        while let Some(emitted) = events.recv().await {
+            let Some((event, id)) = emitted.event.paste() else {
+                continue;
+            };
+            let metadata = match meta::load(&data_dir, &id.to_string()).await {
+                Ok(metadata) => metadata,
+                Err(e) => {
+                    tracing::warn!("Couldn't read the metadata of paste {id} for hooks: {e}");
+                    None
+                }
+            };
+            let input = Input {
+                event,
+                id,
+                timestamp: emitted.timestamp(),
+                metadata,
+            };
+            let input = serde_json::to_vec(&input).expect("Hook input serializes");
+            let path = data_dir.join(id.to_string());
+            for command in &commands {
+                if let Err(e) = run(command, event, id, &path, &input).await {
+                    tracing::error!("Hook {command:?} failed for {event} of paste {id}: {e}");
+                }
+            }
+        }
+    })
+}
+
+async fn run(
+    command: &str,
+    event: &str,
+    id: Uuid,
+    path: &std::path::Path,
+    input: &[u8],
+) -> anyhow::Result<()> {
+    #[cfg(unix)]
+    let mut child = Command::new("sh");
+    #[cfg(unix)]
+    child.arg("-c");
+    #[cfg(not(unix))]
+    let mut child = Command::new("cmd");
+    #[cfg(not(unix))]
+    child.arg("/C");
+    let mut child = child
+        .arg(command)
+        .env("PASTEBIN_EVENT", event)
+        .env("PASTEBIN_PASTE_ID", id.to_string())
+        .env("PASTEBIN_PASTE_PATH", path)
+        .stdin(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()?;
+    let mut stdin = child.stdin.take().expect("stdin is piped");
+    // Hooks that don't read their input close stdin early.
+    stdin.write_all(input).await.ok();
+    drop(stdin);
+    let status = tokio::time::timeout(TIMEOUT, child.wait())
+        .await
+        .map_err(|_| anyhow::anyhow!("Timed out after {} s", TIMEOUT.as_secs()))??;
+    if !status.success() {
+        anyhow::bail!("Exited with {status}");
+    }
+    Ok(())
+}
@@ -0,0 +1,29 @@
+//! How new pastes get their IDs. Paste IDs are UUIDs throughout, in URLs
+//! and file names, but which UUIDs is up to the generator.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use uuid::Uuid;
+
+pub trait IdGenerator: Send + Sync {
+    fn generate(&self) -> Uuid;
+}
+
+/// Random (version 4) UUIDs, which can't be guessed from one another.
+pub struct RandomIds;
+
+impl IdGenerator for RandomIds {
+    fn generate(&self) -> Uuid {
+        Uuid::new_v4()
+    }
+}
+
+/// UUIDs counting up from 1, for tests that expect particular IDs.
+#[derive(Default)]
+pub struct SequentialIds(AtomicU64);
+
+impl IdGenerator for SequentialIds {
+    fn generate(&self) -> Uuid {
+        Uuid::from_u128(u128::from(self.0.fetch_add(1, Ordering::Relaxed)) + 1)
+    }
+}
@@ -0,0 +1,155 @@
+use std::path::{Path, PathBuf};
+
+use clap::ValueEnum;
+
+use crate::service::Service;
+
+/// Layout of a directory being imported.
+#[derive(Clone, Copy, Debug, Default, ValueEnum)]
+pub enum Format {
+    /// Every file below the directory becomes a paste
+    #[default]
+    Plain,
+    /// A directory of `git clone`d gists; `.git` directories are skipped
+    Gist,
+    /// Raw pastebin.com pastes named `<paste_key>` or `<paste_key>.txt`,
+    /// optionally next to a `pastes.xml` listing from the API's `list` call
+    Pastebin,
+}
+
+/// Creates a paste owned by `auth` for every paste found in `dir`.
+///
+/// Returns `(label, paste ID)` pairs, where the label identifies the source
+/// paste: its path relative to `dir`, or its title and key for pastebin.com.
+pub async fn import_dir(
+    service: &Service,
+    dir: &Path,
+    format: Format,
+    auth: (String, String),
+) -> anyhow::Result<Vec<(String, String)>> {
+    let sources = match format {
+        Format::Plain => walk(dir, |_| false).await?,
+        Format::Gist => walk(dir, |name| name == ".git").await?,
+        Format::Pastebin => pastebin_sources(dir).await?,
+    };
+
+    let mut imported = Vec::new();
+    for (label, path) in sources {
+        let file = tokio::fs::File::open(&path).await?;
+        let id = service.create(file, Some(auth.clone())).await?;
+        imported.push((label, id));
+    }
+    Ok(imported)
+}
+
+/// Lists the regular files below `dir` as `(relative path, path)` pairs,
+/// skipping directories for which `skip_dir` returns true.
+///
+/// Symlinks are skipped so a link cycle can't make the walk run forever.
+async fn walk(
+    dir: &Path,
+    skip_dir: impl Fn(&str) -> bool,
+) -> anyhow::Result<Vec<(String, PathBuf)>> {
+    let mut files = Vec::new();
+    let mut pending = vec![dir.to_owned()];
+    while let Some(current) = pending.pop() {
+        let mut entries = Vec::new();
+        let mut read_dir = tokio::fs::read_dir(&current).await?;
+        while let Some(entry) = read_dir.next_entry().await? {
+            entries.push(entry);
+        }
+        entries.sort_by_key(|entry| entry.file_name());
+
+        for entry in entries {
+            let file_type = entry.file_type().await?;
+            let path = entry.path();
+            if file_type.is_dir() {
+                if !skip_dir(&entry.file_name().to_string_lossy()) {
+                    pending.push(path);
+                }
+            } else if file_type.is_file() {
+                let label = path.strip_prefix(dir)?.display().to_string();
+                files.push((label, path));
+            }
+        }
+    }
+    Ok(files)
+}
+
+async fn pastebin_sources(dir: &Path) -> anyhow::Result<Vec<(String, PathBuf)>> {
+    let listing = match tokio::fs::read_to_string(dir.join("pastes.xml")).await {
+        Ok(xml) => parse_pastebin_listing(&xml),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            return walk(dir, |_| true).await;
+        }
+        Err(e) => return Err(e.into()),
+    };
+
+    let mut sources = Vec::new();
+    for (key, title) in listing {
+        if !key.chars().all(|c| c.is_ascii_alphanumeric()) {
+            tracing::warn!("Skipping pastebin.com paste with invalid key {key:?}");
+            continue;
+        }
+        let candidates = [dir.join(format!("{key}.txt")), dir.join(&key)];
+        let Some(path) = candidates.into_iter().find(|path| path.is_file()) else {
+            tracing::warn!("Skipping pastebin.com paste {key}: no raw file found");
+            continue;
+        };
+        let label = match title {
+            Some(title) if !title.is_empty() => format!("{title} ({key})"),
+            _ => key,
+        };
+        sources.push((label, path));
+    }
+    Ok(sources)
+}
+
+/// Extracts `(paste_key, paste_title)` pairs from the XML returned by the
+/// pastebin.com API's `list` option.
+///
+/// The response is a flat sequence of `<paste>` elements with simple text
+/// children, so a full XML parser isn't needed.
+fn parse_pastebin_listing(xml: &str) -> Vec<(String, Option<String>)> {
+    xml.split("<paste>")
+        .skip(1)
+        .filter_map(|paste| {
+            let paste = paste.split("</paste>").next()?;
+            let key = xml_child(paste, "paste_key")?;
+            Some((key, xml_child(paste, "paste_title")))
+        })
+        .collect()
+}
+
+fn xml_child(xml: &str, tag: &str) -> Option<String> {
+    let (_, rest) = xml.split_once(&format!("<{tag}>"))?;
+    let (text, _) = rest.split_once(&format!("</{tag}>"))?;
+    Some(
+        text.trim()
+            .replace("&lt;", "<")
+            .replace("&gt;", ">")
+            .replace("&quot;", "\"")
+            .replace("&apos;", "'")
+            .replace("&amp;", "&"),
+    )
+}
+
+#[test]
+fn test_parse_pastebin_listing() {
+    let xml = "<paste>
+        <paste_key>0b42rwhf</paste_key>
+        <paste_date>1297953260</paste_date>
+        <paste_title>Fish &amp; chips</paste_title>
+    </paste>
+    <paste>
+        <paste_key>0C343n0d</paste_key>
+        <paste_title></paste_title>
+    </paste>";
+    assert_eq!(
+        parse_pastebin_listing(xml),
+        [
+            ("0b42rwhf".to_owned(), Some("Fish & chips".to_owned())),
+            ("0C343n0d".to_owned(), Some(String::new())),
+        ]
+    );
+}
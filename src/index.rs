@@ -0,0 +1,143 @@
+//! An in-memory index of every paste's size, modification time and metadata,
+//! so that listings, HEAD requests and existence checks don't stat and read
+//! files per request. It's filled by a scan in the background once the
+//! server is up; until that's done, lookups miss and callers go to the
+//! filesystem.
+
+use std::{
+    collections::{HashMap, HashSet},
+    path::Path,
+    time::SystemTime,
+};
+
+use parking_lot::RwLock;
+use uuid::Uuid;
+
+use crate::meta::{self, Metadata};
+
+#[derive(Clone)]
+pub struct Entry {
+    pub size: u64,
+    pub written: SystemTime,
+    /// `None` for pastes stored without a metadata file.
+    pub metadata: Option<Metadata>,
+}
+
+impl Entry {
+    /// The entry of paste `id` as stored in `data_dir`, if it exists.
+    pub fn load_blocking(data_dir: &Path, id: &Uuid) -> anyhow::Result<Option<Self>> {
+        let name = id.to_string();
+        let file = match std::fs::metadata(data_dir.join(&name)) {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        Ok(Some(Self {
+            size: file.len(),
+            written: file.modified()?,
+            metadata: meta::load_blocking(data_dir, &name)?,
+        }))
+    }
+}
+
+#[derive(Default)]
+struct Inner {
+    warm: bool,
+    entries: HashMap<Uuid, Entry>,
+    /// Pastes changed while warming, whose scanned entries may be outdated.
+    touched: HashSet<Uuid>,
+}
+
+#[derive(Default)]
+pub struct Index {
+    inner: RwLock<Inner>,
+}
+
+impl Index {
+    /// The entry of paste `id`, or `Some(None)` if there's no such paste.
+    /// `None` until the index is warm.
+    pub fn get(&self, id: &Uuid) -> Option<Option<Entry>> {
+        let inner = self.inner.read();
+        inner.warm.then(|| inner.entries.get(id).cloned())
+    }
+
+    /// Whether paste `id` exists, or `None` until the index is warm.
+    pub fn contains(&self, id: &Uuid) -> Option<bool> {
+        let inner = self.inner.read();
+        inner.warm.then(|| inner.entries.contains_key(id))
+    }
+
+    /// Every paste, or `None` until the index is warm.
+    pub fn all(&self) -> Option<Vec<(Uuid, Entry)>> {
+        let inner = self.inner.read();
+        inner.warm.then(|| {
+            inner
+                .entries
+                .iter()
+                .map(|(id, entry)| (*id, entry.clone()))
+                .collect()
+        })
+    }
+
+    /// Records the current entry of paste `id`, or that it's gone.
+    pub fn set(&self, id: Uuid, entry: Option<Entry>) {
+        let mut inner = self.inner.write();
+        if !inner.warm {
+            inner.touched.insert(id);
+        }
+        match entry {
+            Some(entry) => inner.entries.insert(id, entry),
+            None => inner.entries.remove(&id),
+        };
+    }
+
+    /// Adds entries scanned from the data directory, other than those of
+    /// pastes changed since the scan started, calling `added` with each one
+    /// added while changes are held off.
+    pub fn add_scanned(&self, scanned: Vec<(Uuid, Entry)>, mut added: impl FnMut(Uuid, &Entry)) {
+        let mut inner = self.inner.write();
+        for (id, entry) in scanned {
+            if !inner.touched.contains(&id) {
+                added(id, &entry);
+                inner.entries.insert(id, entry);
+            }
+        }
+    }
+
+    /// Serves lookups from the index, once the scan is done.
+    pub fn warm(&self) {
+        let mut inner = self.inner.write();
+        inner.touched = HashSet::new();
+        inner.warm = true;
+    }
+}
+
+#[test]
+fn test_warming_keeps_later_changes() {
+    let index = Index::default();
+    let [created, deleted, scanned] = [Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4()];
+    let entry = |size| Entry {
+        size,
+        written: SystemTime::UNIX_EPOCH,
+        metadata: None,
+    };
+    assert!(index.contains(&scanned).is_none());
+    index.set(created, Some(entry(2)));
+    index.set(deleted, None);
+    let mut added = Vec::new();
+    index.add_scanned(
+        vec![
+            (created, entry(1)),
+            (deleted, entry(1)),
+            (scanned, entry(1)),
+        ],
+        |id, _| added.push(id),
+    );
+    assert_eq!(added, [scanned]);
+    assert!(index.get(&scanned).is_none());
+    index.warm();
+    assert_eq!(index.get(&created).unwrap().unwrap().size, 2);
+    assert_eq!(index.contains(&deleted), Some(false));
+    assert_eq!(index.contains(&scanned), Some(true));
+    assert_eq!(index.all().unwrap().len(), 2);
+}
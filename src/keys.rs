@@ -0,0 +1,28 @@
+//! Ed25519 keys the instance identifies itself with, such as the one it
+//! signs federation messages with, kept as PKCS#8 files.
+
+use std::path::Path;
+
+use anyhow::{anyhow, bail};
+use ring::{rand::SystemRandom, signature::Ed25519KeyPair};
+
+/// Loads the key at `path`, generating one first if there's none.
+pub fn load_or_generate(path: &Path) -> anyhow::Result<Ed25519KeyPair> {
+    let pkcs8 = match std::fs::read(path) {
+        Ok(pkcs8) => pkcs8,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            let pkcs8 = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new())
+                .map_err(|_| anyhow!("Couldn't generate a key"))?;
+            let mut options = std::fs::OpenOptions::new();
+            options.write(true).create_new(true);
+            #[cfg(unix)]
+            std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+            std::io::Write::write_all(&mut options.open(path)?, pkcs8.as_ref())
+                .map_err(|e| anyhow!("Couldn't write {}: {e}", path.display()))?;
+            tracing::info!("Generated key {}", path.display());
+            pkcs8.as_ref().to_vec()
+        }
+        Err(e) => bail!("Couldn't read {}: {e}", path.display()),
+    };
+    Ed25519KeyPair::from_pkcs8(&pkcs8).map_err(|e| anyhow!("Invalid key {}: {e}", path.display()))
+}
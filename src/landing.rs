@@ -0,0 +1,128 @@
+//! The page at `/`, telling visitors how to use the instance. Command line
+//! clients get it as plain text, so that `curl host` shows the commands to
+//! copy, and browsers as HTML.
+
+use std::{fmt::Write as _, sync::Arc};
+
+use axum::{
+    extract::{Request, State},
+    http::{HeaderMap, header},
+    response::{Html, IntoResponse, Response},
+};
+
+use crate::{browse, client, html::escape};
+
+/// Lets the page use its inline styles, nothing else.
+const CSP: &str = "default-src 'none'; style-src 'unsafe-inline'";
+
+/// User agents of command line clients, which know nothing of HTML.
+const TERMINAL_CLIENTS: &[&str] = &["curl/", "Wget/", "HTTPie/", "xh/", "PowerShell/"];
+
+/// Sections of the usage, as a heading and commands with `{base}` standing
+/// for the instance's URL.
+const USAGE: &[(&str, &[&str])] = &[
+    (
+        "Upload a file, or the output of a command",
+        &[
+            "curl --data-binary @file.txt {base}/paste",
+            "command | curl --data-binary @- {base}/paste",
+            "wget -qO- --post-file=file.txt {base}/paste",
+        ],
+    ),
+    (
+        "Title it, set its language and list it publicly",
+        &["curl --data-binary @main.rs '{base}/paste?title=main.rs&language=rust&public=true'"],
+    ),
+    (
+        "Upload as a user, to replace or delete it later",
+        &[
+            "curl -u user:password --data-binary @file.txt {base}/paste",
+            "curl -u user:password -T file.txt {base}/paste/<id>",
+            "curl -u user:password -X DELETE {base}/paste/<id>",
+        ],
+    ),
+    ("Read a paste", &["curl {base}/paste/<id>"]),
+    (
+        "Use the same binary as a client",
+        &["pastebin --remote {base} client post file.txt"],
+    ),
+];
+
+pub async fn get(State(client): State<Arc<client::Config>>, request: Request) -> Response {
+    let (parts, _) = request.into_parts();
+    // Without a Host header, the commands at least show the paths.
+    let base = client::base_url(&parts, &client).unwrap_or_else(|| client.path_prefix.clone());
+    if is_terminal(&parts.headers) || !browse::wants_html(&parts.headers) {
+        return text(&base).into_response();
+    }
+    (
+        [(header::CONTENT_SECURITY_POLICY, CSP)],
+        Html(page(&base, &client.path_prefix)),
+    )
+        .into_response()
+}
+
+fn is_terminal(headers: &HeaderMap) -> bool {
+    headers
+        .get(header::USER_AGENT)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|agent| {
+            TERMINAL_CLIENTS
+                .iter()
+                .any(|client| agent.starts_with(client))
+        })
+}
+
+fn text(base: &str) -> String {
+    let mut text = String::from("pastebin\n");
+    for (heading, commands) in USAGE {
+        write!(text, "\n{heading}:\n").unwrap();
+        for command in *commands {
+            writeln!(text, "    {}", command.replace("{base}", base)).unwrap();
+        }
+    }
+    write!(
+        text,
+        "\nWrite one in the browser: {base}/ui\nPublic pastes: {base}/browse\n"
+    )
+    .unwrap();
+    text
+}
+
+fn page(base: &str, path_prefix: &str) -> String {
+    let mut page = String::from(
+        "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"><title>pastebin</title>\
+         <style>body{font-family:sans-serif}pre{background:#f4f4f4;padding:.5em}</style>\
+         </head><body>\n<h1>pastebin</h1>\n",
+    );
+    for (heading, commands) in USAGE {
+        write!(page, "<h2>{heading}</h2>\n<pre>").unwrap();
+        for command in *commands {
+            writeln!(page, "{}", escape(&command.replace("{base}", base))).unwrap();
+        }
+        page.push_str("</pre>\n");
+    }
+    write!(
+        page,
+        "<p><a href=\"{0}/ui\">Write a paste</a> <a href=\"{0}/browse\">Newest</a> \
+         <a href=\"{0}/trending\">Trending</a></p>\n\
+         </body></html>\n",
+        escape(path_prefix),
+    )
+    .unwrap();
+    page
+}
+
+#[test]
+fn test_curl_gets_text() {
+    let mut headers = HeaderMap::new();
+    headers.insert(header::USER_AGENT, "curl/8.5.0".parse().unwrap());
+    headers.insert(header::ACCEPT, "text/html".parse().unwrap());
+    assert!(is_terminal(&headers));
+    headers.insert(header::USER_AGENT, "Mozilla/5.0".parse().unwrap());
+    assert!(!is_terminal(&headers));
+    assert!(
+        text("https://example.com")
+            .contains("curl --data-binary @file.txt https://example.com/paste\n")
+    );
+}
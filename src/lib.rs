@@ -0,0 +1,1136 @@
+//! A pastebin server: [`Service`] stores pastes and accounts, and [`router`]
+//! serves it over HTTP. [`PastebinBuilder`] puts the two together in code.
+//! The `pastebin` binary is a thin CLI wrapper around [`run`].
+
+use std::sync::Arc;
+
+use auth::BasicAuth;
+use axum::{
+    Json, Router,
+    body::Body,
+    error_handling::HandleErrorLayer,
+    extract::{FromRef, MatchedPath, Path, Query, Request, State},
+    http::{StatusCode, header},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    routing::{any, get, post, put},
+};
+use cli::{Args, Command};
+use futures::TryStreamExt;
+use replication::Replicator;
+use service::Service;
+use tokio_util::sync::CancellationToken;
+use tower_http::{catch_panic::CatchPanicLayer, trace::TraceLayer};
+use uuid::Uuid;
+
+pub use builder::PastebinBuilder;
+
+mod access_log;
+mod admin;
+mod archive;
+mod auth;
+mod backup;
+mod bench;
+mod blobs;
+mod body;
+mod browse;
+mod buffers;
+pub mod builder;
+mod cache;
+mod checksum;
+mod chunked;
+pub mod cli;
+mod client;
+pub mod clock;
+mod collab;
+mod config;
+mod dav;
+mod debug;
+mod doctor;
+mod durability;
+mod email;
+mod embed;
+mod events;
+mod federation;
+mod feed;
+mod gc;
+mod gemini;
+mod gist;
+mod graphql;
+mod grpc;
+mod highlight;
+mod hooks;
+mod html;
+mod http3;
+pub mod ids;
+mod import;
+mod index;
+mod keys;
+mod landing;
+mod listen;
+mod logging;
+mod meta;
+mod metrics;
+mod notify;
+mod oembed;
+mod ot;
+mod public;
+mod qpack;
+mod rate_limit;
+#[cfg(unix)]
+mod reload;
+mod reminders;
+mod remote;
+mod replication;
+mod request_id;
+mod scrub;
+pub mod service;
+mod sitemap;
+mod sse;
+pub mod state;
+mod stats;
+mod termbin;
+pub mod testing;
+mod timeout;
+mod tls;
+mod tus;
+mod ui;
+#[cfg(feature = "io-uring")]
+mod uring;
+mod usage;
+mod variants;
+mod versions;
+mod view;
+mod webhook;
+mod ws;
+
+/// Runs the command `args` ask for, serving by default.
+pub async fn run(args: Args) -> anyhow::Result<()> {
+    let log_filter = logging::init(&args.log_level, args.log_format)?;
+    match &args.command {
+        None => serve(args, log_filter).await,
+        Some(Command::Import { archive }) => import(&args, archive).await,
+        Some(Command::ImportDir { dir, format }) => {
+            let base_url = args
+                .base_url
+                .clone()
+                .unwrap_or_else(|| format!("http://localhost:{}{}", args.port, args.path_prefix()));
+            import_dir(&args, dir, *format, &base_url).await
+        }
+        Some(Command::RateLimit {
+            user,
+            per_minute,
+            burst,
+        }) => set_rate_limit(&args, user, *per_minute, *burst),
+        Some(Command::Doctor { repair }) => run_doctor(&args, *repair),
+        Some(Command::Bench {
+            concurrency,
+            duration,
+            sizes,
+            reads,
+        }) => {
+            let Some(url) = &args.remote else {
+                anyhow::bail!("--remote is required");
+            };
+            let auth = args.username.clone().zip(args.password.clone());
+            let options = bench::Options {
+                concurrency: *concurrency,
+                duration: std::time::Duration::from_secs(*duration),
+                sizes: sizes.clone(),
+                reads: *reads,
+            };
+            bench::run(remote::Remote::new(url, auth), options).await
+        }
+        Some(Command::Client { command }) => {
+            let Some(url) = &args.remote else {
+                anyhow::bail!("--remote is required");
+            };
+            let auth = args.username.clone().zip(args.password.clone());
+            remote::run(remote::Remote::new(url, auth), command).await
+        }
+    }
+}
+
+fn run_doctor(args: &Args, repair: bool) -> anyhow::Result<()> {
+    if !args.state.exists() {
+        println!("State file {} doesn't exist", args.state.display());
+    }
+    let mut state = state::State::load(&args.state)
+        .map_err(|e| anyhow::anyhow!("Invalid state file {}: {e}", args.state.display()))?;
+    let problems = doctor::check(&mut state, &args.data_dir, repair);
+    for problem in &problems {
+        println!("{problem}");
+    }
+
+    let repaired = if repair {
+        problems.iter().filter(|p| p.is_repairable()).count()
+    } else {
+        0
+    };
+    if repaired > 0 {
+        state.dump(&args.state)?;
+        println!("Repaired {repaired} problem(s)");
+    }
+    match problems.len() - repaired {
+        0 => Ok(()),
+        remaining => anyhow::bail!("{remaining} problem(s) remaining"),
+    }
+}
+
+fn set_rate_limit(
+    args: &Args,
+    username: &str,
+    per_minute: Option<u32>,
+    burst: Option<u32>,
+) -> anyhow::Result<()> {
+    let mut state = state::State::load(&args.state)?;
+    let Some(user) = state.user_mut(username) else {
+        anyhow::bail!("No user {username}");
+    };
+    user.rate_limit = per_minute.map(|per_minute| rate_limit::Rate {
+        per_minute,
+        burst: burst.unwrap_or(per_minute),
+    });
+    state.dump(&args.state)
+}
+
+/// Opens the data directory and state file directly, for commands that run
+/// without a server, and returns the service along with the CLI credentials.
+fn open_offline(args: &Args) -> anyhow::Result<(Service, (String, String))> {
+    let (Some(username), Some(password)) = (&args.username, &args.password) else {
+        anyhow::bail!("--username and --password are required");
+    };
+    let state = state::State::load(&args.state)?;
+    let service = Service::new(args.data_dir.clone(), state)?;
+    // On a fresh instance this creates the account; an existing account must
+    // match the given password for the import to be authorized.
+    service.register_user(username, password).ok();
+    Ok((service, (username.clone(), password.clone())))
+}
+
+async fn import(args: &Args, archive: &std::path::Path) -> anyhow::Result<()> {
+    let (service, auth) = open_offline(args)?;
+    let file = tokio::fs::File::open(archive).await?;
+    let imported = archive::import_tar_gz(&service, file, auth).await;
+    // Persist whatever was imported before a failure, too.
+    service.dump_state(&args.state)?;
+    for (name, id) in imported? {
+        println!("{name} {id}");
+    }
+    Ok(())
+}
+
+async fn import_dir(
+    args: &Args,
+    dir: &std::path::Path,
+    format: import::Format,
+    base_url: &str,
+) -> anyhow::Result<()> {
+    let (service, auth) = open_offline(args)?;
+    let imported = import::import_dir(&service, dir, format, auth).await;
+    service.dump_state(&args.state)?;
+    let base_url = base_url.trim_end_matches('/');
+    for (label, id) in imported? {
+        println!("{label} {base_url}/paste/{id}");
+    }
+    Ok(())
+}
+
+/// Builds the service from the data directory and state file `args` name,
+/// configured as they have it.
+pub fn open_service(args: &Args) -> anyhow::Result<Service> {
+    let state = state::State::load(&args.state)?;
+    let mut service = Service::new(args.data_dir.clone(), state)?
+        .with_admins(args.admin.clone())
+        .with_size_limits(args.size_limits())
+        .with_buffers(args.buffers())
+        .with_durability(args.durability)
+        .with_versions(args.keep_versions);
+    if let Some(budget) = args.storage_budget {
+        service = service.with_storage_budget(budget);
+    }
+    #[cfg(feature = "io-uring")]
+    if args.io_uring {
+        service = service.with_ring(uring::Ring::new(uring::ENTRIES)?);
+    }
+    if let Some(cache) = args.cache() {
+        service = service.with_cache(cache);
+    }
+    if let Some(target) = &args.replicate_to {
+        let target = replication::Target::parse(target, args.replication_token.clone())?;
+        service = service.with_replicator(Replicator::spawn(args.data_dir.clone(), target)?);
+    }
+    Ok(service)
+}
+
+/// What handlers share, handed to them by [`State`] extractors of its
+/// fields. The side servers and background tasks share parts of it, too.
+#[derive(Clone, FromRef)]
+pub struct AppState {
+    pub service: Arc<Service>,
+    limits: Arc<rate_limit::Limits>,
+    public_pastes: Arc<public::Cache>,
+    client: Arc<client::Config>,
+    collab_sessions: Arc<collab::Sessions>,
+    in_flight: Arc<debug::InFlight>,
+    /// Cancelled on shutdown, for streams that would otherwise keep it
+    /// waiting to end.
+    shutdown: CancellationToken,
+    #[from_ref(skip)]
+    federation_key: Option<Arc<ring::signature::Ed25519KeyPair>>,
+    federation_trusted: federation::Trusted,
+    gzip_pending: Arc<variants::Pending>,
+    tus_busy: Arc<tus::Busy>,
+    robots_txt: RobotsTxt,
+    private_stats: PrivateStats,
+    replication_token: Option<ReplicationToken>,
+    timeouts: Option<timeout::Config>,
+    access_log: Option<access_log::Config>,
+}
+
+impl AppState {
+    pub fn new(args: &Args, service: Arc<Service>) -> anyhow::Result<Self> {
+        let federation_key = if args.federate_to.is_empty() {
+            None
+        } else {
+            Some(Arc::new(keys::load_or_generate(&args.federation_key)?))
+        };
+        Ok(Self {
+            service,
+            limits: Arc::new(rate_limit::Limits {
+                create: rate_limit::Limiter::new(args.create_rate()),
+                read: rate_limit::Limiter::new(args.read_rate()),
+                user: rate_limit::Limiter::new(args.user_rate()),
+                user_create: rate_limit::Limiter::new(args.user_create_rate()),
+            }),
+            public_pastes: Arc::new(public::Cache::default()),
+            client: Arc::new(args.client()?),
+            collab_sessions: Arc::new(collab::Sessions::default()),
+            in_flight: Arc::new(debug::InFlight::default()),
+            shutdown: CancellationToken::new(),
+            federation_key,
+            federation_trusted: federation::Trusted::new(&args.federation_trust)?,
+            gzip_pending: Arc::new(variants::Pending::default()),
+            tus_busy: Arc::new(tus::Busy::default()),
+            robots_txt: RobotsTxt(args.robots_txt()?.into()),
+            private_stats: PrivateStats(args.private_stats),
+            replication_token: args
+                .replication_token
+                .as_deref()
+                .map(|token| ReplicationToken(token.into())),
+            timeouts: args.timeouts(),
+            access_log: args.access_log(),
+        })
+    }
+}
+
+async fn serve(args: Args, log_filter: logging::FilterHandle) -> anyhow::Result<()> {
+    let snapshot_schedule = args.snapshot_schedule();
+    let scrub_schedule = args.scrub_schedule();
+    let gc_policy = args.gc_policy();
+    let sync_interval = args.sync_interval();
+    let (state_save_interval, shutdown_timeout) =
+        (args.state_save_interval(), args.shutdown_timeout());
+    let tls_files = args.tls_files();
+    let bind_addresses = args.bind_addresses()?;
+    let tcp_upload = args.tcp_upload_address()?;
+    let gemini = args.gemini_address()?;
+    let email = args.email()?;
+    let gist = args.gist();
+    let notify = args.notify()?;
+    let expiry_reminders = args.expiry_reminders()?;
+    let service = Arc::new(open_service(&args)?);
+    let app_state = AppState::new(&args, service.clone())?;
+    let (limits, client) = (app_state.limits.clone(), app_state.client.clone());
+    let shutdown = app_state.shutdown.clone();
+    events::spawn_log(service.events().subscribe("event log"));
+    if !args.exec_hook.is_empty() {
+        hooks::spawn(
+            service.events().subscribe("exec hooks"),
+            service.data_dir().to_owned(),
+            args.exec_hook.clone(),
+        );
+    }
+    if !args.webhook.is_empty() {
+        webhook::spawn(
+            service.events().subscribe("webhooks"),
+            args.webhook.clone(),
+            args.webhook_secret.clone(),
+        );
+    }
+    if let Some(config) = gist {
+        gist::spawn(
+            service.events().subscribe("gist mirror"),
+            service.clone(),
+            config,
+        );
+    }
+    if let Some(config) = notify {
+        notify::spawn(
+            service.events().subscribe("chat notifier"),
+            service.clone(),
+            config,
+        );
+    }
+    if let Some(key) = &app_state.federation_key {
+        tracing::info!(
+            "Pushing public pastes to {} with federation key {}",
+            args.federate_to.join(", "),
+            federation::encode_key(key)
+        );
+        federation::spawn(
+            service.events().subscribe("federation pusher"),
+            service.clone(),
+            key.clone(),
+            args.federate_to.clone(),
+        );
+    }
+    if let (Some(username), Some(password)) = (&args.username, &args.password)
+        && let Err(e) = service.register_user(username, password)
+    {
+        tracing::warn!("Not registering {username}: {e}");
+    }
+
+    if let Some(schedule) = snapshot_schedule {
+        backup::spawn(service.clone(), schedule);
+    }
+    if let Some(schedule) = scrub_schedule {
+        scrub::spawn(service.clone(), schedule);
+    }
+    gc::spawn(service.clone(), gc_policy);
+    if let Some(config) = expiry_reminders {
+        reminders::spawn(service.clone(), config);
+    }
+    state::spawn_save(service.clone(), args.state.clone(), state_save_interval);
+    if args.durability == durability::Policy::Batch {
+        durability::spawn(service.clone(), sync_interval);
+    }
+    let tls = match tls_files {
+        Some(files) => {
+            let config = files.load().await?;
+            tls::spawn_reload(config.clone(), files);
+            Some(config)
+        }
+        None => None,
+    };
+    #[cfg(unix)]
+    reload::spawn(service.clone(), limits.clone(), log_filter);
+    if let Some(address) = tcp_upload {
+        let listener = tokio::net::TcpListener::bind(address)
+            .await
+            .map_err(|e| anyhow::anyhow!("Couldn't bind {address}: {e}"))?;
+        tracing::info!("Accepting uploads over TCP on {address}");
+        tokio::spawn(termbin::serve(
+            listener,
+            service.clone(),
+            limits.clone(),
+            client.clone(),
+        ));
+    }
+    if let Some((address, config)) = email {
+        let listener = tokio::net::TcpListener::bind(address)
+            .await
+            .map_err(|e| anyhow::anyhow!("Couldn't bind {address}: {e}"))?;
+        tracing::info!(
+            "Accepting pastes by email for {} on {address}",
+            config.domain
+        );
+        tokio::spawn(email::serve(
+            listener,
+            Arc::new(config),
+            service.clone(),
+            limits.clone(),
+            client.clone(),
+        ));
+    }
+    if let (Some(address), Some(config)) = (gemini, &tls) {
+        let listener = tokio::net::TcpListener::bind(address)
+            .await
+            .map_err(|e| anyhow::anyhow!("Couldn't bind {address}: {e}"))?;
+        tracing::info!("Serving public pastes over Gemini on {address}");
+        tokio::spawn(gemini::serve(
+            listener,
+            config.clone(),
+            service.clone(),
+            app_state.public_pastes.clone(),
+            limits.clone(),
+        ));
+    }
+    #[cfg(not(unix))]
+    drop(log_filter);
+
+    let app = router(&args, app_state)?;
+    // Sockets passed in by systemd take precedence over the options.
+    let mut listeners = listen::inherited()?;
+    let mut bound_socket = None;
+    if listeners.is_empty() {
+        for address in bind_addresses {
+            let listener = std::net::TcpListener::bind(address)
+                .map_err(|e| anyhow::anyhow!("Couldn't bind {address}: {e}"))?;
+            listeners.push(listen::Listener::Tcp(listener));
+        }
+        match &args.unix_socket {
+            #[cfg(unix)]
+            Some(path) => {
+                listeners.push(listen::Listener::bind_unix(path)?);
+                bound_socket = Some(path);
+            }
+            #[cfg(not(unix))]
+            Some(_) => anyhow::bail!("Unix sockets aren't supported on this platform"),
+            None => {}
+        }
+    }
+    // Indexing every paste can take a while, so it's done while serving.
+    tokio::spawn({
+        let service = service.clone();
+        async move {
+            if let Err(e) = service.warm_index().await {
+                tracing::error!("Couldn't index pastes: {e}");
+            }
+        }
+    });
+    // HTTP/3 is offered on the UDP ports of the TCP listeners.
+    let (app, http3) = match (&tls, args.http3) {
+        (Some(config), true) => {
+            let addresses: Vec<_> = listeners.iter().filter_map(|l| l.tcp_address()).collect();
+            let endpoints = http3::bind(&addresses, config)?;
+            let alt_svc = http3::alt_svc(&endpoints)?;
+            let app = app.layer(middleware::map_response(move |mut response: Response| {
+                let alt_svc = alt_svc.clone();
+                async move {
+                    response.headers_mut().insert(header::ALT_SVC, alt_svc);
+                    response
+                }
+            }));
+            let http3 = http3::serve(
+                endpoints,
+                app.clone(),
+                config.clone(),
+                shutdown.clone(),
+                shutdown_timeout,
+            );
+            (app, Some(http3))
+        }
+        _ => (app, None),
+    };
+    let http3 = async {
+        match http3 {
+            Some(http3) => http3.await,
+            None => Ok(()),
+        }
+    };
+    tokio::try_join!(
+        listen::serve(listeners, app, tls, shutdown, shutdown_timeout),
+        http3
+    )?;
+    if let Some(path) = bound_socket {
+        std::fs::remove_file(path).ok();
+    }
+
+    tracing::info!("Shutting down, saving state to {}", args.state.display());
+    service.dump_state(&args.state)?;
+    Ok(())
+}
+
+/// The HTTP app serving `state` as `args` configure it, with everything but
+/// the listeners: the other servers and background tasks are up to the
+/// caller.
+pub fn router(args: &Args, state: AppState) -> anyhow::Result<Router> {
+    let app = Router::new()
+        .route("/", get(landing::get))
+        .route("/browse", get(browse::html))
+        .route("/pastes/public", get(browse::json))
+        .route("/trending", get(browse::trending_html))
+        .route("/pastes/trending", get(browse::trending_json))
+        .route("/robots.txt", get(get_robots_txt))
+        .route("/sitemap.xml", get(sitemap::get))
+        .route("/feed.atom", get(feed::instance))
+        .route("/users/{username}/feed.atom", get(feed::user))
+        .route("/paste", post(post_paste))
+        .route(
+            "/paste/{id}",
+            get(get_paste).put(put_paste).delete(delete_paste),
+        )
+        .route("/paste/{id}/fork", post(fork_paste))
+        .route("/paste/{id}/versions", get(get_versions))
+        .route("/paste/{id}/versions/{number}", get(get_version))
+        .route("/paste/{id}/view", get(view::get))
+        .route("/paste/{id}/ws", get(ws::get))
+        .route("/paste/{id}/collab", get(collab::get))
+        .route("/paste/{id}/events", get(sse::paste))
+        .route("/pastes/events", get(sse::user))
+        .route("/oembed", get(oembed::get))
+        .route("/graphql", get(graphql::get).post(graphql::post))
+        .route("/dav", any(dav::collection))
+        .route("/dav/", any(dav::collection))
+        .route("/dav/{name}", any(dav::file))
+        .route("/pastes/archive", get(archive_pastes))
+        .route("/pastes/export", get(export_pastes))
+        .route("/pastes/import", post(import_pastes))
+        .route("/metrics", get(get_metrics))
+        .route("/stats", get(get_stats))
+        .merge(admin::routes())
+        .merge(debug::routes())
+        .merge(grpc::routes())
+        .merge(tus::routes())
+        .merge(chunked::routes())
+        .merge(reminders::routes())
+        .merge(ui::routes())
+        .merge(embed::routes())
+        .merge(federation::routes(
+            state.federation_key.as_deref(),
+            &state.federation_trusted,
+        ))
+        .merge(replication_routes(state.replication_token.is_some()));
+    let app = match state.client.path_prefix.as_str() {
+        "" => app,
+        prefix => Router::new().nest(prefix, app),
+    };
+    let app = app
+        .layer(CatchPanicLayer::custom(panicked))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            rate_limit::middleware,
+        ))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            record_metrics,
+        ))
+        .layer(middleware::from_fn_with_state(state.clone(), debug::track))
+        .layer(
+            TraceLayer::new_for_http().make_span_with(|request: &Request| {
+                let id = request.extensions().get::<request_id::RequestId>();
+                tracing::info_span!(
+                    "request",
+                    id = id.map(request_id::RequestId::as_str),
+                    method = %request.method(),
+                    uri = %request.uri(),
+                )
+            }),
+        )
+        .layer(middleware::from_fn(request_id::middleware));
+
+    let app = app.layer(middleware::from_fn_with_state(
+        state.clone(),
+        timeout::middleware,
+    ));
+    let app = match args.max_concurrent_requests {
+        Some(max) => app.layer(
+            tower::ServiceBuilder::new()
+                .layer(HandleErrorLayer::new(overloaded))
+                .load_shed()
+                .layer(tower::limit::GlobalConcurrencyLimitLayer::new(max)),
+        ),
+        None => app,
+    };
+    let app = match args.cors()? {
+        Some(cors) => app.layer(cors),
+        None => app,
+    };
+    let app = app.layer(middleware::from_fn_with_state(
+        state.clone(),
+        access_log::middleware,
+    ));
+    Ok(app.with_state(state))
+}
+
+#[derive(Clone)]
+struct RobotsTxt(Arc<str>);
+
+async fn get_robots_txt(State(RobotsTxt(robots_txt)): State<RobotsTxt>) -> Response {
+    (
+        [(header::CONTENT_TYPE, "text/plain; charset=utf-8")],
+        robots_txt.to_string(),
+    )
+        .into_response()
+}
+
+/// Answers a request whose handler panicked, after logging the panic in the
+/// span of the request.
+fn panicked(panic: Box<dyn std::any::Any + Send>) -> Response {
+    let message = panic
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| panic.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("unknown cause");
+    tracing::error!("Handler panicked: {message}");
+    (StatusCode::INTERNAL_SERVER_ERROR, "Internal server error").into_response()
+}
+
+/// Answers requests shed because --max-concurrent-requests are in flight.
+async fn overloaded(_: tower::BoxError) -> Response {
+    (
+        StatusCode::SERVICE_UNAVAILABLE,
+        [(header::RETRY_AFTER, "1")],
+        "Server is busy, try again later",
+    )
+        .into_response()
+}
+
+async fn get_stats(
+    State(service): State<Arc<Service>>,
+    State(private): State<PrivateStats>,
+    auth: Option<BasicAuth>,
+) -> Response {
+    let admin = auth.is_some_and(|auth| service.is_admin(&auth.username, &auth.password));
+    if private.0 && !admin {
+        return (StatusCode::FORBIDDEN, "Admins only").into_response();
+    }
+    Json(stats::collect(&service)).into_response()
+}
+
+/// Whether `/stats` is restricted to admins.
+#[derive(Clone, Copy)]
+struct PrivateStats(bool);
+
+async fn get_metrics(State(service): State<Arc<Service>>) -> Response {
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        metrics::render(&service),
+    )
+        .into_response()
+}
+
+/// Counts every request and its latency by matched route. The latency runs
+/// until the response headers are ready, not until the body is sent.
+async fn record_metrics(
+    State(service): State<Arc<Service>>,
+    route: Option<MatchedPath>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let method = request.method().clone();
+    let start = std::time::Instant::now();
+    let response = next.run(request).await;
+    let route = route.as_ref().map_or("unmatched", |route| route.as_str());
+    service.metrics().request(
+        method.as_str(),
+        route,
+        response.status().as_u16(),
+        start.elapsed(),
+    );
+    response
+}
+
+async fn get_paste(
+    State(service): State<Arc<Service>>,
+    State(pending): State<Arc<variants::Pending>>,
+    Path(id): Path<Uuid>,
+    headers: header::HeaderMap,
+) -> Response {
+    if !service.exists(&id) {
+        return (StatusCode::NOT_FOUND, "Paste not found").into_response();
+    }
+    let noindex = match service.metadata(&id).await {
+        Ok(metadata) => metadata.is_some_and(|metadata| metadata.noindex),
+        Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    };
+    let gzipped = if variants::accepts_gzip(&headers) {
+        variants::gzip(&service, &pending, &id)
+            .await
+            .unwrap_or_else(|e| {
+                tracing::warn!("Looking for the gzipped copy of paste {id}: {e}");
+                None
+            })
+    } else {
+        None
+    };
+    let mut response = match gzipped {
+        Some(file) => (
+            USER_CONTENT_HEADERS,
+            [(header::CONTENT_ENCODING, "gzip")],
+            body::stream(service.buffers(), file),
+        )
+            .into_response(),
+        None => {
+            let reader = match service.read(&id).await {
+                Ok(reader) => reader,
+                Err(e) => {
+                    return (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response();
+                }
+            };
+            match body::prefetched(service.buffers(), reader).await {
+                Ok(body) => (USER_CONTENT_HEADERS, body).into_response(),
+                Err(e) => {
+                    return (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response();
+                }
+            }
+        }
+    };
+    response.headers_mut().insert(
+        header::VARY,
+        header::HeaderValue::from_static("accept-encoding"),
+    );
+    if noindex {
+        response.headers_mut().insert(
+            header::HeaderName::from_static("x-robots-tag"),
+            header::HeaderValue::from_static("noindex"),
+        );
+    }
+    response
+}
+
+/// Sent with paste contents so that a browser never runs them as a page of
+/// this site, even if they look like HTML: no sniffing of the content type,
+/// and a sandbox without scripts, styles or any other resources.
+const USER_CONTENT_HEADERS: [(header::HeaderName, &str); 2] = [
+    (header::X_CONTENT_TYPE_OPTIONS, "nosniff"),
+    (
+        header::CONTENT_SECURITY_POLICY,
+        "default-src 'none'; sandbox",
+    ),
+];
+
+async fn post_paste(
+    State(service): State<Arc<Service>>,
+    State(client): State<Arc<client::Config>>,
+    auth: Option<BasicAuth>,
+    Query(options): Query<service::Options>,
+    request: Request,
+) -> Response {
+    if let Err(e) = options.validate() {
+        return (StatusCode::BAD_REQUEST, e.to_string()).into_response();
+    }
+    let (parts, body) = request.into_parts();
+    let reader = match upload_reader(&parts.headers, body) {
+        Ok(reader) => reader,
+        Err(e) => return e.into_response(),
+    };
+    match service
+        .create_with_options(reader, auth.map(Into::into), options)
+        .await
+    {
+        Ok(id) => match client::base_url(&parts, &client) {
+            Some(base_url) => {
+                let url = format!("{base_url}/paste/{id}");
+                ([(header::LOCATION, url.clone())], format!("{url}\n")).into_response()
+            }
+            None => format!("{id}\n").into_response(),
+        },
+        Err(e) => upload_error(e),
+    }
+}
+
+async fn put_paste(
+    State(service): State<Arc<Service>>,
+    Path(id): Path<Uuid>,
+    auth: Option<BasicAuth>,
+    headers: header::HeaderMap,
+    body: Body,
+) -> Response {
+    let reader = match upload_reader(&headers, body) {
+        Ok(reader) => reader,
+        Err(e) => return e.into_response(),
+    };
+
+    match service.replace(&id, reader, auth.map(Into::into)).await {
+        Ok(()) => StatusCode::OK.into_response(),
+        Err(e) => upload_error(e),
+    }
+}
+
+/// Creates a paste with the contents of paste `id`, answering like
+/// `POST /paste`.
+async fn fork_paste(
+    State(service): State<Arc<Service>>,
+    State(client): State<Arc<client::Config>>,
+    Path(id): Path<Uuid>,
+    auth: Option<BasicAuth>,
+    request: Request,
+) -> Response {
+    if let Some(auth) = &auth
+        && !service.authenticate(&auth.username, &auth.password)
+    {
+        return auth::unauthorized();
+    }
+    let (parts, _) = request.into_parts();
+    match service.fork(&id, auth.map(Into::into)).await {
+        Ok(id) => match client::base_url(&parts, &client) {
+            Some(base_url) => {
+                let url = format!("{base_url}/paste/{id}");
+                ([(header::LOCATION, url.clone())], format!("{url}\n")).into_response()
+            }
+            None => format!("{id}\n").into_response(),
+        },
+        Err(e) if is_not_found(&e) => (StatusCode::NOT_FOUND, "Paste not found").into_response(),
+        Err(e) => upload_error(e),
+    }
+}
+
+/// The versions kept of a paste, oldest first, with --keep-versions.
+async fn get_versions(State(service): State<Arc<Service>>, Path(id): Path<Uuid>) -> Response {
+    if !service.exists(&id) {
+        return (StatusCode::NOT_FOUND, "Paste not found").into_response();
+    }
+    match service.versions(&id).await {
+        Ok(versions) => Json(versions).into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
+async fn get_version(
+    State(service): State<Arc<Service>>,
+    Path((id, number)): Path<(Uuid, u64)>,
+) -> Response {
+    if !service.exists(&id) {
+        return (StatusCode::NOT_FOUND, "Paste not found").into_response();
+    }
+    match service.version(&id, number).await {
+        Ok(Some(contents)) => (USER_CONTENT_HEADERS, contents).into_response(),
+        Ok(None) => (StatusCode::NOT_FOUND, "No such version").into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
+/// The body of an upload, which fails at its end if it doesn't match the
+/// request's `Content-Digest`, keeping a corrupted upload from being stored.
+/// Gzipped bodies are decompressed, after checking the digest, which is of
+/// the body as sent; size limits apply to what they decompress to. There's
+/// no zstd, lacking an implementation among the dependencies.
+pub fn upload_reader(
+    headers: &header::HeaderMap,
+    body: Body,
+) -> Result<impl tokio::io::AsyncRead + Unpin + Send + use<>, RejectedUpload> {
+    let gzipped = match headers
+        .get(header::CONTENT_ENCODING)
+        .map(|value| {
+            value
+                .to_str()
+                .unwrap_or_default()
+                .trim()
+                .to_ascii_lowercase()
+        })
+        .as_deref()
+    {
+        None | Some("identity") => false,
+        Some("gzip" | "x-gzip") => true,
+        // Named so that it isn't mistaken for an oversight: there's no zstd
+        // decoder among the dependencies.
+        Some("zstd") => return Err(RejectedUpload::UnsupportedEncoding),
+        Some(_) => return Err(RejectedUpload::UnsupportedEncoding),
+    };
+    let expected = headers
+        .get("content-digest")
+        .map(|value| checksum::parse_content_digest(value.to_str()?))
+        .transpose()
+        .map_err(RejectedUpload::InvalidDigest)?;
+    let reader =
+        tokio_util::io::StreamReader::new(body.into_data_stream().map_err(|e| {
+            std::io::Error::new(std::io::ErrorKind::ConnectionAborted, e.to_string())
+        }));
+    let reader = checksum::ChecksumReader::verifying(reader, expected);
+    if !gzipped {
+        return Ok(tokio_util::either::Either::Left(reader));
+    }
+    let mut decoder =
+        async_compression::tokio::bufread::GzipDecoder::new(tokio::io::BufReader::new(reader));
+    decoder.multiple_members(true);
+    Ok(tokio_util::either::Either::Right(decoder))
+}
+
+/// An upload refused by its headers, before reading its body.
+pub enum RejectedUpload {
+    /// A `Content-Digest` header that can't be checked.
+    InvalidDigest(anyhow::Error),
+    UnsupportedEncoding,
+}
+
+impl IntoResponse for RejectedUpload {
+    fn into_response(self) -> Response {
+        match self {
+            Self::InvalidDigest(e) => (
+                StatusCode::BAD_REQUEST,
+                [("want-content-digest", "sha-256=1")],
+                e.to_string(),
+            )
+                .into_response(),
+            Self::UnsupportedEncoding => (
+                StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                [(header::ACCEPT_ENCODING, "gzip")],
+                "Uploads may only be gzipped",
+            )
+                .into_response(),
+        }
+    }
+}
+
+/// The response to a failed upload: 413 if the paste was over its size
+/// limit, 400 if it didn't match its `Content-Digest` or wasn't validly
+/// gzipped, 500 otherwise.
+pub fn upload_error(e: anyhow::Error) -> Response {
+    let status = match e.downcast_ref::<std::io::Error>().map(std::io::Error::kind) {
+        Some(std::io::ErrorKind::FileTooLarge) => StatusCode::PAYLOAD_TOO_LARGE,
+        Some(std::io::ErrorKind::InvalidData | std::io::ErrorKind::UnexpectedEof) => {
+            StatusCode::BAD_REQUEST
+        }
+        _ => StatusCode::INTERNAL_SERVER_ERROR,
+    };
+    (status, e.to_string()).into_response()
+}
+
+/// Whether `e` is of a paste that doesn't exist.
+fn is_not_found(e: &anyhow::Error) -> bool {
+    e.downcast_ref::<std::io::Error>().map(std::io::Error::kind)
+        == Some(std::io::ErrorKind::NotFound)
+}
+
+async fn delete_paste(
+    State(service): State<Arc<Service>>,
+    Path(id): Path<Uuid>,
+    auth: BasicAuth,
+) -> Response {
+    if !service.authenticate(&auth.username, &auth.password) {
+        return auth::unauthorized();
+    }
+    match service.delete(id, &auth.username, &auth.password).await {
+        Ok(()) => StatusCode::NO_CONTENT.into_response(),
+        Err(e) if is_not_found(&e) => (StatusCode::NOT_FOUND, e.to_string()).into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
+#[derive(serde::Deserialize)]
+struct ArchiveQuery {
+    /// Comma-separated paste IDs.
+    ids: String,
+    #[serde(default = "tar_format")]
+    format: archive::Format,
+}
+
+fn tar_format() -> archive::Format {
+    archive::Format::Tar
+}
+
+async fn archive_pastes(
+    State(service): State<Arc<Service>>,
+    Query(query): Query<ArchiveQuery>,
+) -> Response {
+    let mut ids = Vec::new();
+    for id in query.ids.split(',').filter(|id| !id.is_empty()) {
+        match Uuid::parse_str(id) {
+            Ok(id) if service.exists(&id) => ids.push(id.to_string()),
+            Ok(id) => {
+                return (StatusCode::NOT_FOUND, format!("Paste {id} not found")).into_response();
+            }
+            Err(e) => return (StatusCode::BAD_REQUEST, format!("{id}: {e}")).into_response(),
+        }
+    }
+    archive_response(service, ids, query.format)
+}
+
+#[derive(serde::Deserialize)]
+struct ExportQuery {
+    #[serde(default)]
+    format: archive::Format,
+}
+
+async fn export_pastes(
+    State(service): State<Arc<Service>>,
+    auth: BasicAuth,
+    Query(query): Query<ExportQuery>,
+) -> Response {
+    let Ok(ids) = service.list(&auth.username, &auth.password) else {
+        return auth::unauthorized();
+    };
+    archive_response(service, ids, query.format)
+}
+
+fn archive_response(service: Arc<Service>, ids: Vec<String>, format: archive::Format) -> Response {
+    let body = Body::from_stream(archive::stream(service, ids, format));
+    let disposition = format!("attachment; filename=\"pastes.{}\"", format.extension());
+    (
+        [
+            (header::CONTENT_TYPE, format.content_type().to_owned()),
+            (header::CONTENT_DISPOSITION, disposition),
+        ],
+        body,
+    )
+        .into_response()
+}
+
+async fn import_pastes(
+    State(service): State<Arc<Service>>,
+    auth: BasicAuth,
+    body: Body,
+) -> Response {
+    if !service.authenticate(&auth.username, &auth.password) {
+        return auth::unauthorized();
+    }
+    let reader =
+        tokio_util::io::StreamReader::new(body.into_data_stream().map_err(|e| {
+            std::io::Error::new(std::io::ErrorKind::ConnectionAborted, e.to_string())
+        }));
+
+    match archive::import_tar_gz(&service, reader, auth.into()).await {
+        Ok(imported) => imported
+            .into_iter()
+            .map(|(name, id)| format!("{name} {id}\n"))
+            .collect::<String>()
+            .into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
+#[derive(Clone)]
+struct ReplicationToken(Arc<str>);
+
+/// Routes through which a primary instance pushes pastes to this one. Only
+/// enabled when a replication token is configured.
+fn replication_routes(enabled: bool) -> Router<AppState> {
+    if !enabled {
+        return Router::new();
+    }
+    Router::new().route(
+        "/replication/paste/{id}",
+        put(put_replica).delete(delete_replica),
+    )
+}
+
+fn check_replication_token(headers: &header::HeaderMap, token: Option<&ReplicationToken>) -> bool {
+    use subtle::ConstantTimeEq;
+
+    let Some(token) = token else {
+        return false;
+    };
+    headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .is_some_and(|given| bool::from(given.as_bytes().ct_eq(token.0.as_bytes())))
+}
+
+async fn put_replica(
+    State(service): State<Arc<Service>>,
+    State(token): State<Option<ReplicationToken>>,
+    Path(id): Path<Uuid>,
+    headers: header::HeaderMap,
+    body: Body,
+) -> Response {
+    if !check_replication_token(&headers, token.as_ref()) {
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+    let reader =
+        tokio_util::io::StreamReader::new(body.into_data_stream().map_err(|e| {
+            std::io::Error::new(std::io::ErrorKind::ConnectionAborted, e.to_string())
+        }));
+
+    match service.write_replica(&id, reader).await {
+        Ok(()) => StatusCode::OK.into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
+async fn delete_replica(
+    State(service): State<Arc<Service>>,
+    State(token): State<Option<ReplicationToken>>,
+    Path(id): Path<Uuid>,
+    headers: header::HeaderMap,
+) -> Response {
+    if !check_replication_token(&headers, token.as_ref()) {
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+    match service.delete_replica(&id).await {
+        Ok(()) => StatusCode::OK.into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
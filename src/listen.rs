@@ -0,0 +1,207 @@
+use std::{
+    net::{SocketAddr, TcpListener},
+    time::Duration,
+};
+#[cfg(unix)]
+use std::{os::unix::net::UnixListener, path::Path};
+
+use axum::Router;
+use axum_server::tls_rustls::RustlsConfig;
+use tokio::task::JoinSet;
+use tokio_util::sync::CancellationToken;
+
+pub enum Listener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(UnixListener),
+}
+
+impl Listener {
+    /// Binds a Unix domain socket at `path`, replacing a socket left behind
+    /// by a previous run.
+    #[cfg(unix)]
+    pub fn bind_unix(path: &Path) -> anyhow::Result<Self> {
+        use std::os::unix::fs::FileTypeExt;
+
+        if let Ok(metadata) = std::fs::symlink_metadata(path)
+            && metadata.file_type().is_socket()
+        {
+            std::fs::remove_file(path)?;
+        }
+        UnixListener::bind(path)
+            .map(Self::Unix)
+            .map_err(|e| anyhow::anyhow!("Couldn't bind {}: {e}", path.display()))
+    }
+
+    /// The address of a TCP listener.
+    pub fn tcp_address(&self) -> Option<SocketAddr> {
+        match self {
+            Self::Tcp(listener) => listener.local_addr().ok(),
+            #[cfg(unix)]
+            Self::Unix(_) => None,
+        }
+    }
+
+    fn describe(&self) -> anyhow::Result<String> {
+        Ok(match self {
+            Self::Tcp(listener) => listener.local_addr()?.to_string(),
+            #[cfg(unix)]
+            Self::Unix(listener) => match listener.local_addr()?.as_pathname() {
+                Some(path) => format!("unix:{}", path.display()),
+                None => "an unnamed Unix socket".to_owned(),
+            },
+        })
+    }
+}
+
+/// Listening sockets passed in by a service manager, following the systemd
+/// socket activation protocol (`LISTEN_FDS`). Also works with `systemfd`
+/// during development.
+pub fn inherited() -> anyhow::Result<Vec<Listener>> {
+    let mut fds = listenfd::ListenFd::from_env();
+    let mut listeners = Vec::new();
+    for index in 0..fds.len() {
+        if let Some(listener) = fds.take_tcp_listener(index).unwrap_or(None) {
+            listeners.push(Listener::Tcp(listener));
+            continue;
+        }
+        #[cfg(unix)]
+        if let Some(listener) = fds.take_unix_listener(index).unwrap_or(None) {
+            listeners.push(Listener::Unix(listener));
+            continue;
+        }
+        anyhow::bail!("Inherited socket {index} isn't a stream listener");
+    }
+    Ok(listeners)
+}
+
+/// Serves `app` on every listener, over HTTPS if `tls` is given, until
+/// [`shutdown_signal`] fires and the in-flight requests have finished, or
+/// `timeout` has passed since. `shutdown` is cancelled when the signal
+/// fires. Client addresses are only known for TCP connections. HTTP/2 is
+/// offered through ALPN over TLS, and to clients with prior knowledge
+/// otherwise. HTTP/3 is served separately, by [`crate::http3`].
+pub async fn serve(
+    listeners: Vec<Listener>,
+    app: Router,
+    tls: Option<RustlsConfig>,
+    shutdown: CancellationToken,
+    timeout: Duration,
+) -> anyhow::Result<()> {
+    tokio::spawn({
+        let shutdown = shutdown.clone();
+        async move {
+            shutdown_signal().await;
+            shutdown.cancel();
+        }
+    });
+
+    let mut servers = JoinSet::new();
+    for listener in listeners {
+        let scheme = if tls.is_some() { "https" } else { "http" };
+        tracing::info!("Listening on {scheme}://{}", listener.describe()?);
+        let graceful = shutdown.clone().cancelled_owned();
+        match (listener, &tls) {
+            (Listener::Tcp(listener), None) => {
+                listener.set_nonblocking(true)?;
+                let listener = tokio::net::TcpListener::from_std(listener)?;
+                let app = app
+                    .clone()
+                    .into_make_service_with_connect_info::<SocketAddr>();
+                servers.spawn(async move {
+                    axum::serve(listener, app)
+                        .with_graceful_shutdown(graceful)
+                        .await
+                });
+            }
+            (Listener::Tcp(listener), Some(config)) => {
+                listener.set_nonblocking(true)?;
+                let app = app
+                    .clone()
+                    .into_make_service_with_connect_info::<SocketAddr>();
+                let server = axum_server::from_tcp_rustls(listener, config.clone())?
+                    .handle(shutdown_handle(graceful, timeout));
+                servers.spawn(async move { server.serve(app).await });
+            }
+            #[cfg(unix)]
+            (Listener::Unix(listener), None) => {
+                listener.set_nonblocking(true)?;
+                let listener = tokio::net::UnixListener::from_std(listener)?;
+                let app = app.clone().into_make_service();
+                servers.spawn(async move {
+                    axum::serve(listener, app)
+                        .with_graceful_shutdown(graceful)
+                        .await
+                });
+            }
+            #[cfg(unix)]
+            (Listener::Unix(listener), Some(config)) => {
+                listener.set_nonblocking(true)?;
+                let app = app.clone().into_make_service();
+                let server = axum_server::from_unix_rustls(listener, config.clone())?
+                    .handle(shutdown_handle(graceful, timeout));
+                servers.spawn(async move { server.serve(app).await });
+            }
+        }
+    }
+
+    let drained = async {
+        while let Some(result) = servers.join_next().await {
+            result??;
+        }
+        anyhow::Ok(())
+    };
+    let deadline = async {
+        shutdown.cancelled().await;
+        tokio::time::sleep(timeout).await;
+    };
+    tokio::select! {
+        result = drained => result?,
+        () = deadline => {
+            tracing::warn!("Cutting off the connections still open after {timeout:?}");
+        }
+    }
+    servers.shutdown().await;
+    Ok(())
+}
+
+/// A handle that shuts an HTTPS server down gracefully once `shutdown`
+/// completes, giving connections `timeout` to finish.
+fn shutdown_handle<A: axum_server::Address + Send + Sync + 'static>(
+    shutdown: impl Future<Output = ()> + Send + 'static,
+    timeout: Duration,
+) -> axum_server::Handle<A> {
+    let handle = axum_server::Handle::new();
+    tokio::spawn({
+        let handle = handle.clone();
+        async move {
+            shutdown.await;
+            handle.graceful_shutdown(Some(timeout));
+        }
+    });
+    handle
+}
+
+/// Completes on Ctrl-C or, on Unix, SIGTERM. In-flight requests are then
+/// given until the shutdown timeout to finish before the server stops.
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        tokio::signal::ctrl_c()
+            .await
+            .expect("Couldn't listen for Ctrl-C");
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("Couldn't listen for SIGTERM")
+            .recv()
+            .await;
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        () = ctrl_c => {}
+        () = terminate => {}
+    }
+}
@@ -0,0 +1,44 @@
+use tracing_subscriber::{
+    EnvFilter, Layer, Registry, layer::SubscriberExt, reload, util::SubscriberInitExt,
+};
+
+#[derive(Clone, Copy, Debug, Default, clap::ValueEnum, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    /// Human-readable lines
+    #[default]
+    Text,
+    /// One JSON object per event, for log collectors
+    Json,
+}
+
+/// Installs the global subscriber, logging to stderr so that the output of
+/// offline commands on stdout stays machine-readable.
+///
+/// `filter` uses the `RUST_LOG` syntax, e.g. `info` or `pastebin=debug,info`.
+/// The returned handle changes the filter later on.
+pub fn init(filter: &str, format: Format) -> anyhow::Result<FilterHandle> {
+    let (filter, handle) = reload::Layer::new(parse_filter(filter)?);
+    let layer = tracing_subscriber::fmt::layer().with_writer(std::io::stderr);
+    let layer = match format {
+        Format::Text => layer.boxed(),
+        Format::Json => layer.json().boxed(),
+    };
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(layer)
+        .init();
+    Ok(FilterHandle(handle))
+}
+
+pub struct FilterHandle(reload::Handle<EnvFilter, Registry>);
+
+impl FilterHandle {
+    pub fn set(&self, filter: &str) -> anyhow::Result<()> {
+        Ok(self.0.reload(parse_filter(filter)?)?)
+    }
+}
+
+fn parse_filter(filter: &str) -> anyhow::Result<EnvFilter> {
+    EnvFilter::try_new(filter).map_err(|e| anyhow::anyhow!("Invalid log level {filter:?}: {e}"))
+}
@@ -0,0 +1,75 @@
+//! Per-paste metadata stored next to the paste file, so that ownership can be
+//! recovered from the data directory alone if the state file is lost or
+//! damaged. Checksums live in their own `.sha256` files, see [`crate::checksum`].
+
+use std::{
+    io,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Metadata {
+    /// The user who created the paste, or `None` if it is anonymous.
+    #[serde(default)]
+    pub owner: Option<String>,
+    /// Whether search engines are asked not to index the paste.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub noindex: bool,
+    /// Whether the paste is listed publicly, e.g. in the sitemap.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub public: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    /// Language of the contents, e.g. `rust`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub language: Option<String>,
+    /// The federation key of the instance the paste is mirrored from, if it
+    /// didn't originate here.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub origin: Option<String>,
+    /// Whether the paste is mirrored to a GitHub gist.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub gist: bool,
+    /// The URL of the gist, once it's been created.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gist_url: Option<String>,
+    /// When the paste is deleted, in seconds since the Unix epoch.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<u64>,
+    /// Whether the owner was reminded that the paste is about to expire.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub reminded: bool,
+}
+
+/// Path of the file holding the metadata of paste `id`.
+pub fn path(data_dir: &Path, id: &str) -> PathBuf {
+    data_dir.join(format!("{id}.meta.json"))
+}
+
+/// Reads the metadata of paste `id`, if it has any.
+pub fn load_blocking(data_dir: &Path, id: &str) -> io::Result<Option<Metadata>> {
+    match std::fs::read(path(data_dir, id)) {
+        Ok(contents) => serde_json::from_slice(&contents)
+            .map(Some)
+            .map_err(io::Error::from),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+pub async fn load(data_dir: &Path, id: &str) -> io::Result<Option<Metadata>> {
+    match tokio::fs::read(path(data_dir, id)).await {
+        Ok(contents) => serde_json::from_slice(&contents)
+            .map(Some)
+            .map_err(io::Error::from),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+pub async fn store(data_dir: &Path, id: &str, metadata: &Metadata) -> io::Result<()> {
+    let contents = serde_json::to_vec(metadata)?;
+    crate::checksum::write_atomically(&path(data_dir, id), contents).await
+}
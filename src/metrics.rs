@@ -0,0 +1,274 @@
+use std::{
+    collections::{BTreeMap, VecDeque},
+    fmt::Write,
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, SystemTime},
+};
+
+use parking_lot::Mutex;
+
+use crate::service::Service;
+
+/// Upper bounds in seconds of the request latency histogram buckets.
+const LATENCY_BUCKETS: [f64; 10] = [0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 10.0];
+
+#[derive(Default)]
+struct Histogram {
+    /// Number of observations at most the corresponding bound.
+    buckets: [u64; LATENCY_BUCKETS.len()],
+    count: u64,
+    sum: f64,
+}
+
+impl Histogram {
+    fn observe(&mut self, value: f64) {
+        for (bucket, bound) in self.buckets.iter_mut().zip(LATENCY_BUCKETS) {
+            if value <= bound {
+                *bucket += 1;
+            }
+        }
+        self.count += 1;
+        self.sum += value;
+    }
+}
+
+/// Keyed by method and route, e.g. `("GET", "/paste/{id}")`.
+type RouteKey = (String, String);
+
+#[derive(Default)]
+struct Requests {
+    by_status: BTreeMap<(String, String, u16), u64>,
+    errors: BTreeMap<RouteKey, u64>,
+    latency: BTreeMap<RouteKey, Histogram>,
+}
+
+/// Hours kept in [`Metrics::created_recently`].
+const RECENT_HOURS: u64 = 24;
+
+/// Counters exposed in the Prometheus text format on `/metrics`.
+#[derive(Default)]
+pub struct Metrics {
+    requests: Mutex<Requests>,
+    pastes_created: AtomicU64,
+    pastes_deleted: AtomicU64,
+    /// Pastes created per hour since the UNIX epoch, for the last
+    /// [`RECENT_HOURS`] hours.
+    created_by_hour: Mutex<VecDeque<(u64, u64)>>,
+}
+
+/// Hours from the UNIX epoch until `time`.
+fn hour_of(time: SystemTime) -> u64 {
+    let since_epoch = time
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default();
+    since_epoch.as_secs() / 3600
+}
+
+impl Metrics {
+    /// Records a finished request. `route` should be the matched route
+    /// pattern rather than the actual path, to keep the number of series
+    /// bounded.
+    pub fn request(&self, method: &str, route: &str, status: u16, latency: Duration) {
+        let key = (method.to_owned(), route.to_owned());
+        let mut requests = self.requests.lock();
+        *requests
+            .by_status
+            .entry((key.0.clone(), key.1.clone(), status))
+            .or_default() += 1;
+        if status >= 500 {
+            *requests.errors.entry(key.clone()).or_default() += 1;
+        }
+        requests
+            .latency
+            .entry(key)
+            .or_default()
+            .observe(latency.as_secs_f64());
+    }
+
+    pub fn paste_created(&self, now: SystemTime) {
+        self.pastes_created.fetch_add(1, Ordering::Relaxed);
+        let hour = hour_of(now);
+        let mut by_hour = self.created_by_hour.lock();
+        match by_hour.back_mut() {
+            Some((last, count)) if *last == hour => *count += 1,
+            _ => by_hour.push_back((hour, 1)),
+        }
+        while by_hour
+            .front()
+            .is_some_and(|(oldest, _)| oldest + RECENT_HOURS <= hour)
+        {
+            by_hour.pop_front();
+        }
+    }
+
+    /// Pastes created in the hour of `now` and the previous 23.
+    pub fn created_recently(&self, now: SystemTime) -> u64 {
+        let hour = hour_of(now);
+        self.created_by_hour
+            .lock()
+            .iter()
+            .filter(|(created, _)| created + RECENT_HOURS > hour)
+            .map(|(_, count)| count)
+            .sum()
+    }
+
+    pub fn paste_deleted(&self) {
+        self.pastes_deleted.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn render_requests(&self, out: &mut String) {
+        let requests = self.requests.lock();
+        out.push_str("# HELP pastebin_http_requests_total HTTP requests handled.\n");
+        out.push_str("# TYPE pastebin_http_requests_total counter\n");
+        for ((method, route, status), count) in &requests.by_status {
+            writeln!(
+                out,
+                "pastebin_http_requests_total{{method=\"{method}\",route=\"{route}\",status=\"{status}\"}} {count}"
+            )
+            .unwrap();
+        }
+
+        out.push_str(
+            "# HELP pastebin_http_errors_total HTTP requests that failed with a server error.\n",
+        );
+        out.push_str("# TYPE pastebin_http_errors_total counter\n");
+        for ((method, route), count) in &requests.errors {
+            writeln!(
+                out,
+                "pastebin_http_errors_total{{method=\"{method}\",route=\"{route}\"}} {count}"
+            )
+            .unwrap();
+        }
+
+        out.push_str("# HELP pastebin_http_request_duration_seconds Time to produce a response.\n");
+        out.push_str("# TYPE pastebin_http_request_duration_seconds histogram\n");
+        for ((method, route), histogram) in &requests.latency {
+            let labels = format!("method=\"{method}\",route=\"{route}\"");
+            for (bound, count) in LATENCY_BUCKETS.iter().zip(histogram.buckets) {
+                writeln!(
+                    out,
+                    "pastebin_http_request_duration_seconds_bucket{{{labels},le=\"{bound}\"}} {count}"
+                )
+                .unwrap();
+            }
+            let (count, sum) = (histogram.count, histogram.sum);
+            writeln!(
+                out,
+                "pastebin_http_request_duration_seconds_bucket{{{labels},le=\"+Inf\"}} {count}"
+            )
+            .unwrap();
+            writeln!(
+                out,
+                "pastebin_http_request_duration_seconds_sum{{{labels}}} {sum}"
+            )
+            .unwrap();
+            writeln!(
+                out,
+                "pastebin_http_request_duration_seconds_count{{{labels}}} {count}"
+            )
+            .unwrap();
+        }
+    }
+}
+
+/// Renders all metrics of `service` in the Prometheus text format.
+pub fn render(service: &Service) -> String {
+    let metrics = service.metrics();
+    let (pastes, bytes) = service.usage_totals();
+    let mut out = String::new();
+    metrics.render_requests(&mut out);
+    for (name, kind, help, value) in [
+        (
+            "pastebin_pastes_created_total",
+            "counter",
+            "Pastes created since startup.",
+            metrics.pastes_created.load(Ordering::Relaxed),
+        ),
+        (
+            "pastebin_pastes_deleted_total",
+            "counter",
+            "Pastes deleted since startup, including evicted and expired ones.",
+            metrics.pastes_deleted.load(Ordering::Relaxed),
+        ),
+        (
+            "pastebin_events_dropped_total",
+            "counter",
+            "Events skipped by webhooks, hooks and other subscribers that fell behind.",
+            service.events().dropped(),
+        ),
+        (
+            "pastebin_pastes",
+            "gauge",
+            "Pastes currently stored.",
+            pastes as u64,
+        ),
+        (
+            "pastebin_stored_bytes",
+            "gauge",
+            "Total size of all pastes, before deduplication.",
+            bytes,
+        ),
+    ] {
+        writeln!(
+            out,
+            "# HELP {name} {help}\n# TYPE {name} {kind}\n{name} {value}"
+        )
+        .unwrap();
+    }
+    if let Some((hits, misses, entries, bytes)) = service.cache_stats() {
+        for (name, kind, help, value) in [
+            (
+                "pastebin_cache_hits_total",
+                "counter",
+                "Paste reads served from the cache.",
+                hits,
+            ),
+            (
+                "pastebin_cache_misses_total",
+                "counter",
+                "Paste reads that missed the cache.",
+                misses,
+            ),
+            (
+                "pastebin_cache_pastes",
+                "gauge",
+                "Pastes currently cached.",
+                entries as u64,
+            ),
+            (
+                "pastebin_cache_bytes",
+                "gauge",
+                "Total size of the cached pastes.",
+                bytes as u64,
+            ),
+        ] {
+            writeln!(
+                out,
+                "# HELP {name} {help}\n# TYPE {name} {kind}\n{name} {value}"
+            )
+            .unwrap();
+        }
+    }
+    out
+}
+
+#[test]
+fn test_latency_histogram_is_cumulative() {
+    let metrics = Metrics::default();
+    metrics.request("GET", "/paste/{id}", 200, Duration::from_millis(20));
+    metrics.request("GET", "/paste/{id}", 500, Duration::from_secs(20));
+
+    let mut out = String::new();
+    metrics.render_requests(&mut out);
+    let labels = "method=\"GET\",route=\"/paste/{id}\"";
+    for line in [
+        format!("pastebin_http_requests_total{{{labels},status=\"500\"}} 1"),
+        format!("pastebin_http_errors_total{{{labels}}} 1"),
+        format!("pastebin_http_request_duration_seconds_bucket{{{labels},le=\"0.01\"}} 0"),
+        format!("pastebin_http_request_duration_seconds_bucket{{{labels},le=\"0.025\"}} 1"),
+        format!("pastebin_http_request_duration_seconds_bucket{{{labels},le=\"10\"}} 1"),
+        format!("pastebin_http_request_duration_seconds_bucket{{{labels},le=\"+Inf\"}} 2"),
+    ] {
+        assert!(out.lines().any(|l| l == line), "missing {line:?} in\n{out}");
+    }
+}
@@ -0,0 +1,304 @@
+//! Chat notifications of new pastes. Every paste created that matches the
+//! --notify-filter is announced with its title, link and first lines to the
+//! configured Slack and Discord webhooks and Matrix rooms.
+
+use std::{sync::Arc, time::Duration};
+
+use serde_json::json;
+use tokio::io::AsyncReadExt;
+use uuid::Uuid;
+
+use crate::{
+    events::{Event, Subscription},
+    html::{encode_query, escape},
+    meta::Metadata,
+    service::Service,
+};
+
+const MAX_ATTEMPTS: u32 = 5;
+
+/// Bytes of a paste read for its snippet.
+const READ_LIMIT: u64 = 4096;
+const SNIPPET_LINES: usize = 8;
+const SNIPPET_CHARS: usize = 500;
+
+pub struct Config {
+    /// The --base-url, for the links.
+    pub base_url: String,
+    pub targets: Vec<Target>,
+    pub filter: Filter,
+}
+
+pub enum Target {
+    /// An incoming webhook URL.
+    Slack(String),
+    /// A webhook URL.
+    Discord(String),
+    /// A room posted to as the user of `token`.
+    Matrix {
+        homeserver: String,
+        token: String,
+        room: String,
+    },
+}
+
+impl Target {
+    fn name(&self) -> String {
+        match self {
+            Self::Slack(_) => "Slack".to_owned(),
+            Self::Discord(_) => "Discord".to_owned(),
+            Self::Matrix { room, .. } => format!("Matrix room {room}"),
+        }
+    }
+}
+
+/// Which pastes are announced: by default all public ones.
+pub struct Filter {
+    /// Whether the paste must be public, unlisted, or `None` for either.
+    public: Option<bool>,
+    /// Languages one of which the paste must be in, if any are given.
+    languages: Vec<String>,
+    /// Users one of whom the paste must be owned by, if any are given.
+    users: Vec<String>,
+    /// Text the title must contain, ignoring case.
+    title: Option<String>,
+}
+
+impl Default for Filter {
+    fn default() -> Self {
+        Self {
+            public: Some(true),
+            languages: Vec::new(),
+            users: Vec::new(),
+            title: None,
+        }
+    }
+}
+
+impl Filter {
+    /// Parses terms separated by spaces, all of which must hold: `public:`
+    /// `true`, `false` or `any`, `language:` and `user:` with alternatives
+    /// separated by commas, and `title:` with text to look for.
+    pub fn parse(filter: &str) -> anyhow::Result<Self> {
+        let mut parsed = Self::default();
+        for term in filter.split_whitespace() {
+            let Some((key, value)) = term.split_once(':') else {
+                anyhow::bail!("Invalid notification filter term {term:?}, expected key:value");
+            };
+            let list = || value.split(',').map(|v| v.to_lowercase()).collect();
+            match key {
+                "public" => {
+                    parsed.public = match value {
+                        "true" => Some(true),
+                        "false" => Some(false),
+                        "any" => None,
+                        _ => anyhow::bail!("Invalid {term:?}, expected true, false or any"),
+                    }
+                }
+                "language" => parsed.languages = list(),
+                "user" => parsed.users = list(),
+                "title" => parsed.title = Some(value.to_lowercase()),
+                _ => anyhow::bail!("Unknown notification filter key {key:?}"),
+            }
+        }
+        Ok(parsed)
+    }
+
+    fn matches(&self, metadata: &Metadata) -> bool {
+        let any_of = |allowed: &[String], value: Option<&str>| {
+            allowed.is_empty() || value.is_some_and(|value| allowed.contains(&value.to_lowercase()))
+        };
+        self.public.is_none_or(|public| metadata.public == public)
+            && any_of(&self.languages, metadata.language.as_deref())
+            && any_of(&self.users, metadata.owner.as_deref())
+            && self.title.as_ref().is_none_or(|text| {
+                metadata
+                    .title
+                    .as_ref()
+                    .is_some_and(|title| title.to_lowercase().contains(text))
+            })
+    }
+}
+
+/// A new paste as announced.
+struct Message {
+    title: String,
+    url: String,
+    language: Option<String>,
+    snippet: String,
+}
+
+/// Announces the pastes created among `events` that match the filter in the
+/// background, retrying failed deliveries a few times. Pastes created
+/// while it's too far behind, with a chat service down for a while, are
+/// never announced; `pastebin_events_dropped_total` counts them.
+pub fn spawn(
+    mut events: Subscription,
+    service: Arc<Service>,
+    config: Config,
+) -> tokio::task::JoinHandle<()> {
+    let client = reqwest::Client::new();
+    tokio::spawn(async move {
+        while let Some(emitted) = events.recv().await {
+            let Event::PasteCreated(id) = emitted.event else {
+                continue;
+            };
+            let message = match message(&service, &config, &id).await {
+                Ok(Some(message)) => message,
+                Ok(None) => continue,
+                Err(e) => {
+                    tracing::error!("Couldn't read paste {id} to announce it: {e}");
+                    continue;
+                }
+            };
+            for target in &config.targets {
+                // Matrix drops requests repeating a transaction, so that a
+                // retry of a delivery that went through isn't posted twice.
+                let transaction = Uuid::new_v4();
+                let mut attempt = 1;
+                loop {
+                    match deliver(&client, target, &message, transaction).await {
+                        Ok(()) => break,
+                        Err(e) if attempt < MAX_ATTEMPTS => {
+                            tracing::warn!("Notifying {} failed, retrying: {e}", target.name());
+                            tokio::time::sleep(Duration::from_secs(1 << attempt)).await;
+                            attempt += 1;
+                        }
+                        Err(e) => {
+                            tracing::error!(
+                                "Giving up on notifying {} of paste {id}: {e}",
+                                target.name()
+                            );
+                            break;
+                        }
+                    }
+                }
+            }
+        }
+    })
+}
+
+/// The announcement of paste `id`, if it matches the filter.
+async fn message(service: &Service, config: &Config, id: &Uuid) -> anyhow::Result<Option<Message>> {
+    let Some(metadata) = service.metadata(id).await? else {
+        return Ok(None);
+    };
+    if !config.filter.matches(&metadata) {
+        return Ok(None);
+    }
+    let mut contents = Vec::new();
+    service
+        .read(id)
+        .await?
+        .take(READ_LIMIT)
+        .read_to_end(&mut contents)
+        .await?;
+    Ok(Some(Message {
+        title: metadata.title.unwrap_or_else(|| format!("Paste {id}")),
+        url: format!("{}/paste/{id}/view", config.base_url),
+        language: metadata.language,
+        snippet: snippet(&String::from_utf8_lossy(&contents)),
+    }))
+}
+
+/// The first lines of `contents`, shortened to fit in a chat message.
+fn snippet(contents: &str) -> String {
+    let mut snippet: String = contents
+        .lines()
+        .take(SNIPPET_LINES)
+        .collect::<Vec<_>>()
+        .join("\n");
+    if snippet.chars().count() > SNIPPET_CHARS {
+        snippet = snippet.chars().take(SNIPPET_CHARS).collect();
+        snippet.push('…');
+    }
+    // Keeps the snippet from closing the code block it's shown in.
+    snippet.replace("```", "`\u{200b}``")
+}
+
+async fn deliver(
+    client: &reqwest::Client,
+    target: &Target,
+    message: &Message,
+    transaction: Uuid,
+) -> anyhow::Result<()> {
+    let Message {
+        title,
+        url,
+        language,
+        snippet,
+    } = message;
+    let request = match target {
+        Target::Slack(webhook) => {
+            // Slack's markup only needs these escaped.
+            let slack = |text: &str| {
+                text.replace('&', "&amp;")
+                    .replace('<', "&lt;")
+                    .replace('>', "&gt;")
+            };
+            let mut text = format!("New paste: <{url}|{}>", slack(title));
+            if let Some(language) = language {
+                text.push_str(&format!(" ({})", slack(language)));
+            }
+            if !snippet.is_empty() {
+                text.push_str(&format!("\n```{}```", slack(snippet)));
+            }
+            client.post(webhook).json(&json!({ "text": text }))
+        }
+        Target::Discord(webhook) => {
+            let language = language.as_deref().unwrap_or_default();
+            let description = format!("```{language}\n{snippet}\n```");
+            client.post(webhook).json(&json!({
+                "embeds": [{ "title": title, "url": url, "description": description }],
+                "allowed_mentions": { "parse": [] },
+            }))
+        }
+        Target::Matrix {
+            homeserver,
+            token,
+            room,
+        } => {
+            let mut body = format!("New paste: {title} {url}");
+            let mut html = format!(
+                "New paste: <a href=\"{}\">{}</a>",
+                escape(url),
+                escape(title)
+            );
+            if let Some(language) = language {
+                body.push_str(&format!(" ({language})"));
+                html.push_str(&format!(" ({})", escape(language)));
+            }
+            if !snippet.is_empty() {
+                body.push_str(&format!("\n{snippet}"));
+                html.push_str(&format!("<pre><code>{}</code></pre>", escape(snippet)));
+            }
+            let url = format!(
+                "{homeserver}/_matrix/client/v3/rooms/{}/send/m.room.message/{transaction}",
+                encode_query(room)
+            );
+            client.put(url).bearer_auth(token).json(&json!({
+                "msgtype": "m.notice",
+                "body": body,
+                "format": "org.matrix.custom.html",
+                "formatted_body": html,
+            }))
+        }
+    };
+    request.send().await?.error_for_status()?;
+    Ok(())
+}
+
+#[test]
+fn test_filter() {
+    let filter = Filter::parse("public:any language:rust,Go title:main").unwrap();
+    let metadata = Metadata {
+        language: Some("go".to_owned()),
+        title: Some("Main.go".to_owned()),
+        ..Metadata::default()
+    };
+    assert!(filter.matches(&metadata));
+    assert!(!Filter::default().matches(&metadata));
+    assert!(!Filter::parse("user:alice").unwrap().matches(&metadata));
+    assert!(Filter::parse("public:maybe").is_err());
+    assert!(Filter::parse("size:10").is_err());
+}
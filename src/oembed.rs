@@ -0,0 +1,111 @@
+//! `/oembed`, letting sites that support [oEmbed](https://oembed.com) show
+//! pastes inline by framing their embed.
+
+use std::sync::Arc;
+
+use axum::{
+    Json,
+    extract::{Query, Request, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{client, html::escape, service::Service};
+
+const DEFAULT_WIDTH: u32 = 600;
+const DEFAULT_HEIGHT: u32 = 400;
+
+#[derive(Deserialize)]
+pub struct OembedQuery {
+    /// URL of a paste or its HTML view.
+    url: String,
+    maxwidth: Option<u32>,
+    maxheight: Option<u32>,
+    format: Option<String>,
+}
+
+#[derive(Serialize)]
+struct Embed {
+    version: &'static str,
+    #[serde(rename = "type")]
+    kind: &'static str,
+    title: String,
+    provider_name: &'static str,
+    provider_url: String,
+    html: String,
+    width: u32,
+    height: u32,
+}
+
+/// The ID in a paste URL such as `https://example.com/paste/{id}/view`.
+fn paste_id(url: &str) -> Option<Uuid> {
+    let path = url.split(['?', '#']).next()?;
+    let (_, rest) = path.rsplit_once("/paste/")?;
+    let id = rest.strip_suffix("/view").unwrap_or(rest);
+    Uuid::parse_str(id).ok()
+}
+
+pub async fn get(
+    State(service): State<Arc<Service>>,
+    State(client): State<Arc<client::Config>>,
+    Query(query): Query<OembedQuery>,
+    request: Request,
+) -> Response {
+    if query
+        .format
+        .as_deref()
+        .is_some_and(|format| format != "json")
+    {
+        return (StatusCode::NOT_IMPLEMENTED, "Only JSON is supported").into_response();
+    }
+    let Some(id) = paste_id(&query.url).filter(|id| service.exists(id)) else {
+        return (StatusCode::NOT_FOUND, "No such paste").into_response();
+    };
+    let (parts, _) = request.into_parts();
+    let Some(base_url) = client::base_url(&parts, &client) else {
+        return (StatusCode::BAD_REQUEST, "Missing Host header").into_response();
+    };
+    let metadata = match service.metadata(&id).await {
+        Ok(metadata) => metadata.unwrap_or_default(),
+        Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    };
+    let width = query
+        .maxwidth
+        .map_or(DEFAULT_WIDTH, |max| max.min(DEFAULT_WIDTH));
+    let height = query
+        .maxheight
+        .map_or(DEFAULT_HEIGHT, |max| max.min(DEFAULT_HEIGHT));
+    let title = metadata.title.unwrap_or_else(|| format!("Paste {id}"));
+    let html = format!(
+        "<iframe src=\"{}/paste/{id}/embed\" width=\"{width}\" height=\"{height}\" \
+         title=\"{}\" sandbox frameborder=\"0\"></iframe>",
+        escape(&base_url),
+        escape(&title),
+    );
+    Json(Embed {
+        version: "1.0",
+        kind: "rich",
+        title,
+        provider_name: "Pastebin",
+        provider_url: format!("{base_url}/"),
+        html,
+        width,
+        height,
+    })
+    .into_response()
+}
+
+#[test]
+fn test_paste_id_from_url() {
+    let id = "0b6e1b5e-3c5a-4d6b-9f0e-2f8f4d1c7a11";
+    for url in [
+        format!("https://example.com/paste/{id}"),
+        format!("https://example.com/pb/paste/{id}/view"),
+        format!("http://localhost:3000/paste/{id}?raw#top"),
+    ] {
+        assert_eq!(paste_id(&url), Uuid::parse_str(id).ok(), "{url}");
+    }
+    assert_eq!(paste_id("https://example.com/pastes/export"), None);
+}
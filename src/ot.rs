@@ -0,0 +1,245 @@
+//! Operational transformation of plain text, as used by collaborative
+//! editing.
+//!
+//! An [`Operation`] walks over a whole document, retaining, inserting or
+//! deleting characters. It is written in JSON the way ot.js does, so that its
+//! clients can be used: a positive number retains that many characters, a
+//! negative one deletes them and a string is inserted. Lengths count Unicode
+//! scalar values, not bytes or UTF-16 code units.
+
+use anyhow::bail;
+use serde::{Deserialize, Deserializer, Serialize, Serializer, de::Error};
+
+#[derive(Clone, Debug, PartialEq)]
+enum Component {
+    Retain(usize),
+    Insert(String),
+    Delete(usize),
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Operation(Vec<Component>);
+
+impl Operation {
+    fn retain(&mut self, n: usize) {
+        if n == 0 {
+            return;
+        }
+        match self.0.last_mut() {
+            Some(Component::Retain(m)) => *m += n,
+            _ => self.0.push(Component::Retain(n)),
+        }
+    }
+
+    fn insert(&mut self, text: &str) {
+        if text.is_empty() {
+            return;
+        }
+        // Inserts go before deletes, so that equal operations compare equal.
+        let at = match self.0.last() {
+            Some(Component::Delete(_)) => self.0.len() - 1,
+            _ => self.0.len(),
+        };
+        match at.checked_sub(1).map(|i| &mut self.0[i]) {
+            Some(Component::Insert(s)) => s.push_str(text),
+            _ => self.0.insert(at, Component::Insert(text.to_owned())),
+        }
+    }
+
+    fn delete(&mut self, n: usize) {
+        if n == 0 {
+            return;
+        }
+        match self.0.last_mut() {
+            Some(Component::Delete(m)) => *m += n,
+            _ => self.0.push(Component::Delete(n)),
+        }
+    }
+
+    /// Length of the documents this applies to.
+    fn base_len(&self) -> usize {
+        self.0
+            .iter()
+            .map(|component| match component {
+                Component::Retain(n) | Component::Delete(n) => *n,
+                Component::Insert(_) => 0,
+            })
+            .sum()
+    }
+
+    pub fn apply(&self, text: &str) -> anyhow::Result<String> {
+        let len = text.chars().count();
+        if self.base_len() != len {
+            bail!(
+                "Operation applies to {} characters, not {len}",
+                self.base_len()
+            );
+        }
+        let mut chars = text.chars();
+        let mut result = String::with_capacity(text.len());
+        for component in &self.0 {
+            match component {
+                Component::Retain(n) => result.extend(chars.by_ref().take(*n)),
+                Component::Insert(s) => result.push_str(s),
+                Component::Delete(n) => chars.by_ref().take(*n).for_each(drop),
+            }
+        }
+        Ok(result)
+    }
+}
+
+/// Transforms `a` and `b`, which both apply to the same document, into `a'`
+/// and `b'` such that applying `a` then `b'` gives the same as applying `b`
+/// then `a'`. Where both insert at the same place, `a`'s text comes first.
+pub fn transform(a: &Operation, b: &Operation) -> anyhow::Result<(Operation, Operation)> {
+    use Component::*;
+
+    if a.base_len() != b.base_len() {
+        bail!("Operations apply to documents of different lengths");
+    }
+    // What's left of a component once `used` of its `len` were accounted for.
+    fn rest(
+        component: fn(usize) -> Component,
+        len: usize,
+        used: usize,
+        components: &mut impl Iterator<Item = Component>,
+    ) -> Option<Component> {
+        if len > used {
+            Some(component(len - used))
+        } else {
+            components.next()
+        }
+    }
+
+    let (mut a_prime, mut b_prime) = (Operation::default(), Operation::default());
+    let mut components_a = a.0.iter().cloned();
+    let mut components_b = b.0.iter().cloned();
+    let (mut next_a, mut next_b) = (components_a.next(), components_b.next());
+    loop {
+        (next_a, next_b) = match (next_a.take(), next_b.take()) {
+            (None, None) => break,
+            (Some(Insert(s)), next_b) => {
+                a_prime.insert(&s);
+                b_prime.retain(s.chars().count());
+                (components_a.next(), next_b)
+            }
+            (next_a, Some(Insert(s))) => {
+                a_prime.retain(s.chars().count());
+                b_prime.insert(&s);
+                (next_a, components_b.next())
+            }
+            (None, _) | (_, None) => bail!("Operations have different lengths"),
+            (Some(Retain(n)), Some(Retain(m))) => {
+                let min = n.min(m);
+                a_prime.retain(min);
+                b_prime.retain(min);
+                (
+                    rest(Retain, n, min, &mut components_a),
+                    rest(Retain, m, min, &mut components_b),
+                )
+            }
+            // Both deleted the same characters, so neither has to any more.
+            (Some(Delete(n)), Some(Delete(m))) => {
+                let min = n.min(m);
+                (
+                    rest(Delete, n, min, &mut components_a),
+                    rest(Delete, m, min, &mut components_b),
+                )
+            }
+            (Some(Delete(n)), Some(Retain(m))) => {
+                let min = n.min(m);
+                a_prime.delete(min);
+                (
+                    rest(Delete, n, min, &mut components_a),
+                    rest(Retain, m, min, &mut components_b),
+                )
+            }
+            (Some(Retain(n)), Some(Delete(m))) => {
+                let min = n.min(m);
+                b_prime.delete(min);
+                (
+                    rest(Retain, n, min, &mut components_a),
+                    rest(Delete, m, min, &mut components_b),
+                )
+            }
+        };
+    }
+    Ok((a_prime, b_prime))
+}
+
+impl Serialize for Operation {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let components: Vec<serde_json::Value> = self
+            .0
+            .iter()
+            .map(|component| match component {
+                Component::Retain(n) => (*n as i64).into(),
+                Component::Insert(s) => s.as_str().into(),
+                Component::Delete(n) => (-(*n as i64)).into(),
+            })
+            .collect();
+        components.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Operation {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let mut operation = Operation::default();
+        // Kept within a usize, as is any count merged from it or transformed.
+        let mut base_len = 0usize;
+        for value in Vec::<serde_json::Value>::deserialize(deserializer)? {
+            let n = match value {
+                serde_json::Value::String(s) => {
+                    operation.insert(&s);
+                    continue;
+                }
+                serde_json::Value::Number(n) => match n.as_i64() {
+                    Some(n) if n != 0 => n,
+                    _ => return Err(D::Error::custom(format!("Invalid component {n}"))),
+                },
+                value => return Err(D::Error::custom(format!("Invalid component {value}"))),
+            };
+            let len = usize::try_from(n.unsigned_abs())
+                .ok()
+                .and_then(|len| Some((len, base_len.checked_add(len)?)));
+            let Some((len, sum)) = len else {
+                return Err(D::Error::custom("Operation is too long"));
+            };
+            base_len = sum;
+            if n > 0 {
+                operation.retain(len);
+            } else {
+                operation.delete(len);
+            }
+        }
+        Ok(operation)
+    }
+}
+
+#[test]
+fn test_transformed_operations_converge() {
+    let parse = |json: &str| serde_json::from_str::<Operation>(json).unwrap();
+    let document = "Hello, world";
+    for (a, b) in [
+        (r#"[5, " there", 7]"#, r#"["Oh, ", 12]"#),
+        (r#"[5, -2, 5]"#, r#"[6, -6, "you"]"#),
+        (r#"[3, -6, 3]"#, r#"[5, "!", 7]"#),
+        (r#"[12, "!"]"#, r#"[12, "?"]"#),
+    ] {
+        let (a, b) = (parse(a), parse(b));
+        let (a_prime, b_prime) = transform(&a, &b).unwrap();
+        let ab = b_prime.apply(&a.apply(document).unwrap()).unwrap();
+        let ba = a_prime.apply(&b.apply(document).unwrap()).unwrap();
+        assert_eq!(ab, ba);
+    }
+    assert_eq!(parse(r#"["é", -1, 2]"#).apply("ab√").unwrap(), "éb√");
+}
+
+#[test]
+fn test_overlong_operations_are_rejected() {
+    let max = i64::MAX;
+    assert!(serde_json::from_str::<Operation>(&format!("[{max}, {max}, {max}]")).is_err());
+    assert!(serde_json::from_str::<Operation>(&format!("[-{max}, -{max}, -{max}]")).is_err());
+    let operation = serde_json::from_str::<Operation>(&format!("[{max}]")).unwrap();
+    assert!(operation.apply("text").is_err());
+}
@@ -0,0 +1,31 @@
+//! The list of public pastes shared by the sitemap and the feeds.
+
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use parking_lot::Mutex;
+
+use crate::service::{Recent, Service};
+
+/// How long the list of public pastes is reused before the data directory
+/// is scanned again.
+const CACHE_TTL: Duration = Duration::from_secs(60);
+
+/// The public pastes as of the last scan.
+#[derive(Default)]
+pub struct Cache(Mutex<Option<(Instant, Arc<Vec<Recent>>)>>);
+
+impl Cache {
+    pub async fn pastes(&self, service: &Service) -> anyhow::Result<Arc<Vec<Recent>>> {
+        if let Some((scanned, pastes)) = &*self.0.lock()
+            && scanned.elapsed() < CACHE_TTL
+        {
+            return Ok(pastes.clone());
+        }
+        let pastes = Arc::new(service.public_pastes().await?);
+        *self.0.lock() = Some((Instant::now(), pastes.clone()));
+        Ok(pastes)
+    }
+}
@@ -0,0 +1,392 @@
+//! QPACK (RFC 9204), the compression of HTTP/3 header fields, as far as
+//! the HTTP/3 listener needs it.
+//!
+//! Only the static table is used: the dynamic table keeps its default
+//! capacity of 0, so peers can't refer to entries of it. Fields are encoded
+//! as references to the static table where they're in it, and as literals
+//! without Huffman coding otherwise.
+
+use std::sync::LazyLock;
+
+use anyhow::{anyhow, bail};
+
+/// The static table, of RFC 9204 Appendix A.
+const STATIC: [(&str, &str); 99] = [
+    (":authority", ""),
+    (":path", "/"),
+    ("age", "0"),
+    ("content-disposition", ""),
+    ("content-length", "0"),
+    ("cookie", ""),
+    ("date", ""),
+    ("etag", ""),
+    ("if-modified-since", ""),
+    ("if-none-match", ""),
+    ("last-modified", ""),
+    ("link", ""),
+    ("location", ""),
+    ("referer", ""),
+    ("set-cookie", ""),
+    (":method", "CONNECT"),
+    (":method", "DELETE"),
+    (":method", "GET"),
+    (":method", "HEAD"),
+    (":method", "OPTIONS"),
+    (":method", "POST"),
+    (":method", "PUT"),
+    (":scheme", "http"),
+    (":scheme", "https"),
+    (":status", "103"),
+    (":status", "200"),
+    (":status", "304"),
+    (":status", "404"),
+    (":status", "503"),
+    ("accept", "*/*"),
+    ("accept", "application/dns-message"),
+    ("accept-encoding", "gzip, deflate, br"),
+    ("accept-ranges", "bytes"),
+    ("access-control-allow-headers", "cache-control"),
+    ("access-control-allow-headers", "content-type"),
+    ("access-control-allow-origin", "*"),
+    ("cache-control", "max-age=0"),
+    ("cache-control", "max-age=2592000"),
+    ("cache-control", "max-age=604800"),
+    ("cache-control", "no-cache"),
+    ("cache-control", "no-store"),
+    ("cache-control", "public, max-age=31536000"),
+    ("content-encoding", "br"),
+    ("content-encoding", "gzip"),
+    ("content-type", "application/dns-message"),
+    ("content-type", "application/javascript"),
+    ("content-type", "application/json"),
+    ("content-type", "application/x-www-form-urlencoded"),
+    ("content-type", "image/gif"),
+    ("content-type", "image/jpeg"),
+    ("content-type", "image/png"),
+    ("content-type", "text/css"),
+    ("content-type", "text/html; charset=utf-8"),
+    ("content-type", "text/plain"),
+    ("content-type", "text/plain;charset=utf-8"),
+    ("range", "bytes=0-"),
+    ("strict-transport-security", "max-age=31536000"),
+    (
+        "strict-transport-security",
+        "max-age=31536000; includesubdomains",
+    ),
+    (
+        "strict-transport-security",
+        "max-age=31536000; includesubdomains; preload",
+    ),
+    ("vary", "accept-encoding"),
+    ("vary", "origin"),
+    ("x-content-type-options", "nosniff"),
+    ("x-xss-protection", "1; mode=block"),
+    (":status", "100"),
+    (":status", "204"),
+    (":status", "206"),
+    (":status", "302"),
+    (":status", "400"),
+    (":status", "403"),
+    (":status", "421"),
+    (":status", "425"),
+    (":status", "500"),
+    ("accept-language", ""),
+    ("access-control-allow-credentials", "FALSE"),
+    ("access-control-allow-credentials", "TRUE"),
+    ("access-control-allow-headers", "*"),
+    ("access-control-allow-methods", "get"),
+    ("access-control-allow-methods", "get, post, options"),
+    ("access-control-allow-methods", "options"),
+    ("access-control-expose-headers", "content-length"),
+    ("access-control-request-headers", "content-type"),
+    ("access-control-request-method", "get"),
+    ("access-control-request-method", "post"),
+    ("alt-svc", "clear"),
+    ("authorization", ""),
+    (
+        "content-security-policy",
+        "script-src 'none'; object-src 'none'; base-uri 'none'",
+    ),
+    ("early-data", "1"),
+    ("expect-ct", ""),
+    ("forwarded", ""),
+    ("if-range", ""),
+    ("origin", ""),
+    ("purpose", "prefetch"),
+    ("server", ""),
+    ("timing-allow-origin", "*"),
+    ("upgrade-insecure-requests", "1"),
+    ("user-agent", ""),
+    ("x-forwarded-for", ""),
+    ("x-frame-options", "deny"),
+    ("x-frame-options", "sameorigin"),
+];
+
+/// Lengths of the Huffman codes of the bytes and end of string (256), of
+/// RFC 7541 Appendix B. The codes are canonical, so they follow from these.
+const CODE_LENGTHS: [u8; 257] = [
+    13, 23, 28, 28, 28, 28, 28, 28, 28, 24, 30, 28, 28, 30, 28, 28, 28, 28, 28, 28, 28, 28, 30, 28,
+    28, 28, 28, 28, 28, 28, 28, 28, 6, 10, 10, 12, 13, 6, 8, 11, 10, 10, 8, 11, 8, 6, 6, 6, 5, 5,
+    5, 6, 6, 6, 6, 6, 6, 6, 7, 8, 15, 6, 12, 10, 13, 6, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7,
+    7, 7, 7, 7, 7, 7, 7, 7, 8, 7, 8, 13, 19, 13, 14, 6, 15, 5, 6, 5, 6, 5, 6, 6, 6, 5, 7, 7, 6, 6,
+    6, 5, 6, 7, 6, 5, 5, 6, 7, 7, 7, 7, 7, 15, 11, 14, 13, 28, 20, 22, 20, 20, 22, 22, 22, 23, 22,
+    23, 23, 23, 23, 23, 24, 23, 24, 24, 22, 23, 24, 23, 23, 23, 23, 21, 22, 23, 22, 23, 23, 24, 22,
+    21, 20, 22, 22, 23, 23, 21, 23, 22, 22, 24, 21, 22, 23, 23, 21, 21, 22, 21, 23, 22, 23, 23, 20,
+    22, 22, 22, 23, 22, 22, 23, 26, 26, 20, 19, 22, 23, 22, 25, 26, 26, 26, 27, 27, 26, 24, 25, 19,
+    21, 26, 27, 27, 26, 27, 24, 21, 21, 26, 26, 28, 27, 27, 27, 20, 24, 20, 21, 22, 21, 21, 23, 22,
+    22, 25, 25, 24, 24, 26, 23, 26, 27, 26, 26, 27, 27, 27, 27, 27, 28, 27, 27, 27, 27, 27, 26, 30,
+];
+
+/// The Huffman code in a form quick to decode: the codes of each length are
+/// consecutive, starting at `first`.
+struct Huffman {
+    /// Symbols by code length, then code.
+    symbols: Vec<u16>,
+    first: [u32; 31],
+    count: [u32; 31],
+    /// Where in `symbols` the codes of each length start.
+    offset: [u32; 31],
+}
+
+static HUFFMAN: LazyLock<Huffman> = LazyLock::new(|| {
+    let mut symbols: Vec<u16> = (0..257).collect();
+    symbols.sort_by_key(|&symbol| CODE_LENGTHS[symbol as usize]);
+    let (mut first, mut count, mut offset) = ([0; 31], [0; 31], [0; 31]);
+    for &len in &CODE_LENGTHS {
+        count[len as usize] += 1;
+    }
+    let (mut code, mut index) = (0, 0);
+    for len in 1..31 {
+        first[len] = code;
+        offset[len] = index;
+        code = (code + count[len]) << 1;
+        index += count[len];
+    }
+    Huffman {
+        symbols,
+        first,
+        count,
+        offset,
+    }
+});
+
+fn huffman_decode(encoded: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let huffman = &*HUFFMAN;
+    let mut decoded = Vec::with_capacity(encoded.len() * 8 / 5);
+    let (mut code, mut len) = (0u32, 0);
+    for byte in encoded {
+        for bit in (0..8).rev() {
+            code = code << 1 | u32::from(byte >> bit & 1);
+            len += 1;
+            let index = code.wrapping_sub(huffman.first[len]);
+            if code >= huffman.first[len] && index < huffman.count[len] {
+                match huffman.symbols[(huffman.offset[len] + index) as usize] {
+                    256 => bail!("End of string in Huffman-coded string"),
+                    symbol => decoded.push(symbol as u8),
+                }
+                (code, len) = (0, 0);
+            } else if len == 30 {
+                bail!("Invalid Huffman code");
+            }
+        }
+    }
+    // Padded with the start of the end of string code, which is all ones.
+    if len > 7 || code != (1 << len) - 1 {
+        bail!("Invalid Huffman padding");
+    }
+    Ok(decoded)
+}
+
+/// Reads an integer whose first byte holds `prefix` bits of it.
+fn integer(input: &mut &[u8], prefix: u8) -> anyhow::Result<u64> {
+    let truncated = || anyhow!("Truncated field section");
+    let (&first, rest) = input.split_first().ok_or_else(truncated)?;
+    *input = rest;
+    let max = (1 << prefix) - 1;
+    let mut value = u64::from(first) & max;
+    if value < max {
+        return Ok(value);
+    }
+    for shift in (0..=56).step_by(7) {
+        let (&byte, rest) = input.split_first().ok_or_else(truncated)?;
+        *input = rest;
+        value = value
+            .checked_add(u64::from(byte & 0x7f) << shift)
+            .ok_or_else(|| anyhow!("Integer too large"))?;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    bail!("Integer too large")
+}
+
+/// Reads a string whose length has `prefix` bits in the first byte, which
+/// are preceded by whether it's Huffman-coded.
+fn string(input: &mut &[u8], prefix: u8) -> anyhow::Result<Vec<u8>> {
+    let huffman = input.first().is_some_and(|first| first >> prefix & 1 == 1);
+    let len = integer(input, prefix)?;
+    let Some((string, rest)) = usize::try_from(len)
+        .ok()
+        .and_then(|len| input.split_at_checked(len))
+    else {
+        bail!("Truncated field section");
+    };
+    *input = rest;
+    if huffman {
+        huffman_decode(string)
+    } else {
+        Ok(string.to_vec())
+    }
+}
+
+fn static_entry(index: u64) -> anyhow::Result<(&'static str, &'static str)> {
+    usize::try_from(index)
+        .ok()
+        .and_then(|index| STATIC.get(index))
+        .copied()
+        .ok_or_else(|| anyhow!("No static table entry {index}"))
+}
+
+/// The fields of an encoded field section, as names and values.
+pub fn decode(mut block: &[u8]) -> anyhow::Result<Vec<(Vec<u8>, Vec<u8>)>> {
+    let input = &mut block;
+    let required_insert_count = integer(input, 8)?;
+    // The base only matters for the dynamic table.
+    integer(input, 7)?;
+    if required_insert_count != 0 {
+        bail!("Field section refers to the dynamic table");
+    }
+    let mut fields = Vec::new();
+    while let Some(&first) = input.first() {
+        let field = match first {
+            // Indexed field line.
+            0x80.. => {
+                if first & 0x40 == 0 {
+                    bail!("Field section refers to the dynamic table");
+                }
+                let (name, value) = static_entry(integer(input, 6)?)?;
+                (name.as_bytes().to_vec(), value.as_bytes().to_vec())
+            }
+            // Literal field line with name reference.
+            0x40.. => {
+                if first & 0x10 == 0 {
+                    bail!("Field section refers to the dynamic table");
+                }
+                let (name, _) = static_entry(integer(input, 4)?)?;
+                (name.as_bytes().to_vec(), string(input, 7)?)
+            }
+            // Literal field line with literal name.
+            0x20.. => (string(input, 3)?, string(input, 7)?),
+            // Post-base references, which are to the dynamic table.
+            _ => bail!("Field section refers to the dynamic table"),
+        };
+        fields.push(field);
+    }
+    Ok(fields)
+}
+
+/// Writes `value` after `flags`, with `prefix` bits of it in the first byte.
+fn put_integer(block: &mut Vec<u8>, flags: u8, prefix: u8, value: usize) {
+    let max = (1 << prefix) - 1;
+    if value < max {
+        block.push(flags | value as u8);
+        return;
+    }
+    block.push(flags | max as u8);
+    let mut value = value - max;
+    while value >= 0x80 {
+        block.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    block.push(value as u8);
+}
+
+/// Encodes a field section of `fields`, given as names and values.
+pub fn encode<'a>(fields: impl IntoIterator<Item = (&'a [u8], &'a [u8])>) -> Vec<u8> {
+    // Neither the required insert count nor the base matter here.
+    let mut block = vec![0, 0];
+    for (name, value) in fields {
+        let mut name_index = None;
+        let mut exact = None;
+        for (index, (entry_name, entry_value)) in STATIC.iter().enumerate() {
+            if entry_name.as_bytes() == name {
+                name_index.get_or_insert(index);
+                if entry_value.as_bytes() == value {
+                    exact = Some(index);
+                    break;
+                }
+            }
+        }
+        match (exact, name_index) {
+            (Some(index), _) => put_integer(&mut block, 0xc0, 6, index),
+            (None, Some(index)) => {
+                put_integer(&mut block, 0x50, 4, index);
+                put_integer(&mut block, 0, 7, value.len());
+                block.extend_from_slice(value);
+            }
+            (None, None) => {
+                put_integer(&mut block, 0x20, 3, name.len());
+                block.extend_from_slice(name);
+                put_integer(&mut block, 0, 7, value.len());
+                block.extend_from_slice(value);
+            }
+        }
+    }
+    block
+}
+
+#[test]
+fn test_huffman_decoding_matches_rfc_7541() {
+    let hex = |hex: &str| {
+        (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap())
+            .collect::<Vec<_>>()
+    };
+    for (encoded, decoded) in [
+        ("f1e3c2e5f23a6ba0ab90f4ff", "www.example.com"),
+        ("a8eb10649cbf", "no-cache"),
+        ("25a849e95ba97d7f", "custom-key"),
+        ("25a849e95bb8e8b4bf", "custom-value"),
+        ("6402", "302"),
+        ("aec3771a4b", "private"),
+    ] {
+        assert_eq!(huffman_decode(&hex(encoded)).unwrap(), decoded.as_bytes());
+    }
+    // Padding longer than 7 bits, or not of ones.
+    assert!(huffman_decode(&hex("a8eb10649cbfff")).is_err());
+    assert!(huffman_decode(&hex("a8eb10649cbe")).is_err());
+}
+
+#[test]
+fn test_field_sections_round_trip() {
+    let fields: [(&[u8], &[u8]); 5] = [
+        (b":status", b"200"),
+        (b":status", b"418"),
+        (b"content-type", b"text/plain; charset=utf-8"),
+        (b"x-pastebin-paste", b"https://paste.example.com/paste/1"),
+        (b"x-long", &[b'a'; 300]),
+    ];
+    let block = encode(fields);
+    let decoded = decode(&block).unwrap();
+    let decoded: Vec<_> = decoded
+        .iter()
+        .map(|(name, value)| (name.as_slice(), value.as_slice()))
+        .collect();
+    assert_eq!(decoded, fields);
+    // An indexed :status 200 and an :authority with a Huffman-coded value,
+    // as in RFC 7541 C.4.1.
+    let block = [
+        0, 0, 0xd9, 0x50, 0x8c, 0xf1, 0xe3, 0xc2, 0xe5, 0xf2, 0x3a, 0x6b, 0xa0, 0xab, 0x90, 0xf4,
+        0xff,
+    ];
+    let decoded = decode(&block).unwrap();
+    assert_eq!(decoded[0], (b":status".to_vec(), b"200".to_vec()));
+    assert_eq!(
+        decoded[1],
+        (b":authority".to_vec(), b"www.example.com".to_vec())
+    );
+    // A reference into the dynamic table.
+    assert!(decode(&[1, 0, 0x80]).is_err());
+    assert!(decode(&[0, 0, 0xff, 0x80]).is_err());
+}
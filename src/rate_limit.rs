@@ -0,0 +1,380 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    hash::Hash,
+    net::{IpAddr, Ipv6Addr},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use axum::{
+    extract::{MatchedPath, Request, State},
+    http::{HeaderMap, HeaderValue, Method, StatusCode, header},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+
+use crate::{auth::BasicAuth, service::Service};
+
+/// Number of clients tracked at most. Beyond it, the one seen least recently
+/// is forgotten.
+const MAX_TRACKED: usize = 10_000;
+
+/// Allows `burst` requests at once, refilling at `per_minute`.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Rate {
+    pub per_minute: u32,
+    pub burst: u32,
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+    /// When the bucket was last used, counted in uses of the limiter.
+    used: u64,
+}
+
+/// What a [`Limiter`] tells clients apart by.
+pub trait Key: Hash + Eq + Clone {
+    /// The key whose bucket requests from `self` are taken from.
+    fn bucket(self) -> Self {
+        self
+    }
+}
+
+impl Key for String {}
+
+impl Key for &str {}
+
+/// IPv6 clients usually get a /64 to pick their addresses from, so that's
+/// what they're limited by.
+impl Key for IpAddr {
+    fn bucket(self) -> Self {
+        match self.to_canonical() {
+            IpAddr::V6(ip) => IpAddr::V6(Ipv6Addr::from_bits(ip.to_bits() & !(u64::MAX as u128))),
+            ip => ip,
+        }
+    }
+}
+
+struct Buckets<K> {
+    buckets: HashMap<K, Bucket>,
+    /// The keys of `buckets` by when they were last used.
+    by_use: BTreeMap<u64, K>,
+    uses: u64,
+}
+
+/// A token bucket per key.
+pub struct Limiter<K> {
+    rate: Mutex<Option<Rate>>,
+    buckets: Mutex<Buckets<K>>,
+}
+
+impl<K: Key> Limiter<K> {
+    pub fn new(rate: Option<Rate>) -> Self {
+        Self {
+            rate: Mutex::new(rate),
+            buckets: Mutex::new(Buckets {
+                buckets: HashMap::new(),
+                by_use: BTreeMap::new(),
+                uses: 0,
+            }),
+        }
+    }
+
+    /// Changes the rate, e.g. after the configuration was reloaded. Clients
+    /// keep the tokens they have left.
+    pub fn set_rate(&self, rate: Option<Rate>) {
+        *self.rate.lock() = rate;
+    }
+
+    /// Takes a token for `key`. Returns `None` without a configured rate.
+    pub fn check(&self, key: K) -> Result<Option<Quota>, Throttled> {
+        self.check_with(key, None)
+    }
+
+    /// Like [`Limiter::check`], but with a rate specific to `key` instead of
+    /// the configured one, if given.
+    pub fn check_with(&self, key: K, rate: Option<Rate>) -> Result<Option<Quota>, Throttled> {
+        let Some(rate) = rate.or(*self.rate.lock()) else {
+            return Ok(None);
+        };
+        let refill_per_sec = f64::from(rate.per_minute) / 60.0;
+        let burst = f64::from(rate.burst.max(1));
+        let now = Instant::now();
+
+        let key = key.bucket();
+        let mut buckets = self.buckets.lock();
+        let Buckets {
+            buckets,
+            by_use,
+            uses,
+        } = &mut *buckets;
+        match buckets.get(&key) {
+            Some(bucket) => {
+                by_use.remove(&bucket.used);
+            }
+            None if buckets.len() >= MAX_TRACKED => {
+                if let Some((_, oldest)) = by_use.pop_first() {
+                    buckets.remove(&oldest);
+                }
+            }
+            None => {}
+        }
+        *uses += 1;
+        by_use.insert(*uses, key.clone());
+        let bucket = buckets.entry(key).or_insert(Bucket {
+            tokens: burst,
+            updated: now,
+            used: 0,
+        });
+        bucket.used = *uses;
+        let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * refill_per_sec).min(burst);
+        bucket.updated = now;
+        let allowed = bucket.tokens >= 1.0;
+        if allowed {
+            bucket.tokens -= 1.0;
+        }
+        let until = |tokens: f64| {
+            Duration::try_from_secs_f64((tokens - bucket.tokens) / refill_per_sec)
+                .unwrap_or(Duration::MAX)
+        };
+        let quota = Quota {
+            limit: rate.burst.max(1),
+            remaining: bucket.tokens as u32,
+            reset: until(burst),
+        };
+        if allowed {
+            Ok(Some(quota))
+        } else {
+            Err(Throttled {
+                quota,
+                retry_after: until(1.0),
+            })
+        }
+    }
+}
+
+/// What is left of a client's allowance after a request.
+#[derive(Clone, Copy, Debug)]
+pub struct Quota {
+    /// Requests allowed at once.
+    pub limit: u32,
+    pub remaining: u32,
+    /// Time until the full limit is available again.
+    pub reset: Duration,
+}
+
+impl Quota {
+    /// Adds the `X-RateLimit-*` headers describing this quota. The reset
+    /// time is given in seconds from now.
+    fn add_headers(&self, headers: &mut HeaderMap) {
+        for (name, value) in [
+            ("x-ratelimit-limit", self.limit.into()),
+            ("x-ratelimit-remaining", self.remaining.into()),
+            ("x-ratelimit-reset", whole_secs(self.reset)),
+        ] {
+            headers.insert(name, HeaderValue::from(value));
+        }
+    }
+}
+
+/// A request rejected by a [`Limiter`].
+#[derive(Debug)]
+pub struct Throttled {
+    pub quota: Quota,
+    /// Time until the next request will be allowed.
+    pub retry_after: Duration,
+}
+
+impl IntoResponse for Throttled {
+    fn into_response(self) -> Response {
+        let mut response = (
+            StatusCode::TOO_MANY_REQUESTS,
+            [(header::RETRY_AFTER, whole_secs(self.retry_after))],
+            "Too many requests, slow down",
+        )
+            .into_response();
+        self.quota.add_headers(response.headers_mut());
+        response
+    }
+}
+
+/// Rounds up to whole seconds, as used by `Retry-After`.
+fn whole_secs(duration: Duration) -> u64 {
+    duration.as_secs() + u64::from(duration.subsec_nanos() > 0)
+}
+
+/// Per-client limits on creating and reading pastes, and on all requests
+/// of an authenticated user and the pastes they create.
+pub struct Limits {
+    pub create: Limiter<IpAddr>,
+    pub read: Limiter<IpAddr>,
+    pub user: Limiter<String>,
+    pub user_create: Limiter<String>,
+}
+
+enum Kind {
+    Create,
+    Read,
+}
+
+fn classify(method: &Method, route: &str) -> Option<Kind> {
+    match (method, route) {
+        // Joining an editing session counts as one replacement, and each
+        // part of a resumable upload as an upload of its own.
+        (&Method::POST, "/paste" | "/paste/{id}/fork" | "/pastes/import" | "/uploads")
+        | (&Method::PATCH, "/uploads/{id}")
+        | (&Method::PUT, "/paste/{id}")
+        | (&Method::GET, "/paste/{id}/collab")
+        | (&Method::PUT, "/dav/{name}")
+        | (&Method::POST, "/upload-sessions/{id}/finalize")
+        | (&Method::POST, "/pastebin.v1.Pastes/Create" | "/pastebin.v1.Pastes/Replace") => {
+            Some(Kind::Create)
+        }
+        (
+            &Method::GET,
+            "/paste/{id}"
+            | "/paste/{id}/view"
+            | "/paste/{id}/embed"
+            | "/paste/{id}/ws"
+            | "/paste/{id}/versions/{number}"
+            | "/pastes/archive"
+            | "/pastes/export"
+            | "/graphql"
+            | "/dav/{name}",
+        )
+        | (&Method::POST, "/pastebin.v1.Pastes/Read" | "/graphql") => Some(Kind::Read),
+        _ => None,
+    }
+}
+
+/// Rejects requests with `429 Too Many Requests` once their client or user
+/// has used up its allowance. Clients whose address is unknown aren't limited
+/// by address, and users only count once their credentials are verified.
+pub async fn middleware(
+    State(limits): State<Arc<Limits>>,
+    State(service): State<Arc<Service>>,
+    State(client): State<Arc<crate::client::Config>>,
+    route: Option<MatchedPath>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let kind = route.and_then(|route| {
+        let route = route.as_str().strip_prefix(&client.path_prefix)?;
+        classify(request.method(), route)
+    });
+    let (parts, body) = request.into_parts();
+
+    let auth = parts
+        .headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(BasicAuth::parse);
+    let mut quotas = Vec::new();
+    let mut by_user = false;
+    if let Some(auth) = auth
+        && let Some(rate) = service.user_rate_limit(&auth.username, &auth.password)
+    {
+        // Pastes of users count against --user-create-rate instead of the
+        // limit of their address, if it's set.
+        if matches!(kind, Some(Kind::Create)) {
+            match limits.user_create.check(auth.username.clone()) {
+                Ok(quota) => {
+                    by_user = quota.is_some();
+                    quotas.extend(quota);
+                }
+                Err(throttled) => return throttled.into_response(),
+            }
+        }
+        match limits.user.check_with(auth.username, rate) {
+            Ok(quota) => quotas.extend(quota),
+            Err(throttled) => return throttled.into_response(),
+        }
+    }
+
+    let client = crate::client::address(&parts, &client.proxies);
+    let limiter = match kind {
+        Some(Kind::Create) if by_user => None,
+        Some(Kind::Create) => Some(&limits.create),
+        Some(Kind::Read) => Some(&limits.read),
+        None => None,
+    };
+    if let (Some(limiter), Some(client)) = (limiter, client) {
+        match limiter.check(client) {
+            Ok(quota) => quotas.extend(quota),
+            Err(throttled) => return throttled.into_response(),
+        }
+    }
+
+    let mut response = next.run(Request::from_parts(parts, body)).await;
+    // Report whichever limit the client will run into first.
+    if let Some(quota) = quotas.iter().min_by_key(|quota| quota.remaining) {
+        quota.add_headers(response.headers_mut());
+    }
+    response
+}
+
+#[test]
+fn test_limiter_allows_burst_then_throttles() {
+    let limiter = Limiter::new(Some(Rate {
+        per_minute: 60,
+        burst: 2,
+    }));
+    assert!(limiter.check("a").is_ok());
+    assert!(limiter.check("a").is_ok());
+    let throttled = limiter.check("a").unwrap_err();
+    assert!(throttled.retry_after <= Duration::from_secs(1));
+    assert_eq!(throttled.quota.limit, 2);
+    assert_eq!(throttled.quota.remaining, 0);
+    assert!(throttled.quota.reset > Duration::from_secs(1));
+    assert!(limiter.check("b").is_ok());
+
+    let generous = Rate {
+        per_minute: 60,
+        burst: 5,
+    };
+    for _ in 0..5 {
+        assert!(limiter.check_with("c", Some(generous)).is_ok());
+    }
+    assert!(limiter.check_with("c", Some(generous)).is_err());
+
+    limiter.set_rate(None);
+    assert!(limiter.check("a").unwrap().is_none());
+}
+
+#[test]
+fn test_limiter_forgets_the_least_recent_clients() {
+    let limiter = Limiter::new(Some(Rate {
+        per_minute: 1,
+        burst: 1,
+    }));
+    assert!(limiter.check("first".to_owned()).is_ok());
+    assert!(limiter.check("second".to_owned()).is_ok());
+    for client in 0..MAX_TRACKED - 2 {
+        assert!(limiter.check(client.to_string()).is_ok());
+    }
+    // Throttled, and with that seen more recently than "second".
+    assert!(limiter.check("first".to_owned()).is_err());
+    assert!(limiter.check("third".to_owned()).is_ok());
+    assert_eq!(limiter.buckets.lock().buckets.len(), MAX_TRACKED);
+    assert!(limiter.check("first".to_owned()).is_err());
+    assert!(limiter.check("second".to_owned()).is_ok());
+}
+
+#[test]
+fn test_ipv6_clients_are_limited_by_prefix() {
+    let limiter = Limiter::new(Some(Rate {
+        per_minute: 1,
+        burst: 1,
+    }));
+    let ip = |ip: &str| ip.parse::<IpAddr>().unwrap();
+    assert!(limiter.check(ip("2001:db8:1:2::1")).is_ok());
+    assert!(limiter.check(ip("2001:db8:1:2:ffff::2")).is_err());
+    assert!(limiter.check(ip("2001:db8:1:3::1")).is_ok());
+    assert!(limiter.check(ip("192.0.2.1")).is_ok());
+    assert!(limiter.check(ip("::ffff:192.0.2.1")).is_err());
+    assert!(limiter.check(ip("192.0.2.2")).is_ok());
+}
@@ -0,0 +1,41 @@
+use std::sync::Arc;
+
+use tokio::signal::unix::{SignalKind, signal};
+
+use crate::{cli::Args, logging::FilterHandle, rate_limit::Limits, service::Service};
+
+/// Rereads the configuration on SIGHUP and applies the settings that can
+/// change without a restart: the log level, the storage budget, the upload
+/// size limits and the rate limits. Other changes take effect at the next
+/// restart.
+pub fn spawn(
+    service: Arc<Service>,
+    limits: Arc<Limits>,
+    log_filter: FilterHandle,
+) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut hangups = signal(SignalKind::hangup()).expect("Couldn't listen for SIGHUP");
+        while hangups.recv().await.is_some() {
+            match Args::reload().and_then(|args| apply(&service, &limits, &log_filter, &args)) {
+                Ok(()) => tracing::info!("Reloaded configuration"),
+                Err(e) => tracing::error!("Not reloading configuration: {e}"),
+            }
+        }
+    })
+}
+
+fn apply(
+    service: &Service,
+    limits: &Limits,
+    log_filter: &FilterHandle,
+    args: &Args,
+) -> anyhow::Result<()> {
+    log_filter.set(&args.log_level)?;
+    service.set_storage_budget(args.storage_budget);
+    service.set_size_limits(args.size_limits());
+    limits.create.set_rate(args.create_rate());
+    limits.read.set_rate(args.read_rate());
+    limits.user.set_rate(args.user_rate());
+    limits.user_create.set_rate(args.user_create_rate());
+    Ok(())
+}
@@ -0,0 +1,270 @@
+//! Reminders of expiring pastes: users who set an address with
+//! `PUT /account/email` are mailed through --smtp-relay a while before one
+//! of their pastes expires, with a link to `/paste/{id}/extend`, where they
+//! can push the expiry back.
+
+use std::{sync::Arc, time::Duration};
+
+use axum::{
+    Router,
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::{Html, IntoResponse, Response},
+    routing::{get, put},
+};
+use uuid::Uuid;
+
+use crate::{
+    auth::{self, BasicAuth},
+    email,
+    html::escape,
+    meta::Metadata,
+    service::Service,
+};
+
+/// Longest time between two checks for pastes about to expire.
+const CHECK_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+/// Seconds an extension adds unless told otherwise: a week.
+const DEFAULT_EXTENSION: u64 = 7 * 24 * 60 * 60;
+
+/// Longest address accepted, as SMTP allows.
+const MAX_ADDRESS: usize = 254;
+
+pub struct Config {
+    /// How long before a paste expires its owner is reminded.
+    pub before: Duration,
+    /// Mail server the reminders are sent through, as `host:port`.
+    pub relay: String,
+    /// Domain the reminders come from, also used to greet the relay.
+    pub domain: String,
+    /// The --base-url, for the links.
+    pub base_url: String,
+}
+
+pub fn spawn(service: Arc<Service>, config: Config) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        loop {
+            match remind(&service, &config).await {
+                Ok(0) => {}
+                Ok(reminded) => tracing::info!("Reminded the owners of {reminded} paste(s)"),
+                Err(e) => tracing::error!("Sending expiry reminders failed: {e}"),
+            }
+            tokio::time::sleep(CHECK_INTERVAL.min(config.before)).await;
+        }
+    })
+}
+
+/// Mails the owners of the pastes expiring within `config.before` that
+/// they weren't reminded of yet, returning how many were. A reminder that
+/// couldn't be sent is tried again with the next check.
+async fn remind(service: &Service, config: &Config) -> anyhow::Result<usize> {
+    let mut reminded = 0;
+    for (id, metadata) in service.expiring(config.before).await? {
+        let Some((username, to)) = service.owner_email(&id) else {
+            continue;
+        };
+        let message = message(service, config, &to, &id, &metadata);
+        if let Err(e) = email::send(&config.relay, &config.domain, &to, &message).await {
+            tracing::warn!("Reminding {username} of paste {id}: {e}");
+            continue;
+        }
+        service.mark_reminded(&id).await?;
+        reminded += 1;
+    }
+    Ok(reminded)
+}
+
+fn message(service: &Service, config: &Config, to: &str, id: &Uuid, metadata: &Metadata) -> String {
+    let expires_at = metadata
+        .expires_at
+        .and_then(|expires_at| chrono::DateTime::from_timestamp(expires_at as i64, 0))
+        .unwrap_or_default();
+    let now = chrono::DateTime::<chrono::Utc>::from(service.clock().now());
+    let paste = match &metadata.title {
+        Some(title) => title.replace(char::is_control, " "),
+        None => format!("Paste {id}"),
+    };
+    let base_url = &config.base_url;
+    format!(
+        "From: <pastebin@{domain}>\r\nTo: <{to}>\r\nSubject: Your paste is about to expire\r\n\
+         Date: {date}\r\nMessage-ID: <{message_id}@{domain}>\r\nAuto-Submitted: auto-generated\r\n\
+         MIME-Version: 1.0\r\nContent-Type: text/plain; charset=utf-8\r\n\
+         Content-Transfer-Encoding: 8bit\r\n\r\n\
+         {paste} will be deleted on {expires_at}:\r\n\r\n    {base_url}/paste/{id}\r\n\r\n\
+         To keep it longer, extend it at:\r\n\r\n    {base_url}/paste/{id}/extend\r\n",
+        domain = config.domain,
+        date = now.to_rfc2822(),
+        message_id = service.ids().generate(),
+        expires_at = expires_at.format("%Y-%m-%d %H:%M UTC"),
+    )
+}
+
+pub fn routes() -> Router<crate::AppState> {
+    Router::new()
+        .route("/account/email", put(set_email).delete(clear_email))
+        .route("/paste/{id}/extend", get(form).post(extend))
+}
+
+/// Sets the address reminders are sent to, given as the request body.
+async fn set_email(
+    State(service): State<Arc<Service>>,
+    auth: BasicAuth,
+    address: String,
+) -> Response {
+    let address = address.trim();
+    let valid = address.len() <= MAX_ADDRESS
+        && address.split_once('@').is_some_and(|(local, domain)| {
+            !local.is_empty() && !domain.is_empty() && !domain.contains('@')
+        })
+        && !address.contains(|c: char| c.is_whitespace() || c.is_control() || "<>".contains(c));
+    if !valid {
+        return (StatusCode::BAD_REQUEST, "Invalid email address").into_response();
+    }
+    match service.set_email(&auth.username, &auth.password, Some(address.to_owned())) {
+        Ok(()) => StatusCode::NO_CONTENT.into_response(),
+        Err(_) => auth::unauthorized(),
+    }
+}
+
+async fn clear_email(State(service): State<Arc<Service>>, auth: BasicAuth) -> Response {
+    match service.set_email(&auth.username, &auth.password, None) {
+        Ok(()) => StatusCode::NO_CONTENT.into_response(),
+        Err(_) => auth::unauthorized(),
+    }
+}
+
+/// The page the reminders link to, with a button per extension offered.
+async fn form(State(service): State<Arc<Service>>, Path(id): Path<Uuid>) -> Response {
+    let metadata = match service.metadata(&id).await {
+        Ok(Some(metadata)) if metadata.expires_at.is_some() => metadata,
+        Ok(_) => return (StatusCode::NOT_FOUND, "No expiring paste by that ID").into_response(),
+        Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    };
+    let title = escape(metadata.title.as_deref().unwrap_or("Untitled paste"));
+    let mut page = format!(
+        "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"><title>Extend {title}</title>\
+         <style>body{{font-family:sans-serif}}</style></head><body>\n\
+         <h1>Extend <a href=\"../{id}\">{title}</a></h1>\n<form method=\"post\">\n"
+    );
+    for (label, days) in [("a day", 1), ("a week", 7), ("a month", 30)] {
+        let seconds = days * 24 * 60 * 60;
+        page.push_str(&format!(
+            "<button formaction=\"extend?by={seconds}\">Keep it {label} longer</button>\n"
+        ));
+    }
+    page.push_str("</form>\n</body></html>\n");
+    Html(page).into_response()
+}
+
+#[derive(serde::Deserialize)]
+struct Extension {
+    /// Seconds to add.
+    by: Option<u64>,
+}
+
+/// Pushes the expiry of the owner's paste back, answering with the new one
+/// in seconds since the Unix epoch.
+async fn extend(
+    State(service): State<Arc<Service>>,
+    Path(id): Path<Uuid>,
+    auth: BasicAuth,
+    Query(extension): Query<Extension>,
+) -> Response {
+    let by = extension.by.unwrap_or(DEFAULT_EXTENSION);
+    if by == 0 {
+        return (StatusCode::BAD_REQUEST, "Can't extend by nothing").into_response();
+    }
+    if !service.authenticate(&auth.username, &auth.password) {
+        return auth::unauthorized();
+    }
+    match service
+        .extend(&id, by, &auth.username, &auth.password)
+        .await
+    {
+        Ok(expires_at) => format!("{expires_at}\n").into_response(),
+        Err(e) => (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    }
+}
+
+#[tokio::test]
+async fn test_owners_are_reminded_once_and_can_extend() {
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+
+    use crate::{service::Options, state::State, testing::TempDir};
+
+    // A relay accepting any message, passing on what it was sent.
+    let relay = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = relay.local_addr().unwrap().to_string();
+    let (sent, mut received) = tokio::sync::mpsc::unbounded_channel();
+    tokio::spawn(async move {
+        while let Ok((stream, _)) = relay.accept().await {
+            let (read, mut write) = stream.into_split();
+            let mut lines = BufReader::new(read).lines();
+            write.write_all(b"220 ready\r\n").await.unwrap();
+            let mut data = None::<String>;
+            while let Ok(Some(line)) = lines.next_line().await {
+                match &mut data {
+                    Some(message) if line == "." => {
+                        sent.send(std::mem::take(message)).unwrap();
+                        data = None;
+                        write.write_all(b"250 queued\r\n").await.unwrap();
+                    }
+                    Some(message) => message.push_str(&format!("{line}\n")),
+                    None if line == "DATA" => {
+                        data = Some(String::new());
+                        write.write_all(b"354 go on\r\n").await.unwrap();
+                    }
+                    None => write.write_all(b"250 ok\r\n").await.unwrap(),
+                }
+            }
+        }
+    });
+
+    let dir = TempDir::new().unwrap();
+    let service = Service::new(dir.path().to_owned(), State::default()).unwrap();
+    service.register_user("alice", "secret").unwrap();
+    let auth = || Some(("alice".to_owned(), "secret".to_owned()));
+    let options = |expires_in| Options {
+        expires_in: Some(expires_in),
+        ..Default::default()
+    };
+    let soon = service
+        .create_with_options(&b"soon"[..], auth(), options(60))
+        .await
+        .unwrap();
+    service
+        .create_with_options(&b"later"[..], auth(), options(3 * 60 * 60))
+        .await
+        .unwrap();
+    service
+        .create_with_options(&b"anonymous"[..], None, options(60))
+        .await
+        .unwrap();
+    let config = Config {
+        before: Duration::from_secs(60 * 60),
+        relay: address,
+        domain: "paste.example.com".to_owned(),
+        base_url: "https://paste.example.com".to_owned(),
+    };
+
+    // Without an address, there's nobody to remind.
+    assert_eq!(remind(&service, &config).await.unwrap(), 0);
+    service
+        .set_email("alice", "secret", Some("alice@example.com".to_owned()))
+        .unwrap();
+    assert_eq!(remind(&service, &config).await.unwrap(), 1);
+    let message = received.recv().await.unwrap();
+    assert!(message.contains("To: <alice@example.com>"));
+    assert!(message.contains(&format!("https://paste.example.com/paste/{soon}/extend")));
+    assert_eq!(remind(&service, &config).await.unwrap(), 0);
+
+    let id = Uuid::parse_str(&soon).unwrap();
+    assert!(service.extend(&id, 60, "bob", "secret").await.is_err());
+    let expires_at = service.extend(&id, 24 * 60 * 60, "alice", "secret").await;
+    let metadata = service.metadata(&id).await.unwrap().unwrap();
+    assert_eq!(metadata.expires_at, Some(expires_at.unwrap()));
+    assert!(!metadata.reminded);
+    // Now it isn't due until the day after.
+    assert_eq!(remind(&service, &config).await.unwrap(), 0);
+}
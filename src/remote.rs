@@ -0,0 +1,286 @@
+//! The `client` subcommands, which use another instance over its HTTP API
+//! so that the same binary serves pastes and uploads them.
+
+use std::{fmt::Write as _, path::Path};
+
+use clap::ValueEnum;
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+use tokio::io::AsyncWriteExt;
+use uuid::Uuid;
+
+use crate::{cli::ClientCommand, html};
+
+/// How `client list` prints the pastes.
+#[derive(Clone, Copy, Debug, Default, ValueEnum)]
+pub enum Format {
+    /// Aligned columns, for people
+    #[default]
+    Table,
+    /// A JSON array of the pastes, for scripts
+    Json,
+}
+
+/// An instance at `url`, used as the user of `auth` if given.
+pub struct Remote {
+    url: String,
+    auth: Option<(String, String)>,
+    client: reqwest::Client,
+}
+
+impl Remote {
+    pub fn new(url: &str, auth: Option<(String, String)>) -> Self {
+        Self {
+            url: url.trim_end_matches('/').to_owned(),
+            auth,
+            client: reqwest::Client::new(),
+        }
+    }
+
+    pub fn request(&self, method: reqwest::Method, path: &str) -> reqwest::RequestBuilder {
+        let request = self.client.request(method, format!("{}{path}", self.url));
+        match &self.auth {
+            Some((username, password)) => request.basic_auth(username, Some(password)),
+            None => request,
+        }
+    }
+
+    pub fn is_authenticated(&self) -> bool {
+        self.auth.is_some()
+    }
+}
+
+/// Fails on error responses with the message the instance gave.
+pub async fn check(response: reqwest::Response) -> anyhow::Result<reqwest::Response> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+    let message = response.text().await.unwrap_or_default();
+    anyhow::bail!("{status}: {}", message.trim())
+}
+
+pub async fn run(remote: Remote, command: &ClientCommand) -> anyhow::Result<()> {
+    match command {
+        ClientCommand::Post {
+            file,
+            title,
+            language,
+            public,
+            noindex,
+            anonymous,
+        } => {
+            let title = title.clone().or_else(|| {
+                let name = file.as_deref()?.file_name()?;
+                Some(name.to_string_lossy().into_owned())
+            });
+            let mut query = Vec::new();
+            if let Some(title) = &title {
+                query.push(format!("title={}", html::encode_query(title)));
+            }
+            if let Some(language) = language {
+                query.push(format!("language={}", html::encode_query(language)));
+            }
+            if *public {
+                query.push("public=true".to_owned());
+            }
+            if *noindex {
+                query.push("noindex=true".to_owned());
+            }
+            let remote = if *anonymous {
+                Remote {
+                    auth: None,
+                    ..remote
+                }
+            } else {
+                remote
+            };
+            let url = post(&remote, file.as_deref(), &query.join("&")).await?;
+            println!("{url}");
+            Ok(())
+        }
+        ClientCommand::Get { paste } => get(&remote, paste_id(paste)?).await,
+        ClientCommand::List { format } => {
+            let pastes = list(&remote).await?;
+            match format {
+                Format::Table => print!("{}", table(&pastes)),
+                Format::Json => println!("{}", serde_json::to_string_pretty(&pastes)?),
+            }
+            Ok(())
+        }
+        ClientCommand::Delete { paste } => {
+            let id = paste_id(paste)?;
+            let response = remote
+                .request(reqwest::Method::DELETE, &format!("/paste/{id}"))
+                .send()
+                .await?;
+            check(response).await?;
+            Ok(())
+        }
+    }
+}
+
+/// The ID of `paste`, given as one or as a URL of the paste.
+pub fn paste_id(paste: &str) -> anyhow::Result<Uuid> {
+    paste
+        .split(['/', '?', '#'])
+        .rev()
+        .find_map(|segment| Uuid::parse_str(segment).ok())
+        .ok_or_else(|| anyhow::anyhow!("{paste:?} isn't a paste ID or URL"))
+}
+
+/// Uploads `file`, or stdin without one, and returns the paste's URL.
+async fn post(remote: &Remote, file: Option<&Path>, query: &str) -> anyhow::Result<String> {
+    let body = match file {
+        Some(file) => {
+            let file = tokio::fs::File::open(file)
+                .await
+                .map_err(|e| anyhow::anyhow!("Couldn't open {}: {e}", file.display()))?;
+            reqwest::Body::wrap_stream(tokio_util::io::ReaderStream::new(file))
+        }
+        None => reqwest::Body::wrap_stream(tokio_util::io::ReaderStream::new(tokio::io::stdin())),
+    };
+    let path = match query {
+        "" => "/paste".to_owned(),
+        query => format!("/paste?{query}"),
+    };
+    let response = remote
+        .request(reqwest::Method::POST, &path)
+        .body(body)
+        .send()
+        .await?;
+    let url = check(response).await?.text().await?;
+    Ok(url.trim().to_owned())
+}
+
+/// Writes the contents of paste `id` to stdout as they arrive.
+async fn get(remote: &Remote, id: Uuid) -> anyhow::Result<()> {
+    let response = remote
+        .request(reqwest::Method::GET, &format!("/paste/{id}"))
+        .send()
+        .await?;
+    let mut body = check(response).await?.bytes_stream();
+    let mut stdout = tokio::io::stdout();
+    while let Some(chunk) = body.next().await {
+        stdout.write_all(&chunk?).await?;
+    }
+    stdout.flush().await?;
+    Ok(())
+}
+
+/// A paste as the GraphQL API lists it.
+#[derive(Deserialize, Serialize)]
+struct Paste {
+    id: String,
+    title: Option<String>,
+    language: Option<String>,
+    size: u64,
+    written: String,
+    public: bool,
+}
+
+const LIST_QUERY: &str = "query($page: Int) {
+  me { pastes(page: $page) { id title language size written public } }
+}";
+
+/// All of the user's pastes, newest first, fetched a page at a time.
+async fn list(remote: &Remote) -> anyhow::Result<Vec<Paste>> {
+    #[derive(Deserialize)]
+    struct Response {
+        data: Option<Data>,
+        #[serde(default)]
+        errors: Vec<Error>,
+    }
+    #[derive(Deserialize)]
+    struct Data {
+        me: Option<Me>,
+    }
+    #[derive(Deserialize)]
+    struct Me {
+        pastes: Vec<Paste>,
+    }
+    #[derive(Deserialize)]
+    struct Error {
+        message: String,
+    }
+
+    if remote.auth.is_none() {
+        anyhow::bail!("--username and --password are required");
+    }
+    let mut pastes = Vec::new();
+    for page in 1.. {
+        let body = serde_json::json!({"query": LIST_QUERY, "variables": {"page": page}});
+        let response = remote
+            .request(reqwest::Method::POST, "/graphql")
+            .json(&body)
+            .send()
+            .await?;
+        let response: Response = check(response).await?.json().await?;
+        if let Some(error) = response.errors.first() {
+            anyhow::bail!("{}", error.message);
+        }
+        let Some(me) = response.data.and_then(|data| data.me) else {
+            anyhow::bail!("Not authorized");
+        };
+        if me.pastes.is_empty() {
+            break;
+        }
+        pastes.extend(me.pastes);
+    }
+    Ok(pastes)
+}
+
+fn table(pastes: &[Paste]) -> String {
+    let rows: Vec<[String; 6]> = pastes
+        .iter()
+        .map(|paste| {
+            [
+                paste.id.clone(),
+                paste.written.clone(),
+                paste.size.to_string(),
+                if paste.public { "public" } else { "unlisted" }.to_owned(),
+                paste.language.clone().unwrap_or_default(),
+                // A line break would split the row.
+                paste
+                    .title
+                    .as_deref()
+                    .unwrap_or_default()
+                    .replace(char::is_control, " "),
+            ]
+        })
+        .collect();
+    let header = ["ID", "WRITTEN", "SIZE", "VISIBILITY", "LANGUAGE", "TITLE"].map(str::to_owned);
+    let mut widths = [0; 6];
+    for row in std::iter::once(&header).chain(&rows) {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+    let mut table = String::new();
+    for row in std::iter::once(&header).chain(&rows) {
+        let mut line = String::new();
+        for (i, (cell, width)) in row.iter().zip(widths).enumerate() {
+            if i == 2 {
+                write!(line, "{cell:>width$}  ").unwrap();
+            } else {
+                write!(line, "{cell:<width$}  ").unwrap();
+            }
+        }
+        table.push_str(line.trim_end());
+        table.push('\n');
+    }
+    table
+}
+
+#[test]
+fn test_paste_id_from_url() {
+    let id = "0b6c2a44-94b1-4a59-a18d-b08b47f7e3b6";
+    for paste in [
+        id.to_owned(),
+        format!("https://example.com/paste/{id}"),
+        format!("https://example.com/paste/{id}/view?theme=dark"),
+    ] {
+        assert_eq!(paste_id(&paste).unwrap().to_string(), id);
+    }
+    assert!(paste_id("https://example.com/paste/").is_err());
+}
@@ -0,0 +1,151 @@
+use std::{path::PathBuf, time::Duration};
+
+use tokio::sync::mpsc;
+use uuid::Uuid;
+
+use crate::{checksum, meta};
+
+const MAX_ATTEMPTS: u32 = 5;
+
+/// Where pastes are mirrored to.
+#[derive(Clone, Debug)]
+pub enum Target {
+    /// Another data directory, e.g. on a different disk.
+    Dir(PathBuf),
+    /// Another instance started with the same `--replication-token`.
+    Remote { url: String, token: String },
+}
+
+impl Target {
+    pub fn parse(target: &str, token: Option<String>) -> anyhow::Result<Self> {
+        if target.starts_with("http://") || target.starts_with("https://") {
+            let token = token.ok_or(anyhow::anyhow!(
+                "Replicating to an instance requires --replication-token"
+            ))?;
+            Ok(Self::Remote {
+                url: target.trim_end_matches('/').to_owned(),
+                token,
+            })
+        } else {
+            Ok(Self::Dir(target.into()))
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum Event {
+    Write(Uuid),
+    Delete(Uuid),
+}
+
+/// Mirrors paste writes and deletes to a [`Target`] in the background.
+///
+/// Events are applied one at a time in the order they were sent, so the
+/// target converges on the primary's contents even when it lags behind.
+pub struct Replicator {
+    sender: mpsc::UnboundedSender<Event>,
+}
+
+impl Replicator {
+    pub fn spawn(data_dir: PathBuf, target: Target) -> anyhow::Result<Self> {
+        if let Target::Dir(dir) = &target {
+            std::fs::create_dir_all(dir)?;
+        }
+        let (sender, mut receiver) = mpsc::unbounded_channel();
+        let client = reqwest::Client::new();
+        tokio::spawn(async move {
+            while let Some(event) = receiver.recv().await {
+                let mut attempt = 1;
+                loop {
+                    match apply(&client, &data_dir, &target, event).await {
+                        Ok(()) => break,
+                        Err(e) if attempt < MAX_ATTEMPTS => {
+                            tracing::warn!("Replicating {event:?} failed, retrying: {e}");
+                            tokio::time::sleep(Duration::from_secs(1 << attempt)).await;
+                            attempt += 1;
+                        }
+                        Err(e) => {
+                            tracing::error!("Giving up replicating {event:?}: {e}");
+                            break;
+                        }
+                    }
+                }
+            }
+        });
+        Ok(Self { sender })
+    }
+
+    pub fn send(&self, event: Event) {
+        // The worker only stops when the sender is dropped.
+        self.sender.send(event).ok();
+    }
+}
+
+async fn apply(
+    client: &reqwest::Client,
+    data_dir: &std::path::Path,
+    target: &Target,
+    event: Event,
+) -> anyhow::Result<()> {
+    match (target, event) {
+        (Target::Dir(dir), Event::Write(id)) => {
+            let id = id.to_string();
+            for (from, to) in [
+                (data_dir.join(&id), dir.join(&id)),
+                (
+                    checksum::sidecar_path(data_dir, &id),
+                    checksum::sidecar_path(dir, &id),
+                ),
+                (meta::path(data_dir, &id), meta::path(dir, &id)),
+            ] {
+                let mut tmp_name = to.file_name().unwrap().to_owned();
+                tmp_name.push("~");
+                let tmp = to.with_file_name(tmp_name);
+                match tokio::fs::copy(&from, &tmp).await {
+                    Ok(_) => tokio::fs::rename(&tmp, &to).await?,
+                    // Deleted before we got to it; a Delete event follows.
+                    Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                    Err(e) => return Err(e.into()),
+                }
+            }
+        }
+        (Target::Dir(dir), Event::Delete(id)) => {
+            let id = id.to_string();
+            for path in [
+                dir.join(&id),
+                checksum::sidecar_path(dir, &id),
+                meta::path(dir, &id),
+            ] {
+                match tokio::fs::remove_file(path).await {
+                    Ok(()) => {}
+                    Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                    Err(e) => return Err(e.into()),
+                }
+            }
+        }
+        (Target::Remote { url, token }, Event::Write(id)) => {
+            let file = match tokio::fs::File::open(data_dir.join(id.to_string())).await {
+                Ok(file) => file,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+                Err(e) => return Err(e.into()),
+            };
+            let body = reqwest::Body::wrap_stream(tokio_util::io::ReaderStream::new(file));
+            client
+                .put(format!("{url}/replication/paste/{id}"))
+                .bearer_auth(token)
+                .body(body)
+                .send()
+                .await?
+                .error_for_status()?;
+        }
+        (Target::Remote { url, token }, Event::Delete(id)) => {
+            client
+                .delete(format!("{url}/replication/paste/{id}"))
+                .bearer_auth(token)
+                .send()
+                .await?
+                .error_for_status()?;
+        }
+    }
+    Ok(())
+}
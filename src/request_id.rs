@@ -0,0 +1,99 @@
+use std::sync::Arc;
+
+use axum::{
+    body::Body,
+    extract::Request,
+    http::{HeaderName, HeaderValue},
+    middleware::Next,
+    response::Response,
+};
+
+pub const HEADER: HeaderName = HeaderName::from_static("x-request-id");
+
+/// Longest client-supplied request ID that is passed through unchanged.
+const MAX_LEN: usize = 128;
+
+/// Largest server error body that gets the request ID appended.
+const MAX_ERROR_BODY: usize = 64 * 1024;
+
+/// Identifies a request in logs and in the response, so a failure reported
+/// by a user can be found in the logs.
+#[derive(Clone, Debug)]
+pub struct RequestId(pub Arc<str>);
+
+impl RequestId {
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+/// Takes the request ID from an `X-Request-Id` header set by the client or
+/// a proxy, or generates one, and returns it in the same header. Server
+/// error messages also mention it.
+pub async fn middleware(mut request: Request, next: Next) -> Response {
+    let id = request
+        .headers()
+        .get(&HEADER)
+        .and_then(|value| value.to_str().ok())
+        .filter(|id| !id.is_empty() && id.len() <= MAX_LEN)
+        .map(str::to_owned)
+        .unwrap_or_else(|| uuid::Uuid::new_v4().to_string());
+    let header = HeaderValue::from_str(&id).expect("Request IDs are visible ASCII");
+    request.headers_mut().insert(HEADER, header.clone());
+    let id = RequestId(id.into());
+    request.extensions_mut().insert(id.clone());
+
+    let mut response = next.run(request).await;
+    response.headers_mut().insert(HEADER, header);
+    if !response.status().is_server_error() {
+        return response;
+    }
+    let (parts, body) = response.into_parts();
+    let body = match axum::body::to_bytes(body, MAX_ERROR_BODY).await {
+        Ok(message) if message.is_empty() => format!("Request ID: {}", id.as_str()),
+        Ok(message) => format!(
+            "{} (request ID: {})",
+            String::from_utf8_lossy(&message),
+            id.as_str()
+        ),
+        Err(e) => format!("{e} (request ID: {})", id.as_str()),
+    };
+    let mut response = Response::from_parts(parts, Body::from(body));
+    response
+        .headers_mut()
+        .remove(axum::http::header::CONTENT_LENGTH);
+    response
+}
+
+#[tokio::test]
+async fn test_request_ids_are_passed_through_or_generated() {
+    use axum::{Router, http::StatusCode, middleware::from_fn, routing::get};
+    use tower::ServiceExt;
+
+    let app = Router::new()
+        .route("/ok", get(|| async { "ok" }))
+        .route(
+            "/fail",
+            get(|| async { (StatusCode::INTERNAL_SERVER_ERROR, "Disk full") }),
+        )
+        .layer(from_fn(middleware));
+    let request = |path, id: Option<&str>| {
+        let mut request = Request::get(path);
+        if let Some(id) = id {
+            request = request.header(&HEADER, id);
+        }
+        app.clone().oneshot(request.body(Body::empty()).unwrap())
+    };
+
+    let response = request("/ok", Some("abc-123")).await.unwrap();
+    assert_eq!(response.headers()[&HEADER], "abc-123");
+    for id in [None, Some(""), Some(&*"x".repeat(MAX_LEN + 1))] {
+        let response = request("/ok", id).await.unwrap();
+        let generated = response.headers()[&HEADER].to_str().unwrap();
+        assert!(uuid::Uuid::parse_str(generated).is_ok());
+    }
+
+    let response = request("/fail", Some("abc-123")).await.unwrap();
+    let body = axum::body::to_bytes(response.into_body(), MAX_ERROR_BODY).await;
+    assert_eq!(body.unwrap(), "Disk full (request ID: abc-123)");
+}
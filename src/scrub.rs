@@ -0,0 +1,61 @@
+use std::{sync::Arc, time::Duration};
+
+use crate::{checksum::Integrity, service::Service};
+
+/// Pause between verifying two pastes, so a scrub never saturates the disk.
+const PAUSE: Duration = Duration::from_millis(10);
+
+pub struct Schedule {
+    pub interval: Duration,
+    pub quarantine: bool,
+}
+
+/// Periodically re-verifies the checksum of every paste, reporting (and with
+/// `schedule.quarantine`, moving aside) those whose contents have changed.
+pub fn spawn(service: Arc<Service>, schedule: Schedule) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(schedule.interval).await;
+            match scrub(&service, schedule.quarantine).await {
+                Ok(0) => {}
+                Ok(corrupted) => tracing::warn!("Scrub found {corrupted} corrupted paste(s)"),
+                Err(e) => tracing::error!("Scrub failed: {e}"),
+            }
+        }
+    })
+}
+
+/// Verifies every paste once and returns the number of corrupted ones.
+pub async fn scrub(service: &Arc<Service>, quarantine: bool) -> anyhow::Result<usize> {
+    let ids = {
+        let service = service.clone();
+        tokio::task::spawn_blocking(move || service.paste_ids_on_disk()).await??
+    };
+
+    let mut corrupted = 0;
+    for id in ids {
+        let data_dir = service.data_dir().to_owned();
+        let integrity = tokio::task::spawn_blocking(move || {
+            crate::checksum::verify_blocking(&data_dir, &id.to_string())
+        })
+        .await?;
+        match integrity {
+            Ok(Integrity::Mismatch) => {
+                corrupted += 1;
+                tracing::warn!("Paste {id} doesn't match its checksum");
+                if quarantine {
+                    match service.quarantine(&id).await {
+                        Ok(()) => tracing::warn!("Moved paste {id} to quarantine"),
+                        Err(e) => tracing::error!("Couldn't quarantine paste {id}: {e}"),
+                    }
+                }
+            }
+            Ok(Integrity::Valid | Integrity::Unknown) => {}
+            // Deleted since it was listed.
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => tracing::error!("Couldn't verify paste {id}: {e}"),
+        }
+        tokio::time::sleep(PAUSE).await;
+    }
+    Ok(corrupted)
+}
@@ -1,14 +1,80 @@
-use std::path::{Path, PathBuf};
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+};
 
 use anyhow::anyhow;
 use parking_lot::Mutex;
-use tokio::io::AsyncRead;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 
-use crate::state::State;
+use crate::{
+    blobs,
+    buffers::Pool,
+    cache::{self, Cache},
+    checksum::{self, ChecksumReader},
+    clock::{Clock, SystemClock},
+    durability::{self, Syncer},
+    events::{Bus, Event},
+    ids::{IdGenerator, RandomIds},
+    index::{self, Index},
+    meta::{self, Metadata},
+    metrics::Metrics,
+    rate_limit::Rate,
+    replication::{self, Replicator},
+    state::{State, Users},
+    usage::Usage,
+    versions::{self, Version},
+};
+
+/// How many locks [`Service::snapshot`] and writers share between them,
+/// each covering the pastes whose IDs leave that remainder.
+const WRITE_STRIPES: u128 = 64;
+
+/// A paste listed by [`Service::recent_pastes`] or
+/// [`Service::public_pastes`].
+#[derive(Clone)]
+pub struct Recent {
+    pub id: uuid::Uuid,
+    pub size: u64,
+    pub written: std::time::SystemTime,
+    pub metadata: Metadata,
+}
+
+impl Recent {
+    fn new(id: uuid::Uuid, entry: index::Entry) -> Self {
+        Self {
+            id,
+            size: entry.size,
+            written: entry.written,
+            metadata: entry.metadata.unwrap_or_default(),
+        }
+    }
+}
 
 pub struct Service {
     data_dir: PathBuf,
-    state: Mutex<State>,
+    users: Users,
+    replicator: Option<Replicator>,
+    events: Bus,
+    usage: Usage,
+    storage_budget: Mutex<Option<u64>>,
+    size_limits: Mutex<SizeLimits>,
+    metrics: Metrics,
+    admins: Vec<String>,
+    started: std::time::Instant,
+    cache: Option<Cache>,
+    buffers: Arc<Pool>,
+    index: Index,
+    syncer: Syncer,
+    clock: Arc<dyn Clock>,
+    ids: Arc<dyn IdGenerator>,
+    keep_versions: usize,
+    #[cfg(feature = "io-uring")]
+    ring: Option<Arc<crate::uring::Ring>>,
+    /// Held for writing while a paste's files change and for reading while
+    /// they're copied into a snapshot, so that a snapshot never pairs the
+    /// contents of one write with the checksum of another.
+    writing: Vec<tokio::sync::RwLock<()>>,
 }
 
 impl Service {
@@ -16,116 +82,1574 @@ impl Service {
         std::fs::create_dir_all(&data_dir)?;
         Ok(Self {
             data_dir,
-            state: Mutex::new(state),
+            users: Users::new(state),
+            replicator: None,
+            events: Bus::default(),
+            usage: Usage::default(),
+            storage_budget: Mutex::new(None),
+            size_limits: Mutex::default(),
+            metrics: Metrics::default(),
+            admins: Vec::new(),
+            started: std::time::Instant::now(),
+            cache: None,
+            buffers: Arc::new(Pool::new(256 * 1024, 64)),
+            index: Index::default(),
+            syncer: Syncer::default(),
+            clock: Arc::new(SystemClock),
+            ids: Arc::new(RandomIds),
+            keep_versions: 0,
+            #[cfg(feature = "io-uring")]
+            ring: None,
+            writing: (0..WRITE_STRIPES)
+                .map(|_| tokio::sync::RwLock::new(()))
+                .collect(),
         })
     }
+
+    fn write_lock(&self, id: &uuid::Uuid) -> &tokio::sync::RwLock<()> {
+        &self.writing[(id.as_u128() % WRITE_STRIPES) as usize]
+    }
+
+    /// Fills the paste index and the usage of every paste from the data
+    /// directory, a batch at a time so as not to hold up requests. Lookups go
+    /// to the filesystem until this is done, and the storage budget only
+    /// counts the pastes scanned so far.
+    #[tracing::instrument(skip_all)]
+    pub async fn warm_index(&self) -> anyhow::Result<()> {
+        let data_dir = self.data_dir.clone();
+        let ids = tokio::task::spawn_blocking(move || paste_ids_in(&data_dir)).await??;
+        for batch in ids.chunks(SCAN_BATCH) {
+            let (data_dir, batch) = (self.data_dir.clone(), batch.to_vec());
+            let scanned = tokio::task::spawn_blocking(move || {
+                let mut scanned = Vec::new();
+                for id in batch {
+                    if let Some(entry) = index::Entry::load_blocking(&data_dir, &id)? {
+                        scanned.push((id, entry));
+                    }
+                }
+                anyhow::Ok(scanned)
+            })
+            .await??;
+            self.index.add_scanned(scanned, |id, entry| {
+                self.usage.scanned(id, entry.size, entry.written);
+            });
+        }
+        tracing::info!("Indexed {} pastes", ids.len());
+        self.index.warm();
+        Ok(())
+    }
+
+    /// Updates the index entry of paste `id` after a change to its files.
+    async fn reindex(&self, id: &uuid::Uuid) -> anyhow::Result<()> {
+        let (data_dir, id) = (self.data_dir.clone(), *id);
+        let entry =
+            tokio::task::spawn_blocking(move || index::Entry::load_blocking(&data_dir, &id))
+                .await??;
+        self.index.set(id, entry);
+        Ok(())
+    }
+
+    /// Caps the total size of all pastes at `budget` bytes, evicting the
+    /// least recently read anonymous pastes to make room for new ones.
+    pub fn with_storage_budget(self, budget: u64) -> Self {
+        self.set_storage_budget(Some(budget));
+        self
+    }
+
+    /// Changes the storage budget of a running service. A lower budget takes
+    /// effect with the next write.
+    pub fn set_storage_budget(&self, budget: Option<u64>) {
+        *self.storage_budget.lock() = budget;
+    }
+
+    /// Caps the size of uploaded pastes.
+    pub fn with_size_limits(self, limits: SizeLimits) -> Self {
+        self.set_size_limits(limits);
+        self
+    }
+
+    /// Changes the size limits of a running service, for uploads starting
+    /// after this.
+    pub fn set_size_limits(&self, limits: SizeLimits) {
+        *self.size_limits.lock() = limits;
+    }
+
+    /// The largest paste a user, or someone without credentials, may upload.
+    pub fn size_limit(&self, authenticated: bool) -> Option<u64> {
+        self.size_limits.lock().of(authenticated)
+    }
+
+    /// Takes the time from `clock`, for expirations and views, rather than
+    /// from the system.
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    pub fn clock(&self) -> &dyn Clock {
+        &*self.clock
+    }
+
+    /// Gives new pastes IDs from `ids` rather than random ones.
+    pub fn with_ids(mut self, ids: Arc<dyn IdGenerator>) -> Self {
+        self.ids = ids;
+        self
+    }
+
+    /// Where the IDs of pastes, and of what leads to them, come from.
+    pub fn ids(&self) -> &dyn IdGenerator {
+        &*self.ids
+    }
+
+    /// Grants the given users access to the instance-wide admin endpoints.
+    pub fn with_admins(mut self, admins: Vec<String>) -> Self {
+        self.admins = admins;
+        self
+    }
+
+    /// Keeps small pastes in memory once read, in `cache`.
+    pub fn with_cache(mut self, cache: Cache) -> Self {
+        self.cache = Some(cache);
+        self
+    }
+
+    /// Reads and writes pastes with buffers from `buffers`.
+    pub fn with_buffers(mut self, buffers: Pool) -> Self {
+        self.buffers = Arc::new(buffers);
+        self
+    }
+
+    /// The buffers to serve pastes with.
+    pub fn buffers(&self) -> &Arc<Pool> {
+        &self.buffers
+    }
+
+    /// Syncs written pastes to disk as `policy` has it. With
+    /// [`durability::Policy::Batch`], [`durability::spawn`] must be running.
+    pub fn with_durability(mut self, policy: durability::Policy) -> Self {
+        self.syncer = Syncer::new(policy);
+        self
+    }
+
+    /// Keeps up to `keep` earlier contents of each paste when it's
+    /// replaced, see [`versions`].
+    pub fn with_versions(mut self, keep: usize) -> Self {
+        self.keep_versions = keep;
+        self
+    }
+
+    /// Reads and writes pastes too large to cache through `ring`.
+    #[cfg(feature = "io-uring")]
+    pub fn with_ring(mut self, ring: crate::uring::Ring) -> Self {
+        self.ring = Some(Arc::new(ring));
+        self
+    }
+
+    pub fn syncer(&self) -> &Syncer {
+        &self.syncer
+    }
+
+    /// Mirrors every paste write and delete through `replicator`.
+    pub fn with_replicator(mut self, replicator: Replicator) -> Self {
+        self.replicator = Some(replicator);
+        self
+    }
+
+    fn emit(&self, event: Event) {
+        self.events.emit(event, self.clock.now());
+    }
+
+    fn replicate(&self, event: replication::Event) {
+        if let Some(replicator) = &self.replicator {
+            replicator.send(event);
+        }
+    }
+}
+
+/// Largest pastes in bytes that may be uploaded, with `None` for no limit.
+#[derive(Clone, Copy, Default)]
+pub struct SizeLimits {
+    /// For uploads without credentials.
+    pub anonymous: Option<u64>,
+    /// For uploads by a user.
+    pub user: Option<u64>,
+}
+
+impl SizeLimits {
+    fn of(&self, authenticated: bool) -> Option<u64> {
+        if authenticated {
+            self.user
+        } else {
+            self.anonymous
+        }
+    }
+}
+
+/// The user a paste is created for.
+enum Owner {
+    /// A user who gave their password.
+    Authenticated(String, String),
+    /// A user vouched for by the caller.
+    Trusted(String),
+}
+
+impl Owner {
+    fn username(&self) -> &str {
+        match self {
+            Self::Authenticated(username, _) | Self::Trusted(username) => username,
+        }
+    }
+}
+
+impl From<(String, String)> for Owner {
+    fn from((username, password): (String, String)) -> Self {
+        Self::Authenticated(username, password)
+    }
+}
+
+/// Settings a paste is created with, given as query parameters of
+/// `POST /paste`.
+#[derive(Clone, Debug, Default, serde::Deserialize, serde::Serialize)]
+#[serde(default)]
+pub struct Options {
+    /// Ask search engines not to index the paste.
+    pub noindex: bool,
+    /// List the paste publicly.
+    pub public: bool,
+    pub title: Option<String>,
+    /// Language of the contents, e.g. `rust`.
+    pub language: Option<String>,
+    /// Mirror the paste to a GitHub gist, if the instance has a
+    /// `--github-token`.
+    pub gist: bool,
+    /// Delete the paste this many seconds after it's created.
+    pub expires_in: Option<u64>,
+}
+
+/// What a new paste holds.
+enum Contents<R> {
+    Upload(R),
+    /// The contents of another paste, shared rather than copied.
+    Fork(uuid::Uuid),
+}
+
+/// How [`Service::write_paste`] treats a paste's existing contents.
+#[derive(Clone, Copy, PartialEq)]
+enum Existing {
+    /// Fails if there are any.
+    Fail,
+    Replace,
+    /// Replaces them, keeping them as a version if versions are kept.
+    KeepAsVersion,
+}
+
+/// Longest title a paste may have, in bytes.
+const MAX_TITLE: usize = 200;
+
+impl Options {
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if self
+            .title
+            .as_ref()
+            .is_some_and(|title| title.len() > MAX_TITLE)
+        {
+            anyhow::bail!("Title longer than {MAX_TITLE} bytes");
+        }
+        if self.expires_in == Some(0) {
+            anyhow::bail!("expires_in must be at least a second");
+        }
+        if let Some(language) = &self.language {
+            let valid = |c: char| c.is_ascii_alphanumeric() || "+#-_.".contains(c);
+            if language.is_empty() || language.len() > 32 || !language.chars().all(valid) {
+                anyhow::bail!("Invalid language {language:?}");
+            }
+        }
+        Ok(())
+    }
 }
 
 impl Service {
     pub async fn create(
         &self,
-        mut body: impl AsyncRead + Unpin,
+        body: impl AsyncRead + Unpin,
         auth: Option<(String, String)>,
     ) -> anyhow::Result<String> {
-        if let Some((username, password)) = &auth {
-            self.state
-                .lock()
-                .auth(username, password)
-                .ok_or(anyhow!("Not authorized"))?;
+        self.create_with_options(body, auth, Options::default())
+            .await
+    }
+
+    pub async fn create_with_options(
+        &self,
+        body: impl AsyncRead + Unpin,
+        auth: Option<(String, String)>,
+        options: Options,
+    ) -> anyhow::Result<String> {
+        self.insert(
+            self.ids.generate(),
+            Contents::Upload(body),
+            auth.map(Owner::from),
+            options,
+            None,
+        )
+        .await
+    }
+
+    /// Creates a paste owned by `username` without their password, for
+    /// gateways whose senders the operator vouches for, like email.
+    pub async fn create_for(
+        &self,
+        username: &str,
+        body: impl AsyncRead + Unpin,
+        options: Options,
+    ) -> anyhow::Result<String> {
+        let owner = Owner::Trusted(username.to_owned());
+        self.insert(
+            self.ids.generate(),
+            Contents::Upload(body),
+            Some(owner),
+            options,
+            None,
+        )
+        .await
+    }
+
+    /// Like [`Service::create`], but with a caller-chosen ID. Fails with an
+    /// [`std::io::ErrorKind::AlreadyExists`] error if the ID is taken.
+    pub async fn create_with_id(
+        &self,
+        id: uuid::Uuid,
+        body: impl AsyncRead + Unpin,
+        auth: Option<(String, String)>,
+        options: Options,
+    ) -> anyhow::Result<String> {
+        self.insert(
+            id,
+            Contents::Upload(body),
+            auth.map(Owner::from),
+            options,
+            None,
+        )
+        .await
+    }
+
+    /// Creates a paste with the contents, title and language of paste
+    /// `source`. The contents are shared with it rather than copied, so
+    /// forking a large paste is as quick as a small one.
+    pub async fn fork(
+        &self,
+        source: &uuid::Uuid,
+        auth: Option<(String, String)>,
+    ) -> anyhow::Result<String> {
+        if !self.exists(source) {
+            return Err(std::io::Error::from(std::io::ErrorKind::NotFound).into());
         }
-        let id = uuid::Uuid::new_v4().to_string();
-        let path = self.data_dir.join(&id);
-        let mut file = tokio::fs::File::create_new(path).await?;
-        tokio::io::copy(&mut body, &mut file).await?;
+        let metadata = self.metadata(source).await?.unwrap_or_default();
+        let options = Options {
+            title: metadata.title,
+            language: metadata.language,
+            ..Default::default()
+        };
+        self.insert(
+            self.ids.generate(),
+            Contents::<&[u8]>::Fork(*source),
+            auth.map(Owner::from),
+            options,
+            None,
+        )
+        .await
+    }
 
-        match &auth {
-            None => {}
-            Some((username, password)) => {
-                self.state
-                    .lock()
-                    .auth_mut(username, password)
-                    .ok_or(anyhow!("Not authorized"))?
-                    .paste_ids
-                    .push(id.clone());
+    #[tracing::instrument(skip_all, fields(%id))]
+    async fn insert(
+        &self,
+        id: uuid::Uuid,
+        contents: Contents<impl AsyncRead + Unpin>,
+        owner: Option<Owner>,
+        options: Options,
+        origin: Option<String>,
+    ) -> anyhow::Result<String> {
+        match &owner {
+            Some(Owner::Authenticated(username, password)) => {
+                self.users
+                    .auth(username, password, |_| ())
+                    .ok_or(anyhow!("Not authorized"))?;
+            }
+            Some(Owner::Trusted(username)) if !self.users.contains(username) => {
+                anyhow::bail!("No user {username:?}");
             }
+            _ => {}
+        }
+        let uuid = id;
+        let id = id.to_string();
+        if tokio::fs::try_exists(self.data_dir.join(&id)).await? {
+            return Err(std::io::Error::from(std::io::ErrorKind::AlreadyExists).into());
+        }
+        let limit = self.size_limits.lock().of(owner.is_some());
+        let size = match contents {
+            Contents::Upload(body) => self.write_paste(&uuid, body, Existing::Fail, limit).await?,
+            Contents::Fork(source) => self.share_paste(&source, &uuid, limit).await?,
+        };
+        self.usage.written(uuid, size, self.clock.now());
+        self.metrics.paste_created(self.clock.now());
+        let metadata = Metadata {
+            owner: owner.as_ref().map(|owner| owner.username().to_owned()),
+            noindex: options.noindex,
+            public: options.public,
+            title: options.title,
+            language: options.language,
+            origin,
+            gist: options.gist,
+            gist_url: None,
+            reminded: false,
+            expires_at: options.expires_in.map(|expires_in| {
+                let now = self.clock.now().duration_since(std::time::UNIX_EPOCH);
+                now.unwrap_or_default().as_secs().saturating_add(expires_in)
+            }),
         };
+        if let Err(e) = self.store_metadata(&uuid, &metadata).await {
+            self.remove_files(&uuid).await?;
+            return Err(e.into());
+        }
+        if let Err(e) = self.enforce_budget(&uuid).await {
+            self.remove_files(&uuid).await?;
+            return Err(e);
+        }
+
+        // The credentials were checked above and users stay once created,
+        // but should that change, the paste mustn't stay behind unowned.
+        if let Some(owner) = &owner
+            && !self.users.own(owner.username(), &id)
+        {
+            self.remove_files(&uuid).await?;
+            anyhow::bail!("Not authorized");
+        }
+        self.reindex(&uuid).await?;
+        self.replicate(replication::Event::Write(uuid));
+        self.emit(Event::PasteCreated(uuid));
 
         Ok(id)
     }
 
-    pub async fn read(&self, id: &uuid::Uuid) -> anyhow::Result<tokio::fs::File> {
-        let path = self.data_dir.join(id.to_string());
-        let file = tokio::fs::File::open(path).await?;
-        Ok(file)
+    /// Opens a paste for reading. The returned reader fails at the end of
+    /// the paste if its contents don't match the stored checksum. Small
+    /// pastes are read and checked right away, in one go rather than a
+    /// round trip to the blocking pool for every step, and cached.
+    ///
+    /// Once the index is warm, IDs it doesn't know fail with
+    /// [`std::io::ErrorKind::NotFound`] without touching the disk, so that
+    /// probing for random IDs is cheap.
+    #[tracing::instrument(skip_all, fields(%id))]
+    pub async fn read(&self, id: &uuid::Uuid) -> anyhow::Result<cache::Reader> {
+        self.check_indexed(id)?;
+        if let Some(contents) = self.cache.as_ref().and_then(|cache| cache.get(id)) {
+            self.usage.read(id, self.clock.now());
+            return Ok(cache::Reader::Memory(std::io::Cursor::new(contents)));
+        }
+        let generation = self.cache.as_ref().map(Cache::generation);
+        let small = self
+            .cache
+            .as_ref()
+            .map_or(SMALL_PASTE, |cache| cache.max_paste().max(SMALL_PASTE));
+        let (data_dir, name) = (self.data_dir.clone(), id.to_string());
+        let opened =
+            tokio::task::spawn_blocking(move || open_blocking(&data_dir, &name, small)).await??;
+        self.usage.read(id, self.clock.now());
+        match opened {
+            Opened::Whole(contents) => {
+                if let (Some(cache), Some(generation)) = (&self.cache, generation) {
+                    cache.insert(*id, contents.clone(), generation);
+                }
+                Ok(cache::Reader::Memory(std::io::Cursor::new(contents)))
+            }
+            #[cfg(feature = "io-uring")]
+            Opened::File(file, expected) if let Some(ring) = &self.ring => {
+                let file = crate::uring::File::new(ring.clone(), file);
+                Ok(cache::Reader::Uring(ChecksumReader::verifying(
+                    file, expected,
+                )))
+            }
+            Opened::File(file, expected) => Ok(cache::Reader::File(ChecksumReader::verifying(
+                tokio::fs::File::from_std(file),
+                expected,
+            ))),
+        }
+    }
+
+    /// Like [`Service::read`], but bypassing the cache, for bulk reads
+    /// that would only churn it and need the file's metadata.
+    pub async fn read_file(
+        &self,
+        id: &uuid::Uuid,
+    ) -> anyhow::Result<ChecksumReader<tokio::fs::File>> {
+        self.check_indexed(id)?;
+        let name = id.to_string();
+        let file = tokio::fs::File::open(self.data_dir.join(&name)).await?;
+        let expected = checksum::load(&self.data_dir, &name).await?;
+        self.usage.read(id, self.clock.now());
+        Ok(ChecksumReader::verifying(file, expected))
+    }
+
+    /// Fails if the index is warm and doesn't have paste `id`.
+    fn check_indexed(&self, id: &uuid::Uuid) -> std::io::Result<()> {
+        match self.index.contains(id) {
+            Some(false) => Err(std::io::ErrorKind::NotFound.into()),
+            _ => Ok(()),
+        }
+    }
+
+    /// Hits, misses, pastes and bytes of the cache, if there's one.
+    pub fn cache_stats(&self) -> Option<(u64, u64, usize, usize)> {
+        self.cache.as_ref().map(Cache::stats)
+    }
+
+    /// Drops paste `id` from the cache, once its files have changed.
+    fn uncache(&self, id: &uuid::Uuid) {
+        if let Some(cache) = &self.cache {
+            cache.remove(id);
+        }
+    }
+
+    /// The metadata stored with a paste, if it has any.
+    pub async fn metadata(&self, id: &uuid::Uuid) -> anyhow::Result<Option<Metadata>> {
+        if let Some(entry) = self.index.get(id) {
+            return Ok(entry.and_then(|entry| entry.metadata));
+        }
+        Ok(meta::load(&self.data_dir, &id.to_string()).await?)
     }
 
+    /// The versions kept of paste `id`, oldest first.
+    pub async fn versions(&self, id: &uuid::Uuid) -> anyhow::Result<Vec<Version>> {
+        let (data_dir, name) = (self.data_dir.clone(), id.to_string());
+        Ok(
+            tokio::task::spawn_blocking(move || versions::list_blocking(&data_dir, &name))
+                .await??,
+        )
+    }
+
+    /// The contents of version `number` of paste `id`, if it's kept.
+    pub async fn version(&self, id: &uuid::Uuid, number: u64) -> anyhow::Result<Option<Vec<u8>>> {
+        let _reading = self.write_lock(id).read().await;
+        let (data_dir, name) = (self.data_dir.clone(), id.to_string());
+        Ok(
+            tokio::task::spawn_blocking(move || versions::read_blocking(&data_dir, &name, number))
+                .await??,
+        )
+    }
+
+    #[tracing::instrument(skip_all, fields(%id))]
     pub async fn replace(
         &self,
         id: &uuid::Uuid,
-        mut body: impl AsyncRead + Unpin,
+        body: impl AsyncRead + Unpin,
         auth: Option<(String, String)>,
     ) -> anyhow::Result<()> {
         if let Some((username, password)) = &auth {
-            let mut state = self.state.lock();
-            let user = state
-                .auth(username, password)
-                .ok_or(anyhow!("Not authorized"))?;
-
-            if !user.paste_ids.iter().any(|p| p == &id.to_string()) {
+            if !self.authenticate(username, password) {
+                anyhow::bail!("Not authorized");
+            }
+            if !self.users.owns(username, &id.to_string()) {
                 anyhow::bail!("Paste not found");
             }
         }
 
-        let path = self.data_dir.join(id.to_string());
-        if !path.exists() {
+        if !tokio::fs::try_exists(self.data_dir.join(id.to_string())).await? {
             anyhow::bail!("Paste not found");
         }
-        let mut file = tokio::fs::File::create(path).await?;
-        tokio::io::copy(&mut body, &mut file).await?;
+        let limit = self.size_limits.lock().of(auth.is_some());
+        let size = self
+            .write_paste(id, body, Existing::KeepAsVersion, limit)
+            .await?;
+        self.usage.written(*id, size, self.clock.now());
+        self.reindex(id).await?;
+        self.replicate(replication::Event::Write(*id));
+        self.emit(Event::PasteUpdated(*id));
+        // The previous contents may already have been released, so there's
+        // nothing to roll back to if the budget can't be met.
+        if let Err(e) = self.enforce_budget(id).await {
+            tracing::warn!("Replacing paste {id}: {e}");
+        }
 
         Ok(())
     }
 
-    pub fn delete(
+    #[tracing::instrument(skip_all, fields(id = %id_to_delete))]
+    pub async fn delete(
         &self,
         id_to_delete: uuid::Uuid,
         username: &str,
         password: &str,
     ) -> anyhow::Result<()> {
+        let uuid = id_to_delete;
         let id_to_delete = id_to_delete.to_string();
-        let mut state = self.state.lock();
-        let user = state
-            .auth_mut(username, password)
-            .ok_or(anyhow!("Not authorized"))?;
-        let index = match user
-            .paste_ids
-            .iter()
-            .enumerate()
-            .find(|(_, id)| **id == id_to_delete)
-        {
-            None => anyhow::bail!("Paste not found"),
-            Some((i, _)) => i,
-        };
-        std::fs::remove_file(self.data_dir.join(id_to_delete))?;
-        user.paste_ids.remove(index);
+        if !self.authenticate(username, password) {
+            anyhow::bail!("Not authorized");
+        }
+        let not_found = || std::io::Error::new(std::io::ErrorKind::NotFound, "Paste not found");
+        if !self.users.owns(username, &id_to_delete) {
+            return Err(not_found().into());
+        }
+        // Whichever of concurrent deletes removes the paste file reports
+        // success; the rest of the files follow.
+        match tokio::fs::remove_file(self.data_dir.join(&id_to_delete)).await {
+            Ok(()) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Err(not_found().into()),
+            Err(e) => return Err(e.into()),
+        }
+        self.remove_files(&uuid).await?;
+        // Removing the ID last keeps the paste from looking anonymous, and
+        // so from being purged or evicted, while its files are removed.
+        self.users.release(username, &id_to_delete);
+        self.emit(Event::PasteDeleted(uuid));
         // TODO: clean up dangling entries if state serialization failed
         Ok(())
     }
 
     pub fn register_user(&self, username: &str, password: &str) -> anyhow::Result<()> {
-        self.state.lock().create(username, password);
+        if !self.users.create(username, password) {
+            anyhow::bail!("User already exists");
+        }
+        self.emit(Event::UserRegistered(username.to_owned()));
         Ok(())
     }
 
+    /// Sets or clears where `username` is reminded of expiring pastes.
+    pub fn set_email(
+        &self,
+        username: &str,
+        password: &str,
+        email: Option<String>,
+    ) -> anyhow::Result<()> {
+        if !self.authenticate(username, password) {
+            anyhow::bail!("Not authorized");
+        }
+        self.users.user_mut(username, |user| user.email = email);
+        Ok(())
+    }
+
+    /// The owner of paste `id` and their email address, if they have one.
+    pub fn owner_email(&self, id: &uuid::Uuid) -> Option<(String, String)> {
+        let username = self.users.owner(&id.to_string())?;
+        let email = self.users.user(&username, |user| user.email.clone())??;
+        Some((username, email))
+    }
+
     pub fn list(&self, username: &str, password: &str) -> anyhow::Result<Vec<String>> {
-        let state = self.state.lock();
-        let user = state
-            .auth(username, password)
-            .ok_or(anyhow!("Not authorized"))?;
-        Ok(user.paste_ids.iter().map(|s| s.clone()).collect())
+        self.users
+            .auth(username, password, |user| user.paste_ids.to_vec())
+            .ok_or(anyhow!("Not authorized"))
     }
 
+    /// The rate limit override of a user, or `None` if the credentials are
+    /// wrong.
+    pub fn user_rate_limit(&self, username: &str, password: &str) -> Option<Option<Rate>> {
+        self.users.auth(username, password, |user| user.rate_limit)
+    }
+
+    /// Changes to accounts and ownership so far, see [`Users::changes`].
+    pub fn state_changes(&self) -> u64 {
+        self.users.changes()
+    }
+
+    #[tracing::instrument(skip_all, fields(path = %path.display()))]
     pub fn dump_state(&self, path: &Path) -> anyhow::Result<()> {
-        self.state.lock().dump(path)
+        self.users.dump(path)
+    }
+
+    /// Writes the state file and a copy of every paste into `dest`.
+    ///
+    /// The state is taken before the paste files are listed, and pastes are
+    /// only added to it once their files are written, so the snapshot never
+    /// references pastes created after it was taken. Pastes deleted while
+    /// copying are skipped, and each paste's files are copied while nothing
+    /// writes to it. Must not be called from async code.
+    #[tracing::instrument(skip_all, fields(dest = %dest.display()))]
+    pub fn snapshot(&self, dest: &Path) -> anyhow::Result<()> {
+        let data_dest = dest.join("data");
+        std::fs::create_dir_all(&data_dest)?;
+
+        let users = self.users.to_json()?;
+        crate::state::write_atomically(&dest.join("db.json"), &users)?;
+        let ids = self.paste_ids_on_disk()?;
+
+        for id in ids {
+            let _copying = self.write_lock(&id).blocking_read();
+            let name = id.to_string();
+            let sidecar = checksum::sidecar_path(&self.data_dir, &name);
+            for (from, to) in [
+                (self.data_dir.join(&name), data_dest.join(&name)),
+                (sidecar, checksum::sidecar_path(&data_dest, &name)),
+                (
+                    meta::path(&self.data_dir, &name),
+                    meta::path(&data_dest, &name),
+                ),
+            ] {
+                // Clones rather than copies on filesystems with reflinks.
+                // Hard links would be cheaper still, but would keep the
+                // blobs of deleted pastes alive, see `blobs::release`.
+                match std::fs::copy(from, to) {
+                    Ok(_) => {}
+                    Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                    Err(e) => return Err(e.into()),
+                }
+            }
+            versions::copy_blocking(&self.data_dir, &data_dest, &name)?;
+        }
+        Ok(())
+    }
+
+    pub fn exists(&self, id: &uuid::Uuid) -> bool {
+        self.index
+            .contains(id)
+            .unwrap_or_else(|| self.data_dir.join(id.to_string()).is_file())
+    }
+
+    pub fn authenticate(&self, username: &str, password: &str) -> bool {
+        self.users.auth(username, password, |_| ()).is_some()
+    }
+
+    /// Whether the credentials are valid and belong to an admin.
+    pub fn is_admin(&self, username: &str, password: &str) -> bool {
+        self.admins.iter().any(|admin| admin == username) && self.authenticate(username, password)
+    }
+
+    /// Whether `username` owns paste `id`, without checking credentials.
+    pub fn owns(&self, username: &str, id: &uuid::Uuid) -> bool {
+        self.users.owns(username, &id.to_string())
+    }
+
+    /// The user owning paste `id`, if any.
+    pub fn owner(&self, id: &uuid::Uuid) -> Option<String> {
+        self.users.owner(&id.to_string())
+    }
+
+    pub fn user_exists(&self, username: &str) -> bool {
+        self.users.contains(username)
     }
+
+    pub fn user_count(&self) -> usize {
+        self.users.user_count()
+    }
+
+    pub fn uptime(&self) -> std::time::Duration {
+        self.started.elapsed()
+    }
+
+    pub fn events(&self) -> &Bus {
+        &self.events
+    }
+
+    pub fn metrics(&self) -> &Metrics {
+        &self.metrics
+    }
+
+    /// The number of pastes and their total size in bytes.
+    pub fn usage_totals(&self) -> (usize, u64) {
+        self.usage.totals()
+    }
+
+    /// Pastes by their recent views, see [`Usage::trending`].
+    pub fn trending(&self) -> Vec<(uuid::Uuid, f64)> {
+        self.usage.trending(self.clock.now())
+    }
+
+    pub fn data_dir(&self) -> &Path {
+        &self.data_dir
+    }
+
+    /// Moves a paste, its checksum and metadata out of the data directory into its
+    /// `quarantine` subdirectory, where an operator can inspect it.
+    #[tracing::instrument(skip_all, fields(%id))]
+    pub async fn quarantine(&self, id: &uuid::Uuid) -> anyhow::Result<()> {
+        let uuid = *id;
+        let replication_event = replication::Event::Delete(*id);
+        let id = id.to_string();
+        let quarantine = self.data_dir.join("quarantine");
+        tokio::fs::create_dir_all(&quarantine).await?;
+        let _writing = self.write_lock(&uuid).write().await;
+        tokio::fs::rename(self.data_dir.join(&id), quarantine.join(&id)).await?;
+        self.uncache(&uuid);
+        self.index.set(uuid, None);
+        self.usage.removed(&uuid);
+        self.replicate(replication_event);
+        for (from, to) in [
+            (
+                checksum::sidecar_path(&self.data_dir, &id),
+                checksum::sidecar_path(&quarantine, &id),
+            ),
+            (
+                meta::path(&self.data_dir, &id),
+                meta::path(&quarantine, &id),
+            ),
+        ] {
+            match tokio::fs::rename(from, to).await {
+                Ok(()) => {}
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+        Ok(())
+    }
+
+    /// Stores a paste pushed by a primary instance, creating or overwriting
+    /// it. Ownership isn't replicated, so the paste is stored anonymously.
+    pub async fn write_replica(
+        &self,
+        id: &uuid::Uuid,
+        body: impl AsyncRead + Unpin,
+    ) -> anyhow::Result<()> {
+        let size = self.write_paste(id, body, Existing::Replace, None).await?;
+        self.usage.written(*id, size, self.clock.now());
+        self.reindex(id).await?;
+        self.replicate(replication::Event::Write(*id));
+        self.enforce_budget(id).await
+    }
+
+    /// Removes a paste deleted on a primary instance.
+    pub async fn delete_replica(&self, id: &uuid::Uuid) -> anyhow::Result<()> {
+        self.remove_files(id).await
+    }
+
+    /// Evicts anonymous pastes other than `keep` until the storage budget is
+    /// met, failing if that isn't possible.
+    #[tracing::instrument(skip_all)]
+    async fn enforce_budget(&self, keep: &uuid::Uuid) -> anyhow::Result<()> {
+        let Some(budget) = *self.storage_budget.lock() else {
+            return Ok(());
+        };
+        let mut protected = self.users.owned_paste_ids();
+        protected.insert(*keep);
+        // The state file may have lost track of a paste that its metadata
+        // still names an owner for; spare those too, and pick again.
+        let evict = loop {
+            let candidates = self
+                .usage
+                .eviction_candidates(budget, &protected)
+                .ok_or(anyhow!("Storage budget exceeded"))?;
+            let mut owned = Vec::new();
+            for id in &candidates {
+                if self.has_owner(id).await? {
+                    owned.push(*id);
+                }
+            }
+            if owned.is_empty() {
+                break candidates;
+            }
+            protected.extend(owned);
+        };
+        for id in evict {
+            tracing::info!("Evicting paste {id} to stay within the storage budget");
+            self.remove_files(&id).await?;
+            self.emit(Event::PasteDeleted(id));
+        }
+        Ok(())
+    }
+
+    /// Whether the metadata of paste `id` names an owner for it.
+    async fn has_owner(&self, id: &uuid::Uuid) -> anyhow::Result<bool> {
+        Ok(self
+            .metadata(id)
+            .await?
+            .is_some_and(|metadata| metadata.owner.is_some()))
+    }
+
+    /// Deletes every anonymous paste created before `cutoff` and returns how
+    /// many there were. Pastes are anonymous if neither the state file nor
+    /// their metadata names an owner.
+    #[tracing::instrument(skip_all)]
+    pub async fn purge_anonymous(&self, cutoff: std::time::SystemTime) -> anyhow::Result<usize> {
+        let ids = {
+            let data_dir = self.data_dir.clone();
+            tokio::task::spawn_blocking(move || paste_ids_in(&data_dir)).await??
+        };
+        let owned = self.users.owned_paste_ids();
+
+        let mut purged = 0;
+        for id in ids.into_iter().filter(|id| !owned.contains(id)) {
+            let metadata = match tokio::fs::metadata(self.data_dir.join(id.to_string())).await {
+                Ok(metadata) => metadata,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e.into()),
+            };
+            // Not every filesystem records creation times.
+            let created = metadata.created().or_else(|_| metadata.modified())?;
+            if created < cutoff && !self.has_owner(&id).await? {
+                self.remove_files(&id).await?;
+                self.emit(Event::PasteExpired(id));
+                purged += 1;
+            }
+        }
+        Ok(purged)
+    }
+
+    /// Deletes the pastes whose `expires_in` ran out by `now`, whoever owns
+    /// them, returning how many there were.
+    pub async fn purge_expired(&self, now: std::time::SystemTime) -> anyhow::Result<usize> {
+        let now = now.duration_since(std::time::UNIX_EPOCH)?.as_secs();
+        let ids = {
+            let data_dir = self.data_dir.clone();
+            tokio::task::spawn_blocking(move || paste_ids_in(&data_dir)).await??
+        };
+        let mut purged = 0;
+        for id in ids {
+            let Some(metadata) = meta::load(&self.data_dir, &id.to_string()).await? else {
+                continue;
+            };
+            if metadata
+                .expires_at
+                .is_some_and(|expires_at| expires_at <= now)
+            {
+                self.users.disown(&id.to_string());
+                self.remove_files(&id).await?;
+                self.emit(Event::PasteExpired(id));
+                purged += 1;
+            }
+        }
+        Ok(purged)
+    }
+
+    /// Deletes a paste whoever owns it, for moderation.
+    pub async fn remove(&self, id: &uuid::Uuid) -> anyhow::Result<()> {
+        if !self.exists(id) {
+            anyhow::bail!("Paste not found");
+        }
+        if let Some(owner) = self.users.disown(&id.to_string()) {
+            tracing::info!("Removing paste {id} of {owner}");
+        }
+        self.remove_files(id).await?;
+        self.emit(Event::PasteDeleted(*id));
+        Ok(())
+    }
+
+    /// Changes the title of paste `id`, which must belong to `username`.
+    pub async fn retitle(
+        &self,
+        id: &uuid::Uuid,
+        title: Option<String>,
+        username: &str,
+        password: &str,
+    ) -> anyhow::Result<()> {
+        if !self.authenticate(username, password) {
+            anyhow::bail!("Not authorized");
+        }
+        if !self.users.owns(username, &id.to_string()) {
+            anyhow::bail!("Paste not found");
+        }
+        let mut metadata = self.metadata(id).await?.unwrap_or_default();
+        metadata.title = title;
+        self.store_metadata(id, &metadata).await?;
+        self.reindex(id).await?;
+        self.replicate(replication::Event::Write(*id));
+        self.emit(Event::PasteUpdated(*id));
+        Ok(())
+    }
+
+    /// Pushes the expiry of paste `id` back to `by` seconds from now, or from
+    /// when it was due if that's later, returning the new expiry in seconds
+    /// since the Unix epoch. Only owners may extend their pastes, and only
+    /// ones that expire.
+    pub async fn extend(
+        &self,
+        id: &uuid::Uuid,
+        by: u64,
+        username: &str,
+        password: &str,
+    ) -> anyhow::Result<u64> {
+        if !self.authenticate(username, password) {
+            anyhow::bail!("Not authorized");
+        }
+        if !self.users.owns(username, &id.to_string()) {
+            anyhow::bail!("Paste not found");
+        }
+        let mut metadata = self.metadata(id).await?.unwrap_or_default();
+        let Some(expires_at) = metadata.expires_at else {
+            anyhow::bail!("Paste doesn't expire");
+        };
+        let now = self.clock.now().duration_since(std::time::UNIX_EPOCH)?;
+        let expires_at = expires_at.max(now.as_secs()).saturating_add(by);
+        metadata.expires_at = Some(expires_at);
+        metadata.reminded = false;
+        self.store_metadata(id, &metadata).await?;
+        self.reindex(id).await?;
+        self.replicate(replication::Event::Write(*id));
+        self.emit(Event::PasteUpdated(*id));
+        Ok(expires_at)
+    }
+
+    /// The owned pastes expiring within `within` whose owners weren't
+    /// reminded yet.
+    pub async fn expiring(
+        &self,
+        within: std::time::Duration,
+    ) -> anyhow::Result<Vec<(uuid::Uuid, Metadata)>> {
+        let now = self.clock.now().duration_since(std::time::UNIX_EPOCH)?;
+        let (now, until) = (now.as_secs(), (now + within).as_secs());
+        let ids = {
+            let data_dir = self.data_dir.clone();
+            tokio::task::spawn_blocking(move || paste_ids_in(&data_dir)).await??
+        };
+        let mut expiring = Vec::new();
+        for id in ids {
+            if self.users.owner(&id.to_string()).is_none() {
+                continue;
+            }
+            let Some(metadata) = self.metadata(&id).await? else {
+                continue;
+            };
+            if let Some(expires_at) = metadata.expires_at
+                && !metadata.reminded
+                && (now..=until).contains(&expires_at)
+            {
+                expiring.push((id, metadata));
+            }
+        }
+        Ok(expiring)
+    }
+
+    /// Records that the owner of paste `id` was reminded of its expiry.
+    pub async fn mark_reminded(&self, id: &uuid::Uuid) -> anyhow::Result<()> {
+        let mut metadata = self.metadata(id).await?.unwrap_or_default();
+        metadata.reminded = true;
+        self.store_metadata(id, &metadata).await?;
+        self.reindex(id).await
+    }
+
+    /// Records the URL of the gist paste `id` is mirrored to. Unlike other
+    /// changes, this isn't an update of the paste, so isn't announced.
+    pub async fn record_gist(&self, id: &uuid::Uuid, url: String) -> anyhow::Result<()> {
+        let mut metadata = self.metadata(id).await?.unwrap_or_default();
+        metadata.gist_url = Some(url);
+        self.store_metadata(id, &metadata).await?;
+        self.reindex(id).await?;
+        self.replicate(replication::Event::Write(*id));
+        Ok(())
+    }
+
+    /// Stores paste `id` as mirrored from the federation peer with key
+    /// `origin`, or updates the copy that peer sent before. Pastes from
+    /// anywhere else are never overwritten.
+    pub async fn mirror(
+        &self,
+        id: uuid::Uuid,
+        body: impl AsyncRead + Unpin,
+        options: Options,
+        origin: String,
+    ) -> anyhow::Result<()> {
+        let Some(mut metadata) = self.metadata(&id).await? else {
+            self.insert(id, Contents::Upload(body), None, options, Some(origin))
+                .await?;
+            return Ok(());
+        };
+        if metadata.origin.as_ref() != Some(&origin) {
+            anyhow::bail!("Paste {id} isn't mirrored from this peer");
+        }
+        metadata.title = options.title;
+        metadata.language = options.language;
+        self.store_metadata(&id, &metadata).await?;
+        self.replace(&id, body, None).await
+    }
+
+    /// Deletes the copy of paste `id` mirrored from `origin`, if there's one.
+    pub async fn unmirror(&self, id: &uuid::Uuid, origin: &str) -> anyhow::Result<()> {
+        match self.metadata(id).await? {
+            Some(metadata) if metadata.origin.as_deref() == Some(origin) => self.remove(id).await,
+            _ => Ok(()),
+        }
+    }
+
+    /// The size, modification time and metadata of paste `id`, if it exists.
+    pub async fn paste(&self, id: &uuid::Uuid) -> anyhow::Result<Option<Recent>> {
+        let entry = match self.index.get(id) {
+            Some(entry) => entry,
+            None => {
+                let (data_dir, id) = (self.data_dir.clone(), *id);
+                tokio::task::spawn_blocking(move || index::Entry::load_blocking(&data_dir, &id))
+                    .await??
+            }
+        };
+        Ok(entry.map(|entry| Recent::new(*id, entry)))
+    }
+
+    /// Every indexed paste, newest first, or `None` until the index is warm.
+    fn indexed_newest_first(&self) -> Option<Vec<Recent>> {
+        let mut pastes: Vec<_> = self
+            .index
+            .all()?
+            .into_iter()
+            .map(|(id, entry)| Recent::new(id, entry))
+            .collect();
+        pastes
+            .sort_unstable_by_key(|paste| std::cmp::Reverse((paste.written, paste.size, paste.id)));
+        Some(pastes)
+    }
+
+    /// The `limit` most recently written pastes, newest first.
+    pub async fn recent_pastes(&self, limit: usize) -> anyhow::Result<Vec<Recent>> {
+        if let Some(mut pastes) = self.indexed_newest_first() {
+            pastes.truncate(limit);
+            return Ok(pastes);
+        }
+        let data_dir = self.data_dir.clone();
+        tokio::task::spawn_blocking(move || {
+            newest_first(&data_dir)?
+                .into_iter()
+                .take(limit)
+                .map(|(written, size, id)| {
+                    let metadata =
+                        meta::load_blocking(&data_dir, &id.to_string())?.unwrap_or_default();
+                    Ok(Recent {
+                        id,
+                        size,
+                        written,
+                        metadata,
+                    })
+                })
+                .collect()
+        })
+        .await?
+    }
+
+    /// All pastes created as public, newest first. Pastes marked noindex
+    /// are left out.
+    pub async fn public_pastes(&self) -> anyhow::Result<Vec<Recent>> {
+        if let Some(mut pastes) = self.indexed_newest_first() {
+            pastes.retain(|paste| paste.metadata.public && !paste.metadata.noindex);
+            return Ok(pastes);
+        }
+        let data_dir = self.data_dir.clone();
+        tokio::task::spawn_blocking(move || {
+            let mut pastes = Vec::new();
+            for (written, size, id) in newest_first(&data_dir)? {
+                let Some(metadata) = meta::load_blocking(&data_dir, &id.to_string())? else {
+                    continue;
+                };
+                if metadata.public && !metadata.noindex {
+                    pastes.push(Recent {
+                        id,
+                        size,
+                        written,
+                        metadata,
+                    });
+                }
+            }
+            Ok(pastes)
+        })
+        .await?
+    }
+
+    /// Deletes the files of a paste that no user owns, or that's being
+    /// deleted by its owner.
+    #[tracing::instrument(skip_all, fields(%id))]
+    async fn remove_files(&self, id: &uuid::Uuid) -> anyhow::Result<()> {
+        let name = id.to_string();
+        let digest = checksum::load(&self.data_dir, &name).await?;
+        let writing = self.write_lock(id).write().await;
+        for path in [
+            self.data_dir.join(&name),
+            checksum::sidecar_path(&self.data_dir, &name),
+            meta::path(&self.data_dir, &name),
+        ] {
+            match tokio::fs::remove_file(path).await {
+                Ok(()) => {}
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+        versions::remove(&self.data_dir, &name).await?;
+        drop(writing);
+        self.uncache(id);
+        self.index.set(*id, None);
+        if let Some(digest) = digest {
+            blobs::release(&self.data_dir, &digest).await?;
+        }
+        self.usage.removed(id);
+        self.metrics.paste_deleted();
+        self.replicate(replication::Event::Delete(*id));
+        Ok(())
+    }
+
+    /// Stores `body` as the contents of paste `id` along with its checksum,
+    /// sharing storage with any other paste with the same contents. Returns
+    /// the size of the paste.
+    ///
+    /// The new contents replace any previous ones atomically, so readers and
+    /// other pastes sharing the old contents are unaffected, and they're kept
+    /// as a version if `existing` says so. Bodies over
+    /// `limit` bytes fail with [`std::io::ErrorKind::FileTooLarge`] as soon
+    /// as that many are read.
+    #[tracing::instrument(skip_all, fields(%id))]
+    async fn write_paste(
+        &self,
+        id: &uuid::Uuid,
+        body: impl AsyncRead + Unpin,
+        existing: Existing,
+        limit: Option<u64>,
+    ) -> anyhow::Result<u64> {
+        let name = id.to_string();
+        let replace = existing != Existing::Fail;
+        let tmp = self
+            .data_dir
+            .join(format!("{name}~{}", uuid::Uuid::new_v4().simple()));
+        let mut file = tokio::fs::File::create_new(&tmp).await?;
+        let mut body = ChecksumReader::new(body.take(limit.map_or(u64::MAX, |limit| limit + 1)));
+        let copied = self
+            .write_file(&mut body, &mut file)
+            .await
+            .and_then(|size| match limit {
+                Some(limit) if size > limit => Err(std::io::Error::new(
+                    std::io::ErrorKind::FileTooLarge,
+                    format!("Pastes are limited to {limit} bytes"),
+                )),
+                _ => Ok(size),
+            });
+        let copied = match copied {
+            Ok(size) => self.syncer.before_rename(&file).await.map(|()| size),
+            Err(e) => Err(e),
+        };
+        let size = match copied {
+            Ok(size) => size,
+            Err(e) => {
+                drop(file);
+                tokio::fs::remove_file(&tmp).await.ok();
+                return Err(e.into());
+            }
+        };
+        drop(file);
+
+        let digest = body.digest();
+        let previous = if replace {
+            checksum::load(&self.data_dir, &name).await?
+        } else {
+            None
+        };
+        let path = self.data_dir.join(&name);
+        let writing = self.write_lock(id).write().await;
+        if existing == Existing::KeepAsVersion
+            && self.keep_versions > 0
+            && let Some(previous) = previous.clone().filter(|previous| *previous != digest)
+        {
+            let (data_dir, name, keep) = (self.data_dir.clone(), name.clone(), self.keep_versions);
+            let now = self.clock.now().duration_since(std::time::UNIX_EPOCH);
+            let now = now.unwrap_or_default().as_secs();
+            let recorded = tokio::task::spawn_blocking(move || {
+                versions::record_blocking(&data_dir, &name, &previous, now, keep)
+            })
+            .await?;
+            if let Err(e) = recorded {
+                tokio::fs::remove_file(&tmp).await.ok();
+                return Err(e.into());
+            }
+        }
+        blobs::commit(&self.data_dir, &tmp, &digest, &path, replace).await?;
+        checksum::store(&self.data_dir, &name, &digest).await?;
+        drop(writing);
+        self.syncer
+            .written(vec![
+                blobs::blob_path(&self.data_dir, &digest),
+                blobs::dir(&self.data_dir),
+                checksum::sidecar_path(&self.data_dir, &name),
+                self.data_dir.clone(),
+            ])
+            .await?;
+        self.uncache(id);
+        if let Some(previous) = previous
+            && previous != digest
+        {
+            blobs::release(&self.data_dir, &previous).await?;
+        }
+        Ok(size)
+    }
+
+    /// Stores the contents of paste `source` as those of the new paste `id`,
+    /// with a link to the blob they're in rather than a copy, see [`blobs`].
+    /// Returns the size of the paste.
+    #[tracing::instrument(skip_all, fields(%source, %id))]
+    async fn share_paste(
+        &self,
+        source: &uuid::Uuid,
+        id: &uuid::Uuid,
+        limit: Option<u64>,
+    ) -> anyhow::Result<u64> {
+        let (source_name, name) = (source.to_string(), id.to_string());
+        let path = self.data_dir.join(&name);
+        let reading = self.write_lock(source).read().await;
+        let size = tokio::fs::metadata(self.data_dir.join(&source_name))
+            .await?
+            .len();
+        if let Some(limit) = limit
+            && size > limit
+        {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::FileTooLarge,
+                format!("Pastes are limited to {limit} bytes"),
+            )
+            .into());
+        }
+        let digest = checksum::load(&self.data_dir, &source_name).await?;
+        let linked = match digest.clone() {
+            Some(digest) => {
+                let blob = blobs::blob_path(&self.data_dir, &digest);
+                tokio::fs::hard_link(blob, &path).await.map(|()| digest)
+            }
+            None => Err(std::io::ErrorKind::NotFound.into()),
+        };
+        drop(reading);
+        let digest = match linked {
+            Ok(digest) => digest,
+            // Without a blob, e.g. without a checksum, the contents are
+            // copied after all.
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                let file = tokio::fs::File::open(self.data_dir.join(&source_name)).await?;
+                let body = ChecksumReader::verifying(file, digest);
+                return self.write_paste(id, body, Existing::Fail, limit).await;
+            }
+            Err(e) => return Err(e.into()),
+        };
+        let writing = self.write_lock(id).write().await;
+        if let Err(e) = checksum::store(&self.data_dir, &name, &digest).await {
+            tokio::fs::remove_file(&path).await.ok();
+            return Err(e.into());
+        }
+        drop(writing);
+        self.syncer
+            .written(vec![
+                checksum::sidecar_path(&self.data_dir, &name),
+                self.data_dir.clone(),
+            ])
+            .await?;
+        Ok(size)
+    }
+
+    /// Stores the metadata of paste `id`, syncing it like its contents.
+    async fn store_metadata(&self, id: &uuid::Uuid, metadata: &Metadata) -> std::io::Result<()> {
+        let name = id.to_string();
+        let writing = self.write_lock(id).write().await;
+        meta::store(&self.data_dir, &name, metadata).await?;
+        drop(writing);
+        self.syncer
+            .written(vec![
+                meta::path(&self.data_dir, &name),
+                self.data_dir.clone(),
+            ])
+            .await
+    }
+
+    /// Copies `reader` into the new `file`, through io_uring if there's a
+    /// ring.
+    async fn write_file(
+        &self,
+        reader: &mut (impl AsyncRead + Unpin),
+        file: &mut tokio::fs::File,
+    ) -> std::io::Result<u64> {
+        #[cfg(feature = "io-uring")]
+        if let Some(ring) = &self.ring {
+            let file = file.try_clone().await?.into_std().await;
+            let mut file = crate::uring::File::new(ring.clone(), file);
+            return self.copy(reader, &mut file).await;
+        }
+        self.copy(reader, file).await
+    }
+
+    /// Like [`tokio::io::copy`], with a pooled buffer.
+    async fn copy(
+        &self,
+        reader: &mut (impl AsyncRead + Unpin),
+        file: &mut (impl AsyncWrite + Unpin),
+    ) -> std::io::Result<u64> {
+        let mut buffer = self.buffers.take();
+        let mut copied = 0;
+        let result = loop {
+            buffer.clear();
+            match reader.read_buf(&mut buffer).await {
+                Ok(0) => break file.flush().await.map(|()| copied),
+                Ok(read) => copied += read as u64,
+                Err(e) => break Err(e),
+            }
+            if let Err(e) = file.write_all(&buffer).await {
+                break Err(e);
+            }
+        };
+        self.buffers.put(buffer);
+        result
+    }
+
+    pub fn paste_ids_on_disk(&self) -> anyhow::Result<Vec<uuid::Uuid>> {
+        paste_ids_in(&self.data_dir)
+    }
+}
+
+/// Pastes stat'ed per trip to the blocking pool by [`Service::warm_index`].
+const SCAN_BATCH: usize = 1024;
+
+/// Pastes up to this size are read whole by [`Service::read`], whether or
+/// not they're cached.
+const SMALL_PASTE: u64 = 64 * 1024;
+
+/// A paste opened by [`open_blocking`].
+enum Opened {
+    /// The checked contents of a small paste.
+    Whole(bytes::Bytes),
+    /// A larger paste, and its checksum to check it against while reading.
+    File(std::fs::File, Option<Vec<u8>>),
+}
+
+/// Opens paste `name`, reading it whole if it's at most `small` bytes.
+fn open_blocking(data_dir: &Path, name: &str, small: u64) -> std::io::Result<Opened> {
+    let expected = match std::fs::read_to_string(checksum::sidecar_path(data_dir, name)) {
+        Ok(contents) => checksum::parse_sidecar(&contents),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+        Err(e) => return Err(e),
+    };
+    let mut file = std::fs::File::open(data_dir.join(name))?;
+    let size = file.metadata()?.len();
+    if size > small {
+        return Ok(Opened::File(file, expected));
+    }
+    let mut contents = Vec::with_capacity(size as usize);
+    std::io::Read::read_to_end(&mut file, &mut contents)?;
+    if expected.is_some_and(|expected| checksum::digest(&contents) != expected) {
+        return Err(checksum::mismatch());
+    }
+    Ok(Opened::Whole(contents.into()))
+}
+
+/// The modification time, size and ID of every paste, newest first.
+fn newest_first(data_dir: &Path) -> anyhow::Result<Vec<(std::time::SystemTime, u64, uuid::Uuid)>> {
+    let mut pastes = Vec::new();
+    for id in paste_ids_in(data_dir)? {
+        let metadata = match std::fs::metadata(data_dir.join(id.to_string())) {
+            Ok(metadata) => metadata,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e.into()),
+        };
+        pastes.push((metadata.modified()?, metadata.len(), id));
+    }
+    pastes.sort_unstable_by(|a, b| b.cmp(a));
+    Ok(pastes)
+}
+
+fn paste_ids_in(data_dir: &Path) -> anyhow::Result<Vec<uuid::Uuid>> {
+    let mut ids = Vec::new();
+    for entry in std::fs::read_dir(data_dir)? {
+        let entry = entry?;
+        if !entry.file_type()?.is_file() {
+            continue;
+        }
+        if let Some(id) = entry
+            .file_name()
+            .to_str()
+            .and_then(|name| uuid::Uuid::parse_str(name).ok())
+        {
+            ids.push(id);
+        }
+    }
+    Ok(ids)
+}
+
+#[tokio::test]
+async fn test_budget_spares_pastes_owned_by_metadata() {
+    use crate::{PastebinBuilder, testing::TempDir};
+
+    let dir = TempDir::new().unwrap();
+    let (service, _) = PastebinBuilder::new(dir.path())
+        .user("alice", "secret")
+        .build()
+        .unwrap();
+    let auth = Some(("alice".to_owned(), "secret".to_owned()));
+    let owned = service.create(&b"owned"[..], auth).await.unwrap();
+
+    // Reopened with a lost state file, the paste is only owned by its
+    // metadata.
+    let (service, _) = PastebinBuilder::new(dir.path())
+        .state_file(dir.path().join("lost.json"))
+        .storage_budget(8)
+        .build()
+        .unwrap();
+    service.warm_index().await.unwrap();
+    let error = service.create(&b"anon"[..], None).await.unwrap_err();
+    assert_eq!(error.to_string(), "Storage budget exceeded");
+    assert!(service.exists(&owned.parse().unwrap()));
+}
+
+#[tokio::test]
+async fn test_purge_spares_pastes_owned_by_metadata() {
+    use crate::{PastebinBuilder, testing::TempDir};
+
+    let dir = TempDir::new().unwrap();
+    let (service, _) = PastebinBuilder::new(dir.path())
+        .user("alice", "secret")
+        .build()
+        .unwrap();
+    let auth = Some(("alice".to_owned(), "secret".to_owned()));
+    let owned = service.create(&b"owned"[..], auth).await.unwrap();
+    let anonymous = service.create(&b"anonymous"[..], None).await.unwrap();
+
+    let (service, _) = PastebinBuilder::new(dir.path())
+        .state_file(dir.path().join("lost.json"))
+        .build()
+        .unwrap();
+    let cutoff = std::time::SystemTime::now() + std::time::Duration::from_secs(60);
+    assert_eq!(service.purge_anonymous(cutoff).await.unwrap(), 1);
+    assert!(service.exists(&owned.parse().unwrap()));
+    assert!(!service.exists(&anonymous.parse().unwrap()));
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn test_forks_share_the_contents() {
+    use crate::testing::TestServer;
+
+    let server = TestServer::start_with(|builder| builder.user("alice", "secret"))
+        .await
+        .unwrap();
+    let options = Options {
+        title: Some("Original".to_owned()),
+        ..Default::default()
+    };
+    let source = server
+        .service
+        .create_with_options(&b"shared contents"[..], None, options)
+        .await
+        .unwrap();
+    let fork = server
+        .client()
+        .post(server.url(&format!("/paste/{source}/fork")))
+        .basic_auth("alice", Some("secret"))
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    let fork = fork.trim();
+    assert_eq!(
+        reqwest::get(fork).await.unwrap().text().await.unwrap(),
+        "shared contents"
+    );
+    let id: uuid::Uuid = fork.rsplit('/').next().unwrap().parse().unwrap();
+    assert!(server.service.owns("alice", &id));
+    let metadata = server.service.metadata(&id).await.unwrap().unwrap();
+    assert_eq!(metadata.title.as_deref(), Some("Original"));
+
+    // Both pastes are links to one blob.
+    let blob = blobs::blob_path(server.data_dir(), &checksum::digest(b"shared contents"));
+    let links = || std::os::unix::fs::MetadataExt::nlink(&std::fs::metadata(&blob).unwrap());
+    assert_eq!(links(), 3);
+    server
+        .service
+        .remove(&source.parse().unwrap())
+        .await
+        .unwrap();
+    assert_eq!(links(), 2);
+    assert_eq!(
+        reqwest::get(fork).await.unwrap().text().await.unwrap(),
+        "shared contents"
+    );
+
+    let missing = server
+        .client()
+        .post(server.url(&format!("/paste/{}/fork", uuid::Uuid::nil())))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(missing.status(), reqwest::StatusCode::NOT_FOUND);
 }
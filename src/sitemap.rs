@@ -0,0 +1,74 @@
+//! `/sitemap.xml`, listing the public pastes for search engines.
+
+use std::{fmt::Write, sync::Arc};
+
+use axum::{
+    extract::{Query, Request, State},
+    http::{StatusCode, header},
+    response::{IntoResponse, Response},
+};
+
+use crate::{client, html::escape, public, service::Service};
+
+/// URLs per sitemap, the maximum allowed by the sitemaps protocol. Beyond
+/// that `/sitemap.xml` becomes an index of numbered pages.
+const PAGE_SIZE: usize = 50_000;
+
+#[derive(serde::Deserialize)]
+pub struct PageQuery {
+    /// Page of the sitemap, starting at 1.
+    page: Option<usize>,
+}
+
+pub async fn get(
+    State(service): State<Arc<Service>>,
+    State(cache): State<Arc<public::Cache>>,
+    State(client): State<Arc<client::Config>>,
+    Query(query): Query<PageQuery>,
+    request: Request,
+) -> Response {
+    let (parts, _) = request.into_parts();
+    let Some(base_url) = client::base_url(&parts, &client) else {
+        return (StatusCode::BAD_REQUEST, "Missing Host header").into_response();
+    };
+    let pastes = match cache.pastes(&service).await {
+        Ok(pastes) => pastes,
+        Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    };
+    let base_url = escape(&base_url);
+
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    let pages = pastes.len().div_ceil(PAGE_SIZE);
+    match query.page {
+        None if pages > 1 => {
+            xml.push_str("<sitemapindex xmlns=\"http://www.sitemaps.org/schemas/sitemap/0.9\">\n");
+            for page in 1..=pages {
+                writeln!(
+                    xml,
+                    "<sitemap><loc>{base_url}/sitemap.xml?page={page}</loc></sitemap>"
+                )
+                .unwrap();
+            }
+            xml.push_str("</sitemapindex>\n");
+        }
+        page => {
+            let page = page.unwrap_or(1);
+            if page == 0 || (page > pages && page > 1) {
+                return (StatusCode::NOT_FOUND, "No such page").into_response();
+            }
+            xml.push_str("<urlset xmlns=\"http://www.sitemaps.org/schemas/sitemap/0.9\">\n");
+            for paste in pastes.iter().skip((page - 1) * PAGE_SIZE).take(PAGE_SIZE) {
+                let written = chrono::DateTime::<chrono::Utc>::from(paste.written);
+                writeln!(
+                    xml,
+                    "<url><loc>{base_url}/paste/{}</loc><lastmod>{}</lastmod></url>",
+                    paste.id,
+                    written.format("%Y-%m-%dT%H:%M:%SZ"),
+                )
+                .unwrap();
+            }
+            xml.push_str("</urlset>\n");
+        }
+    }
+    ([(header::CONTENT_TYPE, "application/xml")], xml).into_response()
+}
@@ -0,0 +1,111 @@
+//! Server-Sent Events streams of paste changes, a lighter alternative to
+//! the WebSocket for clients that only listen.
+//!
+//! With --read-timeout, streams are cut off after the timeout like any other
+//! response, and they all end on shutdown; `EventSource` clients reconnect
+//! by themselves.
+
+use std::{collections::HashSet, convert::Infallible, sync::Arc};
+
+use axum::{
+    extract::{Path, State},
+    http::{StatusCode, header},
+    response::{
+        IntoResponse, Response,
+        sse::{self, KeepAlive, Sse},
+    },
+};
+use futures::{Stream, StreamExt};
+use tokio_util::sync::CancellationToken;
+use uuid::Uuid;
+
+use crate::{
+    auth::BasicAuth,
+    events::{Event, Subscription},
+    service::Service,
+};
+
+/// The events of pastes for which `relevant` holds, as SSE events named
+/// after them, e.g. `paste.updated`.
+fn stream(
+    events: Subscription,
+    relevant: impl FnMut(&Event) -> bool + Send + 'static,
+) -> impl Stream<Item = Result<sse::Event, Infallible>> {
+    futures::stream::unfold(
+        (events, relevant),
+        |(mut events, mut relevant)| async move {
+            loop {
+                let emitted = events.recv().await?;
+                let Some((name, id)) = emitted.event.paste() else {
+                    continue;
+                };
+                if !relevant(&emitted.event) {
+                    continue;
+                }
+                let data = serde_json::json!({"id": id, "timestamp": emitted.timestamp()});
+                let event = sse::Event::default().event(name).data(data.to_string());
+                return Some((Ok(event), (events, relevant)));
+            }
+        },
+    )
+}
+
+fn respond(
+    stream: impl Stream<Item = Result<sse::Event, Infallible>> + Send + 'static,
+    shutdown: CancellationToken,
+) -> Response {
+    let stream = stream.take_until(shutdown.cancelled_owned());
+    (
+        // Keeps nginx from holding back events.
+        [("x-accel-buffering", "no"), ("cache-control", "no-cache")],
+        Sse::new(stream).keep_alive(KeepAlive::default()),
+    )
+        .into_response()
+}
+
+/// `GET /paste/{id}/events`
+pub async fn paste(
+    State(service): State<Arc<Service>>,
+    State(shutdown): State<CancellationToken>,
+    Path(id): Path<Uuid>,
+) -> Response {
+    if !service.exists(&id) {
+        return (StatusCode::NOT_FOUND, "Paste not found").into_response();
+    }
+    let events = service.events().subscribe("event stream");
+    let stream = stream(events, move |event| {
+        event.paste().is_some_and(|(_, changed)| changed == id)
+    });
+    respond(stream, shutdown)
+}
+
+/// `GET /pastes/events`, the changes to the pastes of the authenticated
+/// user.
+pub async fn user(
+    State(service): State<Arc<Service>>,
+    State(shutdown): State<CancellationToken>,
+    auth: BasicAuth,
+) -> Response {
+    // Subscribe first so that pastes created meanwhile aren't missed.
+    let events = service.events().subscribe("event stream");
+    let owned = match service.list(&auth.username, &auth.password) {
+        Ok(ids) => ids,
+        Err(e) => {
+            return (
+                StatusCode::UNAUTHORIZED,
+                [(header::WWW_AUTHENTICATE, "Basic")],
+                e.to_string(),
+            )
+                .into_response();
+        }
+    };
+    let mut owned: HashSet<Uuid> = owned.iter().filter_map(|id| id.parse().ok()).collect();
+    let username = auth.username;
+    let stream = stream(events, move |event| match *event {
+        Event::PasteCreated(id) if service.owns(&username, &id) => owned.insert(id),
+        Event::PasteUpdated(id) => owned.contains(&id),
+        Event::PasteDeleted(id) | Event::PasteExpired(id) => owned.remove(&id),
+        _ => false,
+    });
+    respond(stream, shutdown)
+}
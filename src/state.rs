@@ -1,6 +1,16 @@
-use std::{collections::HashMap, io::Write, path::Path};
+use std::{
+    collections::{HashMap, HashSet},
+    hash::{BuildHasher, RandomState},
+    io::Write,
+    path::{Path, PathBuf},
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::Duration,
+};
 
-use hex;
+use parking_lot::RwLock;
 use rand::distr::SampleString;
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use sha2::Digest;
@@ -20,6 +30,13 @@ pub struct User {
     #[serde(deserialize_with = "deserialize_hex")]
     password_hash: Vec<u8>,
     pub paste_ids: Vec<String>,
+    /// Overrides the limit on requests authenticated as this user set with
+    /// --user-rate.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rate_limit: Option<crate::rate_limit::Rate>,
+    /// Where the user is reminded of pastes about to expire.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub email: Option<String>,
 }
 
 fn serialize_hex<S>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error>
@@ -47,19 +64,13 @@ impl State {
     }
 
     pub fn dump(&self, path: &Path) -> anyhow::Result<()> {
-        let mut tmp_name = path.file_name().unwrap().to_owned();
-        tmp_name.push("~");
-        let tmp_path = path.with_file_name(tmp_name);
-
-        let writer = std::fs::File::create(&tmp_path)?;
-        serde_json::to_writer_pretty(writer, &self)?;
-        std::fs::rename(tmp_path, path)?;
-        Ok(())
+        write_atomically(path, &serde_json::to_vec_pretty(self)?)
     }
 
+    #[cfg(test)]
     pub fn create(&mut self, username: &str, password: &str) -> &User {
         let salt = gen_salt();
-        let hash = hashed_password(&password, &salt);
+        let hash = hashed_password(password, &salt);
 
         self.users.insert(
             username.to_owned(),
@@ -68,24 +79,247 @@ impl State {
                 password_hash: hash,
                 password_salt: salt,
                 paste_ids: Vec::new(),
+                rate_limit: None,
+                email: None,
             },
         );
         self.users.get(username).unwrap()
     }
 
-    pub fn auth(&self, username: &str, password: &str) -> Option<&User> {
-        let user = self.users.get(username)?;
-        let hash = hashed_password(password, &user.password_salt);
-        (hash == user.password_hash).then_some(user)
+    pub fn users_mut(&mut self) -> impl Iterator<Item = &mut User> {
+        self.users.values_mut()
     }
 
-    pub fn auth_mut(&mut self, username: &str, password: &str) -> Option<&mut User> {
-        let user = self.users.get_mut(username)?;
-        let hash = hashed_password(password, &user.password_salt);
-        (hash == user.password_hash).then_some(user)
+    /// Looks up a user without checking their password, for maintenance
+    /// tasks run by the operator.
+    pub fn user_mut(&mut self, username: &str) -> Option<&mut User> {
+        self.users.get_mut(username)
     }
 }
 
+pub fn write_atomically(path: &Path, contents: &[u8]) -> anyhow::Result<()> {
+    let mut tmp_name = path.file_name().unwrap().to_owned();
+    tmp_name.push("~");
+    let tmp_path = path.with_file_name(tmp_name);
+
+    std::fs::write(&tmp_path, contents)?;
+    std::fs::rename(tmp_path, path)?;
+    Ok(())
+}
+
+/// Shards users are spread over.
+const SHARDS: usize = 16;
+
+/// The [`State`] of a running instance. Users are spread over shards locked
+/// separately, so that requests of unrelated users don't wait on each other,
+/// and shards are only locked for work in memory: callbacks passed in must
+/// not touch the disk.
+///
+/// The owner of every paste is also kept by paste ID, so that ownership is
+/// checked without going through the user's pastes. Pastes are only given
+/// and taken away through [`Users::own`], [`Users::release`] and
+/// [`Users::disown`], which keep the two in step; callbacks must leave
+/// `paste_ids` alone.
+pub struct Users {
+    hasher: RandomState,
+    shards: Box<[RwLock<HashMap<Username, User>>]>,
+    owners: RwLock<HashMap<String, Username>>,
+    /// Counts changes to users, so that the state file is only saved again
+    /// when there are any.
+    changes: AtomicU64,
+}
+
+impl Users {
+    pub fn new(state: State) -> Self {
+        let users = Self {
+            hasher: RandomState::new(),
+            shards: (0..SHARDS).map(|_| RwLock::default()).collect(),
+            owners: RwLock::default(),
+            changes: AtomicU64::new(0),
+        };
+        let mut owners = users.owners.write();
+        for (username, user) in state.users {
+            for id in &user.paste_ids {
+                owners.entry(id.clone()).or_insert_with(|| username.clone());
+            }
+            users.shard(&username).write().insert(username, user);
+        }
+        drop(owners);
+        users
+    }
+
+    fn shard(&self, username: &str) -> &RwLock<HashMap<Username, User>> {
+        &self.shards[self.hasher.hash_one(username) as usize % SHARDS]
+    }
+
+    /// Creates a user, unless there's one by that name already.
+    pub fn create(&self, username: &str, password: &str) -> bool {
+        let salt = gen_salt();
+        let hash = hashed_password(password, &salt);
+        let mut shard = self.shard(username).write();
+        if shard.contains_key(username) {
+            return false;
+        }
+        shard.insert(
+            username.to_owned(),
+            User {
+                username: username.to_owned(),
+                password_hash: hash,
+                password_salt: salt,
+                paste_ids: Vec::new(),
+                rate_limit: None,
+                email: None,
+            },
+        );
+        self.changes.fetch_add(1, Ordering::Relaxed);
+        true
+    }
+
+    /// Calls `f` with a user, looked up without checking their password.
+    pub fn user<T>(&self, username: &str, f: impl FnOnce(&User) -> T) -> Option<T> {
+        self.shard(username).read().get(username).map(f)
+    }
+
+    /// Like [`Users::user`], for changing the user.
+    pub fn user_mut<T>(&self, username: &str, f: impl FnOnce(&mut User) -> T) -> Option<T> {
+        let changed = self.shard(username).write().get_mut(username).map(f);
+        if changed.is_some() {
+            self.changes.fetch_add(1, Ordering::Relaxed);
+        }
+        changed
+    }
+
+    /// How many times users were changed. Changes made after reading this
+    /// may or may not be in a [`Users::to_json`] taken afterwards.
+    pub fn changes(&self) -> u64 {
+        self.changes.load(Ordering::Relaxed)
+    }
+
+    /// Calls `f` with a user if `password` is theirs.
+    pub fn auth<T>(&self, username: &str, password: &str, f: impl FnOnce(&User) -> T) -> Option<T> {
+        let hash = self.hash(username, password)?;
+        let shard = self.shard(username).read();
+        shard
+            .get(username)
+            .filter(|user| user.password_hash == hash)
+            .map(f)
+    }
+
+    /// Hashes `password` with the salt of a user, outside of any lock as
+    /// it's the slow part of checking credentials. Salts never change.
+    fn hash(&self, username: &str, password: &str) -> Option<Vec<u8>> {
+        let salt = self.user(username, |user| user.password_salt.clone())?;
+        Some(hashed_password(password, &salt))
+    }
+
+    pub fn contains(&self, username: &str) -> bool {
+        self.shard(username).read().contains_key(username)
+    }
+
+    pub fn user_count(&self) -> usize {
+        self.shards.iter().map(|shard| shard.read().len()).sum()
+    }
+
+    /// IDs of all pastes owned by some user.
+    pub fn owned_paste_ids(&self) -> HashSet<uuid::Uuid> {
+        let owners = self.owners.read();
+        owners
+            .keys()
+            .filter_map(|id| uuid::Uuid::parse_str(id).ok())
+            .collect()
+    }
+
+    /// The user who owns paste `id`, if any.
+    pub fn owner(&self, id: &str) -> Option<Username> {
+        self.owners.read().get(id).cloned()
+    }
+
+    pub fn owns(&self, username: &str, id: &str) -> bool {
+        self.owners
+            .read()
+            .get(id)
+            .is_some_and(|owner| owner == username)
+    }
+
+    /// Gives paste `id` to `username`, returning whether there's such a
+    /// user.
+    pub fn own(&self, username: &str, id: &str) -> bool {
+        let owned = self.user_mut(username, |user| user.paste_ids.push(id.to_owned()));
+        if owned.is_some() {
+            self.owners
+                .write()
+                .insert(id.to_owned(), username.to_owned());
+        }
+        owned.is_some()
+    }
+
+    /// Takes paste `id` away from `username`, if theirs.
+    pub fn release(&self, username: &str, id: &str) {
+        {
+            let mut owners = self.owners.write();
+            if owners.get(id).is_some_and(|owner| owner == username) {
+                owners.remove(id);
+            }
+        }
+        self.user_mut(username, |user| user.paste_ids.retain(|owned| owned != id));
+    }
+
+    /// Removes paste `id` from whichever user owns it, returning their name.
+    pub fn disown(&self, id: &str) -> Option<Username> {
+        let owner = self.owners.write().remove(id)?;
+        self.user_mut(&owner, |user| user.paste_ids.retain(|owned| owned != id));
+        Some(owner)
+    }
+
+    /// The users in the format of [`State`], serialized with every shard
+    /// locked so that they're taken at a single point in time.
+    pub fn to_json(&self) -> anyhow::Result<Vec<u8>> {
+        #[derive(Serialize)]
+        struct Dump<'a> {
+            users: HashMap<&'a str, &'a User>,
+        }
+        let shards: Vec<_> = self.shards.iter().map(|shard| shard.read()).collect();
+        let users = shards
+            .iter()
+            .flat_map(|shard| shard.iter())
+            .map(|(username, user)| (username.as_str(), user))
+            .collect();
+        Ok(serde_json::to_vec_pretty(&Dump { users })?)
+    }
+
+    pub fn dump(&self, path: &Path) -> anyhow::Result<()> {
+        write_atomically(path, &self.to_json()?)
+    }
+}
+
+/// Saves the state of `service` to `path` every `interval` that it changed
+/// in, so that little is lost if the process is killed rather than shut
+/// down.
+pub fn spawn_save(
+    service: Arc<crate::service::Service>,
+    path: PathBuf,
+    interval: Duration,
+) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        // Changes are counted from when the state file was loaded.
+        let mut saved = 0;
+        loop {
+            tokio::time::sleep(interval).await;
+            let changes = service.state_changes();
+            if changes == saved {
+                continue;
+            }
+            let (service, path) = (service.clone(), path.clone());
+            let result = tokio::task::spawn_blocking(move || service.dump_state(&path)).await;
+            match result {
+                Ok(Ok(())) => saved = changes,
+                Ok(Err(e)) => tracing::error!("Saving state failed: {e}"),
+                Err(e) => tracing::error!("Saving state panicked: {e}"),
+            }
+        }
+    })
+}
+
 fn hashed_password(password: &str, salt: &str) -> Vec<u8> {
     let mut digest = sha2::Sha256::new();
     digest
@@ -106,3 +340,34 @@ fn gen_salt() -> String {
 fn test_gen_salt() {
     println!("Salt: {}", gen_salt());
 }
+
+#[test]
+fn test_users_round_trip() {
+    let dir = crate::testing::TempDir::new().unwrap();
+    let path = dir.path().join("db.json");
+    let users = Users::new(State::default());
+    assert!(users.create("alice", "secret"));
+    assert!(!users.create("alice", "other"));
+    assert!(users.own("alice", "a"));
+    assert!(!users.own("bob", "b"));
+    assert!(users.auth("alice", "wrong", |_| ()).is_none());
+    // Only actual changes make the state due for saving.
+    let changes = users.changes();
+    assert!(users.user_mut("bob", |_| ()).is_none());
+    assert_eq!(users.changes(), changes);
+    assert!(users.user_mut("alice", |_| ()).is_some());
+    assert_eq!(users.changes(), changes + 1);
+    users.dump(&path).unwrap();
+
+    let users = Users::new(State::load(&path).unwrap());
+    let ids = users.auth("alice", "secret", |user| user.paste_ids.clone());
+    assert_eq!(ids, Some(vec!["a".to_owned()]));
+    assert!(users.owns("alice", "a"));
+    assert_eq!(users.disown("a").as_deref(), Some("alice"));
+    assert!(!users.owns("alice", "a"));
+    assert_eq!(
+        users.auth("alice", "secret", |user| user.paste_ids.len()),
+        Some(0)
+    );
+    assert_eq!(users.user_count(), 1);
+}
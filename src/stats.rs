@@ -0,0 +1,26 @@
+use serde::Serialize;
+
+use crate::service::Service;
+
+/// Instance-wide statistics served on `/stats`. All of them are kept up to
+/// date as pastes are written, so collecting them doesn't touch the disk.
+#[derive(Debug, Serialize)]
+pub struct Stats {
+    pub pastes: usize,
+    /// Total size of all pastes, before deduplication.
+    pub bytes: u64,
+    pub users: usize,
+    pub created_last_24h: u64,
+    pub uptime_seconds: u64,
+}
+
+pub fn collect(service: &Service) -> Stats {
+    let (pastes, bytes) = service.usage_totals();
+    Stats {
+        pastes,
+        bytes,
+        users: service.user_count(),
+        created_last_24h: service.metrics().created_recently(service.clock().now()),
+        uptime_seconds: service.uptime().as_secs(),
+    }
+}
@@ -0,0 +1,86 @@
+//! Uploads over plain TCP, as made popular by termbin: whatever is sent to
+//! the --tcp-upload address becomes a paste, and its URL is sent back, so
+//! that `echo hi | nc host 9999` works without any HTTP client.
+
+use std::{net::SocketAddr, sync::Arc, time::Duration};
+
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+
+use crate::{client, rate_limit::Limits, service::Service};
+
+/// Time without data after which an upload is taken to be complete, since
+/// nc doesn't close its end of the connection by default.
+const IDLE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Largest paste accepted over TCP.
+const MAX_SIZE: usize = 10 * 1024 * 1024;
+
+/// Accepts uploads on `listener` until the process exits. They count
+/// against the create rate limit of their address.
+pub async fn serve(
+    listener: TcpListener,
+    service: Arc<Service>,
+    limits: Arc<Limits>,
+    client: Arc<client::Config>,
+) {
+    loop {
+        let (stream, peer) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                tracing::warn!("Accepting a TCP upload: {e}");
+                continue;
+            }
+        };
+        let (service, limits, client) = (service.clone(), limits.clone(), client.clone());
+        tokio::spawn(async move {
+            let reply = upload(stream, peer, &service, &limits, &client).await;
+            if let Err(e) = reply {
+                tracing::debug!("TCP upload from {peer}: {e}");
+            }
+        });
+    }
+}
+
+async fn upload(
+    mut stream: TcpStream,
+    peer: SocketAddr,
+    service: &Service,
+    limits: &Limits,
+    client: &client::Config,
+) -> std::io::Result<()> {
+    if let Err(throttled) = limits.create.check(peer.ip()) {
+        let seconds = throttled.retry_after.as_secs().max(1);
+        let reply = format!("Too many pastes, try again in {seconds} seconds\n");
+        return stream.write_all(reply.as_bytes()).await;
+    }
+    let mut contents = Vec::new();
+    loop {
+        match tokio::time::timeout(IDLE_TIMEOUT, stream.read_buf(&mut contents)).await {
+            Err(_) | Ok(Ok(0)) => break,
+            Ok(Ok(_)) if contents.len() > MAX_SIZE => {
+                let reply = format!("Pastes are limited to {MAX_SIZE} bytes\n");
+                return stream.write_all(reply.as_bytes()).await;
+            }
+            Ok(Ok(_)) => {}
+            Ok(Err(e)) => return Err(e),
+        }
+    }
+    if contents.is_empty() {
+        return stream.write_all(b"Nothing to paste\n").await;
+    }
+    let reply = match service.create(contents.as_slice(), None).await {
+        Ok(id) => match &client.base_url {
+            Some(base_url) => format!("{base_url}/paste/{id}\n"),
+            None => format!("{id}\n"),
+        },
+        Err(e) => {
+            tracing::error!("Creating a paste uploaded over TCP: {e}");
+            "Couldn't create the paste\n".to_owned()
+        }
+    };
+    stream.write_all(reply.as_bytes()).await?;
+    stream.shutdown().await
+}
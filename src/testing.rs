@@ -0,0 +1,176 @@
+//! Support for end-to-end tests, here and in applications embedding the
+//! server: [`TestServer`] runs an instance in-process on an ephemeral port,
+//! and [`TempDir`] holds data for one built with [`PastebinBuilder`] and
+//! called through `tower::ServiceExt` instead.
+
+use std::{
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use crate::{PastebinBuilder, service::Service};
+
+/// A data directory removed once dropped.
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    pub fn new() -> anyhow::Result<Self> {
+        let path = std::env::temp_dir().join(format!("pastebin-test-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir(&path)?;
+        Ok(Self(path))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        std::fs::remove_dir_all(&self.0).ok();
+    }
+}
+
+/// An instance serving HTTP on localhost until dropped, with its data in a
+/// [`TempDir`].
+pub struct TestServer {
+    pub service: Arc<Service>,
+    address: SocketAddr,
+    client: reqwest::Client,
+    server: tokio::task::JoinHandle<()>,
+    dir: TempDir,
+}
+
+impl TestServer {
+    /// An instance with the default options.
+    pub async fn start() -> anyhow::Result<Self> {
+        Self::start_with(|builder| builder).await
+    }
+
+    /// An instance with the options `configure` sets on its builder. The
+    /// index is warm by the time it's returned, as it would be on a server
+    /// that's been up for a while.
+    pub async fn start_with(
+        configure: impl FnOnce(PastebinBuilder) -> PastebinBuilder,
+    ) -> anyhow::Result<Self> {
+        let dir = TempDir::new()?;
+        let (service, router) = configure(PastebinBuilder::new(dir.path())).build()?;
+        service.warm_index().await?;
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+        let address = listener.local_addr()?;
+        let app = router.into_make_service_with_connect_info::<SocketAddr>();
+        let server = tokio::spawn(async move {
+            if let Err(e) = axum::serve(listener, app).await {
+                tracing::error!("Test server failed: {e}");
+            }
+        });
+        Ok(Self {
+            service,
+            address,
+            client: reqwest::Client::builder().no_proxy().build()?,
+            server,
+            dir,
+        })
+    }
+
+    /// The URL of `path` on the server, such as `/paste`.
+    pub fn url(&self, path: &str) -> String {
+        format!("http://{}{path}", self.address)
+    }
+
+    /// A client for requests to [`TestServer::url`]s, bypassing any proxy.
+    pub fn client(&self) -> &reqwest::Client {
+        &self.client
+    }
+
+    pub fn data_dir(&self) -> &Path {
+        self.dir.path()
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        self.server.abort();
+    }
+}
+
+#[tokio::test]
+async fn test_serves_end_to_end() {
+    let server = TestServer::start_with(|builder| builder.user("alice", "secret"))
+        .await
+        .unwrap();
+    let url = server
+        .client()
+        .post(server.url("/paste"))
+        .basic_auth("alice", Some("secret"))
+        .body("hello")
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    let url = url.trim();
+    assert!(url.starts_with(&server.url("/paste/")));
+    let contents = server.client().get(url).send().await.unwrap();
+    // Pastes can't run scripts or be sniffed as HTML.
+    let headers = contents.headers();
+    assert_eq!(
+        headers["content-security-policy"],
+        "default-src 'none'; sandbox"
+    );
+    assert_eq!(headers["x-content-type-options"], "nosniff");
+    assert_eq!(contents.text().await.unwrap(), "hello");
+
+    let delete = |password| {
+        server
+            .client()
+            .delete(url)
+            .basic_auth("alice", Some(password))
+            .send()
+    };
+    let response = delete("wrong").await.unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::UNAUTHORIZED);
+    let response = delete("secret").await.unwrap();
+    assert!(response.status().is_success());
+    let response = server.client().get(url).send().await.unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::NOT_FOUND);
+    let response = delete("secret").await.unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::NOT_FOUND);
+    assert!(server.data_dir().exists());
+}
+
+#[tokio::test]
+async fn test_uploads_may_only_be_gzipped() {
+    use tokio::io::AsyncWriteExt;
+
+    let server = TestServer::start().await.unwrap();
+    let mut encoder = async_compression::tokio::write::GzipEncoder::new(Vec::new());
+    encoder.write_all(b"hello").await.unwrap();
+    encoder.shutdown().await.unwrap();
+    let upload = |encoding, body| {
+        server
+            .client()
+            .post(server.url("/paste"))
+            .header("content-encoding", encoding)
+            .body(body)
+            .send()
+    };
+
+    let url = upload("gzip", encoder.into_inner()).await.unwrap();
+    let url = url.error_for_status().unwrap().text().await.unwrap();
+    let contents = server.client().get(url.trim()).send().await.unwrap();
+    assert_eq!(contents.text().await.unwrap(), "hello");
+
+    // A zstd frame of "hello".
+    let zstd = b"\x28\xb5\x2f\xfd\x20\x05\x29\x00\x00hello".to_vec();
+    let response = upload("zstd", zstd).await.unwrap();
+    assert_eq!(
+        response.status(),
+        reqwest::StatusCode::UNSUPPORTED_MEDIA_TYPE
+    );
+    assert_eq!(response.headers()["accept-encoding"], "gzip");
+}
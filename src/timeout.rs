@@ -0,0 +1,294 @@
+use std::{
+    io,
+    pin::Pin,
+    sync::{Arc, OnceLock},
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use axum::{
+    body::{Body, Bytes},
+    extract::{Request, State},
+    http::{Method, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use http_body::Frame;
+use tokio::time::{Instant, Sleep};
+
+/// Period over which the upload rate is averaged for `min_upload_rate`.
+const RATE_WINDOW: Duration = Duration::from_secs(10);
+
+/// Time allowed for a whole request, from its first byte until the last
+/// byte of the response.
+#[derive(Clone, Copy)]
+pub struct Config {
+    /// For requests without a body, such as reading a paste or archive.
+    pub read: Option<Duration>,
+    /// For `POST`, `PUT` and `PATCH` requests, including receiving their
+    /// body.
+    pub upload: Option<Duration>,
+    /// Bytes per second below which an upload is aborted, measured over
+    /// [`RATE_WINDOW`].
+    pub min_upload_rate: Option<u64>,
+}
+
+/// Why an upload was cut off, shared between the request body and the
+/// middleware answering for it.
+type Failure = Arc<OnceLock<&'static str>>;
+
+/// Middleware cutting off request and response bodies still streaming when
+/// the timeout runs out, and uploads that trickle in too slowly. An upload
+/// that was cut off is answered with `408 Request Timeout`; a response is
+/// aborted. Without a config, requests may take as long as they like.
+pub async fn middleware(
+    State(config): State<Option<Config>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let Some(config) = config else {
+        return next.run(request).await;
+    };
+    let upload = matches!(
+        *request.method(),
+        Method::POST | Method::PUT | Method::PATCH
+    );
+    let limit = if upload { config.upload } else { config.read };
+    let deadline = limit.map(|limit| Instant::now() + limit);
+    let failure = Failure::default();
+
+    let request = request.map(|mut body| {
+        if let Some(deadline) = deadline {
+            body = Deadline::body(body, deadline, Some(failure.clone()));
+        }
+        if let Some(rate) = config.min_upload_rate.filter(|_| upload) {
+            body = MinRate::body(body, rate, failure.clone());
+        }
+        body
+    });
+    let response = next.run(request).await;
+    if let Some(reason) = failure.get() {
+        return (StatusCode::REQUEST_TIMEOUT, *reason).into_response();
+    }
+    match deadline {
+        Some(deadline) => response.map(|body| Deadline::body(body, deadline, None)),
+        None => response,
+    }
+}
+
+fn fail(
+    failure: Option<&Failure>,
+    reason: &'static str,
+) -> Poll<Option<Result<Frame<Bytes>, axum::Error>>> {
+    if let Some(failure) = failure {
+        failure.set(reason).ok();
+    }
+    let error = io::Error::new(io::ErrorKind::TimedOut, reason);
+    Poll::Ready(Some(Err(axum::Error::new(error))))
+}
+
+/// A body that fails once its deadline has passed.
+struct Deadline {
+    inner: Body,
+    sleep: Pin<Box<Sleep>>,
+    expired: bool,
+    /// Set when the deadline interrupted a request body.
+    failure: Option<Failure>,
+}
+
+impl Deadline {
+    fn body(inner: Body, deadline: Instant, failure: Option<Failure>) -> Body {
+        Body::new(Self {
+            inner,
+            sleep: Box::pin(tokio::time::sleep_until(deadline)),
+            expired: false,
+            failure,
+        })
+    }
+}
+
+impl http_body::Body for Deadline {
+    type Data = Bytes;
+    type Error = axum::Error;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Bytes>, axum::Error>>> {
+        if self.expired {
+            return Poll::Ready(None);
+        }
+        if self.sleep.as_mut().poll(cx).is_pending() {
+            return Pin::new(&mut self.inner).poll_frame(cx);
+        }
+        self.expired = true;
+        fail(self.failure.as_ref(), "Request timed out")
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.expired || self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> http_body::SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+/// A request body that fails if less than `rate` bytes per second arrived
+/// during a [`RATE_WINDOW`].
+struct MinRate {
+    inner: Body,
+    rate: u64,
+    received: u64,
+    window: Pin<Box<Sleep>>,
+    expired: bool,
+    failure: Failure,
+}
+
+impl MinRate {
+    fn body(inner: Body, rate: u64, failure: Failure) -> Body {
+        Body::new(Self {
+            inner,
+            rate,
+            received: 0,
+            window: Box::pin(tokio::time::sleep(RATE_WINDOW)),
+            expired: false,
+            failure,
+        })
+    }
+}
+
+impl http_body::Body for MinRate {
+    type Data = Bytes;
+    type Error = axum::Error;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Bytes>, axum::Error>>> {
+        if self.expired {
+            return Poll::Ready(None);
+        }
+        if self.window.as_mut().poll(cx).is_ready() {
+            if self.received < self.rate * RATE_WINDOW.as_secs() {
+                self.expired = true;
+                return fail(Some(&self.failure), "Upload too slow");
+            }
+            self.received = 0;
+            self.window.as_mut().reset(Instant::now() + RATE_WINDOW);
+            // Registers the new window with the waker.
+            let _ = self.window.as_mut().poll(cx);
+        }
+        let frame = Pin::new(&mut self.inner).poll_frame(cx);
+        if let Poll::Ready(Some(Ok(frame))) = &frame
+            && let Some(data) = frame.data_ref()
+        {
+            self.received += data.len() as u64;
+        }
+        frame
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.expired || self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> http_body::SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+#[cfg(test)]
+fn test_app(config: Config) -> axum::Router {
+    use axum::routing::{get, post};
+
+    axum::Router::new()
+        .route(
+            "/upload",
+            post(|body: Bytes| async move { body.len().to_string() }),
+        )
+        .route(
+            "/download",
+            get(|| async { Body::from_stream(futures::stream::pending::<io::Result<Bytes>>()) }),
+        )
+        .layer(axum::middleware::from_fn_with_state(
+            Some(config),
+            middleware,
+        ))
+}
+
+/// A body of `chunks` chunks of `size` bytes, a second apart, or ending in
+/// one that never comes if `stall`.
+#[cfg(test)]
+fn test_upload(chunks: usize, size: usize, stall: bool) -> Request {
+    use futures::StreamExt;
+
+    let sent = futures::stream::iter(0..chunks).then(move |_| async move {
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        io::Result::Ok(Bytes::from(vec![b'a'; size]))
+    });
+    let body = match stall {
+        true => Body::from_stream(sent.chain(futures::stream::pending())),
+        false => Body::from_stream(sent),
+    };
+    Request::post("/upload").body(body).unwrap()
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_requests_are_cut_off_at_their_deadline() {
+    use tower::ServiceExt;
+
+    let app = test_app(Config {
+        read: Some(Duration::from_secs(5)),
+        upload: Some(Duration::from_secs(30)),
+        min_upload_rate: None,
+    });
+    let response = app.clone().oneshot(test_upload(20, 1, false)).await;
+    assert_eq!(
+        axum::body::to_bytes(response.unwrap().into_body(), 100)
+            .await
+            .unwrap(),
+        "20"
+    );
+
+    let started = Instant::now();
+    let response = app.clone().oneshot(test_upload(1, 1, true)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::REQUEST_TIMEOUT);
+    assert_eq!(started.elapsed(), Duration::from_secs(30));
+
+    // Responses get the shorter timeout of reads, and are aborted.
+    let started = Instant::now();
+    let response = app.oneshot(Request::get("/download").body(Body::empty()).unwrap());
+    let body = axum::body::to_bytes(response.await.unwrap().into_body(), 100).await;
+    assert!(body.is_err());
+    assert_eq!(started.elapsed(), Duration::from_secs(5));
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_uploads_trickling_in_are_cut_off() {
+    use tower::ServiceExt;
+
+    let app = test_app(Config {
+        read: None,
+        upload: None,
+        min_upload_rate: Some(100),
+    });
+    // 200 bytes a second keeps going past a few windows.
+    let response = app.clone().oneshot(test_upload(35, 200, false)).await;
+    let body = axum::body::to_bytes(response.unwrap().into_body(), 100).await;
+    assert_eq!(body.unwrap(), "7000");
+
+    for (size, stall) in [(10, false), (200, true)] {
+        let started = Instant::now();
+        let response = app
+            .clone()
+            .oneshot(test_upload(35, size, stall))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::REQUEST_TIMEOUT);
+        let body = axum::body::to_bytes(response.into_body(), 100)
+            .await
+            .unwrap();
+        assert_eq!(body, "Upload too slow");
+        assert!(started.elapsed() <= RATE_WINDOW * 5);
+    }
+}
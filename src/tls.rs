@@ -0,0 +1,51 @@
+use std::{
+    path::PathBuf,
+    time::{Duration, SystemTime},
+};
+
+use axum_server::tls_rustls::RustlsConfig;
+
+/// How often the certificate files are checked for changes.
+pub const RELOAD_CHECK_INTERVAL: Duration = Duration::from_secs(30);
+
+/// PEM files holding the server's certificate chain and private key.
+#[derive(Clone)]
+pub struct Files {
+    pub cert: PathBuf,
+    pub key: PathBuf,
+}
+
+impl Files {
+    pub async fn load(&self) -> anyhow::Result<RustlsConfig> {
+        RustlsConfig::from_pem_file(&self.cert, &self.key)
+            .await
+            .map_err(|e| anyhow::anyhow!("Couldn't load TLS certificate: {e}"))
+    }
+
+    fn modified(&self) -> Option<(SystemTime, SystemTime)> {
+        let modified = |path| std::fs::metadata(path).and_then(|m| m.modified()).ok();
+        Some((modified(&self.cert)?, modified(&self.key)?))
+    }
+}
+
+/// Reloads `config` from `files` whenever either file changes, so renewed
+/// certificates are picked up without a restart. A failed reload, e.g. while
+/// only one of the files has been replaced, keeps the current certificate
+/// and is retried at the next change.
+pub fn spawn_reload(config: RustlsConfig, files: Files) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut last_modified = files.modified();
+        loop {
+            tokio::time::sleep(RELOAD_CHECK_INTERVAL).await;
+            let modified = files.modified();
+            if modified == last_modified {
+                continue;
+            }
+            last_modified = modified;
+            match config.reload_from_pem_file(&files.cert, &files.key).await {
+                Ok(()) => tracing::info!("Reloaded TLS certificate {}", files.cert.display()),
+                Err(e) => tracing::error!("Couldn't reload TLS certificate: {e}"),
+            }
+        }
+    })
+}
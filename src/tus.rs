@@ -0,0 +1,629 @@
+//! Resumable uploads following the tus protocol (https://tus.io), for pastes
+//! too large to send in one go over a flaky connection.
+//!
+//! `POST /uploads` announces one with its `Upload-Length`, and the contents
+//! follow as `PATCH /uploads/{id}` requests, each starting at the offset
+//! that `HEAD /uploads/{id}` reports. Once all of it arrived, the paste is
+//! created and its URL given in the `x-pastebin-paste` header. Uploads are
+//! kept under the data directory's `uploads` subdirectory, and abandoned
+//! ones are dropped after a day.
+
+use std::{
+    collections::HashSet,
+    path::{Path as FsPath, PathBuf},
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use axum::{
+    Router,
+    extract::{Path, Request, State},
+    http::{HeaderMap, HeaderValue, StatusCode, header, request::Parts},
+    response::{IntoResponse, Response},
+    routing::{head, post},
+};
+use base64::Engine;
+use futures::StreamExt;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use tokio::io::AsyncWriteExt;
+use uuid::Uuid;
+
+use crate::{
+    auth::{self, BasicAuth},
+    client,
+    service::{self, Service},
+};
+
+const VERSION: &str = "1.0.0";
+
+/// Largest upload accepted, whatever the size limits.
+const MAX_SIZE: u64 = 1024 * 1024 * 1024;
+
+/// How long an upload may go without progress before it's dropped.
+const EXPIRY: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Header carrying the URL of the paste a finished upload created.
+const PASTE_HEADER: &str = "x-pastebin-paste";
+
+/// An upload in progress, stored as `uploads/{id}.json` next to the data
+/// received so far.
+#[derive(Deserialize, Serialize)]
+struct Upload {
+    length: u64,
+    /// The user the paste is created for, who alone may continue the upload.
+    owner: Option<String>,
+    options: service::Options,
+    /// The paste created once all of the data arrived.
+    paste: Option<Uuid>,
+}
+
+/// The uploads a `PATCH` or `DELETE` is working on, which others must wait
+/// for.
+#[derive(Default)]
+pub struct Busy(Mutex<HashSet<Uuid>>);
+
+/// Marks an upload as busy until dropped.
+struct Claim<'a> {
+    busy: &'a Busy,
+    id: Uuid,
+}
+
+impl Busy {
+    fn claim(&self, id: Uuid) -> Option<Claim<'_>> {
+        self.0.lock().insert(id).then_some(Claim { busy: self, id })
+    }
+}
+
+impl Drop for Claim<'_> {
+    fn drop(&mut self) {
+        self.busy.0.lock().remove(&self.id);
+    }
+}
+
+pub fn routes() -> Router<crate::AppState> {
+    Router::new()
+        .route("/uploads", post(create).options(options))
+        .route(
+            "/uploads/{id}",
+            head(offset).patch(append).delete(terminate),
+        )
+}
+
+/// A response carrying the protocol version, as all of them must.
+fn respond(response: impl IntoResponse) -> Response {
+    let mut response = response.into_response();
+    response
+        .headers_mut()
+        .insert("tus-resumable", HeaderValue::from_static(VERSION));
+    response
+}
+
+/// The rejection of a request for another version of the protocol.
+fn unsupported_version(headers: &HeaderMap) -> Option<Response> {
+    if headers.get("tus-resumable").is_some_and(|v| v == VERSION) {
+        return None;
+    }
+    Some(respond((
+        StatusCode::PRECONDITION_FAILED,
+        [("tus-version", VERSION)],
+        "Unsupported tus version",
+    )))
+}
+
+fn number(headers: &HeaderMap, name: &str) -> Option<u64> {
+    headers.get(name)?.to_str().ok()?.parse().ok()
+}
+
+fn dir(service: &Service) -> PathBuf {
+    service.data_dir().join("uploads")
+}
+
+/// The `Upload-Expires` header of an upload that just made progress.
+fn expires(service: &Service) -> (&'static str, String) {
+    let expires = chrono::DateTime::<chrono::Utc>::from(service.clock().now() + EXPIRY);
+    (
+        "upload-expires",
+        expires.format("%a, %d %b %Y %H:%M:%S GMT").to_string(),
+    )
+}
+
+/// The largest upload a user, or someone without credentials, may make.
+fn max_size(service: &Service, authenticated: bool) -> u64 {
+    service
+        .size_limit(authenticated)
+        .map_or(MAX_SIZE, |limit| limit.min(MAX_SIZE))
+}
+
+async fn options(State(service): State<Arc<Service>>, auth: Option<BasicAuth>) -> Response {
+    respond((
+        StatusCode::NO_CONTENT,
+        [
+            ("tus-version", VERSION.to_owned()),
+            (
+                "tus-extension",
+                "creation,expiration,termination".to_owned(),
+            ),
+            (
+                "tus-max-size",
+                max_size(&service, auth.is_some()).to_string(),
+            ),
+        ],
+    ))
+}
+
+/// Options of the paste from `Upload-Metadata`, a list of keys with values
+/// in base64. The file name is taken as the title.
+fn parse_metadata(value: &str) -> Option<service::Options> {
+    let mut options = service::Options::default();
+    for pair in value
+        .split(',')
+        .map(str::trim)
+        .filter(|pair| !pair.is_empty())
+    {
+        let (key, value) = match pair.split_once(' ') {
+            Some((key, value)) => {
+                let value = base64::engine::general_purpose::STANDARD
+                    .decode(value.trim())
+                    .ok()?;
+                (key, Some(String::from_utf8(value).ok()?))
+            }
+            None => (pair, None),
+        };
+        // Flags count as set when they have no value.
+        let flag = value.as_deref().is_none_or(|value| value == "true");
+        match key {
+            "filename" | "title" => options.title = value,
+            "language" => options.language = value,
+            "public" => options.public = flag,
+            "noindex" => options.noindex = flag,
+            "gist" => options.gist = flag,
+            _ => {}
+        }
+    }
+    Some(options)
+}
+
+async fn create(
+    State(service): State<Arc<Service>>,
+    State(busy): State<Arc<Busy>>,
+    State(client): State<Arc<client::Config>>,
+    auth: Option<BasicAuth>,
+    request: Request,
+) -> Response {
+    let (parts, _) = request.into_parts();
+    if let Some(response) = unsupported_version(&parts.headers) {
+        return response;
+    }
+    let Some(length) = number(&parts.headers, "upload-length") else {
+        return respond((StatusCode::BAD_REQUEST, "Missing Upload-Length"));
+    };
+    let options = match parts.headers.get("upload-metadata") {
+        None => Some(service::Options::default()),
+        Some(value) => value.to_str().ok().and_then(parse_metadata),
+    };
+    let Some(options) = options else {
+        return respond((StatusCode::BAD_REQUEST, "Invalid Upload-Metadata"));
+    };
+    if let Err(e) = options.validate() {
+        return respond((StatusCode::BAD_REQUEST, e.to_string()));
+    }
+    if let Some(auth) = &auth
+        && !service.authenticate(&auth.username, &auth.password)
+    {
+        return respond(auth::unauthorized());
+    }
+    let max_size = max_size(&service, auth.is_some());
+    if length > max_size {
+        return respond((
+            StatusCode::PAYLOAD_TOO_LARGE,
+            format!("Uploads are limited to {max_size} bytes"),
+        ));
+    }
+
+    let dir = dir(&service);
+    if let Err(e) = sweep(&dir, service.clock().now()).await {
+        tracing::warn!("Dropping expired uploads: {e}");
+    }
+    let id = service.ids().generate();
+    let mut upload = Upload {
+        length,
+        owner: auth.as_ref().map(|auth| auth.username.clone()),
+        options,
+        paste: None,
+    };
+    let created = async {
+        tokio::fs::create_dir_all(&dir).await?;
+        tokio::fs::File::create(dir.join(id.to_string())).await?;
+        store(&dir, &id, &upload).await
+    };
+    if let Err(e) = created.await {
+        return respond((StatusCode::INTERNAL_SERVER_ERROR, e.to_string()));
+    }
+    let location = match client::base_url(&parts, &client) {
+        Some(base_url) => format!("{base_url}/uploads/{id}"),
+        None => format!("{}/uploads/{id}", client.path_prefix),
+    };
+    // Nothing is going to follow for an empty paste.
+    if length == 0 {
+        let Some(_claim) = busy.claim(id) else {
+            return respond((StatusCode::CONFLICT, "Upload in progress"));
+        };
+        if let Err(response) = finish(&service, &dir, &id, &mut upload, auth).await {
+            return response;
+        }
+    }
+    let mut response = respond((
+        StatusCode::CREATED,
+        [(header::LOCATION.as_str(), location), expires(&service)],
+    ));
+    if let Some(paste) = upload.paste {
+        add_paste_header(&mut response, &parts, &client, &paste);
+    }
+    response
+}
+
+/// `HEAD /uploads/{id}`, how much of the upload arrived.
+async fn offset(
+    State(service): State<Arc<Service>>,
+    State(client): State<Arc<client::Config>>,
+    Path(id): Path<Uuid>,
+    auth: Option<BasicAuth>,
+    request: Request,
+) -> Response {
+    let (parts, _) = request.into_parts();
+    if let Some(response) = unsupported_version(&parts.headers) {
+        return response;
+    }
+    let dir = dir(&service);
+    let upload = match load(&service, &dir, &id, auth.as_ref()).await {
+        Ok(upload) => upload,
+        Err(response) => return response,
+    };
+    let received = match received(&dir, &id, &upload).await {
+        Ok(received) => received,
+        Err(e) => return respond((StatusCode::INTERNAL_SERVER_ERROR, e.to_string())),
+    };
+    let mut response = respond((
+        [
+            ("upload-offset", received.to_string()),
+            ("upload-length", upload.length.to_string()),
+            (header::CACHE_CONTROL.as_str(), "no-store".to_owned()),
+        ],
+        StatusCode::OK,
+    ));
+    if let Some(paste) = upload.paste {
+        add_paste_header(&mut response, &parts, &client, &paste);
+    }
+    response
+}
+
+/// `PATCH /uploads/{id}`, more of the upload starting at `Upload-Offset`.
+async fn append(
+    State(service): State<Arc<Service>>,
+    State(busy): State<Arc<Busy>>,
+    State(client): State<Arc<client::Config>>,
+    Path(id): Path<Uuid>,
+    auth: Option<BasicAuth>,
+    request: Request,
+) -> Response {
+    let (parts, body) = request.into_parts();
+    if let Some(response) = unsupported_version(&parts.headers) {
+        return response;
+    }
+    if parts
+        .headers
+        .get(header::CONTENT_TYPE)
+        .is_none_or(|value| value != "application/offset+octet-stream")
+    {
+        return respond((
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            "Expected application/offset+octet-stream",
+        ));
+    }
+    let Some(offset) = number(&parts.headers, "upload-offset") else {
+        return respond((StatusCode::BAD_REQUEST, "Missing Upload-Offset"));
+    };
+    let Some(_claim) = busy.claim(id) else {
+        return respond((StatusCode::CONFLICT, "Upload in progress"));
+    };
+    let dir = dir(&service);
+    let mut upload = match load(&service, &dir, &id, auth.as_ref()).await {
+        Ok(upload) => upload,
+        Err(response) => return response,
+    };
+    let received = match received(&dir, &id, &upload).await {
+        Ok(received) => received,
+        Err(e) => return respond((StatusCode::INTERNAL_SERVER_ERROR, e.to_string())),
+    };
+    if offset != received || upload.paste.is_some() {
+        return respond((
+            StatusCode::CONFLICT,
+            [("upload-offset", received.to_string())],
+            "Upload-Offset doesn't match the upload",
+        ));
+    }
+
+    let mut file = match tokio::fs::OpenOptions::new()
+        .append(true)
+        .open(dir.join(id.to_string()))
+        .await
+    {
+        Ok(file) => file,
+        Err(e) => return respond((StatusCode::INTERNAL_SERVER_ERROR, e.to_string())),
+    };
+    // What arrived before the connection broke is kept, for the client to
+    // resume after it.
+    // The size limit may have been lowered since the upload was created.
+    let max_size = max_size(&service, upload.owner.is_some());
+    let mut received = received;
+    let mut too_long = false;
+    let mut stream = body.into_data_stream();
+    while let Some(Ok(chunk)) = stream.next().await {
+        if received + chunk.len() as u64 > upload.length.min(max_size) {
+            too_long = true;
+            break;
+        }
+        if let Err(e) = file.write_all(&chunk).await {
+            return respond((StatusCode::INTERNAL_SERVER_ERROR, e.to_string()));
+        }
+        received += chunk.len() as u64;
+    }
+    if let Err(e) = file.sync_data().await {
+        return respond((StatusCode::INTERNAL_SERVER_ERROR, e.to_string()));
+    }
+    drop(file);
+    if too_long && upload.length > max_size {
+        return respond((
+            StatusCode::PAYLOAD_TOO_LARGE,
+            [("upload-offset", received.to_string())],
+            format!("Uploads are limited to {max_size} bytes"),
+        ));
+    }
+    if too_long {
+        return respond((
+            StatusCode::BAD_REQUEST,
+            [("upload-offset", received.to_string())],
+            "Data past Upload-Length",
+        ));
+    }
+    if received == upload.length
+        && let Err(response) = finish(&service, &dir, &id, &mut upload, auth).await
+    {
+        return response;
+    }
+    if let Err(e) = touch(&dir, &id, service.clock().now()) {
+        return respond((StatusCode::INTERNAL_SERVER_ERROR, e.to_string()));
+    }
+    let mut response = respond((
+        StatusCode::NO_CONTENT,
+        [("upload-offset", received.to_string()), expires(&service)],
+    ));
+    if let Some(paste) = upload.paste {
+        add_paste_header(&mut response, &parts, &client, &paste);
+    }
+    response
+}
+
+/// `DELETE /uploads/{id}`, dropping an upload the client gave up on.
+async fn terminate(
+    State(service): State<Arc<Service>>,
+    State(busy): State<Arc<Busy>>,
+    Path(id): Path<Uuid>,
+    auth: Option<BasicAuth>,
+    headers: HeaderMap,
+) -> Response {
+    if let Some(response) = unsupported_version(&headers) {
+        return response;
+    }
+    let Some(_claim) = busy.claim(id) else {
+        return respond((StatusCode::CONFLICT, "Upload in progress"));
+    };
+    let dir = dir(&service);
+    if let Err(response) = load(&service, &dir, &id, auth.as_ref()).await {
+        return response;
+    }
+    match remove(&dir, &id).await {
+        Ok(()) => respond(StatusCode::NO_CONTENT),
+        Err(e) => respond((StatusCode::INTERNAL_SERVER_ERROR, e.to_string())),
+    }
+}
+
+fn add_paste_header(response: &mut Response, parts: &Parts, client: &client::Config, id: &Uuid) {
+    let url = match client::base_url(parts, client) {
+        Some(base_url) => format!("{base_url}/paste/{id}"),
+        None => id.to_string(),
+    };
+    if let Ok(url) = HeaderValue::try_from(url) {
+        response.headers_mut().insert(PASTE_HEADER, url);
+    }
+}
+
+/// Upload `id`, if it exists and `auth` may continue it. Other users' are
+/// reported missing, like pastes are.
+async fn load(
+    service: &Service,
+    dir: &FsPath,
+    id: &Uuid,
+    auth: Option<&BasicAuth>,
+) -> Result<Upload, Response> {
+    let upload = match tokio::fs::read(dir.join(format!("{id}.json"))).await {
+        Ok(json) => serde_json::from_slice::<Upload>(&json)
+            .map_err(|e| respond((StatusCode::INTERNAL_SERVER_ERROR, e.to_string())))?,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            return Err(respond((StatusCode::NOT_FOUND, "No such upload")));
+        }
+        Err(e) => return Err(respond((StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))),
+    };
+    let Some(owner) = &upload.owner else {
+        return Ok(upload);
+    };
+    match auth {
+        None => Err(respond(auth::unauthorized())),
+        Some(auth)
+            if auth.username == *owner && service.authenticate(&auth.username, &auth.password) =>
+        {
+            Ok(upload)
+        }
+        Some(_) => Err(respond((StatusCode::NOT_FOUND, "No such upload"))),
+    }
+}
+
+/// How much of `upload` arrived so far.
+async fn received(dir: &FsPath, id: &Uuid, upload: &Upload) -> std::io::Result<u64> {
+    if upload.paste.is_some() {
+        return Ok(upload.length);
+    }
+    Ok(tokio::fs::metadata(dir.join(id.to_string())).await?.len())
+}
+
+/// Creates the paste out of the completed upload `id`, dropping its data.
+async fn finish(
+    service: &Service,
+    dir: &FsPath,
+    id: &Uuid,
+    upload: &mut Upload,
+    auth: Option<BasicAuth>,
+) -> Result<(), Response> {
+    let path = dir.join(id.to_string());
+    let file = tokio::fs::File::open(&path)
+        .await
+        .map_err(|e| respond((StatusCode::INTERNAL_SERVER_ERROR, e.to_string())))?;
+    let paste = service
+        .create_with_options(file, auth.map(Into::into), upload.options.clone())
+        .await
+        .map_err(|e| respond((StatusCode::INTERNAL_SERVER_ERROR, e.to_string())))?;
+    upload.paste = paste.parse().ok();
+    // Kept without its data, for a client that missed the response to ask
+    // where the paste went.
+    store(dir, id, upload)
+        .await
+        .map_err(|e| respond((StatusCode::INTERNAL_SERVER_ERROR, e.to_string())))?;
+    tokio::fs::remove_file(&path)
+        .await
+        .map_err(|e| respond((StatusCode::INTERNAL_SERVER_ERROR, e.to_string())))
+}
+
+async fn store(dir: &FsPath, id: &Uuid, upload: &Upload) -> std::io::Result<()> {
+    let json = serde_json::to_vec(upload).expect("Uploads serialize");
+    tokio::fs::write(dir.join(format!("{id}.json")), json).await
+}
+
+/// Marks upload `id` as having made progress at `now`, which pushes back its
+/// expiry.
+fn touch(dir: &FsPath, id: &Uuid, now: SystemTime) -> std::io::Result<()> {
+    let file = std::fs::File::options()
+        .append(true)
+        .open(dir.join(format!("{id}.json")))?;
+    file.set_modified(now)
+}
+
+async fn remove(dir: &FsPath, id: &Uuid) -> std::io::Result<()> {
+    for path in [dir.join(id.to_string()), dir.join(format!("{id}.json"))] {
+        match tokio::fs::remove_file(path).await {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e),
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// Drops the uploads of `dir` that made no progress for longer than `EXPIRY`
+/// before `now`.
+async fn sweep(dir: &FsPath, now: SystemTime) -> std::io::Result<()> {
+    let mut entries = match tokio::fs::read_dir(dir).await {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    };
+    while let Some(entry) = entries.next_entry().await? {
+        let name = entry.file_name();
+        let Some(id) = name
+            .to_str()
+            .and_then(|name| name.strip_suffix(".json"))
+            .and_then(|id| Uuid::parse_str(id).ok())
+        else {
+            continue;
+        };
+        let modified = entry.metadata().await?.modified()?;
+        if now.duration_since(modified).is_ok_and(|age| age > EXPIRY) {
+            tracing::info!("Dropping expired upload {id}");
+            remove(dir, &id).await?;
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn test_parse_metadata() {
+    let options = parse_metadata("filename bm90ZXMudHh0,language cnVzdA==,public").unwrap();
+    assert_eq!(options.title.as_deref(), Some("notes.txt"));
+    assert_eq!(options.language.as_deref(), Some("rust"));
+    assert!(options.public);
+    assert!(!options.noindex);
+    assert!(parse_metadata("filename !!!").is_none());
+}
+
+#[tokio::test]
+async fn test_upload_length_is_checked_against_the_size_limit() {
+    let server = crate::testing::TestServer::start_with(|builder| {
+        builder
+            .user("alice", "secret")
+            .size_limits(Some(10), Some(100))
+    })
+    .await
+    .unwrap();
+    let create = |length: u64| {
+        server
+            .client()
+            .post(server.url("/uploads"))
+            .header("tus-resumable", VERSION)
+            .header("upload-length", length)
+    };
+    let response = create(11).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    let response = create(10).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let response = create(11)
+        .basic_auth("alice", Some("secret"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let response = create(101)
+        .basic_auth("alice", Some("secret"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+}
+
+#[tokio::test]
+async fn test_parts_are_held_to_a_lowered_size_limit() {
+    let server = crate::testing::TestServer::start().await.unwrap();
+    let created = server
+        .client()
+        .post(server.url("/uploads"))
+        .header("tus-resumable", VERSION)
+        .header("upload-length", 10)
+        .send()
+        .await
+        .unwrap();
+    let location = created.headers()[header::LOCATION].to_str().unwrap();
+    server.service.set_size_limits(service::SizeLimits {
+        anonymous: Some(4),
+        user: None,
+    });
+    let response = server
+        .client()
+        .patch(location)
+        .header("tus-resumable", VERSION)
+        .header("upload-offset", 0)
+        .header(header::CONTENT_TYPE, "application/offset+octet-stream")
+        .body("0123456789")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    assert_eq!(response.headers()["upload-offset"], "0");
+}
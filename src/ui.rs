@@ -0,0 +1,47 @@
+//! The web frontend at `/ui`, for people without a command line: an editor
+//! with a language picker, expiry selector and login. It's a single page
+//! using the same API as other clients, `POST /paste` to create pastes and
+//! GraphQL to log in and list them. Its files are built into the binary.
+
+use axum::{
+    Router,
+    http::header,
+    response::{Html, IntoResponse, Response},
+    routing::get,
+};
+
+/// Lets the page use its own script and style, and talk to the instance.
+const CSP: &str = "default-src 'none'; script-src 'self'; style-src 'self'; connect-src 'self'; \
+                   form-action 'none'; frame-ancestors 'none'";
+
+const INDEX: &str = include_str!("ui/index.html");
+const SCRIPT: &str = include_str!("ui/app.js");
+const STYLE: &str = include_str!("ui/app.css");
+
+pub fn routes() -> Router<crate::AppState> {
+    Router::new()
+        .route("/ui", get(index))
+        .route(
+            "/ui/app.js",
+            get(|| asset("text/javascript; charset=utf-8", SCRIPT)),
+        )
+        .route(
+            "/ui/app.css",
+            get(|| asset("text/css; charset=utf-8", STYLE)),
+        )
+}
+
+async fn index() -> Response {
+    ([(header::CONTENT_SECURITY_POLICY, CSP)], Html(INDEX)).into_response()
+}
+
+async fn asset(content_type: &'static str, contents: &'static str) -> Response {
+    (
+        [
+            (header::CONTENT_TYPE, content_type),
+            (header::CACHE_CONTROL, "public, max-age=3600"),
+        ],
+        contents,
+    )
+        .into_response()
+}
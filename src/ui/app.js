@@ -0,0 +1,239 @@
+// The web frontend: an editor posting to the same API other clients use.
+// Credentials are kept for the browser session and sent as Basic auth.
+"use strict";
+
+const INDENT = "    ";
+
+// Languages guessed from the extension of a title like `main.rs`.
+const EXTENSIONS = {
+  c: "c", cc: "cpp", cpp: "cpp", cs: "csharp", css: "css", diff: "diff", go: "go",
+  h: "c", hpp: "cpp", hs: "haskell", html: "html", java: "java", js: "javascript",
+  json: "json", kt: "kotlin", lua: "lua", md: "markdown", patch: "diff", php: "php",
+  py: "python", rb: "ruby", rs: "rust", sh: "bash", sql: "sql", swift: "swift",
+  toml: "toml", ts: "typescript", xml: "xml", yaml: "yaml", yml: "yaml",
+};
+
+const $ = (selector) => document.querySelector(selector);
+
+function credentials() {
+  const saved = sessionStorage.getItem("credentials");
+  return saved ? JSON.parse(saved) : null;
+}
+
+function headers(extra = {}) {
+  const saved = credentials();
+  if (saved) {
+    extra.Authorization = "Basic " + btoa(unescape(encodeURIComponent(
+      saved.username + ":" + saved.password)));
+  }
+  return extra;
+}
+
+function status(message, error = false) {
+  const element = $("#status");
+  element.replaceChildren(message);
+  element.classList.toggle("error", error);
+}
+
+async function graphql(query) {
+  const response = await fetch("graphql", {
+    method: "POST",
+    headers: headers({ "Content-Type": "application/json" }),
+    body: JSON.stringify({ query }),
+  });
+  if (!response.ok) {
+    throw new Error(response.status === 401 ? "Wrong username or password" : await response.text());
+  }
+  const body = await response.json();
+  if (body.errors && body.errors.length) {
+    throw new Error(body.errors[0].message);
+  }
+  return body.data;
+}
+
+// The editor: a textarea with line numbers, indentation with Tab and
+// Shift+Tab, and new lines keeping the indentation of the one before.
+
+function updateLines(textarea) {
+  const lines = textarea.value.split("\n").length;
+  const gutter = $("#lines");
+  if (gutter.dataset.lines !== String(lines)) {
+    gutter.textContent = Array.from({ length: lines }, (_, i) => i + 1).join("\n");
+    gutter.dataset.lines = lines;
+  }
+  gutter.scrollTop = textarea.scrollTop;
+}
+
+function replaceSelection(textarea, start, end, text, selectStart, selectEnd) {
+  textarea.setRangeText(text, start, end);
+  textarea.setSelectionRange(selectStart, selectEnd);
+  textarea.dispatchEvent(new Event("input"));
+}
+
+function indent(textarea, outdent) {
+  const { value, selectionStart, selectionEnd } = textarea;
+  const lineStart = value.lastIndexOf("\n", selectionStart - 1) + 1;
+  if (!outdent && selectionStart === selectionEnd) {
+    replaceSelection(textarea, selectionStart, selectionEnd, INDENT,
+      selectionStart + INDENT.length, selectionStart + INDENT.length);
+    return;
+  }
+  const block = value.slice(lineStart, selectionEnd);
+  const lines = block.split("\n");
+  const changed = lines.map((line) => {
+    if (!outdent) {
+      return INDENT + line;
+    }
+    const leading = line.match(/^ {0,4}|^\t/)[0];
+    return line.slice(leading.length);
+  });
+  const first = changed[0].length - lines[0].length;
+  const text = changed.join("\n");
+  replaceSelection(textarea, lineStart, selectionEnd, text,
+    Math.max(lineStart, selectionStart + first), lineStart + text.length);
+}
+
+function newLine(textarea) {
+  const { value, selectionStart, selectionEnd } = textarea;
+  const lineStart = value.lastIndexOf("\n", selectionStart - 1) + 1;
+  const leading = value.slice(lineStart, selectionStart).match(/^[ \t]*/)[0];
+  const text = "\n" + leading;
+  replaceSelection(textarea, selectionStart, selectionEnd, text,
+    selectionStart + text.length, selectionStart + text.length);
+}
+
+function setUpEditor(form) {
+  const textarea = form.elements.content;
+  textarea.addEventListener("input", () => updateLines(textarea));
+  textarea.addEventListener("scroll", () => updateLines(textarea));
+  textarea.addEventListener("keydown", (event) => {
+    if (event.key === "Enter" && (event.ctrlKey || event.metaKey)) {
+      event.preventDefault();
+      form.requestSubmit();
+    } else if (event.key === "Tab" && !event.ctrlKey && !event.altKey) {
+      event.preventDefault();
+      indent(textarea, event.shiftKey);
+    } else if (event.key === "Enter" && !event.shiftKey) {
+      event.preventDefault();
+      newLine(textarea);
+    }
+  });
+  form.elements.title.addEventListener("change", () => {
+    const extension = form.elements.title.value.split(".").pop().toLowerCase();
+    if (!form.elements.language.value && EXTENSIONS[extension]) {
+      form.elements.language.value = EXTENSIONS[extension];
+    }
+  });
+  updateLines(textarea);
+}
+
+async function createPaste(form) {
+  const query = new URLSearchParams();
+  for (const name of ["title", "language", "expires_in"]) {
+    if (form.elements[name].value) {
+      query.set(name, form.elements[name].value);
+    }
+  }
+  if (form.elements.public.checked) {
+    query.set("public", "true");
+  }
+  const response = await fetch("paste?" + query, {
+    method: "POST",
+    headers: headers({ "Content-Type": "text/plain; charset=utf-8" }),
+    body: form.elements.content.value,
+  });
+  const text = (await response.text()).trim();
+  if (!response.ok) {
+    throw new Error(text);
+  }
+  return text;
+}
+
+// Logging in and the list of the user's pastes.
+
+async function showAccount() {
+  const saved = credentials();
+  $("#login").hidden = Boolean(saved);
+  $("#account").hidden = !saved;
+  $("#mine").hidden = !saved;
+  if (!saved) {
+    return;
+  }
+  $("#username").textContent = saved.username;
+  const data = await graphql("{ me { pastes { id title language written } } }");
+  const rows = data.me.pastes.map((paste) => {
+    const row = document.createElement("tr");
+    const link = document.createElement("a");
+    link.href = "paste/" + paste.id + "/view";
+    link.textContent = paste.title || "Paste " + paste.id;
+    const remove = document.createElement("button");
+    remove.type = "button";
+    remove.textContent = "Delete";
+    remove.addEventListener("click", () => deletePaste(paste).catch((e) => status(e.message, true)));
+    for (const cell of [link, paste.language || "", paste.written, remove]) {
+      const td = document.createElement("td");
+      td.append(cell);
+      row.append(td);
+    }
+    return row;
+  });
+  $("#pastes").replaceChildren(...rows);
+}
+
+async function deletePaste(paste) {
+  if (!confirm("Delete " + (paste.title || "paste " + paste.id) + "?")) {
+    return;
+  }
+  const response = await fetch("paste/" + paste.id, { method: "DELETE", headers: headers() });
+  if (!response.ok) {
+    throw new Error(await response.text());
+  }
+  await showAccount();
+}
+
+document.addEventListener("DOMContentLoaded", () => {
+  const form = $("#paste");
+  setUpEditor(form);
+  form.addEventListener("submit", async (event) => {
+    event.preventDefault();
+    status("Creating…");
+    try {
+      const url = await createPaste(form);
+      const link = document.createElement("a");
+      link.href = url.includes("://") ? url + "/view" : "paste/" + url + "/view";
+      link.textContent = link.href;
+      status(link);
+      form.elements.content.value = "";
+      updateLines(form.elements.content);
+      if (credentials()) {
+        await showAccount();
+      }
+    } catch (e) {
+      status(e.message, true);
+    }
+  });
+
+  $("#login").addEventListener("submit", async (event) => {
+    event.preventDefault();
+    const login = event.target.elements;
+    const attempt = { username: login.username.value, password: login.password.value };
+    try {
+      sessionStorage.setItem("credentials", JSON.stringify(attempt));
+      const data = await graphql("{ me { username } }");
+      if (!data.me) {
+        throw new Error("Wrong username or password");
+      }
+      login.password.value = "";
+      status("");
+      await showAccount();
+    } catch (e) {
+      sessionStorage.removeItem("credentials");
+      status(e.message, true);
+    }
+  });
+  $("#logout").addEventListener("click", () => {
+    sessionStorage.removeItem("credentials");
+    showAccount();
+  });
+  showAccount().catch((e) => status(e.message, true));
+});
@@ -0,0 +1,518 @@
+//! File reads and writes through io_uring, in builds with the `io-uring`
+//! feature and with --io-uring. A thread per [`Ring`] drives it: reads and
+//! writes at an offset are queued for it and it submits them in batches,
+//! waiting on all of them at once, instead of each chunk of a large paste
+//! taking its own trip to the blocking pool as with [`tokio::fs`].
+//!
+//! The thread owns the buffers of the operations it submitted until the
+//! kernel is done with them, so a [`File`] dropped midway is safe.
+
+use std::{
+    collections::VecDeque,
+    ffi::c_void,
+    os::fd::{AsRawFd, OwnedFd},
+    pin::Pin,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, AtomicU32, Ordering},
+    },
+    task::{Context, Poll, ready},
+};
+
+use anyhow::Context as _;
+use parking_lot::Mutex;
+use rustix::{
+    event::EventfdFlags,
+    io::Errno,
+    io_uring::{
+        IORING_OFF_SQ_RING, IORING_OFF_SQES, IoringEnterFlags, IoringFeatureFlags, IoringOp,
+        io_uring_cqe, io_uring_params, io_uring_ptr, io_uring_sqe, io_uring_user_data,
+    },
+    mm::{MapFlags, ProtFlags},
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    sync::oneshot,
+};
+
+/// Submissions a ring takes at once, and so operations in flight at most.
+pub const ENTRIES: u32 = 256;
+
+/// Bytes read or written per operation at most.
+const CHUNK: usize = 256 * 1024;
+
+/// `user_data` of the read of [`Shared::wake`], which completes whenever
+/// operations are queued.
+const WAKE: u64 = u64::MAX;
+
+/// An operation's result and its buffer back.
+type Completion = (std::io::Result<usize>, Vec<u8>);
+
+pub struct Ring {
+    shared: Arc<Shared>,
+}
+
+/// What a [`Ring`] and its thread share.
+struct Shared {
+    queue: Mutex<VecDeque<Op>>,
+    /// An eventfd written whenever the queue stops being empty.
+    wake: OwnedFd,
+    /// Set once the ring is dropped, for the thread to exit once the
+    /// operations left have completed.
+    closing: AtomicBool,
+}
+
+struct Op {
+    write: bool,
+    file: Arc<std::fs::File>,
+    offset: u64,
+    /// What's written, or empty with room for what's read.
+    buffer: Vec<u8>,
+    done: oneshot::Sender<Completion>,
+}
+
+impl Ring {
+    /// Sets up a ring of `entries` submissions, and the thread driving it.
+    pub fn new(entries: u32) -> anyhow::Result<Self> {
+        let rings = Rings::new(entries).context("Setting up io_uring")?;
+        let shared = Arc::new(Shared {
+            queue: Mutex::default(),
+            wake: rustix::event::eventfd(0, EventfdFlags::CLOEXEC)?,
+            closing: AtomicBool::new(false),
+        });
+        let driven = shared.clone();
+        std::thread::Builder::new()
+            .name("io-uring".to_owned())
+            .spawn(move || drive(rings, &driven))?;
+        Ok(Self { shared })
+    }
+
+    fn submit(&self, op: Op) {
+        let mut queue = self.shared.queue.lock();
+        queue.push_back(op);
+        // If it wasn't empty, the thread is yet to take what's there and
+        // will take this along.
+        if queue.len() == 1 {
+            self.shared.notify();
+        }
+    }
+}
+
+impl Drop for Ring {
+    fn drop(&mut self) {
+        self.shared.closing.store(true, Ordering::SeqCst);
+        self.shared.notify();
+    }
+}
+
+impl Shared {
+    fn notify(&self) {
+        // It only fails if the counter would overflow, and then it's
+        // readable anyway.
+        rustix::io::write(&self.wake, &1u64.to_ne_bytes()).ok();
+    }
+}
+
+/// The mapped submission and completion queues of an io_uring.
+struct Rings {
+    fd: OwnedFd,
+    params: io_uring_params,
+    ring: Mapping,
+    sqes: Mapping,
+}
+
+// The mappings are only ever used by the thread driving the ring.
+unsafe impl Send for Rings {}
+
+struct Mapping {
+    ptr: *mut c_void,
+    len: usize,
+}
+
+impl Drop for Mapping {
+    fn drop(&mut self) {
+        // SAFETY: it was mapped with this length, and nothing refers to it
+        // once the rings are dropped.
+        unsafe { rustix::mm::munmap(self.ptr, self.len).ok() };
+    }
+}
+
+impl Rings {
+    fn new(entries: u32) -> std::io::Result<Self> {
+        let mut params = io_uring_params::default();
+        // SAFETY: the params are initialized, and the ring only refers to
+        // memory once operations are submitted.
+        let fd = unsafe { rustix::io_uring::io_uring_setup(entries, &mut params)? };
+        if !params.features.contains(IoringFeatureFlags::SINGLE_MMAP) {
+            return Err(std::io::Error::other("io_uring needs Linux 5.4 or later"));
+        }
+        let ring_len = (params.sq_off.array as usize + params.sq_entries as usize * 4).max(
+            params.cq_off.cqes as usize
+                + params.cq_entries as usize * std::mem::size_of::<io_uring_cqe>(),
+        );
+        let sqes_len = params.sq_entries as usize * std::mem::size_of::<io_uring_sqe>();
+        let map = |len, offset| {
+            // SAFETY: a fresh shared mapping of the ring, at the offsets the
+            // kernel documents for it.
+            let ptr = unsafe {
+                rustix::mm::mmap(
+                    std::ptr::null_mut(),
+                    len,
+                    ProtFlags::READ | ProtFlags::WRITE,
+                    MapFlags::SHARED | MapFlags::POPULATE,
+                    &fd,
+                    offset,
+                )?
+            };
+            std::io::Result::Ok(Mapping { ptr, len })
+        };
+        let ring = map(ring_len, IORING_OFF_SQ_RING)?;
+        let sqes = map(sqes_len, IORING_OFF_SQES)?;
+        Ok(Self {
+            fd,
+            params,
+            ring,
+            sqes,
+        })
+    }
+
+    /// The `u32` at `offset` into the ring.
+    fn field(&self, offset: u32) -> &AtomicU32 {
+        // SAFETY: the kernel's offsets are of aligned `u32`s within the
+        // mapping, which lives as long as `self`.
+        unsafe { &*self.ring.ptr.byte_add(offset as usize).cast::<AtomicU32>() }
+    }
+
+    /// Queues `sqe` for the next [`Rings::enter`], which must come before
+    /// more than `sq_entries` are queued.
+    fn push(&mut self, sqe: io_uring_sqe) {
+        let sq = self.params.sq_off;
+        let tail = self.field(sq.tail).load(Ordering::Relaxed);
+        let index = tail & self.field(sq.ring_mask).load(Ordering::Relaxed);
+        // SAFETY: `index` is within both arrays, and the kernel doesn't
+        // read the entry until the tail is moved past it.
+        unsafe {
+            self.sqes
+                .ptr
+                .cast::<io_uring_sqe>()
+                .add(index as usize)
+                .write(sqe);
+            let array = self.ring.ptr.byte_add(sq.array as usize).cast::<u32>();
+            array.add(index as usize).write(index);
+        }
+        self.field(sq.tail)
+            .store(tail.wrapping_add(1), Ordering::Release);
+    }
+
+    /// Submits the `queued` entries pushed since the last call, and waits
+    /// for at least one completion, returning how many were submitted.
+    fn enter(&self, queued: u32) -> std::io::Result<u32> {
+        loop {
+            // SAFETY: every entry submitted refers to a buffer and a file
+            // kept alive until its completion is reaped.
+            match unsafe {
+                rustix::io_uring::io_uring_enter(&self.fd, queued, 1, IoringEnterFlags::GETEVENTS)
+            } {
+                Err(Errno::INTR) => continue,
+                result => return Ok(result?),
+            }
+        }
+    }
+
+    /// The `user_data` and result of every completion since the last call.
+    fn reap(&self, mut each: impl FnMut(u64, i32)) {
+        let cq = self.params.cq_off;
+        let mut head = self.field(cq.head).load(Ordering::Relaxed);
+        let tail = self.field(cq.tail).load(Ordering::Acquire);
+        let mask = self.field(cq.ring_mask).load(Ordering::Relaxed);
+        while head != tail {
+            // SAFETY: the kernel filled the entries up to the tail, and
+            // doesn't reuse them until the head moves past them.
+            let cqe = unsafe {
+                &*self
+                    .ring
+                    .ptr
+                    .byte_add(cq.cqes as usize)
+                    .cast::<io_uring_cqe>()
+                    .add((head & mask) as usize)
+            };
+            each(cqe.user_data.u64_(), cqe.res);
+            head = head.wrapping_add(1);
+        }
+        self.field(cq.head).store(head, Ordering::Release);
+    }
+}
+
+/// Submits what's queued and completes it, until the ring is dropped.
+fn drive(mut rings: Rings, shared: &Shared) {
+    // One slot per entry, minus that of the wake-up read, so that neither
+    // queue can overflow.
+    let capacity = rings.params.sq_entries as usize - 1;
+    let mut slots: Vec<Option<Op>> = (0..capacity).map(|_| None).collect();
+    let mut free: Vec<usize> = (0..capacity).rev().collect();
+    let mut woken = Box::new([0u8; 8]);
+    let mut waking = true;
+    rings.push(wake(shared, &mut woken));
+    let mut queued = 1;
+    loop {
+        {
+            let mut queue = shared.queue.lock();
+            while let Some(index) = free.last().copied() {
+                let Some(mut op) = queue.pop_front() else {
+                    break;
+                };
+                free.pop();
+                let (opcode, ptr, len) = if op.write {
+                    (IoringOp::Write, op.buffer.as_mut_ptr(), op.buffer.len())
+                } else {
+                    (IoringOp::Read, op.buffer.as_mut_ptr(), op.buffer.capacity())
+                };
+                let fd = op.file.as_raw_fd();
+                rings.push(sqe(opcode, fd, op.offset, ptr, len as u32, index as u64));
+                slots[index] = Some(op);
+                queued += 1;
+            }
+        }
+        if !waking && free.len() == capacity {
+            return;
+        }
+        match rings.enter(queued) {
+            Ok(submitted) => queued -= submitted,
+            Err(e) => {
+                tracing::error!("Driving io_uring failed: {e}");
+                // The kernel may still write into their buffers.
+                std::mem::forget(slots);
+                std::mem::forget(woken);
+                return;
+            }
+        }
+        let mut woke = false;
+        rings.reap(|user_data, res| {
+            if user_data == WAKE {
+                woke = true;
+                return;
+            }
+            let index = user_data as usize;
+            let Some(mut op) = slots[index].take() else {
+                return;
+            };
+            free.push(index);
+            let result = match res {
+                ..0 => Err(std::io::Error::from_raw_os_error(-res)),
+                read if !op.write => {
+                    // SAFETY: the kernel initialized this many bytes.
+                    unsafe { op.buffer.set_len(read as usize) };
+                    Ok(read as usize)
+                }
+                written => Ok(written as usize),
+            };
+            op.done.send((result, op.buffer)).ok();
+        });
+        if woke {
+            waking = !shared.closing.load(Ordering::SeqCst);
+            if waking {
+                rings.push(wake(shared, &mut woken));
+                queued += 1;
+            }
+        }
+    }
+}
+
+/// The read of [`Shared::wake`] into `woken`.
+fn wake(shared: &Shared, woken: &mut [u8; 8]) -> io_uring_sqe {
+    sqe(
+        IoringOp::Read,
+        shared.wake.as_raw_fd(),
+        u64::MAX,
+        woken.as_mut_ptr(),
+        8,
+        WAKE,
+    )
+}
+
+fn sqe(
+    opcode: IoringOp,
+    fd: i32,
+    offset: u64,
+    buffer: *mut u8,
+    len: u32,
+    user_data: u64,
+) -> io_uring_sqe {
+    let mut sqe = io_uring_sqe {
+        opcode,
+        fd,
+        user_data: io_uring_user_data::from_u64(user_data),
+        ..Default::default()
+    };
+    sqe.off_or_addr2.off = offset;
+    sqe.addr_or_splice_off_in.addr = io_uring_ptr::new(buffer.cast());
+    sqe.len.len = len;
+    sqe
+}
+
+/// A file either read or written through a [`Ring`], in chunks of up to
+/// [`CHUNK`] bytes from its start.
+pub struct File {
+    ring: Arc<Ring>,
+    file: Arc<std::fs::File>,
+    offset: u64,
+    state: State,
+}
+
+enum State {
+    /// Not waiting on the ring, with what was read and not returned yet
+    /// from `position` on.
+    Idle { buffer: Vec<u8>, position: usize },
+    Busy {
+        write: bool,
+        completion: oneshot::Receiver<Completion>,
+    },
+}
+
+impl File {
+    pub fn new(ring: Arc<Ring>, file: std::fs::File) -> Self {
+        Self {
+            ring,
+            file: Arc::new(file),
+            offset: 0,
+            state: State::Idle {
+                buffer: Vec::new(),
+                position: 0,
+            },
+        }
+    }
+
+    fn start(&mut self, write: bool, buffer: Vec<u8>) {
+        let (done, completion) = oneshot::channel();
+        self.ring.submit(Op {
+            write,
+            file: self.file.clone(),
+            offset: self.offset,
+            buffer,
+            done,
+        });
+        self.state = State::Busy { write, completion };
+    }
+
+    /// Waits for the operation in flight, if any, moving past what it read
+    /// or wrote. The rest of a short write is written again.
+    fn poll_idle(&mut self, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        while let State::Busy { write, completion } = &mut self.state {
+            let write = *write;
+            let completion = ready!(Pin::new(completion).poll(cx));
+            self.state = State::Idle {
+                buffer: Vec::new(),
+                position: 0,
+            };
+            let (result, mut buffer) =
+                completion.map_err(|_| std::io::Error::other("The io_uring thread exited"))?;
+            let done = result?;
+            self.offset += done as u64;
+            if write && done < buffer.len() {
+                if done == 0 {
+                    return Poll::Ready(Err(std::io::ErrorKind::WriteZero.into()));
+                }
+                buffer.drain(..done);
+                self.start(true, buffer);
+                continue;
+            }
+            if write {
+                buffer.clear();
+            }
+            self.state = State::Idle {
+                buffer,
+                position: 0,
+            };
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl AsyncRead for File {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        let this = self.get_mut();
+        loop {
+            ready!(this.poll_idle(cx))?;
+            let State::Idle { buffer, position } = &mut this.state else {
+                unreachable!("poll_idle leaves the file idle");
+            };
+            if *position < buffer.len() || buf.remaining() == 0 {
+                let read = (buffer.len() - *position).min(buf.remaining());
+                buf.put_slice(&buffer[*position..*position + read]);
+                *position += read;
+                return Poll::Ready(Ok(()));
+            }
+            // Everything read was returned, including the empty read at
+            // the end of the file.
+            if *position > 0 || buffer.capacity() == 0 {
+                let mut buffer = std::mem::take(buffer);
+                buffer.clear();
+                buffer.reserve_exact(CHUNK);
+                this.start(false, buffer);
+            } else {
+                return Poll::Ready(Ok(()));
+            }
+        }
+    }
+}
+
+impl AsyncWrite for File {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        let this = self.get_mut();
+        ready!(this.poll_idle(cx))?;
+        let State::Idle { buffer, .. } = &mut this.state else {
+            unreachable!("poll_idle leaves the file idle");
+        };
+        let mut buffer = std::mem::take(buffer);
+        buffer.clear();
+        let written = buf.len().min(CHUNK);
+        buffer.extend_from_slice(&buf[..written]);
+        this.start(true, buffer);
+        Poll::Ready(Ok(written))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        self.get_mut().poll_idle(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        self.get_mut().poll_idle(cx)
+    }
+}
+
+#[tokio::test]
+async fn test_large_pastes_round_trip_through_the_ring() {
+    use tokio::io::AsyncReadExt;
+
+    use crate::{service::Service, state::State, testing::TempDir};
+
+    let dir = TempDir::new().unwrap();
+    let service = Service::new(dir.path().to_owned(), State::default())
+        .unwrap()
+        .with_ring(Ring::new(8).unwrap());
+    // More reads than the ring has room for at once, of pastes spanning
+    // several chunks.
+    let contents: Vec<Vec<u8>> = (0..16u8)
+        .map(|n| (0..CHUNK * 3 + 1000).map(|i| (i % 251) as u8 ^ n).collect())
+        .collect();
+    let mut ids = Vec::new();
+    for contents in &contents {
+        ids.push(service.create(&contents[..], None).await.unwrap());
+    }
+    let reads = ids.iter().map(|id| async {
+        let mut reader = service.read(&id.parse().unwrap()).await.unwrap();
+        assert!(matches!(reader, crate::cache::Reader::Uring(_)));
+        let mut read = Vec::new();
+        reader.read_to_end(&mut read).await.unwrap();
+        read
+    });
+    assert_eq!(futures::future::join_all(reads).await, contents);
+}
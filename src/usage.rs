@@ -0,0 +1,167 @@
+use std::{
+    collections::{HashMap, HashSet},
+    time::{Duration, SystemTime},
+};
+
+use parking_lot::Mutex;
+use uuid::Uuid;
+
+/// Time after which a view counts half as much towards [`Usage::trending`].
+const VIEW_HALF_LIFE: Duration = Duration::from_secs(2 * 24 * 60 * 60);
+
+struct Entry {
+    size: u64,
+    last_read: SystemTime,
+    /// Views, each decayed by [`VIEW_HALF_LIFE`] since `last_read`.
+    views: f64,
+}
+
+impl Entry {
+    /// The decayed view count as of `now`.
+    fn views_at(&self, now: SystemTime) -> f64 {
+        let elapsed = now.duration_since(self.last_read).unwrap_or_default();
+        self.views * 0.5f64.powf(elapsed.as_secs_f64() / VIEW_HALF_LIFE.as_secs_f64())
+    }
+}
+
+#[derive(Default)]
+struct Inner {
+    pastes: HashMap<Uuid, Entry>,
+    total: u64,
+}
+
+impl Inner {
+    fn insert(&mut self, id: Uuid, entry: Entry) {
+        self.total += entry.size;
+        if let Some(old) = self.pastes.insert(id, entry) {
+            self.total -= old.size;
+        }
+    }
+}
+
+/// Tracks the size, last read time and recent views of every paste, and
+/// thereby the total storage in use. Views are only counted while running.
+#[derive(Default)]
+pub struct Usage {
+    inner: Mutex<Inner>,
+}
+
+impl Usage {
+    /// Records paste `id` as found on disk at startup, using its
+    /// modification time as the last read time, unless it's been written
+    /// since.
+    pub fn scanned(&self, id: Uuid, size: u64, modified: SystemTime) {
+        let mut inner = self.inner.lock();
+        if !inner.pastes.contains_key(&id) {
+            inner.insert(
+                id,
+                Entry {
+                    size,
+                    last_read: modified,
+                    views: 0.0,
+                },
+            );
+        }
+    }
+
+    /// Records that paste `id` now holds `size` bytes, as of `now`. Views of
+    /// its previous contents still count.
+    pub fn written(&self, id: Uuid, size: u64, now: SystemTime) {
+        let mut inner = self.inner.lock();
+        let views = inner.pastes.get(&id).map_or(0.0, |old| old.views_at(now));
+        inner.insert(
+            id,
+            Entry {
+                size,
+                last_read: now,
+                views,
+            },
+        );
+    }
+
+    /// The number of pastes and their total size.
+    pub fn totals(&self) -> (usize, u64) {
+        let inner = self.inner.lock();
+        (inner.pastes.len(), inner.total)
+    }
+
+    pub fn read(&self, id: &Uuid, now: SystemTime) {
+        if let Some(entry) = self.inner.lock().pastes.get_mut(id) {
+            entry.views = entry.views_at(now) + 1.0;
+            entry.last_read = now;
+        }
+    }
+
+    /// The pastes with the most recent views, most viewed first, along with
+    /// their decayed view counts as of `now`.
+    pub fn trending(&self, now: SystemTime) -> Vec<(Uuid, f64)> {
+        let mut trending: Vec<_> = self
+            .inner
+            .lock()
+            .pastes
+            .iter()
+            .map(|(id, entry)| (*id, entry.views_at(now)))
+            .filter(|(_, views)| *views >= 0.01)
+            .collect();
+        trending.sort_by(|a, b| b.1.total_cmp(&a.1));
+        trending
+    }
+
+    pub fn removed(&self, id: &Uuid) {
+        let mut inner = self.inner.lock();
+        if let Some(old) = inner.pastes.remove(id) {
+            inner.total -= old.size;
+        }
+    }
+
+    /// Picks the least recently read pastes that must go to bring the total
+    /// down to `budget`, skipping any in `keep`.
+    ///
+    /// Returns `None` if evicting every eligible paste still wouldn't be
+    /// enough.
+    pub fn eviction_candidates(&self, budget: u64, keep: &HashSet<Uuid>) -> Option<Vec<Uuid>> {
+        let inner = self.inner.lock();
+        let mut excess = inner.total.saturating_sub(budget);
+        if excess == 0 {
+            return Some(Vec::new());
+        }
+
+        let mut eligible: Vec<_> = inner
+            .pastes
+            .iter()
+            .filter(|(id, _)| !keep.contains(id))
+            .collect();
+        eligible.sort_by_key(|(_, entry)| entry.last_read);
+
+        let mut candidates = Vec::new();
+        for (id, entry) in eligible {
+            candidates.push(*id);
+            excess = excess.saturating_sub(entry.size);
+            if excess == 0 {
+                return Some(candidates);
+            }
+        }
+        None
+    }
+}
+
+#[test]
+fn test_eviction_candidates_are_least_recently_read() {
+    let usage = Usage::default();
+    let [old, recent, owned] = [Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4()];
+    let now = SystemTime::now();
+    for id in [old, owned, recent] {
+        usage.written(id, 10, now);
+    }
+    usage.read(&old, now);
+    usage.read(&recent, now + Duration::from_millis(10));
+
+    let keep = HashSet::from([owned]);
+    assert_eq!(usage.eviction_candidates(30, &keep), Some(vec![]));
+    assert_eq!(
+        usage.eviction_candidates(15, &keep),
+        Some(vec![old, recent])
+    );
+    assert_eq!(usage.eviction_candidates(25, &keep), Some(vec![old]));
+    assert_eq!(usage.eviction_candidates(5, &keep), None);
+}
@@ -0,0 +1,179 @@
+//! Gzipped copies of text pastes, served to clients accepting gzip so that
+//! responses shrink without compressing them on every request. A paste's
+//! copy is made in the background the first time it's asked for gzipped,
+//! and stored by content like its blob: it can't go stale when the paste is
+//! replaced, and pastes with the same contents share it. There's no Brotli,
+//! lacking an implementation among the dependencies.
+
+use std::{
+    collections::HashSet,
+    io,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use async_compression::tokio::write::GzipEncoder;
+use axum::http::{HeaderMap, header};
+use parking_lot::Mutex;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use uuid::Uuid;
+
+use crate::{checksum, service::Service};
+
+/// Pastes smaller than this gain too little from compression.
+const MIN_SIZE: u64 = 1024;
+
+/// Larger pastes aren't compressed, bounding the time spent on one.
+const MAX_SIZE: u64 = 64 * 1024 * 1024;
+
+/// Bytes looked at to tell text from binary contents, which rarely
+/// compress.
+const SNIFF: usize = 8192;
+
+pub fn dir(data_dir: &Path) -> PathBuf {
+    data_dir.join("variants")
+}
+
+fn path(data_dir: &Path, digest: &[u8]) -> PathBuf {
+    dir(data_dir).join(format!("{}.gz", hex::encode(digest)))
+}
+
+/// Digests whose copies are being made, so that each is made once.
+#[derive(Default)]
+pub struct Pending(Mutex<HashSet<Vec<u8>>>);
+
+/// Whether a request's Accept-Encoding allows gzip, by name or as `*`.
+pub fn accepts_gzip(headers: &HeaderMap) -> bool {
+    let mut any = false;
+    let codings = headers
+        .get_all(header::ACCEPT_ENCODING)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','));
+    for coding in codings {
+        let mut parts = coding.split(';').map(str::trim);
+        let name = parts.next().unwrap_or_default();
+        let accepted = !parts.any(|parameter| {
+            parameter
+                .strip_prefix("q=")
+                .and_then(|q| q.parse::<f32>().ok())
+                .is_some_and(|q| q == 0.0)
+        });
+        if name.eq_ignore_ascii_case("gzip") || name.eq_ignore_ascii_case("x-gzip") {
+            return accepted;
+        }
+        if name == "*" {
+            any = accepted;
+        }
+    }
+    any
+}
+
+/// The gzipped contents of paste `id` if they've been made, starting to
+/// make them otherwise.
+pub async fn gzip(
+    service: &Arc<Service>,
+    pending: &Arc<Pending>,
+    id: &Uuid,
+) -> anyhow::Result<Option<tokio::fs::File>> {
+    let data_dir = service.data_dir();
+    let Some(digest) = checksum::load(data_dir, &id.to_string()).await? else {
+        return Ok(None);
+    };
+    let path = path(data_dir, &digest);
+    match tokio::fs::File::open(&path).await {
+        Ok(file) if file.metadata().await?.len() > 0 => return Ok(Some(file)),
+        // An empty copy marks contents not worth compressing.
+        Ok(_) => return Ok(None),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e.into()),
+    }
+    let size = tokio::fs::metadata(data_dir.join(id.to_string()))
+        .await?
+        .len();
+    if (MIN_SIZE..=MAX_SIZE).contains(&size) && pending.0.lock().insert(digest.clone()) {
+        let (service, pending, id) = (service.clone(), pending.clone(), *id);
+        tokio::spawn(async move {
+            if let Err(e) = make(&service, &id, size, &path).await {
+                tracing::warn!("Couldn't gzip paste {id}: {e}");
+            }
+            pending.0.lock().remove(&digest);
+        });
+    }
+    Ok(None)
+}
+
+/// Writes the gzipped contents of paste `id` to `path`, or an empty file if
+/// that wouldn't save at least a tenth of its `size`.
+async fn make(service: &Service, id: &Uuid, size: u64, path: &Path) -> anyhow::Result<()> {
+    let mut reader = service.read_file(id).await?;
+    let mut head = Vec::with_capacity(SNIFF);
+    (&mut reader)
+        .take(SNIFF as u64)
+        .read_to_end(&mut head)
+        .await?;
+    tokio::fs::create_dir_all(path.parent().unwrap()).await?;
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(format!("~{}", Uuid::new_v4().simple()));
+    let tmp = PathBuf::from(tmp);
+    let mut file = tokio::fs::File::create_new(&tmp).await?;
+    let written = async {
+        if !is_text(&head) {
+            return Ok(());
+        }
+        let mut encoder = GzipEncoder::new(&mut file);
+        encoder.write_all(&head).await?;
+        // Fails at the end if the contents don't match their checksum.
+        tokio::io::copy(&mut reader, &mut encoder).await?;
+        encoder.shutdown().await?;
+        if file.metadata().await?.len() > size / 10 * 9 {
+            file.set_len(0).await?;
+        }
+        Ok::<_, io::Error>(())
+    }
+    .await;
+    drop(file);
+    match written {
+        Ok(()) => tokio::fs::rename(&tmp, path).await?,
+        Err(e) => {
+            tokio::fs::remove_file(&tmp).await.ok();
+            return Err(e.into());
+        }
+    }
+    Ok(())
+}
+
+/// Whether `head`, the start of some contents, looks like text: UTF-8
+/// without NUL bytes, allowing for a character cut off at the end.
+fn is_text(head: &[u8]) -> bool {
+    if head.contains(&0) {
+        return false;
+    }
+    match std::str::from_utf8(head) {
+        Ok(_) => true,
+        Err(e) => e.error_len().is_none() && head.len() == SNIFF,
+    }
+}
+
+/// Removes the copy of the contents with `digest`, whose blob is gone.
+pub fn remove_blocking(data_dir: &Path, digest: &[u8]) -> io::Result<()> {
+    match std::fs::remove_file(path(data_dir, digest)) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+#[test]
+fn test_accepts_gzip() {
+    let accepts = |value: &str| {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::ACCEPT_ENCODING, value.parse().unwrap());
+        accepts_gzip(&headers)
+    };
+    assert!(accepts("gzip, deflate, br"));
+    assert!(accepts("br;q=1.0, GZIP;q=0.5"));
+    assert!(!accepts("gzip;q=0, br"));
+    assert!(!accepts("identity"));
+    assert!(!accepts("gzipped"));
+    assert!(!accepts("*, gzip;q=0"));
+}
@@ -0,0 +1,363 @@
+//! Earlier contents of pastes, kept with --keep-versions when they're
+//! replaced. They live in `<id>.versions/`, listed oldest first in its
+//! `versions.json`.
+//!
+//! The oldest version kept, and any too large to compare, are hard links to
+//! their blobs, see [`crate::blobs`], so keeping them copies nothing. Every
+//! other version is a delta against the one before it: what's between the
+//! prefix and suffix it shares with that one. Reading a version replays the
+//! deltas after the closest whole version before it.
+//!
+//! Versions aren't replicated, and don't count towards the storage budget.
+
+use std::{
+    io,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{blobs, checksum};
+
+/// Largest contents compared with the version before, since both are read
+/// whole to do so.
+const MAX_DELTA: u64 = 8 * 1024 * 1024;
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Version {
+    /// Counted from 1 for each paste. Numbers of dropped versions aren't
+    /// reused.
+    pub number: u64,
+    /// When these contents were replaced, in seconds since the Unix epoch.
+    pub replaced_at: u64,
+    pub size: u64,
+    /// Hex-encoded SHA-256 digest of the contents.
+    pub sha256: String,
+    /// Whether this is stored as a delta against the version before it.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub delta: bool,
+}
+
+/// Directory holding the versions of paste `id`.
+pub fn dir(data_dir: &Path, id: &str) -> PathBuf {
+    data_dir.join(format!("{id}.versions"))
+}
+
+fn file(dir: &Path, version: &Version) -> PathBuf {
+    match version.delta {
+        true => dir.join(format!("{}.delta", version.number)),
+        false => dir.join(version.number.to_string()),
+    }
+}
+
+/// The versions kept of paste `id`, oldest first.
+pub fn list_blocking(data_dir: &Path, id: &str) -> io::Result<Vec<Version>> {
+    match std::fs::read(dir(data_dir, id).join("versions.json")) {
+        Ok(contents) => serde_json::from_slice(&contents).map_err(io::Error::from),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(e),
+    }
+}
+
+/// Writes `contents` to `path` through a temporary file, so that `path`
+/// never holds only part of them.
+fn write_atomically(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let mut tmp_name = path.file_name().unwrap().to_owned();
+    tmp_name.push(format!("~{}", uuid::Uuid::new_v4().simple()));
+    let tmp = path.with_file_name(tmp_name);
+    std::fs::write(&tmp, contents)?;
+    std::fs::rename(&tmp, path).inspect_err(|_| {
+        std::fs::remove_file(&tmp).ok();
+    })
+}
+
+/// Keeps the current contents of paste `id`, with the SHA-256 `digest`, as
+/// its newest version, dropping the oldest ones beyond `keep`. Must be
+/// called while nothing else writes to the paste, and before its contents
+/// are replaced.
+pub fn record_blocking(
+    data_dir: &Path,
+    id: &str,
+    digest: &[u8],
+    replaced_at: u64,
+    keep: usize,
+) -> io::Result<()> {
+    let dir = dir(data_dir, id);
+    std::fs::create_dir_all(&dir)?;
+    let mut versions = list_blocking(data_dir, id)?;
+    let current = data_dir.join(id);
+    let mut version = Version {
+        number: versions.last().map_or(1, |last| last.number + 1),
+        replaced_at,
+        size: std::fs::metadata(&current)?.len(),
+        sha256: hex::encode(digest),
+        delta: false,
+    };
+    let delta = match versions.last() {
+        Some(last) if last.size <= MAX_DELTA && version.size <= MAX_DELTA => {
+            let previous = rebuild(&dir, &versions, versions.len() - 1)?;
+            let delta = diff(&previous, &std::fs::read(&current)?);
+            Some(delta).filter(|delta| (delta.len() as u64) < version.size / 2)
+        }
+        _ => None,
+    };
+    match delta {
+        Some(delta) => {
+            version.delta = true;
+            write_atomically(&file(&dir, &version), &delta)?;
+        }
+        None => {
+            // Left behind if recording this version was cut short before.
+            std::fs::remove_file(file(&dir, &version)).ok();
+            std::fs::hard_link(&current, file(&dir, &version))?;
+        }
+    }
+    versions.push(version);
+
+    let dropped = versions.len().saturating_sub(keep);
+    // The oldest version kept has to be whole.
+    if let Some(oldest) = versions
+        .get(dropped)
+        .filter(|oldest| dropped > 0 && oldest.delta)
+    {
+        let contents = rebuild(&dir, &versions, dropped)?;
+        let mut whole = oldest.clone();
+        whole.delta = false;
+        let tmp = dir.join(format!(
+            "{}~{}",
+            whole.number,
+            uuid::Uuid::new_v4().simple()
+        ));
+        std::fs::write(&tmp, &contents)?;
+        std::fs::remove_file(file(&dir, &whole)).ok();
+        blobs::commit_blocking(
+            data_dir,
+            &tmp,
+            &checksum::digest(&contents),
+            &file(&dir, &whole),
+        )?;
+        versions[dropped] = whole;
+    }
+    let dropped: Vec<_> = versions.drain(..dropped).collect();
+    write_atomically(&dir.join("versions.json"), &serde_json::to_vec(&versions)?)?;
+    if let Some(first) = versions.first()
+        && !dropped.is_empty()
+    {
+        // The delta the first version kept was stored as before.
+        let delta = Version {
+            delta: true,
+            ..first.clone()
+        };
+        std::fs::remove_file(file(&dir, &delta)).ok();
+    }
+    for version in &dropped {
+        remove_version(data_dir, &dir, version)?;
+    }
+    Ok(())
+}
+
+fn remove_version(data_dir: &Path, dir: &Path, version: &Version) -> io::Result<()> {
+    match std::fs::remove_file(file(dir, version)) {
+        Ok(()) => {}
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e),
+    }
+    match (version.delta, hex::decode(&version.sha256)) {
+        (false, Ok(digest)) => blobs::release_blocking(data_dir, &digest),
+        _ => Ok(()),
+    }
+}
+
+/// The contents of version `number` of paste `id`, if it's kept. Fails with
+/// [`checksum::mismatch`] if they aren't what was stored.
+pub fn read_blocking(data_dir: &Path, id: &str, number: u64) -> io::Result<Option<Vec<u8>>> {
+    let versions = list_blocking(data_dir, id)?;
+    let Some(index) = versions.iter().position(|version| version.number == number) else {
+        return Ok(None);
+    };
+    let contents = rebuild(&dir(data_dir, id), &versions, index)?;
+    if hex::encode(checksum::digest(&contents)) != versions[index].sha256 {
+        return Err(checksum::mismatch());
+    }
+    Ok(Some(contents))
+}
+
+/// Reads `versions[index]`, applying the deltas up to it.
+fn rebuild(dir: &Path, versions: &[Version], index: usize) -> io::Result<Vec<u8>> {
+    let whole = versions[..=index]
+        .iter()
+        .rposition(|version| !version.delta)
+        .ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidData, "No whole version to start from")
+        })?;
+    let mut contents = std::fs::read(file(dir, &versions[whole]))?;
+    for version in &versions[whole + 1..=index] {
+        contents = apply(&contents, &std::fs::read(file(dir, version))?)?;
+    }
+    Ok(contents)
+}
+
+/// Removes every version of paste `id`.
+pub fn remove_blocking(data_dir: &Path, id: &str) -> io::Result<()> {
+    let dir = dir(data_dir, id);
+    for version in list_blocking(data_dir, id)? {
+        remove_version(data_dir, &dir, &version)?;
+    }
+    match std::fs::remove_dir_all(&dir) {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        result => result,
+    }
+}
+
+pub async fn remove(data_dir: &Path, id: &str) -> io::Result<()> {
+    let (data_dir, id) = (data_dir.to_owned(), id.to_owned());
+    tokio::task::spawn_blocking(move || remove_blocking(&data_dir, &id))
+        .await
+        .map_err(io::Error::other)?
+}
+
+/// Copies the versions of paste `id` into the data directory `dest`.
+pub fn copy_blocking(data_dir: &Path, dest: &Path, id: &str) -> io::Result<()> {
+    let entries = match std::fs::read_dir(dir(data_dir, id)) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    };
+    std::fs::create_dir_all(dir(dest, id))?;
+    for entry in entries {
+        let entry = entry?;
+        std::fs::copy(entry.path(), dir(dest, id).join(entry.file_name()))?;
+    }
+    Ok(())
+}
+
+/// `new` as the length of the prefix and of the suffix it shares with `old`,
+/// followed by what's between them.
+fn diff(old: &[u8], new: &[u8]) -> Vec<u8> {
+    let prefix = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let mut delta = Vec::with_capacity(16 + new.len() - prefix - suffix);
+    delta.extend_from_slice(&(prefix as u64).to_le_bytes());
+    delta.extend_from_slice(&(suffix as u64).to_le_bytes());
+    delta.extend_from_slice(&new[prefix..new.len() - suffix]);
+    delta
+}
+
+fn apply(old: &[u8], delta: &[u8]) -> io::Result<Vec<u8>> {
+    let invalid = || io::Error::new(io::ErrorKind::InvalidData, "Invalid version delta");
+    let (lengths, middle) = delta.split_at_checked(16).ok_or_else(invalid)?;
+    let [prefix, suffix] = [&lengths[..8], &lengths[8..]]
+        .map(|bytes| usize::try_from(u64::from_le_bytes(bytes.try_into().unwrap())));
+    let (Ok(prefix), Ok(suffix)) = (prefix, suffix) else {
+        return Err(invalid());
+    };
+    if prefix
+        .checked_add(suffix)
+        .is_none_or(|shared| shared > old.len())
+    {
+        return Err(invalid());
+    }
+    Ok([&old[..prefix], middle, &old[old.len() - suffix..]].concat())
+}
+
+#[test]
+fn test_deltas_rebuild_the_contents() {
+    for (old, new) in [
+        (
+            &b"fn main() {}\n"[..],
+            &b"fn main() {\n    println!();\n}\n"[..],
+        ),
+        (b"same", b"same"),
+        (b"", b"new"),
+        (b"old", b""),
+        (b"aaaa", b"aa"),
+        (b"ab", b"abab"),
+    ] {
+        assert_eq!(apply(old, &diff(old, new)).unwrap(), new);
+    }
+    let delta = diff(b"abcdef", b"abXYef");
+    assert_eq!(&delta[16..], b"XY");
+    assert!(apply(b"ab", &delta).is_err());
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn test_replaced_contents_are_kept_as_versions() {
+    use crate::testing::TestServer;
+
+    let server = TestServer::start_with(|builder| builder.configure(|args| args.keep_versions = 2))
+        .await
+        .unwrap();
+    let line = |n: usize| format!("line {n} of a paste edited one line at a time\n");
+    let revision = |edited: usize| -> String {
+        (0..100)
+            .map(|n| {
+                if n == edited {
+                    "edited\n".to_owned()
+                } else {
+                    line(n)
+                }
+            })
+            .collect()
+    };
+    let url = server
+        .client()
+        .post(server.url("/paste"))
+        .body(revision(0))
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    let url = url.trim();
+    for edited in 1..4 {
+        let response = server.client().put(url).body(revision(edited)).send();
+        assert!(response.await.unwrap().status().is_success());
+    }
+
+    // The first of the three replaced revisions was dropped, the next is
+    // whole, and the last only holds the lines it changed.
+    let versions: Vec<Version> = reqwest::get(format!("{url}/versions"))
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    let numbers: Vec<_> = versions.iter().map(|v| (v.number, v.delta)).collect();
+    assert_eq!(numbers, [(2, false), (3, true)]);
+    let id = url.rsplit('/').next().unwrap();
+    let dir = dir(server.data_dir(), id);
+    let whole = std::fs::metadata(dir.join("2")).unwrap();
+    assert_eq!(std::os::unix::fs::MetadataExt::nlink(&whole), 2);
+    assert!(std::fs::metadata(dir.join("3.delta")).unwrap().len() < 200);
+    assert!(!dir.join("1").exists());
+    for (number, edited) in [(2, 1), (3, 2)] {
+        let contents = reqwest::get(format!("{url}/versions/{number}"))
+            .await
+            .unwrap();
+        assert_eq!(contents.text().await.unwrap(), revision(edited));
+    }
+    let missing = reqwest::get(format!("{url}/versions/1")).await.unwrap();
+    assert_eq!(missing.status(), reqwest::StatusCode::NOT_FOUND);
+
+    // Deleting the paste takes its versions, and their blobs, with it.
+    server.service.remove(&id.parse().unwrap()).await.unwrap();
+    assert!(!dir.exists());
+    assert!(
+        crate::blobs::unreferenced_blocking(server.data_dir())
+            .unwrap()
+            .is_empty()
+    );
+    assert_eq!(
+        std::fs::read_dir(crate::blobs::dir(server.data_dir()))
+            .unwrap()
+            .count(),
+        0
+    );
+}
@@ -0,0 +1,185 @@
+//! An HTML page showing a paste, with the Open Graph and Twitter card tags
+//! that chat apps and social networks use to preview shared links.
+
+use std::{fmt::Write, sync::Arc};
+
+use axum::{
+    extract::{Path, Request, State},
+    http::{StatusCode, header},
+    response::{Html, IntoResponse, Response},
+};
+use tokio::io::AsyncReadExt;
+use uuid::Uuid;
+
+use crate::{
+    client,
+    html::{encode_query, escape},
+    service::Service,
+};
+
+/// Bytes of a paste shown on the page. Longer pastes are cut off, with a
+/// link to the full contents.
+const VIEW_LIMIT: u64 = 1024 * 1024;
+
+/// Characters of the paste used as the description of previews.
+const SNIPPET: usize = 200;
+
+/// Lets the page use its inline styles, nothing else.
+const CSP: &str = "default-src 'none'; style-src 'unsafe-inline'";
+
+/// The first [`SNIPPET`] characters of `contents`, on a single line.
+fn snippet(contents: &str) -> String {
+    let mut snippet = String::new();
+    for word in contents.split_whitespace() {
+        if !snippet.is_empty() {
+            snippet.push(' ');
+        }
+        snippet.push_str(word);
+        if snippet.chars().count() >= SNIPPET {
+            let mut cut: String = snippet.chars().take(SNIPPET).collect();
+            cut.push('…');
+            return cut;
+        }
+    }
+    snippet
+}
+
+pub async fn get(
+    State(service): State<Arc<Service>>,
+    State(client): State<Arc<client::Config>>,
+    Path(id): Path<Uuid>,
+    request: Request,
+) -> Response {
+    if !service.exists(&id) {
+        return (StatusCode::NOT_FOUND, "Paste not found").into_response();
+    }
+    let (parts, _) = request.into_parts();
+    let reader = match service.read(&id).await {
+        Ok(reader) => reader,
+        Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    };
+    let metadata = match service.metadata(&id).await {
+        Ok(metadata) => metadata.unwrap_or_default(),
+        Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    };
+    // Reading the whole paste verifies its checksum; a cut off one isn't.
+    let mut contents = Vec::new();
+    if let Err(e) = reader.take(VIEW_LIMIT + 1).read_to_end(&mut contents).await {
+        return (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response();
+    }
+    let truncated = contents.len() as u64 > VIEW_LIMIT;
+    contents.truncate(VIEW_LIMIT as usize);
+    let contents = String::from_utf8_lossy(&contents);
+
+    let title = escape(metadata.title.as_deref().unwrap_or(&format!("Paste {id}")));
+    let description = escape(&snippet(&contents));
+    let mut page = String::new();
+    write!(
+        page,
+        "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"><title>{title}</title>\n\
+         <meta property=\"og:type\" content=\"article\">\n\
+         <meta property=\"og:title\" content=\"{title}\">\n\
+         <meta property=\"og:description\" content=\"{description}\">\n\
+         <meta name=\"twitter:card\" content=\"summary\">\n\
+         <meta name=\"twitter:title\" content=\"{title}\">\n\
+         <meta name=\"twitter:description\" content=\"{description}\">\n"
+    )
+    .unwrap();
+    if let Some(base_url) = client::base_url(&parts, &client) {
+        let url = format!("{base_url}/paste/{id}/view");
+        write!(
+            page,
+            "<meta property=\"og:url\" content=\"{}\">\n\
+             <link rel=\"alternate\" type=\"application/json+oembed\" \
+             href=\"{}/oembed?url={}\" title=\"{title}\">\n",
+            escape(&url),
+            escape(&base_url),
+            encode_query(&url),
+        )
+        .unwrap();
+    }
+    if let Some(language) = &metadata.language {
+        let language = escape(language);
+        write!(
+            page,
+            "<meta property=\"article:tag\" content=\"{language}\">\n\
+             <meta name=\"twitter:label1\" content=\"Language\">\n\
+             <meta name=\"twitter:data1\" content=\"{language}\">\n"
+        )
+        .unwrap();
+    }
+    if metadata.noindex {
+        page.push_str("<meta name=\"robots\" content=\"noindex\">\n");
+    }
+    write!(
+        page,
+        "<style>pre{{white-space:pre-wrap}}</style></head><body>\n<h1>{title}</h1>\n\
+         <p><a href=\"../{id}\">Raw</a></p>\n<pre>{}</pre>\n",
+        escape(&contents)
+    )
+    .unwrap();
+    if truncated {
+        writeln!(
+            page,
+            "<p>Cut off, see <a href=\"../{id}\">the raw paste</a>.</p>"
+        )
+        .unwrap();
+    }
+    page.push_str("</body></html>\n");
+    (
+        [
+            (header::CONTENT_SECURITY_POLICY, CSP),
+            (header::X_CONTENT_TYPE_OPTIONS, "nosniff"),
+        ],
+        Html(page),
+    )
+        .into_response()
+}
+
+#[test]
+fn test_snippets_are_one_cut_off_line() {
+    assert_eq!(
+        snippet("fn main() {\n    println!();\n}\n"),
+        "fn main() { println!(); }"
+    );
+    let long = snippet(&"word ".repeat(100));
+    assert_eq!(long.chars().count(), SNIPPET + 1);
+    assert!(long.ends_with('…'));
+}
+
+#[tokio::test]
+async fn test_previews_describe_the_paste_safely() {
+    use crate::testing::TestServer;
+
+    let server = TestServer::start().await.unwrap();
+    let url = server
+        .client()
+        .post(server.url(&format!(
+            "/paste?title={}&language=rust",
+            encode_query("<b>\"Hi\"</b>")
+        )))
+        .body("<script>alert(1)</script>")
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    let response = server
+        .client()
+        .get(format!("{}/view", url.trim()))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.headers()["content-security-policy"], CSP);
+    let page = response.text().await.unwrap();
+    for tag in [
+        "<meta property=\"og:title\" content=\"&lt;b&gt;&quot;Hi&quot;&lt;/b&gt;\">",
+        "<meta property=\"og:description\" content=\"&lt;script&gt;alert(1)&lt;/script&gt;\">",
+        "<meta name=\"twitter:card\" content=\"summary\">",
+        "<meta property=\"article:tag\" content=\"rust\">",
+    ] {
+        assert!(page.contains(tag), "{tag} missing from {page}");
+    }
+    assert!(!page.contains("<script>"));
+}
@@ -0,0 +1,97 @@
+use std::time::Duration;
+
+use serde::Serialize;
+use uuid::Uuid;
+
+use crate::events::Subscription;
+
+const MAX_ATTEMPTS: u32 = 5;
+
+/// Header carrying the HMAC-SHA256 of the payload, keyed with the
+/// `--webhook-secret`, as `sha256=<hex>`.
+const SIGNATURE_HEADER: &str = "x-pastebin-signature";
+
+/// The JSON payload posted to every webhook.
+#[derive(Debug, Serialize)]
+struct Payload {
+    /// E.g. `paste.created`.
+    event: &'static str,
+    id: Uuid,
+    /// When the event happened, in RFC 3339.
+    timestamp: String,
+}
+
+/// Posts the paste lifecycle events among `events` to the `--webhook` URLs
+/// in the background, retrying failed deliveries a few times. Deliveries
+/// go one at a time, so while a URL is down and being retried events queue
+/// up, and once they're more than the bus keeps, the oldest are never
+/// posted; they're counted in `pastebin_events_dropped_total`.
+pub fn spawn(
+    mut events: Subscription,
+    urls: Vec<String>,
+    secret: Option<String>,
+) -> tokio::task::JoinHandle<()> {
+    let client = reqwest::Client::new();
+    tokio::spawn(async move {
+        while let Some(emitted) = events.recv().await {
+            let Some((event, id)) = emitted.event.paste() else {
+                continue;
+            };
+            let payload = Payload {
+                event,
+                id,
+                timestamp: emitted.timestamp(),
+            };
+            let body = serde_json::to_vec(&payload).expect("Payloads serialize");
+            for url in &urls {
+                let mut attempt = 1;
+                loop {
+                    match deliver(&client, url, &body, secret.as_deref()).await {
+                        Ok(()) => break,
+                        Err(e) if attempt < MAX_ATTEMPTS => {
+                            tracing::warn!("Webhook {url} failed, retrying: {e}");
+                            tokio::time::sleep(Duration::from_secs(1 << attempt)).await;
+                            attempt += 1;
+                        }
+                        Err(e) => {
+                            tracing::error!("Giving up on webhook {url} for {payload:?}: {e}");
+                            break;
+                        }
+                    }
+                }
+            }
+        }
+    })
+}
+
+async fn deliver(
+    client: &reqwest::Client,
+    url: &str,
+    body: &[u8],
+    secret: Option<&str>,
+) -> anyhow::Result<()> {
+    let mut request = client
+        .post(url)
+        .header(reqwest::header::CONTENT_TYPE, "application/json")
+        .body(body.to_vec());
+    if let Some(secret) = secret {
+        let signature = hex::encode(hmac_sha256(secret.as_bytes(), body));
+        request = request.header(SIGNATURE_HEADER, format!("sha256={signature}"));
+    }
+    request.send().await?.error_for_status()?;
+    Ok(())
+}
+
+/// HMAC-SHA256 as specified in RFC 2104.
+fn hmac_sha256(key: &[u8], message: &[u8]) -> Vec<u8> {
+    let key = ring::hmac::Key::new(ring::hmac::HMAC_SHA256, key);
+    ring::hmac::sign(&key, message).as_ref().to_vec()
+}
+
+#[test]
+fn test_hmac_sha256_matches_rfc_4231() {
+    assert_eq!(
+        hex::encode(hmac_sha256(b"Jefe", b"what do ya want for nothing?")),
+        "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+    );
+}
@@ -0,0 +1,372 @@
+//! `/paste/{id}/ws`, a WebSocket over which a client is told whenever the
+//! paste is replaced or deleted, instead of polling for changes.
+//!
+//! Only what this and collaborative editing need of RFC 6455 is implemented:
+//! text messages, pings and the closing handshake, but no extensions or
+//! binary messages.
+
+use std::sync::Arc;
+
+use axum::{
+    extract::{Path, Request, State},
+    http::{StatusCode, header},
+    response::{IntoResponse, Response},
+};
+use base64::Engine;
+use hyper_util::rt::TokioIo;
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    sync::mpsc::{self, error::TrySendError},
+};
+use tokio_util::sync::CancellationToken;
+use uuid::Uuid;
+
+use crate::{
+    events::{Event, Subscription},
+    service::Service,
+};
+
+/// Appended to the client's key to prove the server speaks WebSocket.
+const GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/// Largest frame accepted from a client.
+const MAX_FRAME: u64 = 1024 * 1024;
+
+/// Largest message accepted from a client, over all of its frames.
+const MAX_MESSAGE: u64 = 4 * 1024 * 1024;
+
+/// Frames queued for a client that doesn't keep up, after which it's
+/// disconnected.
+const BACKLOG: usize = 256;
+
+const CLOSE_NORMAL: u16 = 1000;
+const CLOSE_TOO_BIG: u16 = 1009;
+
+const CONTINUATION: u8 = 0x0;
+const TEXT: u8 = 0x1;
+const CLOSE: u8 = 0x8;
+const PING: u8 = 0x9;
+const PONG: u8 = 0xa;
+
+/// A WebSocket connection, whose frames are read and written by tasks of
+/// its own. Pings and the closing handshake are taken care of.
+pub struct WebSocket {
+    outgoing: Outgoing,
+    incoming: mpsc::Receiver<String>,
+}
+
+/// The frames queued for a client.
+#[derive(Clone)]
+struct Outgoing {
+    frames: mpsc::Sender<(u8, Vec<u8>)>,
+    /// Cancelled to drop the connection without a closing handshake.
+    dropped: CancellationToken,
+}
+
+impl Outgoing {
+    /// Queues a frame, dropping the connection if there are [`BACKLOG`]
+    /// frames the client hasn't taken yet.
+    fn send(&self, opcode: u8, payload: Vec<u8>) {
+        // Anything but a full queue means the writer stopped, with the
+        // connection closed.
+        if let Err(TrySendError::Full(_)) = self.frames.try_send((opcode, payload)) {
+            tracing::debug!("WebSocket client fell behind, dropping it");
+            self.dropped.cancel();
+        }
+    }
+}
+
+impl WebSocket {
+    fn spawn(io: impl AsyncRead + AsyncWrite + Send + 'static) -> Self {
+        let (mut reader, mut writer) = tokio::io::split(io);
+        let (frames, mut to_write) = mpsc::channel::<(u8, Vec<u8>)>(BACKLOG);
+        let outgoing = Outgoing {
+            frames,
+            dropped: CancellationToken::new(),
+        };
+        let dropped = outgoing.dropped.clone();
+        tokio::spawn(async move {
+            while let Some(Some((opcode, payload))) =
+                dropped.run_until_cancelled(to_write.recv()).await
+            {
+                let written = dropped
+                    .run_until_cancelled(write_frame(&mut writer, opcode, &payload))
+                    .await;
+                if !matches!(written, Some(Ok(()))) || opcode == CLOSE {
+                    break;
+                }
+            }
+            if !dropped.is_cancelled() {
+                writer.shutdown().await.ok();
+            }
+        });
+
+        let (messages, incoming) = mpsc::channel(16);
+        let pongs = outgoing.clone();
+        tokio::spawn(async move {
+            let mut message = Vec::new();
+            loop {
+                let frame = pongs
+                    .dropped
+                    .run_until_cancelled(read_frame(&mut reader))
+                    .await;
+                let (fin, opcode, payload) = match frame {
+                    Some(Ok(frame)) => frame,
+                    Some(Err(e)) => {
+                        tracing::debug!("WebSocket closed: {e}");
+                        break;
+                    }
+                    None => break,
+                };
+                match opcode {
+                    PING => pongs.send(PONG, payload),
+                    CLOSE => {
+                        pongs.send(CLOSE, Vec::new());
+                        break;
+                    }
+                    TEXT | CONTINUATION => {
+                        message.extend_from_slice(&payload);
+                        if message.len() as u64 > MAX_MESSAGE {
+                            pongs.send(CLOSE, CLOSE_TOO_BIG.to_be_bytes().to_vec());
+                            break;
+                        }
+                        if !fin {
+                            continue;
+                        }
+                        let Ok(text) = String::from_utf8(std::mem::take(&mut message)) else {
+                            break;
+                        };
+                        if messages.send(text).await.is_err() {
+                            break;
+                        }
+                    }
+                    // Binary messages aren't used.
+                    _ => message.clear(),
+                }
+            }
+        });
+        Self { outgoing, incoming }
+    }
+
+    /// Queues a text message. A client that doesn't keep up with them is
+    /// disconnected.
+    pub fn send(&self, text: &str) {
+        self.outgoing.send(TEXT, text.as_bytes().to_vec());
+    }
+
+    /// The next text message, or `None` once the client closed the
+    /// connection. Cancel safe.
+    pub async fn recv(&mut self) -> Option<String> {
+        self.incoming.recv().await
+    }
+
+    /// Starts the closing handshake.
+    pub fn close(&self) {
+        self.outgoing
+            .send(CLOSE, CLOSE_NORMAL.to_be_bytes().to_vec());
+    }
+}
+
+/// Answers a WebSocket handshake, running `session` on the connection once
+/// it is established. Returns the response to send, which is an error unless
+/// the request is a valid handshake over HTTP/1.1.
+pub fn upgrade<F, Fut>(request: &mut Request, session: F) -> Response
+where
+    F: FnOnce(WebSocket) -> Fut + Send + 'static,
+    Fut: Future<Output = ()> + Send + 'static,
+{
+    let headers = request.headers();
+    let has = |name: header::HeaderName, token: &str| {
+        headers
+            .get_all(name)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .any(|value| value.trim().eq_ignore_ascii_case(token))
+    };
+    if !has(header::UPGRADE, "websocket") || !has(header::CONNECTION, "upgrade") {
+        return (
+            StatusCode::UPGRADE_REQUIRED,
+            [(header::UPGRADE, "websocket")],
+            "Expected a WebSocket handshake",
+        )
+            .into_response();
+    }
+    if !has(header::SEC_WEBSOCKET_VERSION, "13") {
+        return (
+            StatusCode::BAD_REQUEST,
+            [(header::SEC_WEBSOCKET_VERSION, "13")],
+            "Unsupported WebSocket version",
+        )
+            .into_response();
+    }
+    let Some(key) = headers
+        .get(header::SEC_WEBSOCKET_KEY)
+        .and_then(|key| key.to_str().ok())
+    else {
+        return (StatusCode::BAD_REQUEST, "Missing Sec-WebSocket-Key").into_response();
+    };
+    let accept = accept_key(key);
+    // Only HTTP/1.1 connections can be taken over.
+    let Some(on_upgrade) = request
+        .extensions_mut()
+        .remove::<hyper::upgrade::OnUpgrade>()
+    else {
+        return (StatusCode::BAD_REQUEST, "Connection can't be upgraded").into_response();
+    };
+
+    tokio::spawn(async move {
+        match on_upgrade.await {
+            Ok(upgraded) => session(WebSocket::spawn(TokioIo::new(upgraded))).await,
+            Err(e) => tracing::warn!("WebSocket upgrade failed: {e}"),
+        }
+    });
+    (
+        StatusCode::SWITCHING_PROTOCOLS,
+        [
+            (header::UPGRADE, "websocket".to_owned()),
+            (header::CONNECTION, "Upgrade".to_owned()),
+            (header::SEC_WEBSOCKET_ACCEPT, accept),
+        ],
+    )
+        .into_response()
+}
+
+fn accept_key(key: &str) -> String {
+    let digest = ring::digest::digest(
+        &ring::digest::SHA1_FOR_LEGACY_USE_ONLY,
+        format!("{key}{GUID}").as_bytes(),
+    );
+    base64::engine::general_purpose::STANDARD.encode(digest)
+}
+
+/// `GET /paste/{id}/ws`
+pub async fn get(
+    State(service): State<Arc<Service>>,
+    State(shutdown): State<CancellationToken>,
+    Path(id): Path<Uuid>,
+    mut request: Request,
+) -> Response {
+    if !service.exists(&id) {
+        return (StatusCode::NOT_FOUND, "Paste not found").into_response();
+    }
+    // Subscribe before answering so that no change is missed.
+    let events = service.events().subscribe("WebSocket watcher");
+    upgrade(&mut request, move |socket| {
+        changes(socket, id, events, shutdown)
+    })
+}
+
+/// Sends a message for every change to paste `id` until the paste is gone,
+/// the client closes the connection or the server shuts down.
+async fn changes(
+    mut socket: WebSocket,
+    id: Uuid,
+    mut events: Subscription,
+    shutdown: CancellationToken,
+) {
+    loop {
+        tokio::select! {
+            emitted = events.recv() => {
+                let Some(emitted) = emitted else { break };
+                let Some((name, changed)) = emitted.event.paste() else {
+                    continue;
+                };
+                if changed != id {
+                    continue;
+                }
+                let message = serde_json::json!({
+                    "event": name,
+                    "id": id,
+                    "timestamp": emitted.timestamp(),
+                });
+                socket.send(&message.to_string());
+                if matches!(emitted.event, Event::PasteDeleted(_) | Event::PasteExpired(_)) {
+                    socket.close();
+                    break;
+                }
+            }
+            // Messages from the client are ignored.
+            message = socket.recv() => if message.is_none() {
+                break;
+            },
+            () = shutdown.cancelled() => {
+                socket.close();
+                break;
+            }
+        }
+    }
+}
+
+/// Reads a frame from the client, returning whether it ends a message, its
+/// opcode and its unmasked payload.
+async fn read_frame(reader: &mut (impl AsyncRead + Unpin)) -> anyhow::Result<(bool, u8, Vec<u8>)> {
+    let mut head = [0; 2];
+    reader.read_exact(&mut head).await?;
+    let fin = head[0] & 0x80 != 0;
+    let opcode = head[0] & 0x0f;
+    let masked = head[1] & 0x80 != 0;
+    let len = match head[1] & 0x7f {
+        126 => u64::from(reader.read_u16().await?),
+        127 => reader.read_u64().await?,
+        len => u64::from(len),
+    };
+    if !masked {
+        anyhow::bail!("Unmasked frame from client");
+    }
+    if len > MAX_FRAME {
+        anyhow::bail!("Frame of {len} bytes");
+    }
+    let mut mask = [0; 4];
+    reader.read_exact(&mut mask).await?;
+    let mut payload = vec![0; len as usize];
+    reader.read_exact(&mut payload).await?;
+    for (i, byte) in payload.iter_mut().enumerate() {
+        *byte ^= mask[i % 4];
+    }
+    Ok((fin, opcode, payload))
+}
+
+async fn write_frame(
+    writer: &mut (impl AsyncWrite + Unpin),
+    opcode: u8,
+    payload: &[u8],
+) -> std::io::Result<()> {
+    let mut frame = vec![0x80 | opcode];
+    match payload.len() {
+        len @ 0..=125 => frame.push(len as u8),
+        len @ 126..=0xffff => {
+            frame.push(126);
+            frame.extend_from_slice(&(len as u16).to_be_bytes());
+        }
+        len => {
+            frame.push(127);
+            frame.extend_from_slice(&(len as u64).to_be_bytes());
+        }
+    }
+    frame.extend_from_slice(payload);
+    writer.write_all(&frame).await?;
+    writer.flush().await
+}
+
+#[test]
+fn test_accept_key_matches_rfc_6455() {
+    assert_eq!(
+        accept_key("dGhlIHNhbXBsZSBub25jZQ=="),
+        "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+    );
+}
+
+#[tokio::test]
+async fn test_clients_that_fall_behind_are_dropped() {
+    let (mut client, server) = tokio::io::duplex(64);
+    let socket = WebSocket::spawn(server);
+    for _ in 0..BACKLOG + 16 {
+        socket.send("hello");
+    }
+    assert!(socket.outgoing.dropped.is_cancelled());
+    // The connection closes with what fit through before.
+    let mut received = Vec::new();
+    client.read_to_end(&mut received).await.unwrap();
+    assert!(received.len() < BACKLOG * 7);
+}